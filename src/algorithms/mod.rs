@@ -1,7 +1,9 @@
 mod min_scored;
 pub mod pole_optimization;
+pub mod pole_polish;
 pub mod pole_pretty_connections;
 mod miner_lp;
 
 pub use pole_optimization::*;
+pub use pole_polish::*;
 pub use pole_pretty_connections::*;
@@ -0,0 +1,289 @@
+use good_lp::solvers::{DualValues, SolutionWithDual};
+use good_lp::*;
+use hashbrown::{HashMap, HashSet};
+use itertools::Itertools;
+use petgraph::prelude::*;
+
+use super::{
+    get_pole_coverage_dict, greedy_cover, DistanceConnectivity, PoleCoverSolver, SetCoverILPSolver,
+};
+use crate::better_bp::EntityId;
+use crate::error::OptimizerError;
+use crate::highs_solve::HighsOptions;
+use crate::pole_graph::CandPoleGraph;
+
+/// Solver for huge candidate sets, which keeps the ILP small.
+///
+/// Starts from a greedy cover, then repeatedly solves the LP relaxation over only the current poles
+/// ("columns"), and adds candidate poles whose cost is less than the sum of the duals of the entities they power.
+/// When no such pole is left, the LP is optimal over all candidates; the ILP of [SetCoverILPSolver] is then solved
+/// over only the generated poles. This is price-and-branch, so the result may be slightly worse than the full ILP.
+///
+/// The LP ignores connectivity, so with it, the poles on paths from the generated poles to the roots are also added
+/// (see [DistanceConnectivity::paths_to_roots]); otherwise the final ILP might have no way to connect them.
+pub struct ColumnGenerationSolver<'a> {
+    /// Used for the cost function, solver settings, and the final ILP.
+    pub ilp: SetCoverILPSolver<'a>,
+    /// Max number of poles added per pricing round.
+    pub columns_per_round: usize,
+    pub max_rounds: usize,
+}
+
+const EPS: f64 = 1e-9;
+
+impl ColumnGenerationSolver<'_> {
+    fn cost(&self, graph: &CandPoleGraph, idx: NodeIndex) -> f64 {
+        if self.ilp.fixed_poles.contains(&idx) {
+            0.0
+        } else {
+            (self.ilp.cost)(graph, idx)
+        }
+    }
+
+    /// Solves the LP relaxation over `columns`; returns the dual value of each entity's coverage constraint.
+    fn restricted_duals(
+        &self,
+        graph: &CandPoleGraph,
+        coverage: &HashMap<EntityId, HashSet<NodeIndex>>,
+        columns: &HashSet<NodeIndex>,
+    ) -> Result<(f64, HashMap<EntityId, f64>), OptimizerError> {
+        let mut vars = ProblemVariables::new();
+        let pole_vars = columns
+            .iter()
+            .sorted()
+            .map(|&idx| (idx, vars.add(variable().min(0).max(1))))
+            .collect::<HashMap<_, _>>();
+        let objective: Expression = pole_vars
+            .iter()
+            .map(|(&idx, var)| var.into_expression() * self.cost(graph, idx))
+            .sum();
+        let mut problem = (self.ilp.solver)(vars.minimise(objective));
+        let constraints = coverage
+            .iter()
+            .sorted_by_key(|(entity, _)| **entity)
+            .map(|(entity, poles)| {
+                let var_sum: Expression = poles
+                    .iter()
+                    .filter_map(|idx| pole_vars.get(idx))
+                    .copied()
+                    .sum();
+                (*entity, problem.add_constraint(constraint!(var_sum >= 1)))
+            })
+            .collect_vec();
+        let mut solution = (self.ilp.config)(HighsOptions::default())?
+            .apply(problem)
+            .solve()?;
+        let bound = pole_vars
+            .iter()
+            .map(|(&idx, var)| solution.value(*var) * self.cost(graph, idx))
+            .sum();
+        let dual = solution.compute_dual();
+        let duals = constraints
+            .into_iter()
+            .map(|(entity, constraint)| (entity, dual.dual(constraint).max(0.0)))
+            .collect();
+        Ok((bound, duals))
+    }
+
+    /// Candidate poles not in `columns` with negative reduced cost, most negative first.
+    fn price(
+        &self,
+        graph: &CandPoleGraph,
+        columns: &HashSet<NodeIndex>,
+        duals: &HashMap<EntityId, f64>,
+    ) -> Vec<NodeIndex> {
+        graph
+            .node_indices()
+            .filter(|idx| !columns.contains(idx))
+            .map(|idx| {
+                let dual_sum: f64 = graph[idx]
+                    .powered_entities
+                    .iter()
+                    .map(|entity| duals.get(entity).copied().unwrap_or(0.0))
+                    .sum();
+                (idx, self.cost(graph, idx) - dual_sum)
+            })
+            .filter(|(_, reduced_cost)| *reduced_cost < -EPS)
+            .sorted_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)))
+            .take(self.columns_per_round)
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+}
+
+impl PoleCoverSolver for ColumnGenerationSolver<'_> {
+    fn solve(&self, graph: &CandPoleGraph) -> Result<CandPoleGraph, OptimizerError> {
+        let coverage = get_pole_coverage_dict(graph);
+        // columns may overlap, as they are only candidates for the final ILP, which forbids selecting overlapping poles
+        let mut columns = greedy_cover(
+            graph,
+            &coverage,
+            &self.ilp.fixed_poles,
+            &HashMap::new(),
+            |idx| self.cost(graph, idx),
+        );
+        println!(
+            "Column generation: {} of {} candidate poles from greedy cover",
+            columns.len(),
+            graph.node_count()
+        );
+        for round in 1..=self.max_rounds {
+            let (bound, duals) = self.restricted_duals(graph, &coverage, &columns)?;
+            let new_columns = self.price(graph, &columns, &duals);
+            println!(
+                "Column generation round {}: LP cost {:.2}, adding {} poles",
+                round,
+                bound,
+                new_columns.len()
+            );
+            if new_columns.is_empty() {
+                break;
+            }
+            columns.extend(new_columns);
+        }
+        if let Some(connectivity) = &self.ilp.connectivity {
+            let connecting = connectivity.paths_to_roots(graph, &columns);
+            println!(
+                "Column generation: adding {} poles to connect the generated ones",
+                connecting.difference(&columns).count()
+            );
+            columns.extend(connecting);
+        }
+
+        let mut index_map = HashMap::new();
+        let restricted = graph.filter_map(
+            |idx, node| {
+                columns.contains(&idx).then(|| {
+                    index_map.insert(idx, NodeIndex::new(index_map.len()));
+                    node.clone()
+                })
+            },
+            |_, w| Some(*w),
+        );
+        let final_ilp =
+            SetCoverILPSolver {
+                solver: self.ilp.solver,
+                config: self.ilp.config,
+                cost: self.ilp.cost,
+                connectivity: self.ilp.connectivity.as_ref().map(|connectivity| {
+                    DistanceConnectivity {
+                        center_rel_pos: connectivity.center_rel_pos,
+                        roots: connectivity.roots.clone(),
+                        max_hops: connectivity.max_hops,
+                        connect_fixed: connectivity.connect_fixed,
+                    }
+                }),
+                fixed_poles: self
+                    .ilp
+                    .fixed_poles
+                    .iter()
+                    .map(|idx| index_map[idx])
+                    .collect(),
+                max_count: self.ilp.max_count.clone(),
+                lazy: self.ilp.lazy,
+                max_pole_types: self.ilp.max_pole_types,
+                max_load: self.ilp.max_load.clone(),
+                initial_solution: Default::default(),
+                soft_entities: self.ilp.soft_entities.clone(),
+            };
+        println!(
+            "Solving ILP over {} generated poles",
+            restricted.node_count()
+        );
+        final_ilp.solve(&restricted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::point2;
+
+    use crate::bp_model::test_util::small_pole_prototype;
+    use crate::bp_model::BpModel;
+    use crate::pole_graph::ToCandidatePoleGraph;
+
+    use super::*;
+
+    #[test]
+    fn test_column_generation_covers_all() {
+        let mut model = BpModel::new();
+        let entities = (0..6)
+            .flat_map(|i| [point2(i * 3, 0), point2(i * 3, 5)])
+            .map(|pos| model.add_test_powerable(pos))
+            .collect::<HashSet<_>>();
+
+        let graph = model
+            .with_all_candidate_poles(model.get_bounding_box(), &[&small_pole_prototype()])
+            .get_maximally_connected_pole_graph()
+            .0
+            .to_cand_pole_graph(&model);
+
+        let ilp = SetCoverILPSolver {
+            solver: &highs,
+            config: &Ok,
+            cost: &|_, _| 1.0,
+            connectivity: None,
+            fixed_poles: HashSet::new(),
+            max_count: HashMap::new(),
+            lazy: false,
+            max_pole_types: None,
+            max_load: None,
+            initial_solution: Default::default(),
+            soft_entities: Default::default(),
+        };
+        let solver = ColumnGenerationSolver {
+            ilp,
+            columns_per_round: 5,
+            max_rounds: 100,
+        };
+        let subgraph = solver.solve(&graph).unwrap();
+
+        let powered_entities = subgraph
+            .node_weights()
+            .flat_map(|node| node.powered_entities.iter())
+            .cloned()
+            .collect::<HashSet<_>>();
+        assert_eq!(powered_entities, entities);
+    }
+
+    #[test]
+    fn test_column_generation_connects() {
+        // two groups of entities too far apart for one pole to connect them
+        let mut model = BpModel::new();
+        for pos in [(0, 0), (1, 0), (20, 0), (21, 0)] {
+            model.add_test_powerable(point2(pos.0, pos.1));
+        }
+        let graph = model
+            .with_all_candidate_poles(model.get_bounding_box(), &[&small_pole_prototype()])
+            .get_maximally_connected_pole_graph()
+            .0
+            .to_cand_pole_graph(&model);
+
+        let ilp = SetCoverILPSolver {
+            solver: &highs,
+            config: &Ok,
+            cost: &|_, _| 1.0,
+            connectivity: Some(DistanceConnectivity {
+                center_rel_pos: (0.5, 0.5),
+                roots: vec![],
+                max_hops: None,
+                connect_fixed: false,
+            }),
+            fixed_poles: HashSet::new(),
+            max_count: HashMap::new(),
+            lazy: false,
+            max_pole_types: None,
+            max_load: None,
+            initial_solution: Default::default(),
+            soft_entities: Default::default(),
+        };
+        let solver = ColumnGenerationSolver {
+            ilp,
+            columns_per_round: 5,
+            max_rounds: 100,
+        };
+        let subgraph = solver.solve(&graph).unwrap();
+        assert!(subgraph.node_count() > 2);
+        assert_eq!(petgraph::algo::connected_components(&subgraph), 1);
+    }
+}
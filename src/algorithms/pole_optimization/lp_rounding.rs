@@ -0,0 +1,443 @@
+use std::collections::BTreeMap;
+
+use good_lp::Solution;
+use good_lp::SolverModel;
+use hashbrown::{HashMap, HashSet};
+use itertools::Itertools;
+use petgraph::prelude::*;
+use rand::prelude::StdRng;
+use rand::{Rng, SeedableRng};
+
+use super::{
+    assign_loads, fits_with, get_pole_coverage_dict, greedy_cover, overlap_violations,
+    overlapping_candidates, BuiltProblem, PoleCoverSolver, SetCoverILPSolver,
+};
+use crate::error::OptimizerError;
+use crate::highs_solve::HighsOptions;
+use crate::pole_graph::CandPoleGraph;
+
+/// Approximate solver for very large instances, avoiding MIP branch-and-bound.
+///
+/// Solves the LP relaxation of [SetCoverILPSolver], then does randomized rounding:
+/// each round selects every pole with probability equal to its LP value.
+/// With `O(log n)` rounds this is an `O(log n)` approximation in expectation, where n is the number of entities.
+/// Afterward, uncovered entities and connectivity are repaired greedily, and redundant poles are removed.
+/// No pole is added that overlaps one already selected.
+/// The repair steps do not respect [SetCoverILPSolver::max_count], [SetCoverILPSolver::max_pole_types],
+/// or [SetCoverILPSolver::max_load];
+/// if the result exceeds them, or can't power or connect everything without overlapping poles, this gives an error.
+pub struct LpRoundingSolver<'a> {
+    /// The LP relaxation of this problem is solved.
+    pub lp: SetCoverILPSolver<'a>,
+    /// Number of rounding rounds. If `None`, uses `ceil(ln(number of entities)) + 1`.
+    pub rounds: Option<usize>,
+    pub seed: u64,
+}
+
+const EPS: f64 = 1e-6;
+
+impl LpRoundingSolver<'_> {
+    fn solve_relaxation(
+        &self,
+        graph: &CandPoleGraph,
+    ) -> Result<BTreeMap<NodeIndex, f64>, OptimizerError> {
+        let BuiltProblem {
+            problem, pole_vars, ..
+        } = self.lp.build_problem(graph, true, self.lp.cost);
+        let solution = (self.lp.config)(HighsOptions::default())?
+            .apply(problem)
+            .solve()?;
+        Ok(pole_vars
+            .into_iter()
+            .map(|(idx, var)| (idx, solution.value(var).clamp(0.0, 1.0)))
+            .collect())
+    }
+
+    fn cost(&self, graph: &CandPoleGraph, idx: NodeIndex) -> f64 {
+        if self.lp.fixed_poles.contains(&idx) {
+            0.0
+        } else {
+            (self.lp.cost)(graph, idx)
+        }
+    }
+
+    /// The best pole to add out of `options`: highest LP value, then lowest cost.
+    fn best_pole(
+        &self,
+        graph: &CandPoleGraph,
+        lp_values: &BTreeMap<NodeIndex, f64>,
+        options: impl IntoIterator<Item = NodeIndex>,
+    ) -> Option<NodeIndex> {
+        options.into_iter().min_by(|&a, &b| {
+            lp_values[&b]
+                .total_cmp(&lp_values[&a])
+                .then(self.cost(graph, a).total_cmp(&self.cost(graph, b)))
+                .then(a.cmp(&b))
+        })
+    }
+
+    fn round(
+        &self,
+        graph: &CandPoleGraph,
+        lp_values: &BTreeMap<NodeIndex, f64>,
+        overlaps: &HashMap<NodeIndex, HashSet<NodeIndex>>,
+    ) -> HashSet<NodeIndex> {
+        let coverage = get_pole_coverage_dict(graph);
+        let rounds = self
+            .rounds
+            .unwrap_or_else(|| (coverage.len().max(1) as f64).ln().ceil() as usize + 1);
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut selected = self.lp.fixed_poles.clone();
+        for _ in 0..rounds {
+            for (&idx, &value) in lp_values {
+                if (value >= 1.0 - EPS || (value > EPS && rng.gen_bool(value)))
+                    && fits_with(overlaps, &selected, idx)
+                {
+                    selected.insert(idx);
+                }
+            }
+        }
+
+        // cover any remaining entities
+        for (_, poles) in coverage.iter().sorted_by_key(|(id, _)| **id) {
+            if poles.iter().all(|idx| !selected.contains(idx)) {
+                let options = poles
+                    .iter()
+                    .copied()
+                    .filter(|&idx| fits_with(overlaps, &selected, idx))
+                    .collect_vec();
+                let best = self.best_pole(graph, lp_values, options);
+                selected.extend(best);
+            }
+        }
+
+        // remove redundant poles, most expensive first
+        let mut cover_count = HashMap::new();
+        for idx in &selected {
+            for entity in &graph[*idx].powered_entities {
+                *cover_count.entry(*entity).or_insert(0usize) += 1;
+            }
+        }
+        let removal_order = selected
+            .iter()
+            .copied()
+            .filter(|idx| !self.lp.fixed_poles.contains(idx))
+            .sorted_by(|&a, &b| {
+                self.cost(graph, b)
+                    .total_cmp(&self.cost(graph, a))
+                    .then(lp_values[&a].total_cmp(&lp_values[&b]))
+                    .then(a.cmp(&b))
+            })
+            .collect_vec();
+        for idx in removal_order {
+            let powered = &graph[idx].powered_entities;
+            if powered.iter().all(|entity| cover_count[entity] >= 2) {
+                for entity in powered {
+                    *cover_count.get_mut(entity).unwrap() -= 1;
+                }
+                selected.remove(&idx);
+            }
+        }
+        selected
+    }
+
+    /// Adds poles until every selected pole has a selected neighbour closer to the root.
+    fn repair_connectivity(
+        &self,
+        graph: &CandPoleGraph,
+        lp_values: &BTreeMap<NodeIndex, f64>,
+        overlaps: &HashMap<NodeIndex, HashSet<NodeIndex>>,
+        selected: &mut HashSet<NodeIndex>,
+    ) -> Result<(), OptimizerError> {
+        let Some(connectivity) = &self.lp.connectivity else {
+            return Ok(());
+        };
+        let closer_neighbours = connectivity.closer_neighbours(graph, &self.lp.fixed_poles);
+        let mut to_check = selected.iter().copied().sorted().collect_vec();
+        while let Some(idx) = to_check.pop() {
+            let Some(neighbours) = closer_neighbours.get(&idx) else {
+                continue;
+            };
+            if neighbours.iter().any(|n| selected.contains(n)) {
+                continue;
+            }
+            let options = neighbours
+                .iter()
+                .copied()
+                .filter(|&n| fits_with(overlaps, selected, n))
+                .collect_vec();
+            let best = self
+                .best_pole(graph, lp_values, options)
+                .ok_or("LP rounding could not connect the poles without overlapping ones; try the ILP solver")?;
+            selected.insert(best);
+            to_check.push(best);
+        }
+        Ok(())
+    }
+
+    /// Errors if `selected` breaks `--max-pole-types`, `--max-count`, `--max-hops`, or `--max-load`,
+    /// which rounding doesn't enforce, or leaves entities unpowered because the poles that could power them overlap others.
+    fn check_limits(
+        &self,
+        graph: &CandPoleGraph,
+        overlaps: &HashMap<NodeIndex, HashSet<NodeIndex>>,
+        selected: &HashSet<NodeIndex>,
+    ) -> Result<(), OptimizerError> {
+        let unpowered = get_pole_coverage_dict(graph)
+            .into_iter()
+            .filter(|(entity, poles)| {
+                !self.lp.soft_entities.contains_key(entity) && poles.is_disjoint(selected)
+            })
+            .count();
+        if unpowered > 0 {
+            return Err(format!(
+                "LP rounding could not power {} entities without overlapping poles; try the ILP solver",
+                unpowered
+            )
+            .into());
+        }
+        if overlap_violations(overlaps, selected, &self.lp.fixed_poles) > 0 {
+            return Err("LP rounding selected overlapping poles; try the ILP solver".into());
+        }
+        if let Some(num_types) = self.lp.pole_type_violation(graph, selected.iter().copied()) {
+            return Err(format!(
+                "LP rounding used {} pole types, more than --max-pole-types; try the ILP solver",
+                num_types
+            )
+            .into());
+        }
+        let violations = self
+            .lp
+            .max_count_violations(graph, selected.iter().copied());
+        if !violations.is_empty() {
+            let desc = violations
+                .iter()
+                .map(|(prototype, count)| {
+                    format!(
+                        "{} {} (max {})",
+                        count, prototype.name, self.lp.max_count[prototype]
+                    )
+                })
+                .join(", ");
+            return Err(format!(
+                "LP rounding exceeded --max-count: {}; try the ILP solver",
+                desc
+            )
+            .into());
+        }
+        let far_poles = self.lp.connectivity.as_ref().map_or(0, |connectivity| {
+            connectivity.hop_violations(graph, selected, &self.lp.fixed_poles)
+        });
+        if far_poles > 0 {
+            return Err(format!(
+                "LP rounding left {} poles more than --max-hops from the root; try the ILP solver",
+                far_poles
+            )
+            .into());
+        }
+        if let Some(limit) = &self.lp.max_load {
+            if assign_loads(graph, selected, limit).is_none() {
+                return Err(
+                    "LP rounding overloaded some pole (--max-load); try the ILP solver".into(),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Skips the LP entirely, for when there's no time to solve it: a [greedy_cover],
+    /// with connectivity repaired as after rounding. Fast, but can be far from optimal.
+    pub fn solve_greedy(&self, graph: &CandPoleGraph) -> Result<CandPoleGraph, OptimizerError> {
+        let coverage = get_pole_coverage_dict(graph);
+        let overlaps = overlapping_candidates(graph);
+        let mut selected = greedy_cover(graph, &coverage, &self.lp.fixed_poles, &overlaps, |idx| {
+            self.cost(graph, idx)
+        });
+        let no_lp = graph.node_indices().map(|idx| (idx, 0.0)).collect();
+        self.repair_connectivity(graph, &no_lp, &overlaps, &mut selected)?;
+        self.check_limits(graph, &overlaps, &selected)?;
+        Ok(graph.filter_map(
+            |idx, node| selected.contains(&idx).then(|| node.clone()),
+            |_, w| Some(*w),
+        ))
+    }
+}
+
+impl PoleCoverSolver for LpRoundingSolver<'_> {
+    fn solve(&self, graph: &CandPoleGraph) -> Result<CandPoleGraph, OptimizerError> {
+        let lp_values = self.solve_relaxation(graph)?;
+        let overlaps = overlapping_candidates(graph);
+        let mut selected = self.round(graph, &lp_values, &overlaps);
+        self.repair_connectivity(graph, &lp_values, &overlaps, &mut selected)?;
+        self.check_limits(graph, &overlaps, &selected)?;
+
+        let lower_bound: f64 = lp_values
+            .iter()
+            .map(|(&idx, value)| value * self.cost(graph, idx))
+            .sum();
+        let cost: f64 = selected.iter().map(|&idx| self.cost(graph, idx)).sum();
+        println!(
+            "LP rounding: cost {:.1}, LP lower bound {:.1} (ratio {:.3})",
+            cost,
+            lower_bound,
+            cost / lower_bound.max(EPS)
+        );
+
+        Ok(graph.filter_map(
+            |idx, node| selected.contains(&idx).then(|| node.clone()),
+            |_, w| Some(*w),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::point2;
+    use good_lp::highs;
+
+    use crate::algorithms::DistanceConnectivity;
+    use crate::better_bp::EntityId;
+    use crate::bp_model::test_util::small_pole_prototype;
+    use crate::bp_model::{BpModel, WorldEntity};
+    use crate::pole_graph::{CandPoleNode, ToCandidatePoleGraph};
+
+    use super::*;
+
+    #[test]
+    fn test_lp_rounding_covers_all() {
+        let mut model = BpModel::new();
+        let entities = (0..6)
+            .flat_map(|i| [point2(i * 3, 0), point2(i * 3, 5)])
+            .map(|pos| model.add_test_powerable(pos))
+            .collect::<HashSet<_>>();
+
+        let graph = model
+            .with_all_candidate_poles(model.get_bounding_box(), &[&small_pole_prototype()])
+            .get_maximally_connected_pole_graph()
+            .0
+            .to_cand_pole_graph(&model);
+
+        let solver = LpRoundingSolver {
+            lp: SetCoverILPSolver {
+                solver: &highs,
+                config: &Ok,
+                cost: &|_, _| 1.0,
+                connectivity: None,
+                fixed_poles: HashSet::new(),
+                max_count: HashMap::new(),
+                lazy: false,
+                max_pole_types: None,
+                max_load: None,
+                initial_solution: Default::default(),
+                soft_entities: Default::default(),
+            },
+            rounds: None,
+            seed: 1,
+        };
+        let subgraph = solver.solve(&graph).unwrap();
+
+        let powered_entities = subgraph
+            .node_weights()
+            .flat_map(|node| node.powered_entities.iter())
+            .cloned()
+            .collect::<HashSet<_>>();
+        assert_eq!(powered_entities, entities);
+        // no pole is redundant
+        for idx in subgraph.node_indices() {
+            let others = subgraph
+                .node_indices()
+                .filter(|&other| other != idx)
+                .flat_map(|other| subgraph[other].powered_entities.iter())
+                .collect::<HashSet<_>>();
+            assert!(subgraph[idx]
+                .powered_entities
+                .iter()
+                .any(|entity| !others.contains(entity)));
+        }
+    }
+
+    #[test]
+    fn test_greedy_covers_all() {
+        let mut model = BpModel::new();
+        let entities = (0..6)
+            .map(|i| model.add_test_powerable(point2(i * 4, i % 2 * 4)))
+            .collect::<HashSet<_>>();
+        let graph = model
+            .with_all_candidate_poles(model.get_bounding_box(), &[&small_pole_prototype()])
+            .get_maximally_connected_pole_graph()
+            .0
+            .to_cand_pole_graph(&model);
+
+        let solver = LpRoundingSolver {
+            lp: SetCoverILPSolver {
+                solver: &highs,
+                config: &Ok,
+                cost: &|_, _| 1.0,
+                connectivity: Some(DistanceConnectivity {
+                    center_rel_pos: (0.5, 0.5),
+                    roots: vec![],
+                    max_hops: None,
+                    connect_fixed: false,
+                }),
+                fixed_poles: HashSet::new(),
+                max_count: HashMap::new(),
+                lazy: false,
+                max_pole_types: None,
+                max_load: None,
+                initial_solution: Default::default(),
+                soft_entities: Default::default(),
+            },
+            rounds: None,
+            seed: 1,
+        };
+        let subgraph = solver.solve_greedy(&graph).unwrap();
+        let powered_entities = subgraph
+            .node_weights()
+            .flat_map(|node| node.powered_entities.iter())
+            .cloned()
+            .collect::<HashSet<_>>();
+        assert_eq!(powered_entities, entities);
+    }
+
+    #[test]
+    fn test_greedy_no_overlapping_poles() {
+        let candidate = |x: f64, powered: &[u32]| CandPoleNode {
+            entity: WorldEntity {
+                position: point2(x, 0.5),
+                direction: 0,
+                prototype: small_pole_prototype(),
+            },
+            powered_entities: powered.iter().map(|&id| EntityId(id)).collect(),
+        };
+        // the cheap pole for entity 2 overlaps the pole for entity 1
+        let mut graph = CandPoleGraph::default();
+        graph.add_node(candidate(0.5, &[1]));
+        graph.add_node(candidate(0.5, &[2]));
+        let far = graph.add_node(candidate(5.5, &[2]));
+
+        let solver = LpRoundingSolver {
+            lp: SetCoverILPSolver {
+                solver: &highs,
+                config: &Ok,
+                cost: &|_, idx| if idx == far { 3.0 } else { 1.0 },
+                connectivity: None,
+                fixed_poles: HashSet::new(),
+                max_count: HashMap::new(),
+                lazy: false,
+                max_pole_types: None,
+                max_load: None,
+                initial_solution: Default::default(),
+                soft_entities: Default::default(),
+            },
+            rounds: None,
+            seed: 1,
+        };
+        let subgraph = solver.solve_greedy(&graph).unwrap();
+        let positions = subgraph
+            .node_weights()
+            .map(|node| node.entity.position.x)
+            .sorted_by(f64::total_cmp)
+            .collect_vec();
+        assert_eq!(positions, vec![0.5, 5.5]);
+    }
+}
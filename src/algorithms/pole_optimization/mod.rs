@@ -1,20 +1,29 @@
-use std::error::Error;
-
 use hashbrown::{HashMap, HashSet};
+use itertools::Itertools;
 use petgraph::prelude::*;
 
 
 use crate::better_bp::EntityId;
+use crate::error::OptimizerError;
 use crate::pole_graph::CandPoleGraph;
+use crate::position::{IterTiles, TilePosition};
 
+pub mod column_generation;
+pub mod lp_rounding;
+pub mod model_file;
 pub mod set_cover_ilp;
+pub mod spatial_split;
+pub use column_generation::*;
+pub use lp_rounding::*;
+pub use model_file::*;
 pub use set_cover_ilp::*;
+pub use spatial_split::*;
 
 
 /// A solver for the pole cover problem: given a pole graph, find a subgraph
 /// of poles that still powers all entities and has the minimum cost.
 pub trait PoleCoverSolver {
-    fn solve<'a>(&self, graph: &CandPoleGraph) -> Result<CandPoleGraph, Box<dyn Error + 'a>>;
+    fn solve(&self, graph: &CandPoleGraph) -> Result<CandPoleGraph, OptimizerError>;
 }
 
 pub fn get_pole_coverage_dict(graph: &CandPoleGraph) -> HashMap<EntityId, HashSet<NodeIndex>> {
@@ -31,11 +40,232 @@ pub fn get_pole_coverage_dict(graph: &CandPoleGraph) -> HashMap<EntityId, HashSe
     entity_coverage
 }
 
+/// Groups of candidates that overlap each other: for each tile occupied by more than one candidate,
+/// the candidates on it, without duplicate groups. At most one pole of each group may be selected.
+pub fn candidate_conflicts(graph: &CandPoleGraph) -> Vec<Vec<NodeIndex>> {
+    let mut by_tile = HashMap::<TilePosition, Vec<NodeIndex>>::new();
+    for idx in graph.node_indices() {
+        for tile in graph[idx].entity.world_bbox().iter_tiles() {
+            by_tile.entry(tile).or_default().push(idx);
+        }
+    }
+    by_tile
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .unique()
+        .sorted()
+        .collect()
+}
+
+/// For each candidate, the other candidates it overlaps; see [candidate_conflicts].
+pub fn overlapping_candidates(graph: &CandPoleGraph) -> HashMap<NodeIndex, HashSet<NodeIndex>> {
+    let mut overlaps = HashMap::<NodeIndex, HashSet<NodeIndex>>::new();
+    for group in candidate_conflicts(graph) {
+        for &idx in &group {
+            overlaps
+                .entry(idx)
+                .or_default()
+                .extend(group.iter().copied().filter(|&other| other != idx));
+        }
+    }
+    overlaps
+}
+
+/// If `idx` overlaps none of `selected`, so may be selected too.
+pub fn fits_with(
+    overlaps: &HashMap<NodeIndex, HashSet<NodeIndex>>,
+    selected: &HashSet<NodeIndex>,
+    idx: NodeIndex,
+) -> bool {
+    overlaps
+        .get(&idx)
+        .is_none_or(|others| others.is_disjoint(selected))
+}
+
+/// Number of pairs of `selected` poles that overlap, other than pairs of `fixed_poles`.
+pub fn overlap_violations(
+    overlaps: &HashMap<NodeIndex, HashSet<NodeIndex>>,
+    selected: &HashSet<NodeIndex>,
+    fixed_poles: &HashSet<NodeIndex>,
+) -> usize {
+    selected
+        .iter()
+        .flat_map(|&idx| {
+            overlaps
+                .get(&idx)
+                .into_iter()
+                .flatten()
+                .filter(move |&&other| {
+                    idx < other
+                        && selected.contains(&other)
+                        && !(fixed_poles.contains(&idx) && fixed_poles.contains(&other))
+                })
+        })
+        .count()
+}
+
+/// A quick cover, starting from `fixed_poles`: for each entity in order, if not yet covered,
+/// adds the pole powering the most uncovered entities per cost. Ignores connectivity.
+/// Poles in `overlaps` of one already added are skipped, so an entity only those could power is left uncovered.
+pub fn greedy_cover(
+    graph: &CandPoleGraph,
+    coverage: &HashMap<EntityId, HashSet<NodeIndex>>,
+    fixed_poles: &HashSet<NodeIndex>,
+    overlaps: &HashMap<NodeIndex, HashSet<NodeIndex>>,
+    cost: impl Fn(NodeIndex) -> f64,
+) -> HashSet<NodeIndex> {
+    let mut selected = fixed_poles.clone();
+    let mut covered = selected
+        .iter()
+        .flat_map(|idx| graph[*idx].powered_entities.iter().copied())
+        .collect::<HashSet<_>>();
+    for (entity, poles) in coverage.iter().sorted_by_key(|(entity, _)| **entity) {
+        if covered.contains(entity) {
+            continue;
+        }
+        let best = poles
+            .iter()
+            .copied()
+            .filter(|&idx| fits_with(overlaps, &selected, idx))
+            .max_by(|&a, &b| {
+                let score = |idx: NodeIndex| {
+                    let new = graph[idx]
+                        .powered_entities
+                        .iter()
+                        .filter(|e| !covered.contains(*e))
+                        .count();
+                    new as f64 / cost(idx).max(1e-9)
+                };
+                score(a).total_cmp(&score(b)).then(b.cmp(&a))
+            });
+        let Some(best) = best else {
+            continue;
+        };
+        covered.extend(graph[best].powered_entities.iter().copied());
+        selected.insert(best);
+    }
+    selected
+}
+
+/// Limit on the total load of the entities assigned to each pole (`--max-load`, `--max-load-mw`).
+#[derive(Debug, Clone)]
+pub struct LoadLimit {
+    pub max: f64,
+    /// Load of each entity, e.g. its power draw in watts; entities not listed have none.
+    /// If `None`, every entity counts as 1.
+    pub weights: Option<HashMap<EntityId, f64>>,
+}
+
+impl LoadLimit {
+    /// At most `max` entities per pole.
+    pub fn count(max: usize) -> Self {
+        LoadLimit {
+            max: max as f64,
+            weights: None,
+        }
+    }
+
+    pub fn weight(&self, entity: EntityId) -> f64 {
+        match &self.weights {
+            Some(weights) => weights.get(&entity).copied().unwrap_or(0.0),
+            None => 1.0,
+        }
+    }
+}
+
+/// Assigns each entity powered by `selected` poles to one of them, so that no pole's load is over the limit,
+/// by finding augmenting paths. `None` if no assignment was found.
+/// Exact when every entity counts as 1; with weights this is bin packing, and it may miss an assignment.
+/// Entities not powered by any selected pole are left out.
+pub fn assign_loads(
+    graph: &CandPoleGraph,
+    selected: &HashSet<NodeIndex>,
+    limit: &LoadLimit,
+) -> Option<HashMap<EntityId, NodeIndex>> {
+    let coverage = get_pole_coverage_dict(graph)
+        .into_iter()
+        .map(|(entity, poles)| {
+            let poles = poles
+                .into_iter()
+                .filter(|idx| selected.contains(idx))
+                .sorted()
+                .collect_vec();
+            (entity, poles)
+        })
+        .filter(|(_, poles)| !poles.is_empty())
+        .collect::<HashMap<_, _>>();
+    let mut assigner = LoadAssigner {
+        coverage: &coverage,
+        limit,
+        loads: HashMap::new(),
+        assignment: HashMap::new(),
+    };
+    // heaviest first, which packs better
+    let entities = coverage
+        .keys()
+        .copied()
+        .sorted_by(|a, b| limit.weight(*b).total_cmp(&limit.weight(*a)).then(a.cmp(b)));
+    for entity in entities {
+        if !assigner.assign(entity, &mut HashSet::new()) {
+            return None;
+        }
+    }
+    Some(assigner.assignment)
+}
+
+struct LoadAssigner<'a> {
+    coverage: &'a HashMap<EntityId, Vec<NodeIndex>>,
+    limit: &'a LoadLimit,
+    loads: HashMap<NodeIndex, Vec<EntityId>>,
+    assignment: HashMap<EntityId, NodeIndex>,
+}
+
+impl LoadAssigner<'_> {
+    fn load(&self, pole: NodeIndex) -> f64 {
+        self.loads
+            .get(&pole)
+            .map_or(0.0, |load| load.iter().map(|e| self.limit.weight(*e)).sum())
+    }
+
+    /// Assigns `entity` to a pole with room, moving another entity away to make room if needed.
+    fn assign(&mut self, entity: EntityId, visited: &mut HashSet<NodeIndex>) -> bool {
+        let weight = self.limit.weight(entity);
+        let coverage = self.coverage;
+        for &pole in &coverage[&entity] {
+            if !visited.insert(pole) {
+                continue;
+            }
+            let room = self.limit.max - self.load(pole);
+            let moved = if weight <= room + 1e-9 {
+                None
+            } else {
+                let load = self.loads.get(&pole).cloned().unwrap_or_default();
+                let mut movable = load
+                    .into_iter()
+                    .filter(|other| weight <= room + self.limit.weight(*other) + 1e-9);
+                match movable.find(|other| self.assign(*other, visited)) {
+                    Some(other) => Some(other),
+                    None => continue,
+                }
+            };
+            let load = self.loads.entry(pole).or_default();
+            if let Some(moved) = moved {
+                load.retain(|other| *other != moved);
+            }
+            load.push(entity);
+            self.assignment.insert(entity, pole);
+            return true;
+        }
+        false
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use euclid::point2;
-    use hashbrown::HashSet;
+    use hashbrown::{HashMap, HashSet};
 
+    use super::LoadLimit;
     use crate::bp_model::BpModel;
     use crate::pole_graph::ToCandidatePoleGraph;
 
@@ -60,4 +290,40 @@ mod tests {
         );
         assert_eq!(entity_coverage[&e3], HashSet::from([idx_map[&p2]]));
     }
+
+    #[test]
+    fn test_assign_loads() {
+        let mut model = BpModel::new();
+        let p1 = model.add_test_pole(point2(0, 0));
+        let p2 = model.add_test_pole(point2(4, 1));
+        let e1 = model.add_test_powerable(point2(-2, 1));
+        let e2 = model.add_test_powerable(point2(2, 1));
+        let e3 = model.add_test_powerable(point2(6, 2));
+
+        let (graph, idx_map) = model.get_maximally_connected_pole_graph();
+        let graph = graph.to_cand_pole_graph(&model);
+        let both = HashSet::from([idx_map[&p1], idx_map[&p2]]);
+        let assignment = super::assign_loads(&graph, &both, &LoadLimit::count(2)).unwrap();
+        assert_eq!(assignment.len(), 3);
+        assert_eq!(assignment[&e1], idx_map[&p1]);
+        assert_eq!(assignment[&e3], idx_map[&p2]);
+        assert!(super::assign_loads(&graph, &both, &LoadLimit::count(1)).is_none());
+        let only_p1 = HashSet::from([idx_map[&p1]]);
+        assert_eq!(
+            super::assign_loads(&graph, &only_p1, &LoadLimit::count(2))
+                .unwrap()
+                .len(),
+            2
+        );
+
+        // e1 is heavy, so p1 can only take e1 alone
+        let weighted = LoadLimit {
+            max: 3.0,
+            weights: Some(HashMap::from([(e1, 2.0), (e2, 2.0), (e3, 1.0)])),
+        };
+        let assignment = super::assign_loads(&graph, &both, &weighted).unwrap();
+        assert_eq!(assignment[&e1], idx_map[&p1]);
+        assert_eq!(assignment[&e2], idx_map[&p2]);
+        assert!(super::assign_loads(&graph, &only_p1, &weighted).is_none());
+    }
 }
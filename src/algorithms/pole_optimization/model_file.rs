@@ -0,0 +1,391 @@
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use good_lp::solvers::highs::HighsProblem;
+use good_lp::variable::UnsolvedProblem;
+use good_lp::*;
+use hashbrown::HashMap;
+use itertools::Itertools;
+
+use crate::bp_model::WorldEntity;
+use crate::error::OptimizerError;
+
+/// Terms per line when writing a long expression.
+const TERMS_PER_LINE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RowSense {
+    Leq,
+    Geq,
+    Eq,
+}
+
+/// A linear constraint `expression <= 0`, `>= 0` or `== 0`. Unlike a [Constraint], it can be read back,
+/// so the problem can be written to a file as well as given to the solver.
+#[derive(Debug, Clone)]
+pub struct Row {
+    expression: Expression,
+    sense: RowSense,
+}
+
+impl Row {
+    fn new(
+        lhs: impl IntoAffineExpression,
+        rhs: impl IntoAffineExpression,
+        sense: RowSense,
+    ) -> Self {
+        Row {
+            expression: lhs.into_expression() - rhs.into_expression(),
+            sense,
+        }
+    }
+    pub fn leq(lhs: impl IntoAffineExpression, rhs: impl IntoAffineExpression) -> Self {
+        Row::new(lhs, rhs, RowSense::Leq)
+    }
+    pub fn geq(lhs: impl IntoAffineExpression, rhs: impl IntoAffineExpression) -> Self {
+        Row::new(lhs, rhs, RowSense::Geq)
+    }
+    pub fn eq(lhs: impl IntoAffineExpression, rhs: impl IntoAffineExpression) -> Self {
+        Row::new(lhs, rhs, RowSense::Eq)
+    }
+
+    /// The constant on the right side, with the variables on the left.
+    fn rhs(&self) -> f64 {
+        0.0 - self.expression.constant()
+    }
+
+    pub fn into_constraint(self) -> Constraint {
+        match self.sense {
+            RowSense::Leq => constraint::leq(self.expression, 0),
+            RowSense::Geq => constraint::geq(self.expression, 0),
+            RowSense::Eq => constraint::eq(self.expression, 0),
+        }
+    }
+}
+
+/// A variable of an [IlpModel]. All are between 0 and 1.
+struct Column {
+    var: Variable,
+    name: String,
+    binary: bool,
+}
+
+/// Variables of an [IlpModel], remembering their names and which are binary.
+pub struct ModelVars {
+    vars: ProblemVariables,
+    columns: Vec<Column>,
+}
+
+impl Default for ModelVars {
+    fn default() -> Self {
+        ModelVars {
+            vars: ProblemVariables::new(),
+            columns: vec![],
+        }
+    }
+}
+
+impl ModelVars {
+    /// Adds a variable between 0 and 1; integer if `binary`.
+    pub fn add(&mut self, name: String, binary: bool) -> Variable {
+        let definition = if binary {
+            variable().binary()
+        } else {
+            variable().min(0).max(1)
+        };
+        let var = self.vars.add(definition.name(name.clone()));
+        self.columns.push(Column { var, name, binary });
+        var
+    }
+
+    pub fn count(&self) -> usize {
+        self.columns.len()
+    }
+}
+
+/// A minimization problem, before it's given to the solver or written to a file with [Self::write].
+pub struct IlpModel {
+    pub vars: ModelVars,
+    pub objective: Expression,
+    pub rows: Vec<Row>,
+}
+
+/// Name of the variable for a candidate pole in written models: its prototype and position in half tiles,
+/// so a solution can be matched to the candidates again, with `--import-solution`.
+pub fn pole_column_name(entity: &WorldEntity) -> String {
+    let coordinate = |value: f64| {
+        let half_tiles = (value * 2.0).round() as i64;
+        if half_tiles < 0 {
+            format!("m{}", -half_tiles)
+        } else {
+            half_tiles.to_string()
+        }
+    };
+    file_name(&format!(
+        "pole_{}_{}_{}",
+        entity.prototype.name,
+        coordinate(entity.position.x),
+        coordinate(entity.position.y)
+    ))
+}
+
+/// LP and MPS names can't have most symbols.
+fn file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+impl IlpModel {
+    pub fn into_problem(self, solver: &dyn Fn(UnsolvedProblem) -> HighsProblem) -> HighsProblem {
+        let mut problem = solver(self.vars.vars.minimise(self.objective));
+        for row in self.rows {
+            problem.add_constraint(row.into_constraint());
+        }
+        problem
+    }
+
+    /// Writes the problem in LP format if `path` ends with `.lp`, or free MPS format if it ends with `.mps`.
+    pub fn write(&self, path: &Path) -> Result<(), OptimizerError> {
+        let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+        let mut out = BufWriter::new(File::create(path)?);
+        match extension {
+            "lp" => self.write_lp(&mut out)?,
+            "mps" => self.write_mps(&mut out)?,
+            _ => {
+                return Err(format!("Can't tell the format of {:?}; use .lp or .mps", path).into())
+            }
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Index of each variable in [ModelVars::columns].
+    pub(super) fn column_indices(&self) -> HashMap<Variable, usize> {
+        self.vars
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| (column.var, i))
+            .collect()
+    }
+
+    /// Nonzero terms of `expression` as column indices, in the order the variables were added.
+    fn terms(expression: &Expression, indices: &HashMap<Variable, usize>) -> Vec<(usize, f64)> {
+        expression
+            .clone()
+            .linear_coefficients()
+            .filter(|(_, coefficient)| *coefficient != 0.0)
+            .map(|(var, coefficient)| (indices[&var], coefficient))
+            .sorted_by_key(|(i, _)| *i)
+            .collect()
+    }
+
+    fn names(&self) -> Vec<String> {
+        self.vars
+            .columns
+            .iter()
+            .map(|column| file_name(&column.name))
+            .collect()
+    }
+
+    fn write_lp(&self, out: &mut impl Write) -> std::io::Result<()> {
+        let names = self.names();
+        if names.is_empty() {
+            return writeln!(out, "Minimize\n obj: 0\nEnd");
+        }
+        let indices = self.column_indices();
+        let lp_expression = |expression: &Expression| {
+            let terms = Self::terms(expression, &indices);
+            // an empty expression still needs a term
+            if terms.is_empty() {
+                return format!(" 0 {}", names[0]);
+            }
+            let mut result = String::new();
+            for (i, (column, coefficient)) in terms.into_iter().enumerate() {
+                if i > 0 && i % TERMS_PER_LINE == 0 {
+                    result.push_str("\n   ");
+                }
+                let sign = if coefficient < 0.0 { '-' } else { '+' };
+                write!(result, " {} {} {}", sign, coefficient.abs(), names[column]).unwrap();
+            }
+            result
+        };
+
+        writeln!(out, "\\ Pole cover problem; pole_NAME_X_Y is 1 if that pole is placed, at X and Y in half tiles, with m for minus")?;
+        writeln!(out, "Minimize\n obj:{}", lp_expression(&self.objective))?;
+        writeln!(out, "Subject To")?;
+        for (i, row) in self.rows.iter().enumerate() {
+            let sense = match row.sense {
+                RowSense::Leq => "<=",
+                RowSense::Geq => ">=",
+                RowSense::Eq => "=",
+            };
+            writeln!(
+                out,
+                " c{}:{} {} {}",
+                i,
+                lp_expression(&row.expression),
+                sense,
+                row.rhs()
+            )?;
+        }
+        writeln!(out, "Bounds")?;
+        for (column, name) in self.vars.columns.iter().zip(&names) {
+            if !column.binary {
+                writeln!(out, " 0 <= {} <= 1", name)?;
+            }
+        }
+        writeln!(out, "Binaries")?;
+        for (column, name) in self.vars.columns.iter().zip(&names) {
+            if column.binary {
+                writeln!(out, " {}", name)?;
+            }
+        }
+        writeln!(out, "End")
+    }
+
+    fn write_mps(&self, out: &mut impl Write) -> std::io::Result<()> {
+        let names = self.names();
+        let indices = self.column_indices();
+        // entries of each column, by row name, with the objective first
+        let mut entries = vec![vec![]; names.len()];
+        for (column, coefficient) in Self::terms(&self.objective, &indices) {
+            entries[column].push(("obj".to_string(), coefficient));
+        }
+        for (i, row) in self.rows.iter().enumerate() {
+            for (column, coefficient) in Self::terms(&row.expression, &indices) {
+                entries[column].push((format!("c{}", i), coefficient));
+            }
+        }
+
+        writeln!(out, "* Pole cover problem; pole_NAME_X_Y is 1 if that pole is placed, at X and Y in half tiles, with m for minus")?;
+        writeln!(out, "NAME poles\nROWS\n N obj")?;
+        for (i, row) in self.rows.iter().enumerate() {
+            let sense = match row.sense {
+                RowSense::Leq => 'L',
+                RowSense::Geq => 'G',
+                RowSense::Eq => 'E',
+            };
+            writeln!(out, " {} c{}", sense, i)?;
+        }
+        writeln!(out, "COLUMNS")?;
+        for (name, entries) in names.iter().zip(&entries) {
+            // every column must appear here
+            if entries.is_empty() {
+                writeln!(out, "    {} obj 0", name)?;
+            }
+            for (row, coefficient) in entries {
+                writeln!(out, "    {} {} {}", name, row, coefficient)?;
+            }
+        }
+        writeln!(out, "RHS")?;
+        for (i, row) in self.rows.iter().enumerate() {
+            if row.rhs() != 0.0 {
+                writeln!(out, "    RHS c{} {}", i, row.rhs())?;
+            }
+        }
+        writeln!(out, "BOUNDS")?;
+        for (column, name) in self.vars.columns.iter().zip(&names) {
+            if column.binary {
+                writeln!(out, " BV BND {}", name)?;
+            } else {
+                writeln!(out, " UP BND {} 1", name)?;
+            }
+        }
+        writeln!(out, "ENDATA")
+    }
+}
+
+/// Reads the values of the variables accepted by `is_variable` from a solution file written by another solver.
+/// A line with a variable's name followed by a number gives its value. This reads the plain text solutions
+/// of most solvers, e.g. `name value` from Gurobi or SCIP, or `index name value` from CBC.
+pub fn read_solution(
+    path: &Path,
+    is_variable: impl Fn(&str) -> bool,
+) -> Result<HashMap<String, f64>, OptimizerError> {
+    let mut values = HashMap::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.starts_with('#') {
+            continue;
+        }
+        let value = line
+            .split_whitespace()
+            .tuple_windows()
+            .find_map(|(name, value)| {
+                is_variable(name)
+                    .then(|| value.parse::<f64>().ok().map(|value| (name, value)))
+                    .flatten()
+            });
+        if let Some((name, value)) = value {
+            values.insert(name.to_string(), value);
+        }
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_model() -> IlpModel {
+        let mut vars = ModelVars::default();
+        let a = vars.add("pole_a".to_string(), true);
+        let b = vars.add("pole_b".to_string(), true);
+        let c = vars.add("hops-c".to_string(), false);
+        IlpModel {
+            vars,
+            objective: 2.0 * a + b,
+            rows: vec![Row::geq(a + b, 1), Row::leq(c, a), Row::eq(b, 0)],
+        }
+    }
+
+    #[test]
+    fn test_write_lp() {
+        let mut out = vec![];
+        test_model().write_lp(&mut out).unwrap();
+        let lp = String::from_utf8(out).unwrap();
+        assert!(lp.contains("obj: + 2 pole_a + 1 pole_b"), "{}", lp);
+        assert!(lp.contains("c0: + 1 pole_a + 1 pole_b >= 1"), "{}", lp);
+        assert!(lp.contains("c1: - 1 pole_a + 1 hops_c <= 0"), "{}", lp);
+        assert!(lp.contains("Bounds\n 0 <= hops_c <= 1\nBinaries\n pole_a\n pole_b\nEnd"));
+    }
+
+    #[test]
+    fn test_write_mps() {
+        let mut out = vec![];
+        test_model().write_mps(&mut out).unwrap();
+        let mps = String::from_utf8(out).unwrap();
+        assert!(
+            mps.contains("ROWS\n N obj\n G c0\n L c1\n E c2\n"),
+            "{}",
+            mps
+        );
+        assert!(
+            mps.contains("    pole_a obj 2\n    pole_a c0 1\n"),
+            "{}",
+            mps
+        );
+        assert!(mps.contains("    RHS c0 1\n"), "{}", mps);
+        assert!(mps.contains(" BV BND pole_a\n"), "{}", mps);
+        assert!(mps.contains(" UP BND hops_c 1\n"), "{}", mps);
+    }
+
+    #[test]
+    fn test_read_solution() {
+        let path = std::env::temp_dir().join("model_file_test.sol");
+        std::fs::write(
+            &path,
+            "# Objective value = 1\npole_a 1\n      1 pole_b   0.9999999   0\nother 1\n",
+        )
+        .unwrap();
+        let values = read_solution(&path, |name| name.starts_with("pole_")).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(values.len(), 2);
+        assert_eq!(values["pole_a"], 1.0);
+        assert!(values["pole_b"] > 0.5);
+    }
+}
@@ -1,25 +1,57 @@
-use std::collections::BTreeMap;
-use std::error::Error;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::path::Path;
+use std::time::{Duration, Instant};
 
-use super::{get_pole_coverage_dict, PoleCoverSolver};
+use super::{
+    candidate_conflicts, get_pole_coverage_dict, pole_column_name, IlpModel, LoadLimit, ModelVars,
+    PoleCoverSolver, Row,
+};
 use good_lp::solvers::highs::HighsProblem;
 use good_lp::variable::UnsolvedProblem;
 use good_lp::*;
-use hashbrown::HashSet;
+use hashbrown::{HashMap, HashSet};
 use itertools::Itertools;
 use log::warn;
 use petgraph::prelude::*;
+use petgraph::unionfind::UnionFind;
 
+use crate::algorithms::min_scored::MinScored;
+use crate::better_bp::EntityId;
+use crate::cancel;
+use crate::error::OptimizerError;
+use crate::highs_solve::{solve_interruptible, HighsOptions};
 use crate::pole_graph::CandPoleGraph;
-use crate::position::{BoundingBox, BoundingBoxExt};
+use crate::position::{BoundingBox, BoundingBoxExt, MapPosition};
+use crate::prototype_data::EntityPrototypeRef;
 
 type M = HighsProblem;
 
 pub struct SetCoverILPSolver<'a> {
-    pub solver: &'a dyn Fn(UnsolvedProblem) -> M,
-    pub config: &'a dyn Fn(M) -> Result<M, Box<dyn Error>>,
-    pub cost: &'a dyn Fn(&CandPoleGraph, NodeIndex) -> f64,
+    pub solver: &'a (dyn Fn(UnsolvedProblem) -> M + Sync),
+    pub config: &'a (dyn Fn(HighsOptions) -> Result<HighsOptions, OptimizerError> + Sync),
+    /// Shared by the threads of [super::SpatialSplitSolver], like the solver and its config.
+    pub cost: &'a (dyn Fn(&CandPoleGraph, NodeIndex) -> f64 + Sync),
     pub connectivity: Option<DistanceConnectivity>,
+    /// Poles that must always be selected. These have no cost, and are not required to be connected
+    /// themselves, but may be used to power entities and connect other poles.
+    pub fixed_poles: HashSet<NodeIndex>,
+    /// Maximum number of poles of each prototype in the solution, including fixed poles.
+    pub max_count: HashMap<EntityPrototypeRef, usize>,
+    /// Add coverage and connectivity constraints lazily: solve with a few of them,
+    /// add the ones the solution violates, and re-solve until none are violated.
+    /// Uses much less memory on huge blueprints, but may take more time.
+    pub lazy: bool,
+    /// Maximum number of distinct pole prototypes in the solution, including fixed poles.
+    pub max_pole_types: Option<usize>,
+    /// Maximum load of the entities assigned to each pole; every entity must be assigned to a pole powering it.
+    /// Adds an assignment variable per entity and candidate pole.
+    pub max_load: Option<LoadLimit>,
+    /// Poles of a previous solution, given to the solver as a starting point; may be empty.
+    /// Need not be feasible, e.g. if the blueprint changed since.
+    pub initial_solution: HashSet<NodeIndex>,
+    /// Entities that may be left unpowered, e.g. when [Self::max_count] doesn't allow powering everything,
+    /// each adding its cost to the objective. All other entities must be powered.
+    pub soft_entities: HashMap<EntityId, f64>,
 }
 
 /// A constraint to ensures that poles are connected. Might not be optimal.
@@ -28,10 +60,22 @@ pub struct SetCoverILPSolver<'a> {
 ///
 /// Some "root" poles are selected based on the root_location; then distance to all other poles is calculated.
 /// Adds constraint that if a pole is selected, at least one entity closer to the root pole must be selected.
+/// With several roots, distance is to the nearest root, so each pole connects toward its nearest root;
+/// the roots' groups are connected by the cuts of [SetCoverILPSolver::connectivity_cuts].
 ///
 /// This currently uses Euclidean distance as the distance metric.
+#[derive(Clone)]
 pub struct DistanceConnectivity {
     pub center_rel_pos: (f64, f64),
+    /// If not empty, root poles are the ones closest to these positions instead of the center,
+    /// e.g. an anchor pole, or several along a long blueprint.
+    pub roots: Vec<MapPosition>,
+    /// If set, every selected pole that isn't fixed must be within this many wire hops of a root pole,
+    /// through selected poles. Always constrained in full, even when other constraints are lazy.
+    pub max_hops: Option<usize>,
+    /// If set, groups of only fixed poles must also be connected to the rest, e.g. to reconnect islands of existing poles.
+    /// Fixed poles then also get the closer-neighbour constraint, so the cuts only have to fix what it misses.
+    pub connect_fixed: bool,
 }
 
 impl DistanceConnectivity {
@@ -48,52 +92,242 @@ impl DistanceConnectivity {
         clique
     }
 
+    /// Poles closest to each root position, forming a clique for each.
     pub fn find_root_poles(&self, graph: &CandPoleGraph) -> Vec<NodeIndex> {
-        let bbox = BoundingBox::from_points(graph.node_weights().map(|p| p.entity.position));
-        let pt = bbox.relative_pt_at(self.center_rel_pos);
-        let closest_poles = graph.node_indices().sorted_by_cached_key(|idx| {
-            ((graph[*idx].entity.position - pt).square_length() * 64.0 * 64.0).round() as u64
-        });
-        Self::maximal_clique(graph, closest_poles)
+        let points = if self.roots.is_empty() {
+            let bbox = BoundingBox::from_points(graph.node_weights().map(|p| p.entity.position));
+            vec![bbox.relative_pt_at(self.center_rel_pos)]
+        } else {
+            self.roots.clone()
+        };
+        points
+            .into_iter()
+            .flat_map(|pt| {
+                let closest_poles = graph.node_indices().sorted_by_cached_key(|idx| {
+                    ((graph[*idx].entity.position - pt).square_length() * 64.0 * 64.0).round()
+                        as u64
+                });
+                Self::maximal_clique(graph, closest_poles)
+            })
+            .unique()
+            .collect()
     }
 
-    fn connectivity_constraints(
+    /// Distance along the graph from each pole to the nearest root pole.
+    fn root_distances(
+        graph: &CandPoleGraph,
+        root_poles: &HashSet<NodeIndex>,
+    ) -> HashMap<NodeIndex, f64> {
+        let mut distances = HashMap::new();
+        let mut heap = root_poles
+            .iter()
+            .map(|&pole| MinScored(0.0, pole))
+            .collect::<BinaryHeap<_>>();
+        while let Some(MinScored(distance, pole)) = heap.pop() {
+            if distances.contains_key(&pole) {
+                continue;
+            }
+            distances.insert(pole, distance);
+            for edge in graph.edges(pole) {
+                if !distances.contains_key(&edge.target()) {
+                    heap.push(MinScored(distance + *edge.weight(), edge.target()));
+                }
+            }
+        }
+        distances
+    }
+
+    /// Fewest wire hops along the graph from each pole to the nearest root pole.
+    fn root_hops(graph: &CandPoleGraph, root_poles: &[NodeIndex]) -> HashMap<NodeIndex, usize> {
+        let mut hops = root_poles
+            .iter()
+            .map(|&pole| (pole, 0))
+            .collect::<HashMap<_, _>>();
+        let mut queue = root_poles
+            .iter()
+            .copied()
+            .collect::<std::collections::VecDeque<_>>();
+        while let Some(pole) = queue.pop_front() {
+            let next = hops[&pole] + 1;
+            for neighbor in graph.neighbors(pole) {
+                if !hops.contains_key(&neighbor) {
+                    hops.insert(neighbor, next);
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+        hops
+    }
+
+    /// For [Self::max_hops], a variable per pole and hop count `k`, which can only be positive if the pole
+    /// is selected and within `k` hops of a root pole through selected poles. These can be continuous:
+    /// a positive value needs a positive value at a neighbour for `k - 1`, and so on down to a root pole.
+    /// Only added for hop counts the pole is within in the whole graph.
+    fn hop_vars(
+        &self,
+        graph: &CandPoleGraph,
+        vars: &mut ModelVars,
+    ) -> BTreeMap<(NodeIndex, usize), Variable> {
+        let Some(max_hops) = self.max_hops else {
+            return BTreeMap::new();
+        };
+        Self::root_hops(graph, &self.find_root_poles(graph))
+            .into_iter()
+            .filter(|(_, hops)| *hops <= max_hops)
+            .sorted()
+            .flat_map(|(pole, hops)| (hops..=max_hops).map(move |k| (pole, k)))
+            .map(|(pole, k)| {
+                let name = format!("hops_{}_{}", pole.index(), k);
+                ((pole, k), vars.add(name, false))
+            })
+            .collect()
+    }
+
+    /// Number of selected poles, other than fixed ones, more than [Self::max_hops] hops from a selected root pole
+    /// through selected poles.
+    pub fn hop_violations(
+        &self,
+        graph: &CandPoleGraph,
+        selected: &HashSet<NodeIndex>,
+        fixed_poles: &HashSet<NodeIndex>,
+    ) -> usize {
+        let Some(max_hops) = self.max_hops else {
+            return 0;
+        };
+        let subgraph = graph.filter_map(
+            |idx, node| selected.contains(&idx).then(|| node.clone()),
+            |_, w| Some(*w),
+        );
+        let original = graph
+            .node_indices()
+            .filter(|idx| selected.contains(idx))
+            .collect_vec();
+        let roots = self
+            .find_root_poles(graph)
+            .into_iter()
+            .filter_map(|root| original.binary_search(&root).ok().map(NodeIndex::new))
+            .collect_vec();
+        let hops = Self::root_hops(&subgraph, &roots);
+        subgraph
+            .node_indices()
+            .filter(|idx| !fixed_poles.contains(&original[idx.index()]))
+            .filter(|idx| hops.get(idx).is_none_or(|hops| *hops > max_hops))
+            .count()
+    }
+
+    fn hop_constraints(
         &self,
         graph: &CandPoleGraph,
         pole_vars: &BTreeMap<NodeIndex, Variable>,
-    ) -> Vec<Constraint> {
+        hop_vars: &BTreeMap<(NodeIndex, usize), Variable>,
+        fixed_poles: &HashSet<NodeIndex>,
+    ) -> Vec<Row> {
+        let Some(max_hops) = self.max_hops else {
+            return vec![];
+        };
+        let mut constraints = vec![];
+        for (&(pole, k), &var) in hop_vars {
+            constraints.push(Row::leq(var, pole_vars[&pole]));
+            if k > 0 {
+                let reach: Expression = graph
+                    .neighbors(pole)
+                    .chain([pole])
+                    .unique()
+                    .filter_map(|n| hop_vars.get(&(n, k - 1)).copied())
+                    .sum();
+                constraints.push(Row::leq(var, reach));
+            }
+        }
+        for (pole, &var) in pole_vars {
+            if fixed_poles.contains(pole) {
+                continue;
+            }
+            match hop_vars.get(&(*pole, max_hops)) {
+                Some(&hops) => constraints.push(Row::leq(var, hops)),
+                None => constraints.push(Row::eq(var, 0)),
+            }
+        }
+        constraints
+    }
+
+    /// The root poles, and the poles on a shortest path from each of `poles` to the nearest one,
+    /// so any subgraph containing them can still connect `poles`.
+    /// With [Self::max_hops], also the poles on a path with the fewest hops.
+    pub fn paths_to_roots(
+        &self,
+        graph: &CandPoleGraph,
+        poles: &HashSet<NodeIndex>,
+    ) -> HashSet<NodeIndex> {
+        let root_poles = self.find_root_poles(graph);
+        let distances = Self::root_distances(graph, &root_poles.iter().copied().collect());
+        let mut result = root_poles.iter().copied().collect::<HashSet<_>>();
+        Self::walk_to_roots(graph, poles, &distances, &mut result);
+        if self.max_hops.is_some() {
+            let hops = Self::root_hops(graph, &root_poles)
+                .into_iter()
+                .map(|(pole, hops)| (pole, hops as f64))
+                .collect();
+            Self::walk_to_roots(graph, poles, &hops, &mut result);
+        }
+        result
+    }
+
+    /// Adds the poles from each of `poles` to a root pole to `result`,
+    /// each time stepping to the neighbour with the lowest `distances`.
+    fn walk_to_roots(
+        graph: &CandPoleGraph,
+        poles: &HashSet<NodeIndex>,
+        distances: &HashMap<NodeIndex, f64>,
+        result: &mut HashSet<NodeIndex>,
+    ) {
+        let mut visited = HashSet::new();
+        for &start in poles.iter().sorted() {
+            let mut pole = start;
+            while visited.insert(pole) {
+                let Some(&distance) = distances.get(&pole) else {
+                    break;
+                };
+                result.insert(pole);
+                let next = graph
+                    .neighbors(pole)
+                    .filter(|n| distances.get(n).is_some_and(|d| *d < distance))
+                    .min_by(|a, b| distances[a].total_cmp(&distances[b]).then(a.cmp(b)));
+                match next {
+                    Some(next) => pole = next,
+                    None => break,
+                }
+            }
+        }
+    }
+
+    /// For every non-root pole, the neighbouring poles that are closer to the root poles.
+    /// If a pole is selected, at least one of these must also be selected.
+    pub fn closer_neighbours(
+        &self,
+        graph: &CandPoleGraph,
+        fixed_poles: &HashSet<NodeIndex>,
+    ) -> BTreeMap<NodeIndex, Vec<NodeIndex>> {
         let root_poles = self
             .find_root_poles(graph)
             .into_iter()
             .collect::<HashSet<_>>();
-        let pole1 = *root_poles.iter().next().unwrap();
-        use petgraph::algo::dijkstra;
-        let distances = dijkstra(&graph, pole1, None, |edge| {
-            if root_poles.contains(&edge.target()) {
-                0.0
-            } else {
-                *edge.weight()
-            }
-        });
-        let mut result = vec![];
+        let distances = Self::root_distances(graph, &root_poles);
+        let mut result = BTreeMap::new();
         let mut connected = true;
-        for pole in pole_vars.keys() {
-            if root_poles.contains(pole) {
+        for pole in graph.node_indices() {
+            if root_poles.contains(&pole) || (!self.connect_fixed && fixed_poles.contains(&pole)) {
                 continue;
             }
-            let this_dist = distances.get(pole).cloned();
-            if this_dist.is_none() {
+            let Some(this_dist) = distances.get(&pole).cloned() else {
                 connected = false;
                 continue;
-            }
+            };
             let neighbors = graph
-                .neighbors(*pole)
-                .filter(|n| distances[n] < this_dist.unwrap())
-                // .sorted_by(|a, b| distances[a].partial_cmp(&distances[b]).unwrap())
-                .map(|n| pole_vars[&n]);
-            let var_sum: Option<Expression> = neighbors.sum1();
-            if let Some(var_sum) = var_sum {
-                result.push(constraint!(pole_vars[pole] <= var_sum));
+                .neighbors(pole)
+                .filter(|n| distances[n] < this_dist)
+                .collect_vec();
+            if !neighbors.is_empty() {
+                result.insert(pole, neighbors);
             }
         }
         if !connected {
@@ -101,6 +335,23 @@ impl DistanceConnectivity {
         }
         result
     }
+
+    fn connectivity_constraints(
+        &self,
+        graph: &CandPoleGraph,
+        pole_vars: &BTreeMap<NodeIndex, Variable>,
+        fixed_poles: &HashSet<NodeIndex>,
+        only: Option<&HashSet<NodeIndex>>,
+    ) -> Vec<Row> {
+        self.closer_neighbours(graph, fixed_poles)
+            .into_iter()
+            .filter(|(pole, _)| only.is_none_or(|only| only.contains(pole)))
+            .map(|(pole, neighbors)| {
+                let var_sum: Expression = neighbors.iter().map(|n| pole_vars[n]).sum();
+                Row::leq(pole_vars[&pole], var_sum)
+            })
+            .collect()
+    }
 }
 
 impl SetCoverILPSolver<'_> {
@@ -108,59 +359,650 @@ impl SetCoverILPSolver<'_> {
         &self,
         graph: &CandPoleGraph,
         pole_vars: &BTreeMap<NodeIndex, Variable>,
-    ) -> Vec<Constraint> {
+        unpowered_vars: &BTreeMap<EntityId, Variable>,
+        only: Option<&HashSet<EntityId>>,
+    ) -> Vec<Row> {
         get_pole_coverage_dict(graph)
             .into_iter()
-            .map(|(_, poles)| {
-                let var_sum: Expression = poles.iter().map(|idx| pole_vars[idx]).sum();
-                constraint!(var_sum >= 1)
+            .filter(|(entity, _)| only.is_none_or(|only| only.contains(entity)))
+            .map(|(entity, poles)| {
+                let mut var_sum: Expression = poles.iter().map(|idx| pole_vars[idx]).sum();
+                if let Some(unpowered) = unpowered_vars.get(&entity) {
+                    var_sum += *unpowered;
+                }
+                Row::geq(var_sum, 1)
+            })
+            .collect()
+    }
+
+    fn max_count_constraints(
+        &self,
+        graph: &CandPoleGraph,
+        pole_vars: &BTreeMap<NodeIndex, Variable>,
+    ) -> Vec<Row> {
+        self.max_count
+            .iter()
+            .map(|(prototype, &max)| {
+                let var_sum: Expression = pole_vars
+                    .iter()
+                    .filter(|(idx, _)| graph[**idx].entity.prototype == *prototype)
+                    .map(|(_, var)| *var)
+                    .sum();
+                Row::leq(var_sum, max as f64)
+            })
+            .collect()
+    }
+
+    /// At most one of each group of overlapping candidates may be selected; see [candidate_conflicts].
+    /// Fixed poles are kept even if they overlap each other, but then nothing else on their tiles may be selected.
+    fn conflict_constraints(
+        &self,
+        graph: &CandPoleGraph,
+        pole_vars: &BTreeMap<NodeIndex, Variable>,
+    ) -> Vec<Row> {
+        candidate_conflicts(graph)
+            .into_iter()
+            .filter_map(|group| {
+                let fixed = group
+                    .iter()
+                    .filter(|idx| self.fixed_poles.contains(*idx))
+                    .count();
+                (fixed < group.len()).then(|| {
+                    let var_sum: Expression = group.iter().map(|idx| pole_vars[idx]).sum();
+                    Row::leq(var_sum, fixed.max(1) as f64)
+                })
+            })
+            .collect()
+    }
+
+    /// A pole may only be selected if its prototype's indicator is set (big-M, with M the number of candidates
+    /// of that prototype), and at most [Self::max_pole_types] indicators may be set.
+    fn pole_type_constraints(
+        &self,
+        graph: &CandPoleGraph,
+        pole_vars: &BTreeMap<NodeIndex, Variable>,
+        type_vars: &[(EntityPrototypeRef, Variable)],
+    ) -> Vec<Row> {
+        let Some(max_types) = self.max_pole_types else {
+            return vec![];
+        };
+        let mut constraints = type_vars
+            .iter()
+            .map(|(prototype, type_var)| {
+                let poles = pole_vars
+                    .iter()
+                    .filter(|(idx, _)| graph[**idx].entity.prototype == *prototype)
+                    .map(|(_, var)| *var)
+                    .collect_vec();
+                let big_m = poles.len() as f64;
+                let var_sum: Expression = poles.into_iter().sum();
+                Row::leq(var_sum, big_m * *type_var)
+            })
+            .collect_vec();
+        let num_types: Expression = type_vars.iter().map(|(_, var)| *var).sum();
+        constraints.push(Row::leq(num_types, max_types as f64));
+        constraints
+    }
+
+    /// Each entity is assigned to a pole powering it, unless left unpowered, and each pole's load is at most
+    /// [Self::max_load], if selected. Always added for all entities, even with [Self::lazy].
+    fn load_constraints(
+        &self,
+        pole_vars: &BTreeMap<NodeIndex, Variable>,
+        unpowered_vars: &BTreeMap<EntityId, Variable>,
+        assign_vars: &[(EntityId, NodeIndex, Variable)],
+    ) -> Vec<Row> {
+        let Some(limit) = &self.max_load else {
+            return vec![];
+        };
+        let by_entity = assign_vars
+            .iter()
+            .into_group_map_by(|(entity, _, _)| *entity)
+            .into_iter()
+            .sorted_by_key(|(entity, _)| *entity)
+            .map(|(entity, vars)| {
+                let mut var_sum: Expression = vars.iter().map(|(_, _, var)| *var).sum();
+                if let Some(unpowered) = unpowered_vars.get(&entity) {
+                    var_sum += *unpowered;
+                }
+                Row::geq(var_sum, 1)
+            });
+        let by_pole = assign_vars
+            .iter()
+            .into_group_map_by(|(_, pole, _)| *pole)
+            .into_iter()
+            .sorted_by_key(|(pole, _)| *pole)
+            .map(|(pole, vars)| {
+                let load: Expression = vars
+                    .iter()
+                    .map(|(entity, _, var)| var.into_expression() * limit.weight(*entity))
+                    .sum();
+                Row::leq(load, limit.max * pole_vars[&pole])
+            });
+        by_entity.chain(by_pole).collect()
+    }
+
+    /// Number of distinct prototypes in `selected`, if more than [Self::max_pole_types].
+    pub fn pole_type_violation(
+        &self,
+        graph: &CandPoleGraph,
+        selected: impl IntoIterator<Item = NodeIndex>,
+    ) -> Option<usize> {
+        let max_types = self.max_pole_types?;
+        let num_types = selected
+            .into_iter()
+            .map(|idx| graph[idx].entity.prototype.clone())
+            .unique()
+            .count();
+        (num_types > max_types).then_some(num_types)
+    }
+
+    /// Prototypes with more poles in `selected` than allowed by [Self::max_count].
+    pub fn max_count_violations(
+        &self,
+        graph: &CandPoleGraph,
+        selected: impl IntoIterator<Item = NodeIndex>,
+    ) -> Vec<(EntityPrototypeRef, usize)> {
+        let counts = selected
+            .into_iter()
+            .counts_by(|idx| graph[idx].entity.prototype.clone());
+        self.max_count
+            .iter()
+            .filter_map(|(prototype, &max)| {
+                let count = counts.get(prototype).copied().unwrap_or(0);
+                (count > max).then(|| (prototype.clone(), count))
             })
             .collect()
     }
 }
 
-impl PoleCoverSolver for SetCoverILPSolver<'_> {
-    fn solve<'a>(&self, graph: &CandPoleGraph) -> Result<CandPoleGraph, Box<dyn Error + 'a>> {
-        let mut vars = ProblemVariables::new();
+/// Result of solving only the LP relaxation of the pole cover problem.
+#[derive(Debug, Clone)]
+pub struct LpEstimate {
+    pub num_variables: usize,
+    pub num_constraints: usize,
+    /// Lower bound on the number of poles in any solution.
+    pub min_poles: f64,
+    /// Number of variables that are not integral in the relaxation.
+    pub num_fractional: usize,
+    pub lp_time: Duration,
+}
+
+impl LpEstimate {
+    /// Very rough guess of the MIP solve time; a heuristic, not calibrated against real solves.
+    /// Branching is needed roughly in proportion to the number of fractional variables.
+    pub fn rough_mip_time(&self) -> Duration {
+        self.lp_time
+            .mul_f64(1.0 + self.num_fractional as f64 / 20.0)
+    }
+}
+
+/// Which coverage and connectivity constraints to add to the problem; `None` means all of them.
+#[derive(Debug, Default)]
+pub(super) struct ActiveConstraints {
+    pub entities: Option<HashSet<EntityId>>,
+    pub connected_poles: Option<HashSet<NodeIndex>>,
+    pub cuts: Vec<ComponentCut>,
+}
+
+/// If `inside` and `outside` are both selected, some pole in `boundary` must be too,
+/// as every path between them leaves the component `inside` was in through `boundary`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct ComponentCut {
+    pub boundary: Vec<NodeIndex>,
+    pub inside: NodeIndex,
+    pub outside: NodeIndex,
+}
+
+/// Most times to re-solve with new cuts before giving up on connecting the solution.
+const MAX_CUT_ROUNDS: usize = 20;
+
+fn too_many_cut_rounds() -> OptimizerError {
+    format!(
+        "Poles are still not all connected after {} rounds of connectivity cuts; use --no-connectivity to allow separate networks",
+        MAX_CUT_ROUNDS
+    )
+    .into()
+}
+
+/// Groups of selected poles connected by wires, largest first.
+fn selected_components(
+    graph: &CandPoleGraph,
+    selected: &HashSet<NodeIndex>,
+) -> Vec<Vec<NodeIndex>> {
+    let mut union_find = UnionFind::new(graph.node_count());
+    for edge in graph.edge_references() {
+        if selected.contains(&edge.source()) && selected.contains(&edge.target()) {
+            union_find.union(edge.source().index(), edge.target().index());
+        }
+    }
+    selected
+        .iter()
+        .copied()
+        .sorted()
+        .into_group_map_by(|idx| union_find.find(idx.index()))
+        .into_values()
+        .sorted_by_key(|component| (std::cmp::Reverse(component.len()), component[0]))
+        .collect()
+}
+
+pub(super) struct BuiltProblem {
+    pub problem: M,
+    pub pole_vars: BTreeMap<NodeIndex, Variable>,
+    pub num_constraints: usize,
+}
+
+impl SetCoverILPSolver<'_> {
+    /// Builds the ILP, or its LP relaxation if `relaxed` is set.
+    pub(super) fn build_problem(
+        &self,
+        graph: &CandPoleGraph,
+        relaxed: bool,
+        cost: &dyn Fn(&CandPoleGraph, NodeIndex) -> f64,
+    ) -> BuiltProblem {
+        self.build_problem_with(graph, relaxed, cost, &ActiveConstraints::default())
+    }
+
+    pub(super) fn build_problem_with(
+        &self,
+        graph: &CandPoleGraph,
+        relaxed: bool,
+        cost: &dyn Fn(&CandPoleGraph, NodeIndex) -> f64,
+        active: &ActiveConstraints,
+    ) -> BuiltProblem {
+        let (model, pole_vars) = self.build_model(graph, relaxed, cost, active);
+        let num_constraints = model.rows.len();
+        BuiltProblem {
+            problem: model.into_problem(self.solver),
+            pole_vars,
+            num_constraints,
+        }
+    }
+
+    /// The full ILP, with all constraints, for `--export-model`.
+    pub fn export_model(&self, graph: &CandPoleGraph, path: &Path) -> Result<(), OptimizerError> {
+        let (model, _) = self.build_model(graph, false, self.cost, &ActiveConstraints::default());
+        println!(
+            "Writing ILP with {} variables and {} constraints to {:?}",
+            model.vars.count(),
+            model.rows.len(),
+            path
+        );
+        model.write(path)
+    }
+
+    #[tracing::instrument(name = "ilp_build", skip_all)]
+    fn build_model(
+        &self,
+        graph: &CandPoleGraph,
+        relaxed: bool,
+        cost: &dyn Fn(&CandPoleGraph, NodeIndex) -> f64,
+        active: &ActiveConstraints,
+    ) -> (IlpModel, BTreeMap<NodeIndex, Variable>) {
+        let mut vars = ModelVars::default();
 
         let pole_vars = graph
             .node_indices()
             .map(|idx| {
-                let var = variable().binary().name(format!("pole_{}", idx.index()));
-                (idx, vars.add(var))
+                let name = pole_column_name(&graph[idx].entity);
+                (idx, vars.add(name, !relaxed))
             })
             .collect::<BTreeMap<_, _>>();
+        // indicator of whether each prototype is used at all
+        let type_vars = match self.max_pole_types {
+            Some(_) => graph
+                .node_weights()
+                .map(|node| node.entity.prototype.clone())
+                .unique()
+                .sorted_by(|a, b| a.name.cmp(&b.name))
+                .map(|prototype| {
+                    let name = format!("type_{}", prototype.name);
+                    (prototype, vars.add(name, !relaxed))
+                })
+                .collect_vec(),
+            None => vec![],
+        };
+        // With a load limit, assignment of entities to poles. When counting entities, these can be continuous,
+        // as for any selection of poles, if there is a fractional assignment, there is also an integral one.
+        // Weighted loads could be split between poles, so then they must be binary.
+        let assign_vars = match &self.max_load {
+            Some(limit) => get_pole_coverage_dict(graph)
+                .into_iter()
+                .sorted_by_key(|(entity, _)| *entity)
+                .flat_map(|(entity, poles)| {
+                    poles.into_iter().sorted().map(move |pole| (entity, pole))
+                })
+                .map(|(entity, pole)| {
+                    let name = format!("assign_{}_{}", entity.0, pole.index());
+                    let binary = limit.weights.is_some() && !relaxed;
+                    (entity, pole, vars.add(name, binary))
+                })
+                .collect_vec(),
+            None => vec![],
+        };
+
+        // whether each soft entity is left unpowered; continuous, as with poles selected, it is 0 or 1 at best
+        let unpowered_vars = if self.soft_entities.is_empty() {
+            BTreeMap::new()
+        } else {
+            get_pole_coverage_dict(graph)
+                .into_keys()
+                .filter(|entity| self.soft_entities.contains_key(entity))
+                .sorted()
+                .map(|entity| {
+                    let name = format!("unpowered_{}", entity.0);
+                    (entity, vars.add(name, false))
+                })
+                .collect::<BTreeMap<_, _>>()
+        };
 
-        let cost_expr: Expression = pole_vars
+        let hop_vars = match &self.connectivity {
+            Some(connectivity) => connectivity.hop_vars(graph, &mut vars),
+            None => BTreeMap::new(),
+        };
+
+        let mut cost_expr: Expression = pole_vars
             .iter()
-            .map(|(id, var)| var.into_expression() * (self.cost)(graph, *id))
+            .filter(|(id, _)| !self.fixed_poles.contains(*id))
+            .map(|(id, var)| var.into_expression() * cost(graph, *id))
             .sum();
+        for (entity, var) in &unpowered_vars {
+            cost_expr += var.into_expression() * self.soft_entities[entity];
+        }
 
-        // println!("num vars: {}", vars.len());
+        let mut constraints = self.add_set_cover_constraints(
+            graph,
+            &pole_vars,
+            &unpowered_vars,
+            active.entities.as_ref(),
+        );
+        for idx in &self.fixed_poles {
+            constraints.push(Row::eq(pole_vars[idx], 1));
+        }
+        constraints.extend(self.max_count_constraints(graph, &pole_vars));
+        constraints.extend(self.conflict_constraints(graph, &pole_vars));
+        constraints.extend(self.pole_type_constraints(graph, &pole_vars, &type_vars));
+        constraints.extend(self.load_constraints(&pole_vars, &unpowered_vars, &assign_vars));
+        if let Some(connectivity) = &self.connectivity {
+            constraints.extend(connectivity.connectivity_constraints(
+                graph,
+                &pole_vars,
+                &self.fixed_poles,
+                active.connected_poles.as_ref(),
+            ));
+            constraints.extend(connectivity.hop_constraints(
+                graph,
+                &pole_vars,
+                &hop_vars,
+                &self.fixed_poles,
+            ));
+        }
+        for cut in &active.cuts {
+            let boundary_sum: Expression = cut.boundary.iter().map(|n| pole_vars[n]).sum();
+            constraints.push(Row::geq(
+                boundary_sum,
+                pole_vars[&cut.inside] + pole_vars[&cut.outside] - 1,
+            ));
+        }
+        let model = IlpModel {
+            vars,
+            objective: cost_expr,
+            rows: constraints,
+        };
+        (model, pole_vars)
+    }
+
+    /// Solves only the LP relaxation with every pole costing 1,
+    /// to quickly get a lower bound on the number of poles needed.
+    pub fn estimate(&self, graph: &CandPoleGraph) -> Result<LpEstimate, OptimizerError> {
+        let start = Instant::now();
+        let BuiltProblem {
+            problem,
+            pole_vars,
+            num_constraints,
+        } = self.build_problem(graph, true, &|_, _| 1.0);
+        let solution = (self.config)(HighsOptions::default())?
+            .apply(problem)
+            .solve()?;
+        let values = pole_vars
+            .values()
+            .map(|var| solution.value(*var))
+            .collect_vec();
+        Ok(LpEstimate {
+            num_variables: pole_vars.len(),
+            num_constraints,
+            min_poles: values.iter().sum(),
+            num_fractional: values
+                .iter()
+                .filter(|&&value| value > 1e-6 && value < 1.0 - 1e-6)
+                .count(),
+            lp_time: start.elapsed(),
+        })
+    }
+}
+
+impl SetCoverILPSolver<'_> {
+    fn solve_selected(
+        &self,
+        graph: &CandPoleGraph,
+        active: &ActiveConstraints,
+    ) -> Result<HashSet<NodeIndex>, OptimizerError> {
+        let (model, pole_vars) = self.build_model(graph, false, self.cost, active);
+        let columns = model.column_indices();
+        let options = (self.config)(HighsOptions::default())?;
+        let initial_solution = (!self.initial_solution.is_empty()).then(|| {
+            let mut values = vec![0.0; columns.len()];
+            for (idx, var) in &pole_vars {
+                if self.initial_solution.contains(idx) {
+                    values[columns[var]] = 1.0;
+                }
+            }
+            values
+        });
+        let problem = model.into_problem(self.solver);
 
-        let mut problem = (self.solver)(vars.minimise(cost_expr));
+        let start = Instant::now();
+        // outside a pipeline, nothing can cancel the solve
+        let token = cancel::current().unwrap_or_default();
+        let values = tracing::info_span!("ilp_solve").in_scope(|| {
+            solve_interruptible(problem, &options, initial_solution.as_deref(), &token)
+        })?;
 
-        for constraint in self.add_set_cover_constraints(graph, &pole_vars) {
-            problem.add_constraint(constraint);
+        let selected: HashSet<NodeIndex> = pole_vars
+            .into_iter()
+            .filter(|(_, var)| values[columns[var]] > 0.5)
+            .map(|(idx, _)| idx)
+            .collect();
+        // On reaching the time limit, HiGHS returns its best solution so far;
+        // if that doesn't cover everything it must, no feasible solution was found.
+        let uncovered = get_pole_coverage_dict(graph)
+            .into_iter()
+            .any(|(entity, poles)| {
+                active
+                    .entities
+                    .as_ref()
+                    .is_none_or(|only| only.contains(&entity))
+                    && !self.soft_entities.contains_key(&entity)
+                    && poles.is_disjoint(&selected)
+            });
+        if uncovered {
+            return Err(OptimizerError::SolverTimeout {
+                elapsed: start.elapsed(),
+            });
         }
-        if let Some(connectivity) = &self.connectivity {
-            for constraint in connectivity.connectivity_constraints(graph, &pole_vars) {
-                problem.add_constraint(constraint);
+        Ok(selected)
+    }
+
+    /// Entities whose candidate poles don't overlap; if these are covered, most others likely are too.
+    fn initial_lazy_entities(
+        coverage: &HashMap<EntityId, HashSet<NodeIndex>>,
+    ) -> HashSet<EntityId> {
+        let mut used_poles = HashSet::new();
+        let mut entities = HashSet::new();
+        for (entity, poles) in coverage.iter().sorted_by_key(|(entity, _)| **entity) {
+            if poles.is_disjoint(&used_poles) {
+                used_poles.extend(poles.iter().copied());
+                entities.insert(*entity);
             }
         }
+        entities
+    }
 
-        let problem = (self.config)(problem)?;
+    /// Checks exactly that the selected poles are connected, as the connectivity heuristic doesn't guarantee it,
+    /// e.g. when poles connect through fixed poles. For each group of poles that isn't connected to the rest,
+    /// returns a cut separating it from the largest group.
+    /// Groups with only fixed poles need not be connected, unless [DistanceConnectivity::connect_fixed].
+    pub(super) fn connectivity_cuts(
+        &self,
+        graph: &CandPoleGraph,
+        selected: &HashSet<NodeIndex>,
+    ) -> Vec<ComponentCut> {
+        let Some(connectivity) = &self.connectivity else {
+            return vec![];
+        };
+        let components = selected_components(graph, selected)
+            .into_iter()
+            .filter_map(|component| {
+                component
+                    .iter()
+                    .copied()
+                    .find(|idx| !self.fixed_poles.contains(idx))
+                    .or_else(|| connectivity.connect_fixed.then(|| component[0]))
+                    .map(|representative| (component, representative))
+            })
+            .collect_vec();
+        if components.len() <= 1 {
+            return vec![];
+        }
+        let main = components[0].1;
+        components
+            .iter()
+            .map(|(component, representative)| {
+                let members = component.iter().copied().collect::<HashSet<_>>();
+                let boundary = component
+                    .iter()
+                    .flat_map(|&idx| graph.neighbors(idx))
+                    .filter(|n| !members.contains(n))
+                    .unique()
+                    .sorted()
+                    .collect();
+                let outside = if *representative == main {
+                    components[1].1
+                } else {
+                    main
+                };
+                ComponentCut {
+                    boundary,
+                    inside: *representative,
+                    outside,
+                }
+            })
+            .collect()
+    }
 
-        let solution = problem.solve()?;
+    /// Solves, then adds cuts and re-solves until the solution is connected.
+    fn solve_connected(&self, graph: &CandPoleGraph) -> Result<HashSet<NodeIndex>, OptimizerError> {
+        let mut active = ActiveConstraints::default();
+        for _ in 0..=MAX_CUT_ROUNDS {
+            let selected = self.solve_selected(graph, &active)?;
+            let cuts = self.connectivity_cuts(graph, &selected);
+            if cuts.is_empty() {
+                return Ok(selected);
+            }
+            println!(
+                "Solution has {} separate groups of poles; re-solving with connectivity cuts",
+                cuts.len()
+            );
+            active.cuts.extend(cuts);
+        }
+        Err(too_many_cut_rounds())
+    }
 
-        let subgraph: CandPoleGraph = graph.filter_map(
-            |idx, entity| {
-                if solution.value(pole_vars[&idx]) > 0.5 {
-                    Some(entity.clone())
-                } else {
-                    None
+    fn solve_lazy(&self, graph: &CandPoleGraph) -> Result<HashSet<NodeIndex>, OptimizerError> {
+        let coverage = get_pole_coverage_dict(graph);
+        let closer_neighbours = match &self.connectivity {
+            Some(connectivity) => connectivity.closer_neighbours(graph, &self.fixed_poles),
+            None => BTreeMap::new(),
+        };
+        let mut active = ActiveConstraints {
+            entities: Some(Self::initial_lazy_entities(&coverage)),
+            connected_poles: Some(HashSet::new()),
+            cuts: vec![],
+        };
+        let mut iteration = 0;
+        let mut cut_rounds = 0;
+        loop {
+            iteration += 1;
+            let selected = self.solve_selected(graph, &active)?;
+            let active_entities = active.entities.as_ref().unwrap();
+            // soft entities with constraints may be left unpowered
+            let uncovered = coverage
+                .iter()
+                .filter(|(entity, poles)| {
+                    poles.is_disjoint(&selected)
+                        && !(self.soft_entities.contains_key(*entity)
+                            && active_entities.contains(*entity))
+                })
+                .map(|(entity, _)| *entity)
+                .collect_vec();
+            let disconnected = closer_neighbours
+                .iter()
+                .filter(|(pole, neighbours)| {
+                    selected.contains(*pole) && !neighbours.iter().any(|n| selected.contains(n))
+                })
+                .map(|(pole, _)| *pole)
+                .collect_vec();
+            println!(
+                "Lazy constraints, iteration {}: {} uncovered entities, {} disconnected poles",
+                iteration,
+                uncovered.len(),
+                disconnected.len()
+            );
+            if uncovered.is_empty() && disconnected.is_empty() {
+                // only checked once the heuristic is satisfied, as it usually connects everything
+                let cuts = self.connectivity_cuts(graph, &selected);
+                if cuts.is_empty() {
+                    return Ok(selected);
+                }
+                if cut_rounds == MAX_CUT_ROUNDS {
+                    return Err(too_many_cut_rounds());
                 }
-            },
+                cut_rounds += 1;
+                println!(
+                    "Solution has {} separate groups of poles; adding connectivity cuts",
+                    cuts.len()
+                );
+                active.cuts.extend(cuts);
+                continue;
+            }
+            active.entities.as_mut().unwrap().extend(uncovered);
+            active
+                .connected_poles
+                .as_mut()
+                .unwrap()
+                .extend(disconnected);
+        }
+    }
+
+    /// Like [PoleCoverSolver::solve], but returns the indices of the selected poles.
+    pub(super) fn solve_indices(
+        &self,
+        graph: &CandPoleGraph,
+    ) -> Result<HashSet<NodeIndex>, OptimizerError> {
+        if self.lazy {
+            self.solve_lazy(graph)
+        } else {
+            self.solve_connected(graph)
+        }
+    }
+}
+
+impl PoleCoverSolver for SetCoverILPSolver<'_> {
+    fn solve(&self, graph: &CandPoleGraph) -> Result<CandPoleGraph, OptimizerError> {
+        let selected = self.solve_indices(graph)?;
+
+        let subgraph: CandPoleGraph = graph.filter_map(
+            |idx, entity| selected.contains(&idx).then(|| entity.clone()),
             |_, w| Some(*w),
         );
         Ok(subgraph)
@@ -172,9 +1014,13 @@ mod test {
     use euclid::point2;
     use hashbrown::HashSet;
 
+    use crate::algorithms::{assign_loads, LoadLimit};
     use crate::bp_model::test_util::small_pole_prototype;
-    use crate::bp_model::BpModel;
-    use crate::pole_graph::ToCandidatePoleGraph;
+    use crate::bp_model::{BpModel, WorldEntity};
+    use crate::pole_graph::{CandPoleNode, ToCandidatePoleGraph};
+    use crate::position::TileSpaceExt;
+    use crate::prototype_data::{EntityPrototype, PoleData};
+    use crate::rcid::RcId;
 
     use super::*;
 
@@ -196,6 +1042,13 @@ mod test {
             config: &Ok,
             cost: &|_, _| 1.0,
             connectivity: None,
+            fixed_poles: HashSet::new(),
+            max_count: HashMap::new(),
+            lazy: false,
+            max_pole_types: None,
+            max_load: None,
+            initial_solution: Default::default(),
+            soft_entities: Default::default(),
         };
         let subgraph = solver.solve(&graph).unwrap();
 
@@ -207,4 +1060,516 @@ mod test {
 
         assert_eq!(powered_entities, HashSet::from([e1, e2, e3]));
     }
+
+    #[test]
+    fn test_estimate() {
+        let mut model = BpModel::new();
+        model.add_test_powerable(point2(-2, 1));
+        model.add_test_powerable(point2(6, 2));
+
+        let graph = model
+            .with_all_candidate_poles(model.get_bounding_box(), &[&small_pole_prototype()])
+            .get_maximally_connected_pole_graph()
+            .0
+            .to_cand_pole_graph(&model);
+
+        let solver = SetCoverILPSolver {
+            solver: &highs,
+            config: &Ok,
+            cost: &|_, _| 1.0,
+            connectivity: None,
+            fixed_poles: HashSet::new(),
+            max_count: HashMap::new(),
+            lazy: false,
+            max_pole_types: None,
+            max_load: None,
+            initial_solution: Default::default(),
+            soft_entities: Default::default(),
+        };
+        let estimate = solver.estimate(&graph).unwrap();
+        assert_eq!(estimate.num_variables, graph.node_count());
+        assert_eq!(estimate.num_constraints, 2);
+        assert!((estimate.min_poles - 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_no_overlapping_poles() {
+        let big_pole = RcId::new(EntityPrototype {
+            type_: "electric-pole".to_string(),
+            name: "test-2x2".to_string(),
+            tile_width: 2,
+            tile_height: 2,
+            collision_box: BoundingBox::new(point2(-1.0, -1.0), point2(1.0, 1.0)),
+            uses_power: false,
+            pole_data: Some(PoleData {
+                wire_distance: 9.0,
+                supply_radius: 3.5,
+                supply_area: None,
+            }),
+            inserter_data: None,
+            turret_data: None,
+            energy_data: None,
+            fluid_data: None,
+            direction_boxes: None,
+        });
+        let candidate =
+            |prototype: &EntityPrototypeRef, x: f64, y: f64, powered: &[u32]| CandPoleNode {
+                entity: WorldEntity {
+                    position: point2(x, y),
+                    direction: 0,
+                    prototype: prototype.clone(),
+                },
+                powered_entities: powered.iter().map(|&id| EntityId(id)).collect(),
+            };
+        // the two cheap poles overlap, so the expensive one must be used instead
+        let mut graph = CandPoleGraph::default();
+        let small = graph.add_node(candidate(&small_pole_prototype(), 0.5, 0.5, &[1]));
+        let big = graph.add_node(candidate(&big_pole, 1.0, 1.0, &[2]));
+        let alone = graph.add_node(candidate(&small_pole_prototype(), 6.5, 0.5, &[1, 2]));
+        assert_eq!(candidate_conflicts(&graph), vec![vec![small, big]]);
+
+        let solver = SetCoverILPSolver {
+            solver: &highs,
+            config: &Ok,
+            cost: &|_, idx| if idx == alone { 3.0 } else { 1.0 },
+            connectivity: None,
+            fixed_poles: HashSet::new(),
+            max_count: HashMap::new(),
+            lazy: false,
+            max_pole_types: None,
+            max_load: None,
+            initial_solution: Default::default(),
+            soft_entities: Default::default(),
+        };
+        assert_eq!(
+            solver.solve_indices(&graph).unwrap(),
+            HashSet::from([alone])
+        );
+    }
+
+    #[test]
+    fn test_fixed_poles_kept() {
+        let mut model = BpModel::new();
+        model.add_test_powerable(point2(0, 0));
+        let decorative = model.add_test_pole(point2(20, 20));
+
+        let (graph, idx_map) = model
+            .with_all_candidate_poles(model.get_bounding_box(), &[&small_pole_prototype()])
+            .get_maximally_connected_pole_graph();
+        let graph = graph.to_cand_pole_graph(&model);
+
+        let solver = SetCoverILPSolver {
+            solver: &highs,
+            config: &Ok,
+            cost: &|_, _| 1.0,
+            connectivity: None,
+            fixed_poles: HashSet::from([idx_map[&decorative]]),
+            max_count: HashMap::new(),
+            lazy: false,
+            max_pole_types: None,
+            max_load: None,
+            initial_solution: Default::default(),
+            soft_entities: Default::default(),
+        };
+        let subgraph = solver.solve(&graph).unwrap();
+
+        assert_eq!(subgraph.node_count(), 2);
+        assert!(subgraph
+            .node_weights()
+            .any(|node| node.entity == model.get(decorative).unwrap().entity));
+    }
+
+    #[test]
+    fn test_max_count() {
+        let mut model = BpModel::new();
+        model.add_test_powerable(point2(-2, 1));
+        model.add_test_powerable(point2(2, 1));
+        model.add_test_powerable(point2(6, 2));
+
+        // same pole data, but different prototypes
+        let limited = small_pole_prototype();
+        let other = small_pole_prototype();
+        let graph = model
+            .with_all_candidate_poles(model.get_bounding_box(), &[&limited, &other])
+            .get_maximally_connected_pole_graph()
+            .0
+            .to_cand_pole_graph(&model);
+
+        let solver = SetCoverILPSolver {
+            solver: &highs,
+            config: &Ok,
+            cost: &|_, _| 1.0,
+            connectivity: None,
+            fixed_poles: HashSet::new(),
+            max_count: HashMap::from([(limited.clone(), 0)]),
+            lazy: false,
+            max_pole_types: None,
+            max_load: None,
+            initial_solution: Default::default(),
+            soft_entities: Default::default(),
+        };
+        let subgraph = solver.solve(&graph).unwrap();
+
+        assert!(subgraph.node_count() > 0);
+        assert!(subgraph
+            .node_weights()
+            .all(|node| node.entity.prototype == other));
+        assert!(solver
+            .max_count_violations(&graph, graph.node_indices())
+            .iter()
+            .any(|(prototype, _)| *prototype == limited));
+    }
+
+    #[test]
+    fn test_soft_entities() {
+        let mut model = BpModel::new();
+        let required = model.add_test_powerable(point2(0, 0));
+        let soft = model.add_test_powerable(point2(20, 0));
+        let prototype = small_pole_prototype();
+        let graph = model
+            .with_all_candidate_poles(model.get_bounding_box(), &[&prototype])
+            .get_maximally_connected_pole_graph()
+            .0
+            .to_cand_pole_graph(&model);
+
+        for lazy in [false, true] {
+            // only one pole, which can't power both
+            let solver = SetCoverILPSolver {
+                solver: &highs,
+                config: &Ok,
+                cost: &|_, _| 1.0,
+                connectivity: None,
+                fixed_poles: HashSet::new(),
+                max_count: HashMap::from([(prototype.clone(), 1)]),
+                lazy,
+                max_pole_types: None,
+                max_load: None,
+                initial_solution: Default::default(),
+                soft_entities: HashMap::from([(soft, 10.0)]),
+            };
+            let solution = solver.solve(&graph).unwrap();
+            let powered = solution
+                .node_weights()
+                .flat_map(|node| node.powered_entities.iter().copied())
+                .collect::<HashSet<_>>();
+            assert_eq!(powered, HashSet::from([required]));
+        }
+    }
+
+    #[test]
+    fn test_max_pole_types() {
+        let mut model = BpModel::new();
+        for x in [-6, -2, 2, 6] {
+            model.add_test_powerable(point2(x, 0));
+        }
+        let left = small_pole_prototype();
+        let right = small_pole_prototype();
+        let graph = model
+            .with_all_candidate_poles(model.get_bounding_box(), &[&left, &right])
+            .get_maximally_connected_pole_graph()
+            .0
+            .to_cand_pole_graph(&model);
+
+        // each prototype is cheaper on its own side, so the best solution uses both
+        let cost = |graph: &CandPoleGraph, idx: NodeIndex| {
+            let entity = &graph[idx].entity;
+            let cheap = (entity.prototype == left) == (entity.position.x < 0.0);
+            if cheap {
+                1.0
+            } else {
+                1.5
+            }
+        };
+        let mut solver = SetCoverILPSolver {
+            solver: &highs,
+            config: &Ok,
+            cost: &cost,
+            connectivity: None,
+            fixed_poles: HashSet::new(),
+            max_count: HashMap::new(),
+            lazy: false,
+            max_pole_types: None,
+            max_load: None,
+            initial_solution: Default::default(),
+            soft_entities: Default::default(),
+        };
+        let types_used = |subgraph: &CandPoleGraph| {
+            subgraph
+                .node_weights()
+                .map(|node| node.entity.prototype.clone())
+                .unique()
+                .count()
+        };
+        assert_eq!(types_used(&solver.solve(&graph).unwrap()), 2);
+
+        solver.max_pole_types = Some(1);
+        assert_eq!(types_used(&solver.solve(&graph).unwrap()), 1);
+        assert_eq!(
+            solver.pole_type_violation(&graph, graph.node_indices()),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn test_max_load() {
+        let mut model = BpModel::new();
+        for (x, y) in [(0, 0), (2, 0), (0, 2), (2, 2)] {
+            model.add_test_powerable(point2(x, y));
+        }
+        let graph = model
+            .with_all_candidate_poles(model.get_bounding_box(), &[&small_pole_prototype()])
+            .get_maximally_connected_pole_graph()
+            .0
+            .to_cand_pole_graph(&model);
+
+        let mut solver = SetCoverILPSolver {
+            solver: &highs,
+            config: &Ok,
+            cost: &|_, _| 1.0,
+            connectivity: None,
+            fixed_poles: HashSet::new(),
+            max_count: HashMap::new(),
+            lazy: false,
+            max_pole_types: None,
+            max_load: None,
+            initial_solution: Default::default(),
+            soft_entities: Default::default(),
+        };
+        assert_eq!(solver.solve(&graph).unwrap().node_count(), 1);
+
+        solver.max_load = Some(LoadLimit::count(2));
+        let subgraph = solver.solve(&graph).unwrap();
+        assert_eq!(subgraph.node_count(), 2);
+        let selected = subgraph.node_indices().collect();
+        assert!(assign_loads(&subgraph, &selected, &LoadLimit::count(2)).is_some());
+    }
+
+    #[test]
+    fn test_lazy_matches_full() {
+        let mut model = BpModel::new();
+        for i in 0..6 {
+            model.add_test_powerable(point2(i * 3, 0));
+            model.add_test_powerable(point2(i * 3, 5));
+        }
+
+        let graph = model
+            .with_all_candidate_poles(model.get_bounding_box(), &[&small_pole_prototype()])
+            .get_maximally_connected_pole_graph()
+            .0
+            .to_cand_pole_graph(&model);
+
+        let mut solver = SetCoverILPSolver {
+            solver: &highs,
+            config: &Ok,
+            cost: &|_, _| 1.0,
+            connectivity: Some(DistanceConnectivity {
+                center_rel_pos: (0.5, 0.5),
+                roots: vec![],
+                max_hops: None,
+                connect_fixed: false,
+            }),
+            fixed_poles: HashSet::new(),
+            max_count: HashMap::new(),
+            lazy: false,
+            max_pole_types: None,
+            max_load: None,
+            initial_solution: Default::default(),
+            soft_entities: Default::default(),
+        };
+        let full = solver.solve(&graph).unwrap();
+        solver.lazy = true;
+        let lazy = solver.solve(&graph).unwrap();
+
+        assert_eq!(lazy.node_count(), full.node_count());
+    }
+
+    #[test]
+    fn test_connectivity_cuts() {
+        let mut model = BpModel::new();
+        let xs = [0, 5, 10, 15, 20];
+        model.add_test_poles(&xs.map(|x| point2(x, 0)));
+        model.add_test_powerable(point2(-1, 0));
+        model.add_test_powerable(point2(21, 0));
+        let graph = model
+            .get_maximally_connected_pole_graph()
+            .0
+            .to_cand_pole_graph(&model);
+        let pole_at = |x: i32| {
+            graph
+                .node_indices()
+                .find(|idx| graph[*idx].entity.position == point2(x, 0).center_map_pos())
+                .unwrap()
+        };
+        let mut solver = SetCoverILPSolver {
+            solver: &highs,
+            config: &Ok,
+            cost: &|_, _| 1.0,
+            connectivity: Some(DistanceConnectivity {
+                center_rel_pos: (0.5, 0.5),
+                roots: vec![],
+                max_hops: None,
+                connect_fixed: false,
+            }),
+            // the pole at 20 can connect to this, but it isn't connected to the rest
+            fixed_poles: HashSet::from([pole_at(15)]),
+            max_count: HashMap::new(),
+            lazy: false,
+            max_pole_types: None,
+            max_load: None,
+            initial_solution: Default::default(),
+            soft_entities: Default::default(),
+        };
+
+        let separate = HashSet::from([pole_at(0), pole_at(5), pole_at(15), pole_at(20)]);
+        let cuts = solver.connectivity_cuts(&graph, &separate);
+        assert_eq!(cuts.len(), 2);
+        assert_eq!(cuts[0].boundary, vec![pole_at(10)]);
+
+        let solution = solver.solve(&graph).unwrap();
+        assert_eq!(solution.node_count(), 5);
+
+        solver.lazy = true;
+        let solution = solver.solve(&graph).unwrap();
+        assert_eq!(solution.node_count(), 5);
+    }
+
+    #[test]
+    fn test_connect_fixed() {
+        let mut model = BpModel::new();
+        let xs = [0, 5, 10, 15, 20];
+        model.add_test_poles(&xs.map(|x| point2(x, 0)));
+        model.add_test_powerable(point2(-1, 0));
+        model.add_test_powerable(point2(21, 0));
+        let graph = model
+            .get_maximally_connected_pole_graph()
+            .0
+            .to_cand_pole_graph(&model);
+        let pole_at = |x: i32| {
+            graph
+                .node_indices()
+                .find(|idx| graph[*idx].entity.position == point2(x, 0).center_map_pos())
+                .unwrap()
+        };
+        let solve = |connect_fixed| {
+            SetCoverILPSolver {
+                solver: &highs,
+                config: &Ok,
+                cost: &|_, _| 1.0,
+                connectivity: Some(DistanceConnectivity {
+                    center_rel_pos: (0.5, 0.5),
+                    roots: vec![],
+                    max_hops: None,
+                    connect_fixed,
+                }),
+                // two islands, each powering one entity
+                fixed_poles: HashSet::from([pole_at(0), pole_at(20)]),
+                max_count: HashMap::new(),
+                lazy: false,
+                max_pole_types: None,
+                max_load: None,
+                initial_solution: Default::default(),
+                soft_entities: Default::default(),
+            }
+            .solve(&graph)
+            .unwrap()
+            .node_count()
+        };
+        assert_eq!(solve(false), 2);
+        assert_eq!(solve(true), 5);
+    }
+
+    #[test]
+    fn test_max_hops() {
+        let mut model = BpModel::new();
+        model.add_test_poles(&[0, 5, 10, 15, 20].map(|x| point2(x, 0)));
+        model.add_test_powerable(point2(-1, 0));
+        model.add_test_powerable(point2(21, 0));
+        let graph = model
+            .get_maximally_connected_pole_graph()
+            .0
+            .to_cand_pole_graph(&model);
+        let solve = |max_hops| {
+            SetCoverILPSolver {
+                solver: &highs,
+                config: &Ok,
+                cost: &|_, _| 1.0,
+                // the root poles are at 0 and 5, so the pole at 20 is 3 hops away
+                connectivity: Some(DistanceConnectivity {
+                    center_rel_pos: (0.5, 0.5),
+                    roots: vec![point2(0.5, 0.5)],
+                    max_hops: Some(max_hops),
+                    connect_fixed: false,
+                }),
+                fixed_poles: HashSet::new(),
+                max_count: HashMap::new(),
+                lazy: false,
+                max_pole_types: None,
+                max_load: None,
+                initial_solution: Default::default(),
+                soft_entities: Default::default(),
+            }
+            .solve(&graph)
+        };
+        let solution = solve(3).unwrap();
+        assert_eq!(solution.node_count(), 5);
+        assert!(solve(2).is_err());
+
+        let connectivity = DistanceConnectivity {
+            center_rel_pos: (0.5, 0.5),
+            roots: vec![point2(0.5, 0.5)],
+            max_hops: Some(2),
+            connect_fixed: false,
+        };
+        let all = graph.node_indices().collect();
+        assert_eq!(
+            connectivity.hop_violations(&graph, &all, &HashSet::new()),
+            1
+        );
+    }
+
+    #[test]
+    fn test_root_position() {
+        let mut model = BpModel::new();
+        model.add_test_poles(&[point2(0, 0), point2(5, 0), point2(10, 0), point2(30, 0)]);
+        let graph = model
+            .get_maximally_connected_pole_graph()
+            .0
+            .to_cand_pole_graph(&model);
+        let root_position = |connectivity: DistanceConnectivity| {
+            let roots = connectivity.find_root_poles(&graph);
+            graph[roots[0]].entity.position
+        };
+
+        let center = DistanceConnectivity {
+            center_rel_pos: (0.5, 0.5),
+            roots: vec![],
+            max_hops: None,
+            connect_fixed: false,
+        };
+        assert_eq!(root_position(center), point2(10, 0).center_map_pos());
+        let anchor = DistanceConnectivity {
+            center_rel_pos: (0.5, 0.5),
+            roots: vec![point2(40.5, 0.5)],
+            max_hops: None,
+            connect_fixed: false,
+        };
+        assert_eq!(root_position(anchor), point2(30, 0).center_map_pos());
+
+        // each pole gets closer to its nearest root
+        let two_roots = DistanceConnectivity {
+            center_rel_pos: (0.5, 0.5),
+            roots: vec![point2(0.5, 0.5), point2(30.5, 0.5)],
+            max_hops: None,
+            connect_fixed: false,
+        };
+        let closer = two_roots.closer_neighbours(&graph, &HashSet::new());
+        let pole_at = |x: i32| {
+            graph
+                .node_indices()
+                .find(|idx| graph[*idx].entity.position == point2(x, 0).center_map_pos())
+                .unwrap()
+        };
+        assert_eq!(closer[&pole_at(10)], vec![pole_at(5)]);
+        assert!(!closer.contains_key(&pole_at(30)));
+    }
 }
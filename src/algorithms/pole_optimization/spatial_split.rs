@@ -0,0 +1,532 @@
+use std::sync::Mutex;
+
+use euclid::vec2;
+use hashbrown::{HashMap, HashSet};
+use itertools::Itertools;
+use petgraph::prelude::*;
+
+use super::{
+    fits_with, get_pole_coverage_dict, greedy_cover, overlapping_candidates, DistanceConnectivity,
+    PoleCoverSolver, SetCoverILPSolver,
+};
+use crate::better_bp::EntityId;
+use crate::cancel;
+use crate::error::OptimizerError;
+use crate::pole_graph::{CandPoleGraph, CandPoleNode};
+use crate::position::{self, BoundingBox, MapPosition};
+use crate::prototype_data::EntityPrototypeRef;
+
+/// Solver for blueprints too big for one ILP: splits the area into square regions,
+/// solves each region's ILP separately, then stitches them together with a smaller ILP.
+///
+/// Each region's ILP covers the entities in the region, using the candidates within `overlap` of it.
+/// Of its solution, poles inside the region and not near a border with another region are kept.
+/// The stitching ILP then chooses the rest of the poles, near region borders, with the kept poles fixed.
+/// It also gets every candidate powering an entity the kept poles don't, so it always covers everything.
+/// If poles kept from two regions overlap, only one is kept, and the stitching ILP powers what the other did.
+///
+/// Regions are solved concurrently, each on its own thread.
+pub struct SpatialSplitSolver<'a> {
+    /// Used for the cost function, solver settings, and the region and stitching ILPs.
+    /// Its connectivity roots are only used when stitching; each region is connected around its own center.
+    /// `max_hops` is only enforced for poles chosen when stitching, as poles kept from regions are fixed then.
+    /// `max_count` is split between the regions by their number of entities; with `max_pole_types`,
+    /// all regions use the same types, chosen up front with a greedy cover. As the stitching ILP may then need
+    /// more of some type, or a type not chosen, either may still make it infeasible.
+    pub ilp: SetCoverILPSolver<'a>,
+    /// Width and height of each region, in tiles.
+    pub region_size: f64,
+    pub overlap: f64,
+    /// Most regions solved at once.
+    pub threads: usize,
+}
+
+/// Square regions of `size` tiles, covering all candidate positions.
+struct RegionGrid {
+    min: MapPosition,
+    size: f64,
+    cols: usize,
+    rows: usize,
+}
+
+impl RegionGrid {
+    fn new(graph: &CandPoleGraph, size: f64) -> Self {
+        let bbox = BoundingBox::from_points(graph.node_weights().map(|node| node.entity.position));
+        RegionGrid {
+            min: bbox.min,
+            size,
+            cols: (bbox.width() / size).ceil().max(1.0) as usize,
+            rows: (bbox.height() / size).ceil().max(1.0) as usize,
+        }
+    }
+
+    fn region_of(&self, position: MapPosition) -> (usize, usize) {
+        let index = |value: f64, min: f64, count: usize| {
+            (((value - min) / self.size).floor().max(0.0) as usize).min(count - 1)
+        };
+        (
+            index(position.x, self.min.x, self.cols),
+            index(position.y, self.min.y, self.rows),
+        )
+    }
+
+    fn bounds(&self, (col, row): (usize, usize)) -> BoundingBox {
+        let min = self.min + vec2(col as f64, row as f64) * self.size;
+        BoundingBox::new(min, min + vec2(self.size, self.size))
+    }
+
+    /// If `position` is within `distance` of a border between two regions.
+    fn near_border(&self, position: MapPosition, distance: f64) -> bool {
+        let near = |value: f64, min: f64, count: usize| {
+            (1..count).any(|i| (value - (min + i as f64 * self.size)).abs() < distance)
+        };
+        near(position.x, self.min.x, self.cols) || near(position.y, self.min.y, self.rows)
+    }
+}
+
+/// A region to solve, with the entities assigned to it.
+struct Region {
+    /// Its number, and the number of regions, for progress messages.
+    index: (usize, usize),
+    /// Column and row in the [RegionGrid].
+    cell: (usize, usize),
+    entities: HashSet<EntityId>,
+    /// Its share of [SetCoverILPSolver::max_count], besides fixed poles.
+    max_count: HashMap<EntityPrototypeRef, usize>,
+}
+
+/// The candidates in `keep`, powering only the entities in `powers`.
+/// Returns the subgraph, and the original index of each of its nodes.
+fn restrict(
+    graph: &CandPoleGraph,
+    keep: impl Fn(NodeIndex) -> bool,
+    powers: impl Fn(EntityId) -> bool,
+) -> (CandPoleGraph, Vec<NodeIndex>) {
+    let mut original = vec![];
+    let subgraph = graph.filter_map(
+        |idx, node| {
+            keep(idx).then(|| {
+                original.push(idx);
+                CandPoleNode {
+                    entity: node.entity.clone(),
+                    powered_entities: node
+                        .powered_entities
+                        .iter()
+                        .copied()
+                        .filter(|entity| powers(*entity))
+                        .collect(),
+                }
+            })
+        },
+        |_, w| Some(*w),
+    );
+    (subgraph, original)
+}
+
+impl SpatialSplitSolver<'_> {
+    /// `ilp`, but over the subgraph with the given original indices.
+    fn sub_ilp(
+        &self,
+        original: &[NodeIndex],
+        connectivity: Option<DistanceConnectivity>,
+    ) -> SetCoverILPSolver<'_> {
+        let sub_index = original
+            .iter()
+            .enumerate()
+            .map(|(i, idx)| (*idx, NodeIndex::new(i)))
+            .collect::<HashMap<_, _>>();
+        let map = |indices: &HashSet<NodeIndex>| {
+            indices
+                .iter()
+                .filter_map(|idx| sub_index.get(idx))
+                .copied()
+                .collect()
+        };
+        SetCoverILPSolver {
+            solver: self.ilp.solver,
+            config: self.ilp.config,
+            cost: self.ilp.cost,
+            connectivity,
+            fixed_poles: map(&self.ilp.fixed_poles),
+            max_count: self.ilp.max_count.clone(),
+            lazy: self.ilp.lazy,
+            max_pole_types: self.ilp.max_pole_types,
+            max_load: self.ilp.max_load.clone(),
+            initial_solution: map(&self.ilp.initial_solution),
+            soft_entities: self.ilp.soft_entities.clone(),
+        }
+    }
+
+    /// For [SetCoverILPSolver::max_pole_types], the prototypes the regions may use, so that together they don't use
+    /// more than allowed: those of fixed poles, then those a [greedy_cover] of the whole area uses most.
+    /// `None` if any may be used.
+    fn allowed_types(&self, graph: &CandPoleGraph) -> Option<HashSet<EntityPrototypeRef>> {
+        let max_types = self.ilp.max_pole_types?;
+        let fixed_types = self
+            .ilp
+            .fixed_poles
+            .iter()
+            .map(|idx| graph[*idx].entity.prototype.clone())
+            .collect::<HashSet<_>>();
+        let greedy = greedy_cover(
+            graph,
+            &get_pole_coverage_dict(graph),
+            &self.ilp.fixed_poles,
+            &HashMap::new(),
+            |idx| (self.ilp.cost)(graph, idx),
+        );
+        let by_use = greedy
+            .iter()
+            .filter(|idx| !self.ilp.fixed_poles.contains(*idx))
+            .counts_by(|idx| graph[*idx].entity.prototype.clone())
+            .into_iter()
+            .filter(|(prototype, _)| !fixed_types.contains(prototype))
+            .sorted_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.name.cmp(&b.name)))
+            .map(|(prototype, _)| prototype);
+        let num_fixed_types = fixed_types.len();
+        Some(
+            fixed_types
+                .into_iter()
+                .chain(by_use.take(max_types.saturating_sub(num_fixed_types)))
+                .collect(),
+        )
+    }
+
+    /// [SetCoverILPSolver::max_count] of each region, besides its fixed poles: what is left after the fixed poles,
+    /// split between the regions in proportion to their number of entities.
+    fn region_max_counts(
+        &self,
+        graph: &CandPoleGraph,
+        region_entities: &[usize],
+    ) -> Vec<HashMap<EntityPrototypeRef, usize>> {
+        let mut max_counts = vec![HashMap::new(); region_entities.len()];
+        for (prototype, &max) in &self.ilp.max_count {
+            let fixed = self
+                .ilp
+                .fixed_poles
+                .iter()
+                .filter(|idx| graph[**idx].entity.prototype == *prototype)
+                .count();
+            for (region, share) in apportion(max.saturating_sub(fixed), region_entities)
+                .into_iter()
+                .enumerate()
+            {
+                max_counts[region].insert(prototype.clone(), share);
+            }
+        }
+        max_counts
+    }
+
+    /// Solves one region; returns the poles to keep from it.
+    fn solve_region(
+        &self,
+        graph: &CandPoleGraph,
+        grid: &RegionGrid,
+        region: &Region,
+        allowed_types: Option<&HashSet<EntityPrototypeRef>>,
+    ) -> Result<Vec<NodeIndex>, OptimizerError> {
+        let Region {
+            index: (i, num_regions),
+            cell,
+            entities,
+            max_count,
+        } = region;
+        let bounds = grid.bounds(*cell);
+        let extended = bounds.inflate(self.overlap, self.overlap);
+        let (subgraph, original) = restrict(
+            graph,
+            |idx| {
+                let entity = &graph[idx].entity;
+                extended.contains(entity.position)
+                    && (self.ilp.fixed_poles.contains(&idx)
+                        || allowed_types.is_none_or(|types| types.contains(&entity.prototype)))
+            },
+            |entity| entities.contains(&entity),
+        );
+        println!(
+            "Region {} of {}: {} entities, {} candidate poles",
+            i + 1,
+            num_regions,
+            entities.len(),
+            subgraph.node_count()
+        );
+        let connectivity = self
+            .ilp
+            .connectivity
+            .as_ref()
+            .map(|_| DistanceConnectivity {
+                center_rel_pos: (0.5, 0.5),
+                roots: vec![bounds.center()],
+                max_hops: None,
+                connect_fixed: false,
+            });
+        let mut ilp = SetCoverILPSolver {
+            // the candidates are already only of the allowed types
+            max_pole_types: None,
+            ..self.sub_ilp(&original, connectivity)
+        };
+        // fixed poles count toward max_count too
+        ilp.max_count = max_count
+            .iter()
+            .map(|(prototype, &max)| {
+                let fixed = ilp
+                    .fixed_poles
+                    .iter()
+                    .filter(|idx| subgraph[**idx].entity.prototype == *prototype)
+                    .count();
+                (prototype.clone(), max + fixed)
+            })
+            .collect();
+        Ok(ilp
+            .solve_indices(&subgraph)?
+            .into_iter()
+            .map(|idx| original[idx.index()])
+            .filter(|idx| {
+                let position = graph[*idx].entity.position;
+                bounds.contains(position) && !grid.near_border(position, self.overlap)
+            })
+            .collect())
+    }
+
+    /// Solves each region, up to [Self::threads] at once; returns the poles to keep.
+    fn solve_regions(
+        &self,
+        graph: &CandPoleGraph,
+        grid: &RegionGrid,
+        allowed_types: Option<&HashSet<EntityPrototypeRef>>,
+    ) -> Result<HashSet<NodeIndex>, OptimizerError> {
+        // an entity is in the region of the average position of the poles that can power it
+        let entities_by_region = get_pole_coverage_dict(graph)
+            .into_iter()
+            .map(|(entity, poles)| {
+                let sum = poles.iter().fold(vec2(0.0, 0.0), |sum, idx| {
+                    sum + graph[*idx].entity.position.to_vector()
+                });
+                (
+                    grid.region_of((sum / poles.len() as f64).to_point()),
+                    entity,
+                )
+            })
+            .into_grouping_map()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .sorted_by_key(|(region, _)| (region.1, region.0))
+            .collect_vec();
+        let max_counts = self.region_max_counts(
+            graph,
+            &entities_by_region
+                .iter()
+                .map(|(_, entities)| entities.len())
+                .collect_vec(),
+        );
+        let num_regions = entities_by_region.len();
+        let regions = entities_by_region
+            .into_iter()
+            .zip(max_counts)
+            .enumerate()
+            .map(|(i, ((cell, entities), max_count))| Region {
+                index: (i, num_regions),
+                cell,
+                entities,
+                max_count,
+            })
+            .collect_vec();
+
+        let threads = self.threads.clamp(1, num_regions.max(1));
+        // settings are per thread
+        let exact_game_math = position::exact_game_math();
+        let cancel = cancel::current();
+        let queue = Mutex::new(regions.iter());
+        let kept = Mutex::new(HashSet::new());
+        let first_error = Mutex::new(None);
+        std::thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| {
+                    position::use_exact_game_math(exact_game_math);
+                    let solve_queued = || -> Result<(), OptimizerError> {
+                        loop {
+                            let next = queue.lock().unwrap().next();
+                            let Some(region) = next else {
+                                break Ok(());
+                            };
+                            let poles = self.solve_region(graph, grid, region, allowed_types)?;
+                            kept.lock().unwrap().extend(poles);
+                        }
+                    };
+                    let result = match &cancel {
+                        Some(token) => cancel::with_token(token, solve_queued),
+                        None => solve_queued(),
+                    };
+                    if let Err(err) = result {
+                        // stop the other threads after their current region
+                        queue.lock().unwrap().by_ref().for_each(drop);
+                        first_error.lock().unwrap().get_or_insert(err);
+                    }
+                });
+            }
+        });
+        if let Some(err) = first_error.into_inner().unwrap() {
+            return Err(err);
+        }
+
+        // each region only avoids overlaps within itself
+        let overlaps = overlapping_candidates(graph);
+        let mut no_overlaps = HashSet::new();
+        for idx in kept
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .sorted_by_key(|idx| (!self.ilp.fixed_poles.contains(idx), *idx))
+        {
+            if self.ilp.fixed_poles.contains(&idx) || fits_with(&overlaps, &no_overlaps, idx) {
+                no_overlaps.insert(idx);
+            }
+        }
+        Ok(no_overlaps)
+    }
+}
+
+/// Splits `total` into whole shares in proportion to `weights`, by largest remainder, so they add up to `total`.
+fn apportion(total: usize, weights: &[usize]) -> Vec<usize> {
+    let weight_sum: usize = weights.iter().sum();
+    if weight_sum == 0 {
+        return vec![0; weights.len()];
+    }
+    let mut shares = weights
+        .iter()
+        .map(|&weight| total * weight / weight_sum)
+        .collect_vec();
+    let left = total - shares.iter().sum::<usize>();
+    let by_remainder = (0..weights.len())
+        .sorted_by_key(|&i| (std::cmp::Reverse(total * weights[i] % weight_sum), i))
+        .collect_vec();
+    for &i in by_remainder.iter().take(left) {
+        shares[i] += 1;
+    }
+    shares
+}
+
+impl PoleCoverSolver for SpatialSplitSolver<'_> {
+    fn solve(&self, graph: &CandPoleGraph) -> Result<CandPoleGraph, OptimizerError> {
+        if graph.node_count() == 0 {
+            return Ok(graph.clone());
+        }
+        let grid = RegionGrid::new(graph, self.region_size);
+        println!(
+            "Splitting into {}x{} regions of {} tiles",
+            grid.cols, grid.rows, self.region_size
+        );
+        let allowed_types = self.allowed_types(graph);
+        let kept = self.solve_regions(graph, &grid, allowed_types.as_ref())?;
+
+        let covered = kept
+            .iter()
+            .flat_map(|idx| graph[*idx].powered_entities.iter().copied())
+            .collect::<HashSet<_>>();
+        let (subgraph, original) = restrict(
+            graph,
+            |idx| {
+                let node = &graph[idx];
+                let allowed = allowed_types
+                    .as_ref()
+                    .is_none_or(|types| types.contains(&node.entity.prototype));
+                kept.contains(&idx)
+                    || self.ilp.fixed_poles.contains(&idx)
+                    || (allowed
+                        && (grid.near_border(node.entity.position, self.overlap)
+                            || !node.powered_entities.is_subset(&covered)))
+            },
+            |_| true,
+        );
+        println!(
+            "Stitching {} poles with {} candidates near region borders",
+            kept.len(),
+            subgraph.node_count() - kept.len()
+        );
+        let mut ilp = self.sub_ilp(&original, self.ilp.connectivity.clone());
+        ilp.fixed_poles.extend(
+            original
+                .iter()
+                .enumerate()
+                .filter(|(_, idx)| kept.contains(*idx))
+                .map(|(i, _)| NodeIndex::new(i)),
+        );
+        let selected = ilp
+            .solve_indices(&subgraph)?
+            .into_iter()
+            .map(|idx| original[idx.index()])
+            .collect::<HashSet<_>>();
+        Ok(graph.filter_map(
+            |idx, node| selected.contains(&idx).then(|| node.clone()),
+            |_, w| Some(*w),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::point2;
+    use petgraph::algo::connected_components;
+
+    use crate::bp_model::test_util::small_pole_prototype;
+    use crate::bp_model::BpModel;
+    use crate::pole_graph::ToCandidatePoleGraph;
+
+    use super::*;
+
+    #[test]
+    fn test_split_covers_and_connects() {
+        let mut model = BpModel::new();
+        let entities = (0..30)
+            .flat_map(|i| [point2(i * 3, 0), point2(i * 3, 4)])
+            .map(|pos| model.add_test_powerable(pos))
+            .collect::<HashSet<_>>();
+
+        let graph = model
+            .with_all_candidate_poles(model.get_bounding_box(), &[&small_pole_prototype()])
+            .get_maximally_connected_pole_graph()
+            .0
+            .to_cand_pole_graph(&model);
+
+        let solver = SpatialSplitSolver {
+            ilp: SetCoverILPSolver {
+                solver: &good_lp::highs,
+                config: &Ok,
+                cost: &|_, _| 1.0,
+                connectivity: Some(DistanceConnectivity {
+                    center_rel_pos: (0.5, 0.5),
+                    roots: vec![],
+                    max_hops: None,
+                    connect_fixed: false,
+                }),
+                fixed_poles: HashSet::new(),
+                max_count: HashMap::new(),
+                lazy: false,
+                max_pole_types: None,
+                max_load: None,
+                initial_solution: Default::default(),
+                soft_entities: Default::default(),
+            },
+            region_size: 24.0,
+            overlap: 6.0,
+            threads: 2,
+        };
+        let solution = solver.solve(&graph).unwrap();
+
+        let powered_entities = solution
+            .node_weights()
+            .flat_map(|node| node.powered_entities.iter())
+            .cloned()
+            .collect::<HashSet<_>>();
+        assert_eq!(powered_entities, entities);
+        assert_eq!(connected_components(&solution), 1);
+    }
+
+    #[test]
+    fn test_apportion() {
+        assert_eq!(apportion(10, &[1, 1, 2]), vec![3, 2, 5]);
+        assert_eq!(apportion(3, &[5, 5, 5]), vec![1, 1, 1]);
+        assert_eq!(apportion(2, &[1, 1, 1]), vec![1, 1, 0]);
+        assert_eq!(apportion(4, &[0, 0]), vec![0, 0]);
+    }
+}
@@ -0,0 +1,523 @@
+use std::collections::BTreeSet;
+use std::time::{Duration, Instant};
+
+use hashbrown::{HashMap, HashSet};
+use itertools::Itertools;
+use petgraph::prelude::*;
+use petgraph::unionfind::UnionFind;
+use rand::prelude::StdRng;
+use rand::{Rng, SeedableRng};
+
+use super::{assign_loads, overlapping_candidates, DistanceConnectivity, LoadLimit};
+use crate::better_bp::EntityId;
+use crate::cancel::CancellationToken;
+use crate::error::OptimizerError;
+use crate::pole_graph::CandPoleGraph;
+use crate::prototype_data::EntityPrototypeRef;
+
+/// Improves an existing pole cover solution, without changing which entities are powered.
+pub trait PolePostOptimizer {
+    /// `solution` is a subgraph of `candidates`, as returned by a [super::PoleCoverSolver].
+    fn optimize(
+        &self,
+        candidates: &CandPoleGraph,
+        solution: &CandPoleGraph,
+    ) -> Result<CandPoleGraph, OptimizerError>;
+}
+
+/// Finds the nodes in `candidates` corresponding to nodes in `solution`.
+pub fn candidate_indices(
+    candidates: &CandPoleGraph,
+    solution: &CandPoleGraph,
+) -> HashSet<NodeIndex> {
+    let key = |graph: &CandPoleGraph, idx: NodeIndex| {
+        let entity = &graph[idx].entity;
+        (
+            entity.prototype.name.clone(),
+            (entity.position.x * 2.0).round() as i64,
+            (entity.position.y * 2.0).round() as i64,
+        )
+    };
+    let by_key = candidates
+        .node_indices()
+        .map(|idx| (key(candidates, idx), idx))
+        .collect::<HashMap<_, _>>();
+    solution
+        .node_indices()
+        .filter_map(|idx| by_key.get(&key(solution, idx)).copied())
+        .collect()
+}
+
+/// Simulated annealing over the selected poles.
+///
+/// Moves are removing a pole, or replacing it with a nearby candidate pole (possibly of a different type).
+/// Every move keeps all entities powered, never places a pole overlapping another,
+/// never increases the number of separate pole networks, and keeps to the limits below.
+/// The objective is, in order of importance: total pole cost, wire length, then how far wires are from being axis-aligned.
+pub struct LocalSearchPolisher<'a> {
+    pub time_limit: Duration,
+    pub seed: u64,
+    pub cost: &'a dyn Fn(&CandPoleGraph, NodeIndex) -> f64,
+    /// Poles (in the candidate graph) that are never moved or removed.
+    pub fixed_poles: &'a HashSet<NodeIndex>,
+    /// Max distance a pole is moved in one step.
+    pub move_radius: f64,
+    /// If cancelled, stops early and returns the current solution.
+    pub cancel: &'a CancellationToken,
+    /// Most poles of each prototype; a move never adds a pole of a prototype at its limit.
+    pub max_count: &'a HashMap<EntityPrototypeRef, usize>,
+    pub max_pole_types: Option<usize>,
+    /// If it has [DistanceConnectivity::max_hops], moves never increase the number of poles too many hops from a root.
+    pub connectivity: Option<&'a DistanceConnectivity>,
+    /// If a candidate pole may be added, e.g. to keep to `--chunk-align require`.
+    pub can_add: &'a dyn Fn(&CandPoleGraph, NodeIndex) -> bool,
+    /// If the solution keeps to this limit, moves that break it are rejected.
+    pub max_load: Option<&'a LoadLimit>,
+}
+
+/// Weight of pole cost relative to wire length; large so that pole cost always dominates.
+const POLE_COST_WEIGHT: f64 = 1000.0;
+const MISALIGNMENT_WEIGHT: f64 = 0.5;
+
+#[derive(Debug, Clone, Copy)]
+struct Evaluation {
+    networks: usize,
+    score: f64,
+}
+
+struct SearchState<'a> {
+    graph: &'a CandPoleGraph,
+    cost: &'a dyn Fn(&CandPoleGraph, NodeIndex) -> f64,
+    /// All candidate edges, sorted by length.
+    sorted_edges: Vec<EdgeIndex>,
+    /// Position of each edge in [Self::sorted_edges], by edge index.
+    edge_rank: Vec<usize>,
+    selected: HashSet<NodeIndex>,
+    /// Ranks of the edges between selected poles.
+    selected_edges: BTreeSet<usize>,
+    pole_cost: f64,
+    /// Number of selected poles of each prototype; prototypes with none are left out.
+    counts: HashMap<EntityPrototypeRef, usize>,
+    cover_count: HashMap<EntityId, usize>,
+    /// See [overlapping_candidates].
+    overlaps: HashMap<NodeIndex, HashSet<NodeIndex>>,
+}
+
+impl SearchState<'_> {
+    /// If `added` overlaps no selected pole other than `removed`.
+    fn fits_instead_of(&self, removed: NodeIndex, added: NodeIndex) -> bool {
+        self.overlaps.get(&added).is_none_or(|others| {
+            others
+                .iter()
+                .all(|&other| other == removed || !self.selected.contains(&other))
+        })
+    }
+
+    /// If every entity powered by `removed` stays powered after replacing it with `added`.
+    fn can_replace(&self, removed: NodeIndex, added: Option<NodeIndex>) -> bool {
+        self.graph[removed].powered_entities.iter().all(|entity| {
+            self.cover_count[entity] >= 2
+                || added.is_some_and(|added| self.graph[added].powered_entities.contains(entity))
+        })
+    }
+
+    fn add(&mut self, idx: NodeIndex) {
+        let graph = self.graph;
+        self.selected.insert(idx);
+        for edge in graph.edges(idx) {
+            if self.selected.contains(&edge.target()) {
+                self.selected_edges
+                    .insert(self.edge_rank[edge.id().index()]);
+            }
+        }
+        self.pole_cost += (self.cost)(graph, idx);
+        *self
+            .counts
+            .entry(graph[idx].entity.prototype.clone())
+            .or_insert(0) += 1;
+        for entity in &graph[idx].powered_entities {
+            *self.cover_count.entry(*entity).or_insert(0) += 1;
+        }
+    }
+
+    fn remove(&mut self, idx: NodeIndex) {
+        let graph = self.graph;
+        self.selected.remove(&idx);
+        for edge in graph.edges(idx) {
+            self.selected_edges
+                .remove(&self.edge_rank[edge.id().index()]);
+        }
+        self.pole_cost -= (self.cost)(graph, idx);
+        let prototype = &graph[idx].entity.prototype;
+        let count = self.counts.get_mut(prototype).unwrap();
+        *count -= 1;
+        if *count == 0 {
+            self.counts.remove(prototype);
+        }
+        for entity in &graph[idx].powered_entities {
+            *self.cover_count.get_mut(entity).unwrap() -= 1;
+        }
+    }
+
+    fn replace(&mut self, removed: NodeIndex, added: Option<NodeIndex>) {
+        self.remove(removed);
+        if let Some(added) = added {
+            self.add(added);
+        }
+    }
+
+    /// Uses the minimum spanning forest of the selected poles as an estimate of the final wires.
+    fn evaluate(&self) -> Evaluation {
+        let graph = self.graph;
+        let index = self
+            .selected
+            .iter()
+            .enumerate()
+            .map(|(i, &idx)| (idx, i))
+            .collect::<HashMap<_, _>>();
+        let mut union_find = UnionFind::new(index.len());
+        let mut networks = self.selected.len();
+        let mut wire_score = 0.0;
+        for &rank in &self.selected_edges {
+            let edge = self.sorted_edges[rank];
+            let (a, b) = graph.edge_endpoints(edge).unwrap();
+            if union_find.union(index[&a], index[&b]) {
+                networks -= 1;
+                let delta = graph[a].entity.position - graph[b].entity.position;
+                wire_score += graph[edge] + MISALIGNMENT_WEIGHT * delta.x.abs().min(delta.y.abs());
+            }
+        }
+        Evaluation {
+            networks,
+            score: self.pole_cost * POLE_COST_WEIGHT + wire_score,
+        }
+    }
+}
+
+impl LocalSearchPolisher<'_> {
+    fn nearby_candidates(&self, graph: &CandPoleGraph, idx: NodeIndex) -> Vec<NodeIndex> {
+        let position = graph[idx].entity.position;
+        graph
+            .neighbors(idx)
+            .filter(|&n| (graph[n].entity.position - position).length() <= self.move_radius)
+            .sorted()
+            .dedup()
+            .collect()
+    }
+
+    /// If replacing `removed` with `added` keeps to the pole count and type limits, and `added` may be added.
+    fn within_count_limits(
+        &self,
+        state: &SearchState,
+        removed: NodeIndex,
+        added: NodeIndex,
+    ) -> bool {
+        let graph = state.graph;
+        let prototype = &graph[added].entity.prototype;
+        if *prototype == graph[removed].entity.prototype {
+            return (self.can_add)(graph, added);
+        }
+        let count = state.counts.get(prototype).copied().unwrap_or(0);
+        let types = state.counts.len() + usize::from(count == 0)
+            - usize::from(state.counts[&graph[removed].entity.prototype] == 1);
+        self.max_count.get(prototype).is_none_or(|&max| count < max)
+            && self.max_pole_types.is_none_or(|max| types <= max)
+            && (self.can_add)(graph, added)
+    }
+
+    fn hop_violations(&self, state: &SearchState) -> usize {
+        self.connectivity.map_or(0, |connectivity| {
+            connectivity.hop_violations(state.graph, &state.selected, self.fixed_poles)
+        })
+    }
+}
+
+impl PolePostOptimizer for LocalSearchPolisher<'_> {
+    fn optimize(
+        &self,
+        candidates: &CandPoleGraph,
+        solution: &CandPoleGraph,
+    ) -> Result<CandPoleGraph, OptimizerError> {
+        let start = Instant::now();
+        let selected = candidate_indices(candidates, solution);
+        if selected.len() != solution.node_count() {
+            return Err("Solution is not a subgraph of the candidate pole graph".into());
+        }
+        let mut cover_count = HashMap::new();
+        for idx in &selected {
+            for entity in &candidates[*idx].powered_entities {
+                *cover_count.entry(*entity).or_insert(0usize) += 1;
+            }
+        }
+        let sorted_edges = candidates
+            .edge_indices()
+            .sorted_by(|&a, &b| candidates[a].total_cmp(&candidates[b]))
+            .collect_vec();
+        let mut edge_rank = vec![0; candidates.edge_count()];
+        for (rank, edge) in sorted_edges.iter().enumerate() {
+            edge_rank[edge.index()] = rank;
+        }
+        let mut state = SearchState {
+            graph: candidates,
+            cost: self.cost,
+            sorted_edges,
+            edge_rank,
+            selected: HashSet::new(),
+            selected_edges: BTreeSet::new(),
+            pole_cost: 0.0,
+            counts: HashMap::new(),
+            cover_count: HashMap::new(),
+            overlaps: overlapping_candidates(candidates),
+        };
+        for idx in selected {
+            state.add(idx);
+        }
+        let mut hop_violations = self.hop_violations(&state);
+        let check_loads = self
+            .max_load
+            .filter(|limit| assign_loads(candidates, &state.selected, limit).is_some());
+
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut current = state.evaluate();
+        let initial_score = current.score;
+        let mut iterations = 0;
+        let mut accepted = 0;
+        while start.elapsed() < self.time_limit && !self.cancel.is_cancelled() {
+            iterations += 1;
+            let movable = state
+                .selected
+                .iter()
+                .copied()
+                .filter(|idx| !self.fixed_poles.contains(idx))
+                .sorted()
+                .collect_vec();
+            if movable.is_empty() {
+                break;
+            }
+            let removed = movable[rng.gen_range(0..movable.len())];
+            let added = if rng.gen_bool(0.2) {
+                None
+            } else {
+                let nearby = self
+                    .nearby_candidates(candidates, removed)
+                    .into_iter()
+                    .filter(|&idx| {
+                        !state.selected.contains(&idx)
+                            && state.fits_instead_of(removed, idx)
+                            && self.within_count_limits(&state, removed, idx)
+                    })
+                    .collect_vec();
+                if nearby.is_empty() {
+                    continue;
+                }
+                Some(nearby[rng.gen_range(0..nearby.len())])
+            };
+            if !state.can_replace(removed, added) {
+                continue;
+            }
+
+            state.replace(removed, added);
+            let new = state.evaluate();
+            let temperature = 1.0 - start.elapsed().as_secs_f64() / self.time_limit.as_secs_f64();
+            let delta = new.score - current.score;
+            let mut accept = new.networks <= current.networks
+                && (delta <= 0.0 || rng.gen_bool((-delta / temperature.max(1e-3)).exp().min(1.0)));
+            // the slower limits are only checked for moves that would otherwise be accepted
+            let new_hop_violations = if accept {
+                self.hop_violations(&state)
+            } else {
+                0
+            };
+            accept = accept
+                && new_hop_violations <= hop_violations
+                && check_loads
+                    .is_none_or(|limit| assign_loads(candidates, &state.selected, limit).is_some());
+            if accept {
+                current = new;
+                hop_violations = new_hop_violations;
+                accepted += 1;
+            } else {
+                // undo
+                if let Some(added) = added {
+                    state.replace(added, Some(removed));
+                } else {
+                    state.add(removed);
+                }
+            }
+        }
+        println!(
+            "Polish: {} moves tried, {} accepted; score {:.1} -> {:.1}",
+            iterations, accepted, initial_score, current.score
+        );
+
+        Ok(candidates.filter_map(
+            |idx, node| state.selected.contains(&idx).then(|| node.clone()),
+            |_, w| Some(*w),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::point2;
+
+    use crate::bp_model::test_util::small_pole_prototype;
+    use crate::bp_model::{BpModel, WorldEntity};
+    use crate::pole_graph::{CandPoleNode, ToCandidatePoleGraph};
+    use crate::position::BoundingBox;
+    use crate::prototype_data::{EntityPrototype, EntityPrototypeRef, PoleData};
+    use crate::rcid::RcId;
+
+    use super::*;
+
+    #[test]
+    fn test_polish_keeps_entities_powered() {
+        let mut model = BpModel::new();
+        let e1 = model.add_test_powerable(point2(0, 0));
+        let e2 = model.add_test_powerable(point2(4, 0));
+
+        let candidates = model
+            .with_all_candidate_poles(model.get_bounding_box(), &[&small_pole_prototype()])
+            .get_maximally_connected_pole_graph()
+            .0
+            .to_cand_pole_graph(&model);
+        // a redundant solution: every candidate pole
+        let solution = candidates.clone();
+
+        let fixed_poles = HashSet::new();
+        let polisher = LocalSearchPolisher {
+            time_limit: Duration::from_millis(200),
+            seed: 0,
+            cost: &|_, _| 1.0,
+            fixed_poles: &fixed_poles,
+            move_radius: 2.0,
+            cancel: &CancellationToken::new(),
+            max_count: &HashMap::new(),
+            max_pole_types: None,
+            connectivity: None,
+            can_add: &|_, _| true,
+            max_load: None,
+        };
+        let result = polisher.optimize(&candidates, &solution).unwrap();
+
+        assert!(result.node_count() < solution.node_count());
+        let powered_entities = result
+            .node_weights()
+            .flat_map(|node| node.powered_entities.iter())
+            .cloned()
+            .collect::<HashSet<_>>();
+        assert_eq!(powered_entities, HashSet::from([e1, e2]));
+    }
+
+    fn big_pole_prototype() -> EntityPrototypeRef {
+        RcId::new(EntityPrototype {
+            type_: "electric-pole".to_string(),
+            name: "test-2x2".to_string(),
+            tile_width: 2,
+            tile_height: 2,
+            collision_box: BoundingBox::new(point2(-1.0, -1.0), point2(1.0, 1.0)),
+            uses_power: false,
+            pole_data: Some(PoleData {
+                wire_distance: 9.0,
+                supply_radius: 3.5,
+                supply_area: None,
+            }),
+            inserter_data: None,
+            turret_data: None,
+            energy_data: None,
+            fluid_data: None,
+            direction_boxes: None,
+        })
+    }
+
+    fn candidate(prototype: &EntityPrototypeRef, x: f64, y: f64, powered: &[u32]) -> CandPoleNode {
+        CandPoleNode {
+            entity: WorldEntity {
+                position: point2(x, y),
+                direction: 0,
+                prototype: prototype.clone(),
+            },
+            powered_entities: powered.iter().map(|&id| EntityId(id)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_polish_no_overlapping_poles() {
+        let big_pole = big_pole_prototype();
+        // moving the expensive pole onto the cheaper big one would overlap the fixed pole
+        let mut candidates = CandPoleGraph::default();
+        let fixed = candidates.add_node(candidate(&small_pole_prototype(), 0.5, 0.5, &[1]));
+        let expensive = candidates.add_node(candidate(&small_pole_prototype(), 4.5, 0.5, &[2]));
+        let big = candidates.add_node(candidate(&big_pole, 1.0, 1.0, &[2]));
+        candidates.add_edge(fixed, expensive, 4.0);
+        candidates.add_edge(fixed, big, 0.7);
+        candidates.add_edge(expensive, big, 3.5);
+        let solution = candidates.filter_map(
+            |idx, node| (idx != big).then(|| node.clone()),
+            |_, w| Some(*w),
+        );
+
+        let fixed_poles = HashSet::from([fixed]);
+        let polisher = LocalSearchPolisher {
+            time_limit: Duration::from_millis(100),
+            seed: 0,
+            cost: &|_, idx| if idx == expensive { 2.0 } else { 1.0 },
+            fixed_poles: &fixed_poles,
+            move_radius: 4.0,
+            cancel: &CancellationToken::new(),
+            max_count: &HashMap::new(),
+            max_pole_types: None,
+            connectivity: None,
+            can_add: &|_, _| true,
+            max_load: None,
+        };
+        let result = polisher.optimize(&candidates, &solution).unwrap();
+        assert_eq!(
+            candidate_indices(&candidates, &result),
+            HashSet::from([fixed, expensive])
+        );
+    }
+
+    #[test]
+    fn test_polish_keeps_max_count() {
+        let big_pole = big_pole_prototype();
+        // the expensive pole can move onto the cheaper big one, unless big poles are limited
+        let mut candidates = CandPoleGraph::default();
+        let fixed = candidates.add_node(candidate(&small_pole_prototype(), 0.5, 0.5, &[1]));
+        let expensive = candidates.add_node(candidate(&small_pole_prototype(), 4.5, 0.5, &[2]));
+        let big = candidates.add_node(candidate(&big_pole, 5.0, 3.0, &[2]));
+        candidates.add_edge(fixed, expensive, 4.0);
+        candidates.add_edge(fixed, big, 5.0);
+        candidates.add_edge(expensive, big, 2.5);
+        let solution = candidates.filter_map(
+            |idx, node| (idx != big).then(|| node.clone()),
+            |_, w| Some(*w),
+        );
+
+        let fixed_poles = HashSet::from([fixed]);
+        let polish = |max_count: &HashMap<EntityPrototypeRef, usize>| {
+            let polisher = LocalSearchPolisher {
+                time_limit: Duration::from_millis(100),
+                seed: 0,
+                cost: &|_, idx| if idx == expensive { 2.0 } else { 1.0 },
+                fixed_poles: &fixed_poles,
+                move_radius: 4.0,
+                cancel: &CancellationToken::new(),
+                max_count,
+                max_pole_types: None,
+                connectivity: None,
+                can_add: &|_, _| true,
+                max_load: None,
+            };
+            candidate_indices(
+                &candidates,
+                &polisher.optimize(&candidates, &solution).unwrap(),
+            )
+        };
+        assert_eq!(polish(&HashMap::new()), HashSet::from([fixed, big]));
+        assert_eq!(
+            polish(&HashMap::from([(big_pole.clone(), 0)])),
+            HashSet::from([fixed, expensive])
+        );
+    }
+}
@@ -1,9 +1,13 @@
 use std::cmp::max;
 use std::collections::BinaryHeap;
 
+use hashbrown::HashMap;
+
+use clap::ValueEnum;
 use euclid::{Angle, Point2D};
 use itertools::Itertools;
 use num_traits::{Num, Signed};
+use petgraph::graph::EdgeReference;
 use petgraph::prelude::*;
 use petgraph::unionfind::UnionFind;
 use petgraph::visit::{IntoNodeReferences, NodeIndexable};
@@ -67,21 +71,85 @@ impl<N: Clone> PoleConnector<N> for WeightedMSTConnector {
     }
 }
 
+/// Which direction [PrettyPoleConnector] prefers wires to run in, e.g. along a main bus.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WireAxis {
+    /// The longer side of the area the poles span, if it is at least twice as long as the other.
+    Auto,
+    /// Horizontal
+    X,
+    /// Vertical
+    Y,
+    /// Horizontal and vertical equally.
+    Any,
+}
+
+/// How much longer one side must be than the other for [WireAxis::Auto] to pick it.
+const AUTO_AXIS_RATIO: f64 = 2.0;
+/// Extra weight on wires along the preferred axis, on top of the preference for axis-aligned wires.
+const AXIS_PREFERENCE: f64 = 2.0;
+
+impl WireAxis {
+    /// The preferred axis, [WireAxis::X] or [WireAxis::Y], for poles at `positions`; `None` if there is none.
+    pub fn resolve(self, positions: impl Iterator<Item = MapPosition>) -> Option<WireAxis> {
+        match self {
+            WireAxis::X | WireAxis::Y => Some(self),
+            WireAxis::Any => None,
+            WireAxis::Auto => {
+                let size = euclid::Box2D::from_points(positions).size();
+                if size.width >= size.height * AUTO_AXIS_RATIO && size.width > 0.0 {
+                    Some(WireAxis::X)
+                } else if size.height >= size.width * AUTO_AXIS_RATIO && size.height > 0.0 {
+                    Some(WireAxis::Y)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
 /// Currently assumes that the input graph is maximally connected;
 /// all poles that can connect have an edge between them.
 /// (If not true, may produce crossings.)
+#[derive(Clone)]
 pub struct PrettyPoleConnector {
     /// Any 2 edges must have an angle at least this large
     pub min_angle: Angle<f64>,
     /// Any 2 adjacent angles must sum to at least this large
     pub min_adjacent_angle: Angle<f64>,
+    pub wire_axis: WireAxis,
+    /// If set, wires are added only up to its average number per pole, only along axes if it has almost
+    /// only those, and along its axis if [Self::wire_axis] is [WireAxis::Auto].
+    pub style: Option<WireStyle>,
+    /// Considers wires one area of [BUCKET_SIZE] at a time, in grid order, instead of sorting all of them.
+    /// Near-linear time for very large solutions; wires across area borders may be chosen differently.
+    pub bucketed: bool,
+    /// Only adds wires up to the longest one needed to connect the poles, so that is as short as it can be.
+    pub minimize_longest_wire: bool,
 }
 
+/// Solutions with at least this many poles are connected with [PrettyPoleConnector::bucketed].
+pub const BUCKETED_MIN_POLES: usize = 10_000;
+/// Side of the square areas [PrettyPoleConnector::bucketed] sorts wires within, in tiles.
+const BUCKET_SIZE: f64 = 32.0;
+
 impl PrettyPoleConnector {
     pub fn default() -> Self {
         Self {
             min_angle: Angle::degrees(30.0),
             min_adjacent_angle: Angle::degrees(100.0),
+            wire_axis: WireAxis::Any,
+            style: None,
+            bucketed: false,
+            minimize_longest_wire: false,
+        }
+    }
+
+    pub fn with_wire_axis(wire_axis: WireAxis) -> Self {
+        Self {
+            wire_axis,
+            ..Self::default()
         }
     }
 }
@@ -101,6 +169,58 @@ fn orientation<T: Num + Copy, U>(a: Point2D<T, U>, b: Point2D<T, U>, c: Point2D<
     (b - a).cross(c - a)
 }
 
+/// Fraction of wires that must be horizontal or vertical for [WireStyle] to only add those.
+const STRICT_AXIS_ALIGNED: f64 = 0.9;
+
+fn is_axis_aligned(a: MapPosition, b: MapPosition) -> bool {
+    (a.x - b.x).abs() < 1e-6 || (a.y - b.y).abs() < 1e-6
+}
+
+/// The style of existing wires, e.g. the input blueprint's, for [PrettyPoleConnector] to mimic.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WireStyle {
+    /// Average number of wires per pole; below 2 for a spanning tree, more for a mesh.
+    pub mean_degree: f64,
+    /// Fraction of wires that are horizontal or vertical.
+    pub axis_aligned: f64,
+    /// The axis most wires run along, if at least twice as many run along it as along the other.
+    pub axis: Option<WireAxis>,
+}
+
+impl WireStyle {
+    /// The style of the wires in `graph`; `None` if it has none.
+    pub fn detect<N: WithPosition>(graph: &UnGraph<N, f64>) -> Option<Self> {
+        if graph.edge_count() == 0 {
+            return None;
+        }
+        let (mut horizontal, mut vertical) = (0, 0);
+        for edge in graph.edge_references() {
+            let (a, b) = (
+                graph[edge.source()].position(),
+                graph[edge.target()].position(),
+            );
+            if (a.y - b.y).abs() < 1e-6 {
+                horizontal += 1;
+            } else if (a.x - b.x).abs() < 1e-6 {
+                vertical += 1;
+            }
+        }
+        let num_wires = graph.edge_count() as f64;
+        let axis = if horizontal > 0 && horizontal as f64 >= vertical as f64 * AUTO_AXIS_RATIO {
+            Some(WireAxis::X)
+        } else if vertical > 0 && vertical as f64 >= horizontal as f64 * AUTO_AXIS_RATIO {
+            Some(WireAxis::Y)
+        } else {
+            None
+        };
+        Some(WireStyle {
+            mean_degree: 2.0 * num_wires / graph.node_count() as f64,
+            axis_aligned: (horizontal + vertical) as f64 / num_wires,
+            axis,
+        })
+    }
+}
+
 fn line_seg_intersects<T: Signed + Num + Copy, U>(
     a: Point2D<T, U>,
     b: Point2D<T, U>,
@@ -173,32 +293,124 @@ impl PrettyPoleConnector {
         true
     }
 
-    fn edge_weight(orig_weight: f64, src: MapPosition, tgt: MapPosition) -> f64 {
+    /// Lower for wires along an axis, and lower still along `axis`, if given.
+    fn edge_weight(
+        orig_weight: f64,
+        src: MapPosition,
+        tgt: MapPosition,
+        axis: Option<WireAxis>,
+    ) -> f64 {
         let vec = (tgt - src).normalize();
         let axis_alightment = (vec.x.abs() - vec.y.abs()).powi(2);
-        orig_weight / (1.0 + 2.0 * axis_alightment)
+        let along = match axis {
+            Some(WireAxis::X) => vec.x.abs(),
+            Some(WireAxis::Y) => vec.y.abs(),
+            _ => 0.0,
+        };
+        orig_weight / (1.0 + 2.0 * axis_alightment) / (1.0 + AXIS_PREFERENCE * along.powi(2))
+    }
+
+    /// Edges as (weight, original weight, source, target), grouped into areas by their midpoint.
+    /// Areas are in grid order, and edges within each are by weight, then by index, as a stable sort would.
+    fn bucketed_edges<N: WithPosition>(
+        graph: &UnGraph<N, f64>,
+        weight: impl Fn(EdgeReference<f64>) -> f64,
+    ) -> impl Iterator<Item = (f64, f64, NodeIndex, NodeIndex)> {
+        let mut buckets = HashMap::<(i64, i64), BinaryHeap<_>>::new();
+        for edge in graph.edge_references() {
+            let middle = graph[edge.source()]
+                .position()
+                .lerp(graph[edge.target()].position(), 0.5);
+            let bucket = (
+                (middle.x / BUCKET_SIZE).floor() as i64,
+                (middle.y / BUCKET_SIZE).floor() as i64,
+            );
+            buckets.entry(bucket).or_default().push(MinScored(
+                (weight(edge), edge.id().index()),
+                (*edge.weight(), edge.source(), edge.target()),
+            ));
+        }
+        buckets
+            .into_iter()
+            .sorted_by_key(|&((x, y), _)| (y, x))
+            .flat_map(|(_, mut heap)| {
+                std::iter::from_fn(move || heap.pop()).map(
+                    |MinScored((wt, _), (orig_wt, source, target))| (wt, orig_wt, source, target),
+                )
+            })
     }
 }
 
+/// The longest wire in a minimum spanning forest of `graph`; no spanning forest has a shorter longest wire.
+fn bottleneck_length<N>(graph: &UnGraph<N, f64>) -> Option<f64> {
+    let mut uf = UnionFind::new(graph.node_bound());
+    graph
+        .edge_references()
+        .sorted_by(|a, b| a.weight().partial_cmp(b.weight()).unwrap())
+        .filter(|edge| uf.union(edge.source().index(), edge.target().index()))
+        .map(|edge| *edge.weight())
+        .last()
+}
+
 impl<N: WithPosition + Clone> PoleConnector<N> for PrettyPoleConnector {
     fn connect_poles(&self, graph: &UnGraph<N, f64>) -> UnGraph<N, f64> {
+        let limited;
+        let graph = match self
+            .minimize_longest_wire
+            .then(|| bottleneck_length(graph))
+            .flatten()
+        {
+            Some(limit) => {
+                // keeps all nodes, so indices are unchanged
+                limited = graph.filter_map(
+                    |_, node| Some(node.clone()),
+                    |_, &length| (length <= limit).then_some(length),
+                );
+                &limited
+            }
+            None => graph,
+        };
         let mut result = WeightedMSTConnector.connect_poles(graph);
-        let edges = graph
-            .edge_references()
-            .map(|edge| {
-                let source = edge.source();
-                let target = edge.target();
-                let wt = *edge.weight();
-                (
-                    Self::edge_weight(wt, graph[source].position(), graph[target].position()),
-                    wt,
-                    source,
-                    target,
-                )
-            })
-            .sorted_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        let axis = match self.style.and_then(|style| style.axis) {
+            Some(axis) if self.wire_axis == WireAxis::Auto => Some(axis),
+            _ => self
+                .wire_axis
+                .resolve(graph.node_weights().map(|node| node.position())),
+        };
+        let max_wires = self
+            .style
+            .map(|style| (style.mean_degree * graph.node_count() as f64 / 2.0).round() as usize);
+        let only_axis_aligned = self
+            .style
+            .is_some_and(|style| style.axis_aligned >= STRICT_AXIS_ALIGNED);
+        let weight = |edge: EdgeReference<f64>| {
+            Self::edge_weight(
+                *edge.weight(),
+                graph[edge.source()].position(),
+                graph[edge.target()].position(),
+                axis,
+            )
+        };
+        let edges: Box<dyn Iterator<Item = _>> = if self.bucketed {
+            Box::new(Self::bucketed_edges(graph, weight))
+        } else {
+            Box::new(
+                graph
+                    .edge_references()
+                    .map(|edge| (weight(edge), *edge.weight(), edge.source(), edge.target()))
+                    .sorted_by(|a, b| a.0.partial_cmp(&b.0).unwrap()),
+            )
+        };
 
         for (_, orig_wt, source, target) in edges {
+            if max_wires.is_some_and(|max_wires| result.edge_count() >= max_wires) {
+                break;
+            }
+            if only_axis_aligned
+                && !is_axis_aligned(graph[source].position(), graph[target].position())
+            {
+                continue;
+            }
             if self.can_connect(graph, &result, source, target) {
                 result.update_edge(source, target, orig_wt);
             }
@@ -210,13 +422,38 @@ impl<N: WithPosition + Clone> PoleConnector<N> for PrettyPoleConnector {
 
 #[cfg(test)]
 mod tests {
+    use std::time::Instant;
+
     use euclid::point2;
+    use petgraph::algo::connected_components;
 
-    use crate::bp_model::BpModel;
+    use crate::bp_model::{BpModel, WorldEntity};
     use crate::position::TilePosition;
 
     use super::*;
 
+    /// Candidate graph of a `size` x `size` grid of small poles, 5 tiles apart.
+    fn grid_candidates(size: i32) -> UnGraph<WorldEntity, f64> {
+        let mut model = BpModel::new();
+        let grid = (0..size)
+            .cartesian_product(0..size)
+            .map(|(x, y)| point2(x * 5, y * 5))
+            .collect_vec();
+        model.add_test_poles(&grid);
+        model.get_maximally_connected_pole_graph().0
+    }
+
+    fn edge_set(graph: &UnGraph<WorldEntity, f64>) -> Vec<(usize, usize)> {
+        graph
+            .edge_references()
+            .map(|edge| {
+                let (a, b) = (edge.source().index(), edge.target().index());
+                (a.min(b), a.max(b))
+            })
+            .sorted()
+            .collect()
+    }
+
     #[test]
     fn test_is_left() {
         assert!(is_left(point2::<_, ()>(0, 0), point2(1, 0), point2(0, 1)));
@@ -237,6 +474,62 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_wire_axis() {
+        let positions = [point2(0.0, 0.0), point2(30.0, 5.0)];
+        assert_eq!(
+            WireAxis::Auto.resolve(positions.into_iter()),
+            Some(WireAxis::X)
+        );
+        let square = [point2(0.0, 0.0), point2(30.0, 20.0)];
+        assert_eq!(WireAxis::Auto.resolve(square.into_iter()), None);
+        assert_eq!(WireAxis::Y.resolve(square.into_iter()), Some(WireAxis::Y));
+
+        let weight = |tgt, axis| PrettyPoleConnector::edge_weight(5.0, point2(0.0, 0.0), tgt, axis);
+        let (horizontal, vertical) = (point2(5.0, 0.0), point2(0.0, 5.0));
+        assert_eq!(weight(horizontal, None), weight(vertical, None));
+        assert!(weight(horizontal, Some(WireAxis::X)) < weight(vertical, Some(WireAxis::X)));
+        assert!(weight(vertical, Some(WireAxis::Y)) < weight(horizontal, Some(WireAxis::Y)));
+    }
+
+    #[test]
+    fn test_wire_style() {
+        let mut model = BpModel::new();
+        let poles = model.add_test_poles(&[point2(0, 0), point2(5, 0), point2(10, 0)]);
+        model.add_cable_connection(poles[0], poles[1]);
+        model.add_cable_connection(poles[1], poles[2]);
+        let style = WireStyle::detect(&model.get_current_pole_graph().0).unwrap();
+        assert_eq!(
+            style,
+            WireStyle {
+                mean_degree: 4.0 / 3.0,
+                axis_aligned: 1.0,
+                axis: Some(WireAxis::X),
+            }
+        );
+
+        // a tree style gives a spanning tree of a grid, which is more connected by default
+        let mut model = BpModel::new();
+        let grid = (0..3)
+            .cartesian_product(0..3)
+            .map(|(x, y)| point2(x * 5, y * 5))
+            .collect_vec();
+        model.add_test_poles(&grid);
+        let cand = model.get_maximally_connected_pole_graph().0;
+        let default = PrettyPoleConnector::default().connect_poles(&cand);
+        assert!(default.edge_count() > 8);
+        let connector = PrettyPoleConnector {
+            style: Some(style),
+            ..PrettyPoleConnector::default()
+        };
+        let styled = connector.connect_poles(&cand);
+        assert_eq!(styled.edge_count(), 8);
+        assert!(styled.edge_references().all(|edge| is_axis_aligned(
+            styled[edge.source()].position(),
+            styled[edge.target()].position()
+        )));
+    }
+
     #[test]
     fn test_does_not_allow_crossing() {
         for (a, b, c, d) in INTERSECTING_SEGS {
@@ -258,4 +551,82 @@ mod tests {
             assert!(!res);
         }
     }
+
+    #[test]
+    fn test_bucketed() {
+        let bucketed = PrettyPoleConnector {
+            bucketed: true,
+            ..PrettyPoleConnector::default()
+        };
+        // within one area, the same as sorting all wires
+        let small = grid_candidates(4);
+        assert_eq!(
+            edge_set(&bucketed.connect_poles(&small)),
+            edge_set(&PrettyPoleConnector::default().connect_poles(&small))
+        );
+
+        let large = grid_candidates(20);
+        let result = bucketed.connect_poles(&large);
+        assert_eq!(connected_components(&result), 1);
+        let segment = |edge: EdgeReference<f64>| {
+            (
+                result[edge.source()].position,
+                result[edge.target()].position,
+            )
+        };
+        for (e1, e2) in result.edge_references().tuple_combinations() {
+            let ((a, b), (c, d)) = (segment(e1), segment(e2));
+            let shares_pole = [c, d].contains(&a) || [c, d].contains(&b);
+            assert!(shares_pole || !line_seg_intersects(a, b, c, d));
+        }
+    }
+
+    #[test]
+    fn test_minimize_longest_wire() {
+        // a triangle with a base of 6, and two sides of about 5.8
+        let mut model = BpModel::new();
+        model.add_test_poles(&[point2(0, 0), point2(6, 0), point2(3, 5)]);
+        let candidates = model.get_maximally_connected_pole_graph().0;
+        let connector = PrettyPoleConnector {
+            minimize_longest_wire: true,
+            ..PrettyPoleConnector::default()
+        };
+        let result = connector.connect_poles(&candidates);
+        assert_eq!(connected_components(&result), 1);
+        assert!(result.edge_weights().all(|&length| length < 6.0));
+        // the base is added without it
+        let default = PrettyPoleConnector::default().connect_poles(&candidates);
+        assert!(default.edge_weights().any(|&length| length == 6.0));
+    }
+
+    /// Benchmark against sorting all wires:
+    /// `cargo test --release bench_bucketed_connector -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_bucketed_connector() {
+        let candidates = grid_candidates(150);
+
+        let start = Instant::now();
+        let sorted = PrettyPoleConnector::default().connect_poles(&candidates);
+        let sorted_time = start.elapsed();
+
+        let start = Instant::now();
+        let bucketed = PrettyPoleConnector {
+            bucketed: true,
+            ..PrettyPoleConnector::default()
+        }
+        .connect_poles(&candidates);
+        let bucketed_time = start.elapsed();
+
+        println!(
+            "{} poles, {} candidate wires: sorted {:?} ({} wires), bucketed {:?} ({} wires)",
+            candidates.node_count(),
+            candidates.edge_count(),
+            sorted_time,
+            sorted.edge_count(),
+            bucketed_time,
+            bucketed.edge_count()
+        );
+        assert_eq!(connected_components(&bucketed), 1);
+    }
 }
@@ -0,0 +1,82 @@
+use crate::bp_model::BpModel;
+
+/// Statistics of a blueprint used to pick candidate poles.
+#[derive(Debug, Clone, Copy)]
+pub struct ModelStats {
+    pub powered_entities: usize,
+    pub occupied_tiles: usize,
+    pub area: usize,
+}
+
+impl ModelStats {
+    pub fn of_model(model: &BpModel) -> Self {
+        let bbox = model.get_bounding_box();
+        ModelStats {
+            powered_entities: model.all_entities().filter(|e| e.uses_power()).count(),
+            occupied_tiles: model.num_occupied_tiles(),
+            area: bbox.area().max(0) as usize,
+        }
+    }
+
+    /// Fraction of tiles in the bounding box that are occupied.
+    pub fn density(&self) -> f64 {
+        self.occupied_tiles as f64 / self.area.max(1) as f64
+    }
+
+    /// Powered entities per 100 tiles.
+    pub fn power_density(&self) -> f64 {
+        self.powered_entities as f64 * 100.0 / self.area.max(1) as f64
+    }
+}
+
+/// Dense builds with many powered entities benefit from substations, which take less room per area covered.
+const SUBSTATION_MIN_POWER_DENSITY: f64 = 4.0;
+const SUBSTATION_MIN_AREA: usize = 18 * 18;
+/// Medium poles are better than small poles unless entities are sparse.
+const MEDIUM_MIN_DENSITY: f64 = 0.25;
+
+/// Picks candidate pole types based on entity density. Returns the pole names, and a reason for the choice.
+pub fn choose_pole_types(stats: &ModelStats) -> (&'static [&'static str], &'static str) {
+    if stats.power_density() >= SUBSTATION_MIN_POWER_DENSITY && stats.area >= SUBSTATION_MIN_AREA {
+        (
+            &["medium-electric-pole", "substation"],
+            "large, dense build with many powered entities",
+        )
+    } else if stats.density() >= MEDIUM_MIN_DENSITY {
+        (&["medium-electric-pole"], "dense build")
+    } else {
+        (&["small-electric-pole"], "sparse build")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_choose_pole_types() {
+        let sparse = ModelStats {
+            powered_entities: 5,
+            occupied_tiles: 20,
+            area: 400,
+        };
+        assert_eq!(choose_pole_types(&sparse).0, ["small-electric-pole"]);
+
+        let dense = ModelStats {
+            powered_entities: 5,
+            occupied_tiles: 200,
+            area: 400,
+        };
+        assert_eq!(choose_pole_types(&dense).0, ["medium-electric-pole"]);
+
+        let dense_powered = ModelStats {
+            powered_entities: 100,
+            occupied_tiles: 1000,
+            area: 2000,
+        };
+        assert_eq!(
+            choose_pole_types(&dense_powered).0,
+            ["medium-electric-pole", "substation"]
+        );
+    }
+}
@@ -1,8 +1,10 @@
+use std::cell::Cell;
 use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::ops::Deref;
 
+use euclid::Vector2D;
 use factorio_blueprint::objects as fbp;
 use factorio_blueprint::objects::{
     Blueprint, Color, Connection, ControlBehavior, EntityFilterMode, EntityNumber, EntityPriority,
@@ -11,15 +13,21 @@ use factorio_blueprint::objects::{
 };
 use itertools::Itertools;
 use noisy_float::types::R64;
+use serde::{Deserialize, Serialize};
 
-use crate::position::{MapPosition, ToMapPosition, ToPosition};
+use crate::intern::Name;
+use crate::position::{
+    MapPosition, MapPositionExt, MapSpace, TilePosition, TileSpace, TileSpaceExt, ToMapPosition,
+    ToPosition,
+};
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Ord, PartialOrd)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Ord, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct EntityId(pub u32);
 
 #[derive(Clone)]
 pub struct BlueprintEntityData {
-    pub name: Prototype,
+    pub name: Name,
     pub position: MapPosition,
     pub direction: Option<u8>,
 
@@ -52,9 +60,9 @@ pub struct BlueprintEntityData {
 }
 
 impl BlueprintEntityData {
-    pub fn new(name: Prototype, position: MapPosition, direction: Option<u8>) -> Self {
+    pub fn new(name: impl Into<Name>, position: MapPosition, direction: Option<u8>) -> Self {
         Self {
-            name,
+            name: name.into(),
             position,
             direction,
             orientation: None,
@@ -162,6 +170,7 @@ pub struct OutgoingConnection {
     pub color: WireColor,
 }
 
+#[derive(Clone)]
 pub struct ConnectionPoint(Option<HashSet<OutgoingConnection>>);
 
 #[allow(dead_code)]
@@ -204,7 +213,7 @@ impl Debug for ConnectionPoint {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BlueprintEntity {
     id: EntityId,
     pub data: BlueprintEntityData,
@@ -247,11 +256,38 @@ impl BlueprintEntity {
     }
 }
 
-#[derive(Debug)]
+thread_local! {
+    static STABLE_NUMBERING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Makes [BlueprintEntities::to_blueprint_entities] keep entity numbers from the input blueprint
+/// (`--stable-numbering`), so unchanged entities diff cleanly.
+pub fn use_stable_numbering(stable: bool) {
+    STABLE_NUMBERING.with(|cell| cell.set(stable));
+}
+
+/// If [use_stable_numbering] is on, in this thread.
+pub fn stable_numbering() -> bool {
+    STABLE_NUMBERING.with(|cell| cell.get())
+}
+
+#[derive(Debug, Clone)]
 #[non_exhaustive]
 pub struct BlueprintEntities {
     pub entities: HashMap<EntityId, BlueprintEntity>,
     next_entity_id: EntityId,
+    /// Ids below this came from the input blueprint, and are its entity numbers.
+    first_new_id: EntityId,
+    /// Name and position of removed input entities, so an identical entity added back can get its number.
+    removed_input: HashMap<(Name, (i64, i64)), EntityId>,
+}
+
+/// Position key for matching entities, in half tiles.
+fn position_key(position: MapPosition) -> (i64, i64) {
+    (
+        (position.x * 2.0).round() as i64,
+        (position.y * 2.0).round() as i64,
+    )
 }
 
 impl BlueprintEntities {
@@ -260,6 +296,8 @@ impl BlueprintEntities {
         Self {
             entities: Default::default(),
             next_entity_id: EntityId(0),
+            first_new_id: EntityId(0),
+            removed_input: HashMap::new(),
         }
     }
 
@@ -270,28 +308,59 @@ impl BlueprintEntities {
         id
     }
 
-    // /// still keeps connections to this entity. See also remove_invalid_connections
-    // pub fn remove(&mut self, id: EntityId) -> Option<BlueprintEntity> {
-    //     self.entities.remove(&id)
-    // }
-    // 
-    // pub fn remove_invalid_connections(&mut self) {
-    //     // rust borrow checker is a bit too strict here
-    //     let keys = self.entities.keys().copied().collect::<HashSet<_>>();
-    //     let remove_connections = |pt: &mut ConnectionPoint| {
-    //         if let Some(set) = &mut pt.0 {
-    //             set.retain(|conn| keys.contains(&conn.dest.entity_id));
-    //         }
-    //         pt.clear_if_empty();
-    //     };
-    //     for entity in (&mut self.entities).values_mut() {
-    //         if let Some(set) = &mut entity.neighbours {
-    //             set.retain(|id| keys.contains(id));
-    //         }
-    //         remove_connections(&mut entity.connections.0);
-    //         remove_connections(&mut entity.connections.1);
-    //     }
-    // }
+    /// Removes all entities not matching the predicate, along with any wire or cable connections
+    /// to them. Returns the removed entities.
+    pub fn retain(&mut self, mut f: impl FnMut(&BlueprintEntity) -> bool) -> Vec<BlueprintEntity> {
+        let to_remove = self
+            .entities
+            .values()
+            .filter(|entity| !f(entity))
+            .map(|entity| entity.id)
+            .collect_vec();
+        let removed = to_remove
+            .iter()
+            .filter_map(|id| self.entities.remove(id))
+            .collect_vec();
+        for entity in &removed {
+            if entity.id < self.first_new_id {
+                let key = (entity.data.name.clone(), position_key(entity.data.position));
+                self.removed_input.insert(key, entity.id);
+            }
+        }
+        if !removed.is_empty() {
+            self.remove_invalid_connections();
+        }
+        removed
+    }
+
+    fn remove_invalid_connections(&mut self) {
+        // rust borrow checker is a bit too strict here
+        let keys = self.entities.keys().copied().collect::<HashSet<_>>();
+        let remove_connections = |pt: &mut ConnectionPoint| {
+            if let Some(set) = &mut pt.0 {
+                set.retain(|conn| keys.contains(&conn.dest.entity_id));
+            }
+            pt.clear_if_empty();
+        };
+        for entity in self.entities.values_mut() {
+            if let Some(set) = &mut entity.neighbours {
+                set.retain(|id| keys.contains(id));
+                if set.is_empty() {
+                    entity.neighbours = None;
+                }
+            }
+            remove_connections(&mut entity.connections.0);
+            remove_connections(&mut entity.connections.1);
+        }
+    }
+
+    /// Moves all entities by `offset`.
+    /// Pickup and drop positions are relative to the entity, so do not change.
+    pub fn translate(&mut self, offset: Vector2D<f64, MapSpace>) {
+        for entity in self.entities.values_mut() {
+            entity.data.position += offset;
+        }
+    }
 
     pub fn has_id(&self, id: EntityId) -> bool {
         self.entities.contains_key(&id)
@@ -350,7 +419,7 @@ impl BlueprintEntities {
                 let result = BlueprintEntity::new(
                     id,
                     BlueprintEntityData {
-                        name: entity.name.clone(),
+                        name: Name::new(&entity.name),
                         position: entity.position.to_map_position(),
                         direction: entity.direction,
                         orientation: entity.orientation,
@@ -396,6 +465,8 @@ impl BlueprintEntities {
         let mut res = Self {
             entities,
             next_entity_id: EntityId(max_id + 1),
+            first_new_id: EntityId(max_id + 1),
+            removed_input: HashMap::new(),
         };
         for bp_entity in &bp.entities {
             if bp_entity.neighbours.is_none() {
@@ -464,21 +535,81 @@ impl BlueprintEntities {
         res
     }
 
-    pub fn to_blueprint_entities(&self) -> Vec<fbp::Entity> {
-        let mut sorted_entities = self.entities.values().collect::<Vec<_>>();
-        sorted_entities.sort_by_key(|entity| entity.id);
-
-        let id_to_new = sorted_entities
+    /// Entity numbers for the output: by default, entities are numbered in id order.
+    /// With [use_stable_numbering], input entities keep their numbers; a new entity with the same name and position
+    /// as a removed input entity gets its number, and other new entities are numbered after all input entities.
+    fn entity_numbers(&self, sorted_entities: &[&BlueprintEntity]) -> HashMap<EntityId, usize> {
+        if !stable_numbering() {
+            return sorted_entities
+                .iter()
+                .enumerate()
+                .map(|(i, entity)| (entity.id, i + 1))
+                .collect();
+        }
+        let mut reused = HashSet::new();
+        let mut next_number = self.first_new_id.0 as usize;
+        sorted_entities
             .iter()
-            .enumerate()
-            .map(|(i, entity)| (entity.id, EntityNumber::new(i + 1).unwrap()))
-            .collect::<HashMap<_, _>>();
+            .map(|entity| {
+                if entity.id < self.first_new_id {
+                    return (entity.id, entity.id.0 as usize);
+                }
+                let key = (entity.data.name.clone(), position_key(entity.data.position));
+                let number = match self.removed_input.get(&key) {
+                    Some(old_id) if reused.insert(*old_id) => old_id.0 as usize,
+                    _ => {
+                        next_number += 1;
+                        next_number - 1
+                    }
+                };
+                (entity.id, number)
+            })
+            .collect()
+    }
+
+    fn sorted_entities(&self) -> Vec<&BlueprintEntity> {
+        self.entities
+            .values()
+            .sorted_by_key(|entity| entity.id)
+            .collect()
+    }
+
+    fn new_entity_numbers(
+        &self,
+        sorted_entities: &[&BlueprintEntity],
+    ) -> HashMap<EntityId, EntityNumber> {
+        self.entity_numbers(sorted_entities)
+            .into_iter()
+            .map(|(id, number)| (id, EntityNumber::new(number).unwrap()))
+            .collect()
+    }
+
+    /// Sets the blueprint's entities, and updates the locomotives of its train schedules to their new entity numbers.
+    /// Schedules whose locomotives were all removed are dropped.
+    pub fn write_to_blueprint(&self, bp: &mut Blueprint) {
+        let id_to_new = self.new_entity_numbers(&self.sorted_entities());
+        for schedule in &mut bp.schedules {
+            schedule.locomotives = schedule
+                .locomotives
+                .iter()
+                .filter_map(|number| id_to_new.get(&EntityId(number.get() as u32)))
+                .copied()
+                .collect();
+        }
+        bp.schedules
+            .retain(|schedule| !schedule.locomotives.is_empty());
+        bp.entities = self.to_blueprint_entities();
+    }
+
+    pub fn to_blueprint_entities(&self) -> Vec<fbp::Entity> {
+        let sorted_entities = self.sorted_entities();
+        let id_to_new = self.new_entity_numbers(&sorted_entities);
 
-        let new_entities = sorted_entities
+        let mut new_entities: Vec<fbp::Entity> = sorted_entities
             .iter()
             .map(|old_entity| fbp::Entity {
                 entity_number: id_to_new[&old_entity.id],
-                name: old_entity.data.name.clone(),
+                name: old_entity.data.name.to_string(),
                 position: old_entity.data.position.to_position(),
                 direction: old_entity.data.direction,
                 orientation: old_entity.data.orientation,
@@ -591,14 +722,65 @@ impl BlueprintEntities {
                 }),
             })
             .collect();
+        new_entities.sort_by_key(|entity| entity.entity_number);
 
         new_entities
     }
 }
 
+/// A blueprint's tiles (concrete, landfill, ...), in the blueprint's order, so they are written back unchanged.
+#[derive(Debug, Clone, Default)]
+pub struct BlueprintTiles {
+    tiles: Vec<(Prototype, TilePosition)>,
+}
+
+impl BlueprintTiles {
+    pub fn from_blueprint(bp: &Blueprint) -> Self {
+        BlueprintTiles {
+            tiles: bp
+                .tiles
+                .iter()
+                .map(|tile| {
+                    (
+                        tile.name.clone(),
+                        tile.position.to_map_position().tile_pos(),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    pub fn to_blueprint_tiles(&self) -> Vec<fbp::Tile> {
+        self.tiles
+            .iter()
+            .map(|(name, position)| fbp::Tile {
+                name: name.clone(),
+                position: position.corner_map_pos().to_position(),
+            })
+            .collect()
+    }
+
+    /// Adds a tile after the blueprint's own ones.
+    pub fn add(&mut self, name: &str, position: TilePosition) {
+        self.tiles.push((name.into(), position));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, TilePosition)> {
+        self.tiles
+            .iter()
+            .map(|(name, position)| (name.as_str(), *position))
+    }
+
+    pub fn translate(&mut self, offset: Vector2D<i32, TileSpace>) {
+        for (_, position) in &mut self.tiles {
+            *position += offset;
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use euclid::point2;
+    use euclid::{point2, vec2};
     use std::collections::HashSet;
 
     use super::*;
@@ -659,13 +841,140 @@ mod tests {
     }
 
     #[test]
-    fn test_add_get_entity() {
+    fn test_tiles_roundtrip() {
+        let tile = |name: &str, x: f64, y: f64| fbp::Tile {
+            name: name.to_string(),
+            position: point2(x, y).to_position(),
+        };
+        let mut bp =
+            match BlueprintCodec::decode(std::fs::File::open("test-data/bigtest.txt").unwrap())
+                .unwrap()
+            {
+                Container::Blueprint(bp) => bp,
+                _ => panic!("not a blueprint"),
+            };
+        bp.tiles = vec![
+            tile("refined-concrete", 3.0, -2.0),
+            tile("hazard-concrete-left", -1.0, 0.0),
+            tile("refined-concrete", 0.0, 0.0),
+        ];
+
+        let mut tiles = BlueprintTiles::from_blueprint(&bp);
+        assert_eq!(tiles.iter().count(), 3);
+        assert_eq!(
+            tiles.iter().nth(1),
+            Some(("hazard-concrete-left", point2(-1, 0)))
+        );
+        assert_eq!(
+            serde_json::to_value(tiles.to_blueprint_tiles()).unwrap(),
+            serde_json::to_value(&bp.tiles).unwrap()
+        );
+
+        tiles.translate(vec2(2, 1));
+        assert_eq!(
+            serde_json::to_value(&tiles.to_blueprint_tiles()[0]).unwrap(),
+            serde_json::to_value(tile("refined-concrete", 5.0, -1.0)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_schedules_renumbered() {
+        let json = serde_json::json!({"blueprint": {
+            "item": "blueprint",
+            "version": 281479275675648u64,
+            "entities": [
+                {"entity_number": 3, "name": "small-electric-pole", "position": {"x": 0.5, "y": 0.5}},
+                {"entity_number": 7, "name": "locomotive", "position": {"x": 4, "y": 3}, "orientation": 0.5},
+                {"entity_number": 9, "name": "locomotive", "position": {"x": 4, "y": 10}, "orientation": 0.5},
+            ],
+            "schedules": [
+                {"locomotives": [7], "schedule": [{"station": "A"}]},
+                {"locomotives": [9], "schedule": [{"station": "B"}]},
+            ],
+        }});
+        let Container::Blueprint(mut bp) =
+            crate::bp_io::decode(json.to_string().as_bytes()).unwrap()
+        else {
+            panic!("not a blueprint");
+        };
+        let mut entities = BlueprintEntities::from_blueprint(&bp);
+        entities.retain(|entity| entity.id() != EntityId(9));
+        entities.write_to_blueprint(&mut bp);
+
+        assert_eq!(bp.entities.len(), 2);
+        assert_eq!(bp.schedules.len(), 1);
+        let locomotive = bp.schedules[0].locomotives[0];
+        let entity = bp
+            .entities
+            .iter()
+            .find(|entity| entity.entity_number == locomotive)
+            .unwrap();
+        assert_eq!(entity.name, "locomotive");
+        assert_eq!(locomotive.get(), 2);
+    }
+
+    #[test]
+    fn test_retain_removes_connections() {
         let mut entities = BlueprintEntities::new();
-        let id = entities.add_entity(BlueprintEntityData::new(
-            "test".into(),
-            point2(0.0, 0.0),
+        let pole1 = entities.add_entity(BlueprintEntityData::new("pole", point2(0.5, 0.5), None));
+        let pole2 = entities.add_entity(BlueprintEntityData::new("pole", point2(3.5, 0.5), None));
+        let lamp = entities.add_entity(BlueprintEntityData::new("lamp", point2(1.5, 0.5), None));
+        entities.add_cable_connection(pole1, pole2);
+        let wire = |entity_id| ConnectionPointId {
+            entity_id,
+            circuit_id: false,
+        };
+        entities.add_wire_connection(wire(pole1), wire(lamp), WireColor::Red);
+
+        let removed = entities.retain(|entity| entity.name != "lamp");
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].id(), lamp);
+        assert!(!entities.has_id(lamp));
+
+        let pole1 = entities.get(pole1).unwrap();
+        assert!(!pole1.connection_pt(false).has_any());
+        assert_eq!(pole1.neighbours, Some(HashSet::from([pole2])));
+    }
+
+    #[test]
+    fn test_stable_numbering() {
+        let file = std::fs::File::open("test-data/bigtest.txt").unwrap();
+        let bp = match BlueprintCodec::decode(file).unwrap() {
+            Container::Blueprint(bp) => bp,
+            _ => panic!("not a blueprint"),
+        };
+        let mut entities = BlueprintEntities::from_blueprint(&bp);
+        let num_entities = bp.entities.len();
+        // remove the first two, then add back an identical copy of the second, and a new entity
+        let second = entities.get(EntityId(2)).unwrap().data.clone();
+        entities.retain(|entity| entity.id.0 > 2);
+        entities.add_entity(BlueprintEntityData::new(
+            "small-lamp",
+            point2(1000.5, 1000.5),
             None,
         ));
+        entities.add_entity(second);
+
+        use_stable_numbering(true);
+        let new_bp = entities.to_blueprint_entities();
+        use_stable_numbering(false);
+
+        let numbers = new_bp
+            .iter()
+            .map(|entity| entity.entity_number.get())
+            .collect_vec();
+        assert_eq!(numbers, (2..=num_entities + 1).collect_vec());
+        for (new, old) in new_bp.iter().zip(bp.entities.iter().skip(1)) {
+            assert_eq!(new.name, old.name);
+            assert_eq!(new.position, old.position);
+        }
+        assert_eq!(new_bp.last().unwrap().name, "small-lamp");
+    }
+
+    #[test]
+    fn test_add_get_entity() {
+        let mut entities = BlueprintEntities::new();
+        let id = entities.add_entity(BlueprintEntityData::new("test", point2(0.0, 0.0), None));
         assert!(entities.has_id(id));
         let entity = entities.get(id);
         assert!(entity.is_some());
@@ -0,0 +1,306 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::sync::Mutex;
+
+use clap::Args;
+use factorio_blueprint::objects::Blueprint;
+use factorio_blueprint::Container;
+use log::warn;
+
+use crate::bp_io::{self, BlueprintFormat};
+use crate::bp_stream::{self, BookWriter};
+use crate::error::OptimizerError;
+use crate::{better_bp, pipeline, position, prototype_data, OptimizePoles};
+
+/// Which blueprints in a book to process.
+/// Blueprints are numbered from 1 in book order, counting into nested books, as in `stats`.
+#[derive(Args, Debug, Clone, Default)]
+pub struct BookSelectArgs {
+    #[arg(
+        long,
+        value_name = "GLOB",
+        help = "In a book, only optimize blueprints whose label matches this pattern; '*' matches anything, '?' one character. Can be given multiple times"
+    )]
+    pub select: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "INDICES",
+        help = "In a book, only optimize blueprints with these indices, counting from 1 and into nested books. Format: '2,5-7'"
+    )]
+    pub index: Option<String>,
+}
+
+impl BookSelectArgs {
+    pub fn is_empty(&self) -> bool {
+        self.select.is_empty() && self.index.is_none()
+    }
+
+    /// Whether the blueprint with the 1-based `index` and `label` is selected.
+    /// With both `--select` and `--index`, a blueprint must match both.
+    pub fn selects(&self, index: usize, label: &str) -> Result<bool, OptimizerError> {
+        let index_ok = match &self.index {
+            Some(indices) => parse_indices(indices)?
+                .iter()
+                .any(|range| range.contains(&index)),
+            None => true,
+        };
+        let label_ok =
+            self.select.is_empty() || self.select.iter().any(|glob| glob_match(glob, label));
+        Ok(index_ok && label_ok)
+    }
+}
+
+/// Parses '2,5-7' into ranges.
+fn parse_indices(input: &str) -> Result<Vec<RangeInclusive<usize>>, OptimizerError> {
+    let parse = |s: &str| {
+        s.trim()
+            .parse::<usize>()
+            .ok()
+            .filter(|&i| i > 0)
+            .ok_or_else(|| format!("Invalid blueprint index: {:?}", s.trim()))
+    };
+    input
+        .split(',')
+        .map(|part| match part.split_once('-') {
+            Some((start, end)) => Ok(parse(start)?..=parse(end)?),
+            None => parse(part).map(|i| i..=i),
+        })
+        .collect::<Result<_, String>>()
+        .map_err(OptimizerError::from)
+}
+
+/// Matches `text` against a pattern where `*` matches any string and `?` any one character.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    // position after the last '*' in pattern, and in text where it's matched up to
+    let mut backtrack = None;
+    let (mut p, mut t) = (0, 0);
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bt)) => {
+                    backtrack = Some((bp, bt + 1));
+                    p = bp;
+                    t = bt + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Optimizes one blueprint in a book, replacing it unless the input was kept.
+fn optimize_entry(
+    index: usize,
+    bp: &mut Blueprint,
+    args: &OptimizePoles,
+) -> Result<(), OptimizerError> {
+    let state = pipeline::run_optimize_pipeline(bp.clone(), args)?;
+    // print the whole report at once, so reports from concurrent jobs don't interleave
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    writeln!(out, "Blueprint {} {:?}:", index, bp.label)?;
+    state.report.print();
+    out.flush()?;
+    if !state.report.kept_input {
+        *bp = state.blueprint;
+    }
+    Ok(())
+}
+
+/// Warns about options that do nothing for a book.
+pub fn warn_unused_options(args: &OptimizePoles) {
+    if args.output_book {
+        warn!("--output-book does nothing, as the input is already a book");
+    }
+    if args.lua_script.is_some() {
+        warn!("--lua-script does nothing for books");
+    }
+    if args.certificate {
+        warn!("--certificate does nothing for books");
+    }
+}
+
+/// Optimizes the selected blueprints in a book, leaving the others as they are.
+/// With `--jobs` above 1, blueprints are optimized concurrently, each thread loading its own prototype data.
+/// Writes nothing if `out_file` is None.
+pub fn run_optimize_book(
+    mut container: Container,
+    args: &OptimizePoles,
+    out_file: Option<&Path>,
+    format: BlueprintFormat,
+) -> Result<(), OptimizerError> {
+    let mut selected = vec![];
+    for (i, bp) in bp_io::blueprints_mut(&mut container)
+        .into_iter()
+        .enumerate()
+    {
+        if args.book_select.selects(i + 1, &bp.label)? {
+            selected.push((i + 1, bp));
+        }
+    }
+    let num_selected = selected.len();
+    if num_selected == 0 {
+        warn!("no blueprints in the book were selected");
+    }
+
+    let jobs = args.jobs.clamp(1, num_selected.max(1));
+    if jobs == 1 {
+        for (index, bp) in selected {
+            optimize_entry(index, bp, args)?;
+        }
+    } else {
+        println!("Optimizing {} blueprints with {} jobs", num_selected, jobs);
+        // spinners from several threads would garble each other
+        let args = &OptimizePoles {
+            quiet: true,
+            ..args.clone()
+        };
+        let mod_pack = prototype_data::mod_pack();
+        let stable_numbering = better_bp::stable_numbering();
+        let exact_game_math = position::exact_game_math();
+        let queue = Mutex::new(selected.into_iter());
+        let first_error = Mutex::new(None);
+        std::thread::scope(|scope| {
+            for _ in 0..jobs {
+                scope.spawn(|| {
+                    better_bp::use_stable_numbering(stable_numbering);
+                    position::use_exact_game_math(exact_game_math);
+                    let result = mod_pack.map_or(Ok(()), prototype_data::use_mod_pack);
+                    let result = result.and_then(|()| loop {
+                        let next = queue.lock().unwrap().next();
+                        let Some((index, bp)) = next else {
+                            break Ok(());
+                        };
+                        optimize_entry(index, bp, args)?;
+                    });
+                    if let Err(err) = result {
+                        // stop the other jobs after their current blueprint
+                        queue.lock().unwrap().by_ref().for_each(drop);
+                        first_error.lock().unwrap().get_or_insert(err);
+                    }
+                });
+            }
+        });
+        if let Some(err) = first_error.into_inner().unwrap() {
+            return Err(err);
+        }
+    }
+    if num_selected > 0 {
+        println!("Optimized {} blueprints", num_selected);
+    }
+    match out_file {
+        Some(out_file) => {
+            bp_io::encode(BufWriter::new(File::create(out_file)?), &container, format)
+        }
+        None => Ok(()),
+    }
+}
+
+/// Like [run_optimize_book], but for `--low-memory`: reads, optimizes and writes one entry of the book at a time,
+/// so only one is in memory at once. Blueprints are optimized one at a time, whatever `--jobs` is.
+/// If the input is not a book, does nothing and returns it.
+pub fn run_optimize_book_streaming(
+    in_file: &Path,
+    args: &OptimizePoles,
+    out_file: Option<&Path>,
+    format: BlueprintFormat,
+) -> Result<Option<Container>, OptimizerError> {
+    // created with the first entry, so nothing is written if the input is not a book
+    let mut writer = None;
+    let mut num_blueprints = 0;
+    let mut num_selected = 0;
+    let reader = BufReader::new(File::open(in_file)?);
+    let container = bp_stream::decode_book_streaming(reader, &mut |mut entry| {
+        for bp in bp_io::blueprints_mut(&mut entry.item) {
+            num_blueprints += 1;
+            if args.book_select.selects(num_blueprints, &bp.label)? {
+                num_selected += 1;
+                optimize_entry(num_blueprints, bp, args)?;
+            }
+        }
+        if let Some(out_file) = out_file {
+            let book_writer = match &mut writer {
+                Some(book_writer) => book_writer,
+                None => writer.insert(BookWriter::new(
+                    BufWriter::new(File::create(out_file)?),
+                    format,
+                )?),
+            };
+            book_writer.write_entry(&entry)?;
+        }
+        Ok(())
+    })?;
+    if !matches!(container, Container::BlueprintBook(_)) {
+        return Ok(Some(container));
+    }
+    warn_unused_options(args);
+    if args.jobs > 1 {
+        warn!("--jobs does nothing with --low-memory; blueprints are optimized one at a time");
+    }
+    if args.verify_roundtrip {
+        warn!("--verify-roundtrip does nothing for books with --low-memory");
+    }
+    if num_selected == 0 {
+        warn!("no blueprints in the book were selected");
+    } else {
+        println!("Optimized {} blueprints", num_selected);
+    }
+    if let Some(out_file) = out_file {
+        let book_writer = match writer {
+            Some(book_writer) => book_writer,
+            None => BookWriter::new(BufWriter::new(File::create(out_file)?), format)?,
+        };
+        book_writer.finish(&container)?;
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_indices() {
+        assert_eq!(parse_indices("2,5-7").unwrap(), vec![2..=2, 5..=7]);
+        assert_eq!(parse_indices(" 3 ").unwrap(), vec![3..=3]);
+        assert!(parse_indices("0").is_err());
+        assert!(parse_indices("a-2").is_err());
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("smelting*", "smelting array"));
+        assert!(glob_match("*array", "smelting array"));
+        assert!(glob_match("s?elt*ar*y", "smelting array"));
+        assert!(glob_match("*a*a*", "banana"));
+        assert!(!glob_match("smelting", "smelting array"));
+        assert!(!glob_match("?", ""));
+    }
+
+    #[test]
+    fn test_selects() {
+        let args = BookSelectArgs {
+            select: vec!["mall*".to_string()],
+            index: Some("1-3".to_string()),
+        };
+        assert!(args.selects(2, "mall 2").unwrap());
+        assert!(!args.selects(4, "mall 4").unwrap());
+        assert!(!args.selects(1, "smelting").unwrap());
+        assert!(BookSelectArgs::default().selects(10, "").unwrap());
+    }
+}
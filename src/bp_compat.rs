@@ -0,0 +1,330 @@
+//! Normalizes blueprint JSON from older (0.17 to 1.1) exports and third-party tools before deserializing,
+//! and describes constructs that can't be read.
+
+use serde_json::{json, Map, Value};
+
+/// Oldest and newest major.minor versions known to be readable.
+const MIN_VERSION: (u64, u64) = (0, 17);
+const MAX_VERSION: (u64, u64) = (1, 1);
+
+/// Splits a blueprint `version` number into (major, minor, patch, developer).
+pub fn parse_version(version: u64) -> (u64, u64, u64, u64) {
+    (
+        version >> 48,
+        (version >> 32) & 0xffff,
+        (version >> 16) & 0xffff,
+        version & 0xffff,
+    )
+}
+
+/// Wraps a bare blueprint object as `{"blueprint": ...}`.
+pub fn wrap_container(value: Value) -> Value {
+    let is_container = value
+        .as_object()
+        .is_some_and(|obj| obj.contains_key("blueprint") || obj.contains_key("blueprint_book"));
+    if is_container {
+        value
+    } else {
+        json!({ "blueprint": value })
+    }
+}
+
+/// Fixes known variations in place, in a container object. Returns a description of each fix applied.
+pub fn normalize(container: &mut Value) -> Vec<String> {
+    let mut fixes = vec![];
+    if let Some(bp) = container.get_mut("blueprint") {
+        normalize_blueprint(bp, &mut fixes);
+    }
+    if let Some(book) = container.get_mut("blueprint_book") {
+        normalize_book(book, &mut fixes);
+    }
+    fixes
+}
+
+fn normalize_book(book: &mut Value, fixes: &mut Vec<String>) {
+    let Some(items) = book.get_mut("blueprints").and_then(Value::as_array_mut) else {
+        return;
+    };
+    let before = items.len();
+    items.retain(|item| item.get("blueprint").is_some() || item.get("blueprint_book").is_some());
+    if items.len() < before {
+        fixes.push(format!(
+            "removed {} upgrade/deconstruction planners from book",
+            before - items.len()
+        ));
+    }
+    for item in items {
+        fixes.extend(normalize(item));
+    }
+}
+
+fn normalize_blueprint(bp: &mut Value, fixes: &mut Vec<String>) {
+    let Some(bp) = bp.as_object_mut() else {
+        return;
+    };
+    if !bp.contains_key("icons") {
+        bp.insert("icons".into(), json!([]));
+        fixes.push("added missing icons".into());
+    }
+    if !bp.contains_key("version") {
+        bp.insert("version".into(), json!(0));
+        fixes.push("added missing version".into());
+    }
+    let Some(entities) = bp.get_mut("entities").and_then(Value::as_array_mut) else {
+        return;
+    };
+    if entities
+        .iter()
+        .all(|entity| entity.get("entity_number").is_none())
+        && !entities.is_empty()
+    {
+        for (i, entity) in entities.iter_mut().enumerate() {
+            if let Some(entity) = entity.as_object_mut() {
+                entity.insert("entity_number".into(), json!(i + 1));
+            }
+        }
+        fixes.push("numbered entities without entity_number".into());
+    }
+    let num_connections: usize = entities.iter_mut().map(normalize_entity).sum();
+    if num_connections > 0 {
+        fixes.push(format!(
+            "converted {} circuit connection points from arrays to objects",
+            num_connections
+        ));
+    }
+}
+
+/// Fixes known variations in place in one entity, apart from a missing entity_number.
+/// Returns the number of circuit connection points converted from arrays.
+pub fn normalize_entity(entity: &mut Value) -> usize {
+    entity.as_object_mut().map_or(0, normalize_connections)
+}
+
+/// Some tools write circuit connection points as an array instead of `{"1": ..., "2": ...}`,
+/// or entity ids as strings. Returns the number of connection points converted from arrays.
+fn normalize_connections(entity: &mut Map<String, Value>) -> usize {
+    let Some(connections) = entity.get_mut("connections") else {
+        return 0;
+    };
+    let mut count = 0;
+    if let Some(points) = connections.as_array() {
+        let map = points
+            .iter()
+            .enumerate()
+            .filter(|(_, point)| !point.is_null())
+            .map(|(i, point)| ((i + 1).to_string(), point.clone()))
+            .collect::<Map<_, _>>();
+        count += map.len();
+        *connections = Value::Object(map);
+    }
+    for point in connections
+        .as_object_mut()
+        .into_iter()
+        .flat_map(|map| map.values_mut())
+    {
+        for color in ["red", "green"] {
+            let Some(wires) = point.get_mut(color).and_then(Value::as_array_mut) else {
+                continue;
+            };
+            for wire in wires.iter_mut().filter_map(Value::as_object_mut) {
+                let id = wire.get("entity_id").and_then(Value::as_str);
+                if let Some(id) = id.and_then(|id| id.parse::<u64>().ok()) {
+                    wire.insert("entity_id".into(), json!(id));
+                }
+            }
+        }
+    }
+    count
+}
+
+/// Constructs in a container that can't be read. Empty if nothing known is wrong.
+pub fn unsupported_constructs(container: &Value) -> Vec<String> {
+    let mut found = vec![];
+    if let Some(bp) = container.get("blueprint") {
+        blueprint_unsupported(bp, &mut found);
+    }
+    if let Some(items) = container
+        .get("blueprint_book")
+        .and_then(|book| book.get("blueprints"))
+        .and_then(Value::as_array)
+    {
+        for item in items {
+            found.extend(unsupported_constructs(item));
+        }
+    }
+    if container.get("blueprint").is_none() && container.get("blueprint_book").is_none() {
+        let keys = container
+            .as_object()
+            .map(|obj| obj.keys().cloned().collect::<Vec<_>>().join(", "))
+            .unwrap_or_default();
+        found.push(format!(
+            "not a blueprint or blueprint book (has keys: {})",
+            keys
+        ));
+    }
+    found.sort();
+    found.dedup();
+    found
+}
+
+fn blueprint_unsupported(bp: &Value, found: &mut Vec<String>) {
+    if let Some(version) = bp.get("version").and_then(Value::as_u64) {
+        let (major, minor, patch, _) = parse_version(version);
+        if version != 0 && !(MIN_VERSION..=MAX_VERSION).contains(&(major, minor)) {
+            found.push(format!(
+                "blueprint from Factorio {}.{}.{}; only 0.17 to 1.1 are supported",
+                major, minor, patch
+            ));
+        }
+    }
+    if bp.get("wires").is_some() {
+        found.push("\"wires\" array (Factorio 2.0 wire format)".into());
+    }
+    for entity in bp
+        .get("entities")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        entity_unsupported(entity, found);
+    }
+}
+
+/// Adds the constructs in one entity that can't be read to `found`.
+pub fn entity_unsupported(entity: &Value, found: &mut Vec<String>) {
+    let name = entity
+        .get("name")
+        .and_then(Value::as_str)
+        .unwrap_or("<unnamed>");
+    let position = entity.get("position");
+    if !position.is_some_and(|pos| pos.get("x").is_some() && pos.get("y").is_some()) {
+        found.push(format!("{} without an x/y position", name));
+    }
+    for (key, point) in entity
+        .get("connections")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flatten()
+    {
+        if (key == "1" || key == "2") && !point.is_object() {
+            found.push(format!(
+                "{} with circuit connection point {} that is not an object",
+                name, key
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bp(value: Value) -> Value {
+        json!({ "blueprint": value })
+    }
+
+    #[test]
+    fn test_parse_version() {
+        // 1.1.107.0
+        assert_eq!(parse_version(281479278690304), (1, 1, 107, 0));
+    }
+
+    #[test]
+    fn test_wrap_bare_blueprint() {
+        let bare = json!({"entities": [], "version": 0});
+        assert_eq!(wrap_container(bare.clone()), bp(bare));
+        let book = json!({"blueprint_book": {"blueprints": []}});
+        assert_eq!(wrap_container(book.clone()), book);
+    }
+
+    #[test]
+    fn test_missing_icons_and_version() {
+        let mut container = bp(json!({"entities": []}));
+        let fixes = normalize(&mut container);
+        assert_eq!(fixes.len(), 2);
+        assert_eq!(container["blueprint"]["icons"], json!([]));
+        assert_eq!(container["blueprint"]["version"], json!(0));
+        assert!(normalize(&mut container).is_empty());
+    }
+
+    #[test]
+    fn test_missing_entity_numbers() {
+        let mut container = bp(json!({
+            "icons": [], "version": 0,
+            "entities": [
+                {"name": "small-electric-pole", "position": {"x": 0.5, "y": 0.5}},
+                {"name": "small-electric-pole", "position": {"x": 3.5, "y": 0.5}},
+            ]
+        }));
+        normalize(&mut container);
+        assert_eq!(container["blueprint"]["entities"][1]["entity_number"], 2);
+    }
+
+    #[test]
+    fn test_connection_formats() {
+        let mut container = bp(json!({
+            "icons": [], "version": 0,
+            "entities": [{
+                "entity_number": 1,
+                "name": "small-lamp",
+                "position": {"x": 0.5, "y": 0.5},
+                "connections": [{"red": [{"entity_id": "2"}]}, null]
+            }]
+        }));
+        let fixes = normalize(&mut container);
+        assert_eq!(fixes.len(), 1);
+        assert_eq!(
+            container["blueprint"]["entities"][0]["connections"],
+            json!({"1": {"red": [{"entity_id": 2}]}})
+        );
+        assert!(unsupported_constructs(&container).is_empty());
+    }
+
+    #[test]
+    fn test_book_with_planners() {
+        let mut container = json!({"blueprint_book": {"blueprints": [
+            {"index": 0, "blueprint": {"icons": [], "version": 0, "entities": []}},
+            {"index": 1, "upgrade_planner": {}},
+            {"index": 2, "deconstruction_planner": {}},
+        ]}});
+        let fixes = normalize(&mut container);
+        assert_eq!(
+            fixes,
+            ["removed 2 upgrade/deconstruction planners from book"]
+        );
+        assert_eq!(
+            container["blueprint_book"]["blueprints"]
+                .as_array()
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_unsupported_constructs() {
+        let container = bp(json!({
+            // 2.0.7.0
+            "version": 562949953880064u64,
+            "wires": [[1, 1, 2, 1]],
+            "entities": [
+                {"entity_number": 1, "name": "small-lamp"},
+                {"entity_number": 2, "name": "small-lamp", "position": {"x": 0.5, "y": 0.5},
+                 "connections": {"1": [1, 2]}},
+            ]
+        }));
+        assert_eq!(
+            unsupported_constructs(&container),
+            [
+                "\"wires\" array (Factorio 2.0 wire format)",
+                "blueprint from Factorio 2.0.7; only 0.17 to 1.1 are supported",
+                "small-lamp with circuit connection point 1 that is not an object",
+                "small-lamp without an x/y position",
+            ]
+        );
+        assert_eq!(
+            unsupported_constructs(&json!({"upgrade_planner": {}})),
+            ["not a blueprint or blueprint book (has keys: upgrade_planner)"]
+        );
+    }
+}
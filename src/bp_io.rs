@@ -0,0 +1,268 @@
+use std::io::{Read, Write};
+
+use base64::prelude::*;
+use clap::ValueEnum;
+use factorio_blueprint::objects::Blueprint;
+use factorio_blueprint::{BlueprintCodec, Container};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::bp_compat;
+use crate::bp_stream;
+use crate::error::OptimizerError;
+
+/// How a blueprint is stored in a file.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BlueprintFormat {
+    /// Blueprint string, as copied from the game (base64-encoded zlib-compressed JSON)
+    #[default]
+    String,
+    /// Plain JSON, like `{"blueprint": {...}}`
+    Json,
+}
+
+impl BlueprintFormat {
+    /// Guesses the format from the content: JSON starts with `{`, blueprint strings with a version digit.
+    pub fn detect(content: &[u8]) -> Self {
+        match content.iter().find(|c| !c.is_ascii_whitespace()) {
+            Some(b'{') => BlueprintFormat::Json,
+            _ => BlueprintFormat::String,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            BlueprintFormat::String => "txt",
+            BlueprintFormat::Json => "json",
+        }
+    }
+}
+
+/// The JSON in a blueprint string: a version byte `0`, then base64-encoded zlib-compressed JSON.
+fn decode_string(content: &[u8]) -> Result<Value, OptimizerError> {
+    let text = std::str::from_utf8(content)
+        .map_err(|err| OptimizerError::Decode(err.to_string()))?
+        .trim();
+    let Some(data) = text.strip_prefix('0') else {
+        return Err(OptimizerError::Decode(format!(
+            "Unknown blueprint string version {:?}; expected '0'",
+            text.chars().next()
+        )));
+    };
+    let compressed = BASE64_STANDARD
+        .decode(data)
+        .map_err(|err| OptimizerError::Decode(err.to_string()))?;
+    serde_json::from_reader(ZlibDecoder::new(compressed.as_slice()))
+        .map_err(|err| OptimizerError::Decode(err.to_string()))
+}
+
+/// Decodes a blueprint string or blueprint JSON, detected by content.
+/// Accepts both a wrapped container (`{"blueprint": ...}`) and a bare blueprint object,
+/// and fixes known variations from older versions and other tools (see [bp_compat]).
+/// With `--low-memory`, uses [bp_stream::decode_streaming].
+pub fn decode(mut reader: impl Read) -> Result<Container, OptimizerError> {
+    if bp_stream::low_memory() {
+        return bp_stream::decode_streaming(reader);
+    }
+    let mut content = vec![];
+    reader.read_to_end(&mut content)?;
+    let value = match BlueprintFormat::detect(&content) {
+        BlueprintFormat::Json => serde_json::from_slice(&content)
+            .map_err(|err| OptimizerError::Decode(err.to_string()))?,
+        BlueprintFormat::String => decode_string(&content)?,
+    };
+    let mut value = bp_compat::wrap_container(value);
+    let fixes = bp_compat::normalize(&mut value);
+    let unsupported = bp_compat::unsupported_constructs(&value);
+    let result = Container::deserialize(&value).map_err(|err| err.to_string());
+    report_findings(&fixes, &unsupported, result)
+}
+
+/// Prints the fixes applied while decoding, and warns about or adds to the error the unsupported constructs found.
+pub fn report_findings(
+    fixes: &[String],
+    unsupported: &[String],
+    result: Result<Container, String>,
+) -> Result<Container, OptimizerError> {
+    if !fixes.is_empty() {
+        println!("Fixed older blueprint format: {}", fixes.join("; "));
+    }
+    match result {
+        Ok(container) => {
+            if !unsupported.is_empty() {
+                println!(
+                    "Warning: may not be read correctly: {}",
+                    unsupported.join("; ")
+                );
+            }
+            Ok(container)
+        }
+        Err(err) if unsupported.is_empty() => Err(OptimizerError::Decode(err)),
+        Err(err) => Err(OptimizerError::Decode(format!(
+            "{}\nUnsupported: {}",
+            err,
+            unsupported.join("; ")
+        ))),
+    }
+}
+
+pub fn encode(
+    writer: impl Write,
+    container: &Container,
+    format: BlueprintFormat,
+) -> Result<(), OptimizerError> {
+    match format {
+        BlueprintFormat::String => BlueprintCodec::encode(writer, container)
+            .map_err(|err| OptimizerError::Other(err.to_string()))?,
+        BlueprintFormat::Json => serde_json::to_writer_pretty(writer, container)?,
+    }
+    Ok(())
+}
+
+/// Encodes blueprint JSON that may have fields [Container] doesn't, e.g. a description.
+pub fn encode_value(
+    mut writer: impl Write,
+    value: &Value,
+    format: BlueprintFormat,
+) -> Result<(), OptimizerError> {
+    match format {
+        BlueprintFormat::String => {
+            let mut encoder = ZlibEncoder::new(vec![], Compression::best());
+            serde_json::to_writer(&mut encoder, value)?;
+            let compressed = encoder.finish()?;
+            write!(writer, "0{}", BASE64_STANDARD.encode(compressed))?;
+        }
+        BlueprintFormat::Json => serde_json::to_writer_pretty(writer, value)?,
+    }
+    Ok(())
+}
+
+/// A book of `blueprints`, in order, with the first one active.
+/// Its version is the first blueprint's.
+pub fn make_book(label: &str, blueprints: &[Blueprint]) -> Result<Container, OptimizerError> {
+    let entries = blueprints
+        .iter()
+        .enumerate()
+        .map(|(i, bp)| Ok(json!({ "index": i, "blueprint": serde_json::to_value(bp)? })))
+        .collect::<Result<Vec<_>, OptimizerError>>()?;
+    let version = entries
+        .first()
+        .map(|entry| entry["blueprint"]["version"].clone())
+        .ok_or("A book needs at least one blueprint")?;
+    let value = json!({
+        "blueprint_book": {
+            "item": "blueprint-book",
+            "label": label,
+            "active_index": 0,
+            "version": version,
+            "blueprints": entries,
+        }
+    });
+    Container::deserialize(&value).map_err(|err| OptimizerError::Other(err.to_string()))
+}
+
+/// All blueprints in the container, including ones nested in books.
+pub fn blueprints_mut(container: &mut Container) -> Vec<&mut Blueprint> {
+    match container {
+        Container::Blueprint(bp) => vec![bp],
+        Container::BlueprintBook(book) => book
+            .blueprints
+            .iter_mut()
+            .flat_map(|value| blueprints_mut(&mut value.item))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(
+            BlueprintFormat::detect(b"  {\"a\": 1}"),
+            BlueprintFormat::Json
+        );
+        assert_eq!(BlueprintFormat::detect(b"0eNq..."), BlueprintFormat::String);
+    }
+
+    #[test]
+    fn test_json_roundtrip() {
+        let container = decode(File::open("test-data/bigtest.txt").unwrap()).unwrap();
+        let Container::Blueprint(bp) = &container else {
+            panic!("not a blueprint");
+        };
+
+        let mut json = vec![];
+        encode(&mut json, &container, BlueprintFormat::Json).unwrap();
+        let Container::Blueprint(decoded) = decode(json.as_slice()).unwrap() else {
+            panic!("not a blueprint");
+        };
+        assert_eq!(decoded.entities.len(), bp.entities.len());
+
+        let bare_json = serde_json::to_vec(bp).unwrap();
+        let Container::Blueprint(decoded) = decode(bare_json.as_slice()).unwrap() else {
+            panic!("not a blueprint");
+        };
+        assert_eq!(decoded.entities.len(), bp.entities.len());
+    }
+
+    #[test]
+    fn test_encode_value() {
+        let value = json!({"blueprint": {"item": "blueprint", "description": "kept"}});
+        let mut encoded = vec![];
+        encode_value(&mut encoded, &value, BlueprintFormat::String).unwrap();
+        assert_eq!(decode_string(&encoded).unwrap(), value);
+    }
+
+    #[test]
+    fn test_make_book() {
+        let Container::Blueprint(bp) =
+            decode(File::open("test-data/bigtest.txt").unwrap()).unwrap()
+        else {
+            panic!("not a blueprint");
+        };
+        let mut book = make_book("both", &[bp.clone(), bp.clone()]).unwrap();
+        assert_eq!(blueprints_mut(&mut book).len(), 2);
+
+        let mut encoded = vec![];
+        encode(&mut encoded, &book, BlueprintFormat::String).unwrap();
+        let mut decoded = decode(encoded.as_slice()).unwrap();
+        let blueprints = blueprints_mut(&mut decoded);
+        assert_eq!(blueprints.len(), 2);
+        assert_eq!(blueprints[1].entities.len(), bp.entities.len());
+    }
+
+    #[test]
+    fn test_decode_string_matches_codec() {
+        let content = std::fs::read("test-data/bigtest.txt").unwrap();
+        let value = decode_string(&content).unwrap();
+        let Container::Blueprint(bp) =
+            BlueprintCodec::decode(std::io::Cursor::new(&content)).unwrap()
+        else {
+            panic!("not a blueprint");
+        };
+        assert_eq!(
+            value["blueprint"]["entities"].as_array().unwrap().len(),
+            bp.entities.len()
+        );
+    }
+
+    #[test]
+    fn test_decode_error_lists_unsupported() {
+        let json = br#"{"blueprint": {"icons": [], "version": 0, "item": "blueprint",
+            "entities": [{"entity_number": 1, "name": "small-lamp"}]}}"#;
+        let err = decode(json.as_slice()).err().unwrap().to_string();
+        assert!(err.starts_with("Could not read blueprint: "), "{}", err);
+        assert!(
+            err.contains("small-lamp without an x/y position"),
+            "{}",
+            err
+        );
+    }
+}
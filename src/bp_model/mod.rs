@@ -1,16 +1,19 @@
 use crate::better_bp::{BlueprintEntities, BlueprintEntityData, EntityId};
 use crate::position::{
-    BoundingBox, BoundingBoxExt, CardinalDirection, IterTiles, MapPosition, Rotate,
-    TileBoundingBox, TilePosition,
+    within_distance, BoundingBox, BoundingBoxExt, CardinalDirection, IterTiles, MapPosition,
+    Rotate, TileBoundingBox, TilePosition,
 };
-use crate::prototype_data::{EntityPrototypeDict, EntityPrototypeRef, PoleData};
-use euclid::vec2;
+use crate::prototype_data::{prototype_by_name, EntityPrototypeDict, EntityPrototypeRef, PoleData};
+use euclid::{point2, vec2};
 use hashbrown::{HashMap, HashSet};
 use itertools::Itertools;
+use petgraph::unionfind::UnionFind;
+use serde::{Deserialize, Serialize};
 use std::ops::Deref;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct WorldEntity {
+    #[serde(with = "prototype_by_name")]
     pub prototype: EntityPrototypeRef,
     pub position: MapPosition,
     pub direction: u8,
@@ -21,6 +24,9 @@ impl WorldEntity {
      * Returns bbox, from the entity's perspective: (0,0) is the center of the entity.
      */
     pub fn local_bbox(&self) -> BoundingBox {
+        if let Some(boxes) = &self.prototype.direction_boxes {
+            return boxes[(self.direction % 8) as usize];
+        }
         let bbox = self.prototype.collision_box;
         bbox.rotate(CardinalDirection::from_u8_rounding(self.direction))
     }
@@ -34,20 +40,101 @@ impl WorldEntity {
     }
 }
 
+/// Name of the tile that makes water buildable.
+pub const LANDFILL: &str = "landfill";
+
+/// An end of a rail piece, where another rail can join it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RailEnd {
+    /// In half tiles, so that it is exact.
+    pub half_tile_position: (i32, i32),
+    /// Direction a train leaving the rail here travels in, 0 to 7 as in blueprints.
+    pub direction: u8,
+}
+
+impl RailEnd {
+    fn new(center: MapPosition, offset: MapPosition, direction: u8) -> Self {
+        let position = center + offset.to_vector();
+        RailEnd {
+            half_tile_position: (
+                (position.x * 2.0).round() as i32,
+                (position.y * 2.0).round() as i32,
+            ),
+            direction: direction % 8,
+        }
+    }
+
+    pub fn position(&self) -> MapPosition {
+        point2(
+            self.half_tile_position.0 as f64 / 2.0,
+            self.half_tile_position.1 as f64 / 2.0,
+        )
+    }
+
+    /// If `other` continues the track from this end.
+    pub fn joins(&self, other: &RailEnd) -> bool {
+        self.half_tile_position == other.half_tile_position
+            && other.direction == (self.direction + 4) % 8
+    }
+}
+
+impl WorldEntity {
+    /// Both ends of a straight or curved rail, following Factorio 1.1 rail geometry.
+    pub fn rail_ends(&self) -> Option<[RailEnd; 2]> {
+        let direction = self.direction % 8;
+        let end = |x: f64, y: f64, end_direction: u8| {
+            RailEnd::new(self.position, point2(x, y), end_direction)
+        };
+        match self.prototype.type_.as_str() {
+            "straight-rail" => Some(match direction {
+                0 | 4 => [end(0.0, -1.0, 0), end(0.0, 1.0, 4)],
+                2 | 6 => [end(-1.0, 0.0, 6), end(1.0, 0.0, 2)],
+                // diagonal pieces cut across one corner of the 2x2 cell
+                1 => [end(0.0, -1.0, 7), end(1.0, 0.0, 3)],
+                3 => [end(1.0, 0.0, 1), end(0.0, 1.0, 5)],
+                5 => [end(0.0, 1.0, 3), end(-1.0, 0.0, 7)],
+                _ => [end(-1.0, 0.0, 5), end(0.0, -1.0, 1)],
+            }),
+            "curved-rail" => {
+                // directions 0 and 1 are mirror images; the others are rotations of them
+                let (straight, diagonal, diagonal_direction) = if direction.is_multiple_of(2) {
+                    (point2(1.0, 4.0), point2(-2.0, -3.0), 7)
+                } else {
+                    (point2(-1.0, 4.0), point2(2.0, -3.0), 1)
+                };
+                let rotation = CardinalDirection::from_u8_rounding(direction & !1);
+                Some([
+                    RailEnd::new(
+                        self.position,
+                        straight.rotate(rotation),
+                        4 + (direction & !1),
+                    ),
+                    RailEnd::new(
+                        self.position,
+                        diagonal.rotate(rotation),
+                        diagonal_direction + (direction & !1),
+                    ),
+                ])
+            }
+            _ => None,
+        }
+    }
+}
+
 impl WorldEntity {
     fn from_bp_entity(
         prototype_dict: &EntityPrototypeDict,
         bp_entity: &BlueprintEntityData,
     ) -> Self {
         WorldEntity {
-            prototype: prototype_dict[&bp_entity.name].clone(),
+            prototype: prototype_dict[bp_entity.name.as_str()].clone(),
             position: bp_entity.position,
             direction: bp_entity.direction.unwrap_or(0),
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModelEntity {
     pub entity: WorldEntity,
     id: EntityId,
@@ -67,13 +154,13 @@ impl ModelEntity {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum EntityExtraData {
     Pole(PoleConnections),
     None,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PoleConnections {
     pub connections: HashSet<EntityId>,
 }
@@ -115,11 +202,81 @@ impl ModelEntity {
     }
 }
 
-#[derive(Clone, Debug)]
+/// Serialized as a list of entities; the tile index is rebuilt when deserializing.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(into = "ModelData", from = "ModelData")]
 pub struct BpModel {
     by_tile: HashMap<TilePosition, Vec<EntityId>>,
     all_entities: HashMap<EntityId, ModelEntity>,
+    /// Ids of the entities of each prototype, for [Self::entities_of_type] and [Self::poles].
+    by_prototype: HashMap<EntityPrototypeRef, HashSet<EntityId>>,
     next_id: EntityId,
+    /// Empty tiles new entities can't be placed on (`--avoid-tiles`).
+    reserved_tiles: HashSet<TilePosition>,
+    /// Names of the blueprint's tiles (concrete, landfill, ...) by position.
+    floor_tiles: HashMap<TilePosition, String>,
+    /// Water tiles without landfill (`--water-mask`). New entities can only be placed on them if [Self::allow_landfill].
+    water_tiles: HashSet<TilePosition>,
+    /// If new entities can be placed on [Self::water_tiles], by adding landfill under them.
+    allow_landfill: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ModelData {
+    entities: Vec<ModelEntity>,
+    next_id: EntityId,
+    #[serde(default)]
+    reserved_tiles: Vec<TilePosition>,
+    #[serde(default)]
+    floor_tiles: Vec<(TilePosition, String)>,
+    #[serde(default)]
+    water_tiles: Vec<TilePosition>,
+    #[serde(default)]
+    allow_landfill: bool,
+}
+
+impl From<BpModel> for ModelData {
+    fn from(model: BpModel) -> Self {
+        ModelData {
+            entities: model
+                .all_entities
+                .into_values()
+                .sorted_by_key(|entity| entity.id)
+                .collect(),
+            next_id: model.next_id,
+            reserved_tiles: model
+                .reserved_tiles
+                .into_iter()
+                .sorted_by_key(|tile| (tile.y, tile.x))
+                .collect(),
+            floor_tiles: model
+                .floor_tiles
+                .into_iter()
+                .sorted_by_key(|(tile, _)| (tile.y, tile.x))
+                .collect(),
+            water_tiles: model
+                .water_tiles
+                .into_iter()
+                .sorted_by_key(|tile| (tile.y, tile.x))
+                .collect(),
+            allow_landfill: model.allow_landfill,
+        }
+    }
+}
+
+impl From<ModelData> for BpModel {
+    fn from(data: ModelData) -> Self {
+        let mut model = BpModel::new();
+        for entity in data.entities {
+            model.add_internal(entity);
+        }
+        model.next_id = data.next_id;
+        model.reserve_tiles(data.reserved_tiles);
+        model.floor_tiles.extend(data.floor_tiles);
+        model.water_tiles.extend(data.water_tiles);
+        model.allow_landfill = data.allow_landfill;
+        model
+    }
 }
 
 impl BpModel {
@@ -127,7 +284,12 @@ impl BpModel {
         BpModel {
             by_tile: HashMap::new(),
             all_entities: HashMap::new(),
+            by_prototype: HashMap::new(),
             next_id: EntityId(1),
+            reserved_tiles: HashSet::new(),
+            floor_tiles: HashMap::new(),
+            water_tiles: HashSet::new(),
+            allow_landfill: false,
         }
     }
     pub fn from_bp_entities(
@@ -154,11 +316,30 @@ impl BpModel {
         res
     }
 
+    /// Adds all entities from another blueprint, with new ids. Existing entities may be overlapped.
+    pub fn add_bp_entities(
+        &mut self,
+        bp: &BlueprintEntities,
+        prototype_dict: &EntityPrototypeDict,
+    ) -> Vec<EntityId> {
+        bp.entities
+            .values()
+            .sorted_by_key(|entity| entity.id())
+            .map(|entity| {
+                self.add_overlap(WorldEntity::from_bp_entity(prototype_dict, &entity.data))
+            })
+            .collect()
+    }
+
     fn add_internal(&mut self, entity: ModelEntity) {
         let id = entity.id;
         for tile in entity.world_bbox().iter_tiles() {
             self.by_tile.entry(tile).or_default().push(id);
         }
+        self.by_prototype
+            .entry(entity.prototype.clone())
+            .or_default()
+            .insert(id);
         if let Some(x) = self.all_entities.insert(id, entity) {
             panic!("Entity with id {:?} already exists: {:?}", id, x);
         }
@@ -171,11 +352,79 @@ impl BpModel {
         id
     }
 
+    /// If the entity overlaps no other entity, no reserved tile, and no water unless landfill is allowed.
     pub fn can_place(&self, entity: &WorldEntity) -> bool {
+        entity.world_bbox().iter_tiles().all(|tile| {
+            !self.occupied(tile)
+                && !self.reserved_tiles.contains(&tile)
+                && (self.allow_landfill || !self.water_tiles.contains(&tile))
+        })
+    }
+
+    /// Keeps new entities placed with [Self::can_place] off these tiles.
+    pub fn reserve_tiles(&mut self, tiles: impl IntoIterator<Item = TilePosition>) {
+        self.reserved_tiles.extend(tiles);
+    }
+
+    /// Adds the blueprint's floor tiles; a later tile at the same position replaces an earlier one.
+    pub fn add_floor_tiles<'a>(
+        &mut self,
+        tiles: impl IntoIterator<Item = (&'a str, TilePosition)>,
+    ) {
+        self.floor_tiles.extend(
+            tiles
+                .into_iter()
+                .map(|(name, position)| (position, name.to_string())),
+        );
+    }
+
+    /// Name of the floor tile at `tile`, if any.
+    #[allow(dead_code)]
+    pub fn floor_tile(&self, tile: TilePosition) -> Option<&str> {
+        self.floor_tiles.get(&tile).map(String::as_str)
+    }
+
+    /// Marks `tiles` as water, except those the blueprint already has landfill on.
+    /// If `allow_landfill`, new entities can still be placed on them; see [Self::landfill_needed].
+    /// Returns the number of water tiles without landfill.
+    pub fn set_water_tiles(
+        &mut self,
+        tiles: impl IntoIterator<Item = TilePosition>,
+        allow_landfill: bool,
+    ) -> usize {
+        let floor_tiles = &self.floor_tiles;
+        self.water_tiles = tiles
+            .into_iter()
+            .filter(|tile| floor_tiles.get(tile).map(String::as_str) != Some(LANDFILL))
+            .collect();
+        self.allow_landfill = allow_landfill;
+        self.water_tiles.len()
+    }
+
+    pub fn water_tiles(&self) -> &HashSet<TilePosition> {
+        &self.water_tiles
+    }
+
+    /// Water tiles under `entity` that need landfill before it can be built.
+    pub fn landfill_needed<'a>(
+        &'a self,
+        entity: &WorldEntity,
+    ) -> impl Iterator<Item = TilePosition> + 'a {
         entity
             .world_bbox()
             .iter_tiles()
-            .all(|tile| !self.occupied(tile))
+            .filter(|tile| self.water_tiles.contains(tile))
+    }
+
+    /// Positions of floor tiles with one of `names`.
+    pub fn floor_tiles_named<'a>(
+        &'a self,
+        names: &'a [String],
+    ) -> impl Iterator<Item = TilePosition> + 'a {
+        self.floor_tiles
+            .iter()
+            .filter(|(_, name)| names.contains(name))
+            .map(|(position, _)| *position)
     }
 
     pub fn add_no_overlap(&mut self, entity: WorldEntity) -> Option<EntityId> {
@@ -209,6 +458,17 @@ impl BpModel {
 
     pub fn remove(&mut self, id: &EntityId) {
         let entity = self.all_entities.remove(id).unwrap();
+        if let Some(pole) = entity.pole_connections() {
+            for other_id in &pole.connections {
+                if let Some(other) = self
+                    .all_entities
+                    .get_mut(other_id)
+                    .and_then(|other| other.pole_connections_mut())
+                {
+                    other.connections.remove(id);
+                }
+            }
+        }
         for tile in entity.world_bbox().iter_tiles() {
             let entities = self.by_tile.get_mut(&tile).unwrap();
             entities.retain(|x| x != id);
@@ -216,6 +476,11 @@ impl BpModel {
                 self.by_tile.remove(&tile);
             }
         }
+        let of_type = self.by_prototype.get_mut(&entity.prototype).unwrap();
+        of_type.remove(id);
+        if of_type.is_empty() {
+            self.by_prototype.remove(&entity.prototype);
+        }
     }
 
     pub fn retain(&mut self, mut f: impl FnMut(&ModelEntity) -> bool) {
@@ -234,10 +499,57 @@ impl BpModel {
         self.by_tile.contains_key(&tile)
     }
 
+    pub fn num_occupied_tiles(&self) -> usize {
+        self.by_tile.len()
+    }
+
+    /// Pairs of entities that occupy the same tile, where at least one matches `f`.
+    pub fn overlapping_pairs(&self, f: impl Fn(&ModelEntity) -> bool) -> Vec<(EntityId, EntityId)> {
+        self.by_tile
+            .values()
+            .filter(|ids| ids.len() > 1)
+            .flat_map(|ids| ids.iter().sorted().tuple_combinations())
+            .filter(|(a, b)| f(&self.all_entities[*a]) || f(&self.all_entities[*b]))
+            .map(|(a, b)| (*a, *b))
+            .sorted()
+            .dedup()
+            .collect()
+    }
+
+    /// All entities, in no particular order.
     pub fn all_entities(&self) -> impl Iterator<Item = &ModelEntity> + '_ {
         self.all_entities.values()
     }
 
+    /// Entities of the given prototype, in no particular order.
+    #[allow(dead_code)]
+    pub fn entities_of_type(
+        &self,
+        prototype: &EntityPrototypeRef,
+    ) -> impl Iterator<Item = &ModelEntity> + '_ {
+        self.by_prototype
+            .get(prototype)
+            .into_iter()
+            .flatten()
+            .map(|id| &self.all_entities[id])
+    }
+
+    /// All poles, in no particular order. Doesn't look at other entities.
+    pub fn poles(&self) -> impl Iterator<Item = &ModelEntity> + '_ {
+        self.by_prototype
+            .iter()
+            .filter(|(prototype, _)| prototype.is_pole())
+            .flat_map(|(_, ids)| ids)
+            .map(|id| &self.all_entities[id])
+    }
+
+    /// All entities by id.
+    #[allow(dead_code)]
+    pub fn entities_by_id(&self) -> &HashMap<EntityId, ModelEntity> {
+        &self.all_entities
+    }
+
+    /// All entities, ordered by the first tile they occupy (by x, then y). Deterministic, unlike [Self::all_entities].
     pub fn all_entities_grid_order(&self) -> impl Iterator<Item = &ModelEntity> + '_ {
         self.by_tile
             .iter()
@@ -251,11 +563,13 @@ impl BpModel {
         self.all_entities.get(&id)
     }
 
+    /// The entity's prototype must not be changed, as entities are indexed by it.
     #[allow(dead_code)]
     pub fn get_mut(&mut self, id: EntityId) -> Option<&mut ModelEntity> {
         self.all_entities.get_mut(&id)
     }
 
+    /// Entities occupying the given tile; more than one if entities overlap.
     pub fn get_at_tile(&self, tile: TilePosition) -> impl Iterator<Item = &ModelEntity> + '_ {
         self.by_tile
             .get(&tile)
@@ -276,10 +590,9 @@ impl BpModel {
         pole_data: PoleData,
         target_entity: &WorldEntity,
     ) -> bool {
-        const EPS: f64 = 1e-6;
         target_entity.prototype.pole_data.is_some_and(|pd| {
             let max_dist = pole_data.wire_distance.min(pd.wire_distance);
-            (pole_pos - target_entity.position).square_length() <= max_dist * max_dist + EPS
+            within_distance(pole_pos, target_entity.position, max_dist)
         })
     }
 
@@ -302,22 +615,62 @@ impl BpModel {
         pole_pos: MapPosition,
         pole_data: PoleData,
     ) -> impl Iterator<Item = &ModelEntity> + '_ {
-        let this_area_dist = pole_data.supply_radius;
-        // poles in circle around map_pos with radius
-        BoundingBox::around_point(pole_pos, this_area_dist)
+        pole_data
+            .supply_box()
+            .translate(pole_pos.to_vector())
             .round_out_to_tiles()
             .iter_tiles()
             .flat_map(|tile| self.get_at_tile(tile))
             .filter(|entity| entity.uses_power())
             .unique_by(|entity| entity.id)
     }
+
+    /// Entities that use power, but are not in the supply area of any pole.
+    pub fn unpowered_entities(&self) -> impl Iterator<Item = &ModelEntity> + '_ {
+        let powered = self
+            .poles()
+            .filter_map(|entity| Some((entity.position, entity.pole_data()?.0)))
+            .flat_map(|(pos, pole_data)| self.powered_entities(pos, pole_data))
+            .map(|entity| entity.id)
+            .collect::<HashSet<_>>();
+        self.all_entities()
+            .filter(move |entity| entity.uses_power() && !powered.contains(&entity.id))
+    }
+
+    /// Groups of poles connected by copper wire, each sorted by id, in order of their first pole.
+    pub fn pole_networks(&self) -> Vec<Vec<EntityId>> {
+        let poles = self.poles().map(|entity| entity.id).sorted().collect_vec();
+        let index = poles
+            .iter()
+            .enumerate()
+            .map(|(i, &id)| (id, i))
+            .collect::<HashMap<_, _>>();
+        let mut union_find = UnionFind::new(poles.len());
+        for &id in &poles {
+            for other in &self.all_entities[&id]
+                .pole_connections()
+                .unwrap()
+                .connections
+            {
+                if let Some(&other_index) = index.get(other) {
+                    union_find.union(index[&id], other_index);
+                }
+            }
+        }
+        poles
+            .iter()
+            .into_group_map_by(|&&id| union_find.find(index[&id]))
+            .into_values()
+            .map(|network| network.into_iter().copied().collect_vec())
+            .sorted_by_key(|network| network[0])
+            .collect()
+    }
 }
 
 impl BlueprintEntities {
     pub fn add_poles_from(&mut self, model: &BpModel) -> HashMap<EntityId, EntityId> {
         let id_map = model
-            .all_entities()
-            .filter(|entity| entity.prototype.pole_data.is_some())
+            .poles()
             .map(|entity| {
                 (
                     entity.id,
@@ -357,6 +710,14 @@ pub mod test_util {
     use crate::bp_model::{BpModel, WorldEntity};
 
     pub fn small_pole_prototype() -> EntityPrototypeRef {
+        test_pole_prototype(PoleData {
+            wire_distance: 7.5,
+            supply_radius: 2.5,
+            supply_area: None,
+        })
+    }
+    /// A 1x1 pole with the given ranges.
+    pub fn test_pole_prototype(pole_data: PoleData) -> EntityPrototypeRef {
         RcId::new(EntityPrototype {
             type_: "electric-pole".to_string(),
             name: "test".to_string(),
@@ -364,10 +725,12 @@ pub mod test_util {
             tile_height: 1,
             collision_box: BoundingBox::new(point2(-0.5, -0.5), point2(0.5, 0.5)),
             uses_power: false,
-            pole_data: Some(PoleData {
-                wire_distance: 7.5,
-                supply_radius: 2.5,
-            }),
+            pole_data: Some(pole_data),
+            inserter_data: None,
+            turret_data: None,
+            energy_data: None,
+            fluid_data: None,
+            direction_boxes: None,
         })
     }
     pub fn powerable_prototype() -> EntityPrototypeRef {
@@ -379,6 +742,11 @@ pub mod test_util {
             uses_power: true,
             collision_box: BoundingBox::new(point2(-0.5, -0.5), point2(0.5, 0.5)),
             pole_data: None,
+            inserter_data: None,
+            turret_data: None,
+            energy_data: None,
+            fluid_data: None,
+            direction_boxes: None,
         })
     }
     impl BpModel {
@@ -424,6 +792,11 @@ mod tests {
             collision_box: BoundingBox::new(point2(-0.5, -0.5), point2(0.5, 0.5)),
             uses_power,
             pole_data: None,
+            inserter_data: None,
+            turret_data: None,
+            energy_data: None,
+            fluid_data: None,
+            direction_boxes: None,
         })
     }
 
@@ -448,6 +821,38 @@ mod tests {
         assert!(grid.get_at_tile(point2(0, 0)).next().is_none());
     }
     #[test]
+    fn overlapping_pairs() {
+        let mut grid = BpModel::new();
+        let pole = grid.add_test_pole(point2(0, 0));
+        let other = grid.add_test_powerable(point2(0, 0));
+        grid.add_test_powerable(point2(2, 0));
+        grid.add_test_powerable(point2(2, 0));
+
+        let poles = grid.overlapping_pairs(|entity| entity.prototype.is_pole());
+        assert_eq!(poles, vec![(pole, other)]);
+        assert_eq!(grid.overlapping_pairs(|_| true).len(), 2);
+    }
+    #[test]
+    fn reserved_tiles() {
+        let mut grid = BpModel::new();
+        grid.reserve_tiles([point2(1, 0)]);
+        let pole_at = |x: f64| WorldEntity {
+            position: point2(x, 0.5),
+            direction: 0,
+            prototype: small_pole_prototype(),
+        };
+        assert!(grid.can_place(&pole_at(0.5)));
+        assert!(!grid.can_place(&pole_at(1.5)));
+        assert!(grid
+            .with_all_candidate_poles(
+                TileBoundingBox::new(point2(0, 0), point2(3, 1)),
+                &[small_pole_prototype()]
+            )
+            .get_at_tile(point2(1, 0))
+            .next()
+            .is_none());
+    }
+    #[test]
     fn powered_entities() {
         let mut grid = BpModel::new();
         let id1 = grid.add_overlap(WorldEntity {
@@ -473,6 +878,19 @@ mod tests {
         assert_eq!(powered2, vec![]);
     }
 
+    #[test]
+    fn unpowered_entities() {
+        let mut model = BpModel::new();
+        model.add_test_pole(point2(0, 0));
+        model.add_test_powerable(point2(1, 1));
+        let far = model.add_test_powerable(point2(10, 10));
+        let unpowered = model
+            .unpowered_entities()
+            .map(|entity| entity.id)
+            .collect_vec();
+        assert_eq!(unpowered, vec![far]);
+    }
+
     #[test]
     fn connectable_poles() {
         let mut grid = BpModel::new();
@@ -520,4 +938,114 @@ mod tests {
         assert_eq!(pole2.neighbours, Some(HashSet::from([i1, i3])));
         assert_eq!(pole3.neighbours, Some(HashSet::from([i2])));
     }
+
+    #[test]
+    fn test_entities_by_type() {
+        let mut model = BpModel::new();
+        let pole1 = model.add_test_pole(point2(0, 0));
+        let pole2 = model.add_test_pole(point2(2, 0));
+        let powerable = model.add_test_powerable(point2(1, 0));
+        let poles = |model: &BpModel| model.poles().map(|e| e.id()).sorted().collect_vec();
+        assert_eq!(poles(&model), vec![pole1, pole2]);
+
+        let prototype = model.get(powerable).unwrap().prototype.clone();
+        assert_eq!(
+            model
+                .entities_of_type(&prototype)
+                .map(|e| e.id())
+                .collect_vec(),
+            vec![powerable]
+        );
+
+        model.remove(&pole1);
+        assert_eq!(poles(&model), vec![pole2]);
+        model.remove_all_poles();
+        assert_eq!(poles(&model), vec![]);
+        assert_eq!(model.all_entities().count(), 1);
+        model.remove(&powerable);
+        assert_eq!(model.entities_of_type(&prototype).count(), 0);
+    }
+
+    #[test]
+    fn water_tiles() {
+        let mut grid = BpModel::new();
+        grid.add_floor_tiles([(LANDFILL, point2(2, 0))]);
+        assert_eq!(grid.set_water_tiles([point2(1, 0), point2(2, 0)], false), 1);
+        let pole_at = |x: f64| WorldEntity {
+            position: point2(x, 0.5),
+            direction: 0,
+            prototype: small_pole_prototype(),
+        };
+        assert!(grid.can_place(&pole_at(0.5)));
+        assert!(!grid.can_place(&pole_at(1.5)));
+        assert!(grid.can_place(&pole_at(2.5)));
+
+        grid.set_water_tiles([point2(1, 0), point2(2, 0)], true);
+        assert!(grid.can_place(&pole_at(1.5)));
+        assert_eq!(
+            grid.landfill_needed(&pole_at(1.5)).collect_vec(),
+            vec![point2(1, 0)]
+        );
+        assert_eq!(grid.landfill_needed(&pole_at(2.5)).count(), 0);
+    }
+
+    #[test]
+    fn test_serde_roundtrip() {
+        let mut model = BpModel::new();
+        let pole1 = model.add_test_pole(point2(0, 0));
+        let pole2 = model.add_test_pole(point2(3, 0));
+        model.add_cable_connection(pole1, pole2);
+        let powerable = model.add_test_powerable(point2(1, 1));
+        model.reserve_tiles([point2(5, 5)]);
+        model.add_floor_tiles([("refined-concrete", point2(1, 1))]);
+
+        let json = serde_json::to_string(&model).unwrap();
+        let dict = EntityPrototypeDict(std::sync::Arc::new(std::collections::HashMap::from([
+            ("test".to_string(), small_pole_prototype()),
+            ("solar-panel".to_string(), powerable_prototype()),
+        ])));
+        let loaded: BpModel =
+            crate::prototype_data::with_serde_prototypes(&dict, || serde_json::from_str(&json))
+                .unwrap();
+
+        assert_eq!(loaded.all_entities().count(), 3);
+        assert_eq!(loaded.next_id, model.next_id);
+        assert_eq!(loaded.reserved_tiles, model.reserved_tiles);
+        assert_eq!(loaded.floor_tile(point2(1, 1)), Some("refined-concrete"));
+        assert_eq!(loaded.floor_tile(point2(0, 0)), None);
+        assert_eq!(
+            loaded
+                .get(pole1)
+                .unwrap()
+                .pole_connections()
+                .unwrap()
+                .connections,
+            HashSet::from([pole2])
+        );
+        let at_tile = loaded.get_at_tile(point2(1, 1)).map(|e| e.id).collect_vec();
+        assert_eq!(at_tile, vec![powerable]);
+    }
+
+    #[test]
+    fn curved_rail_footprint() {
+        let bp = crate::read_blueprint(&"test-data/curved-rail-poles.json".into()).unwrap();
+        let dict = crate::prototype_data::load_prototype_data().unwrap();
+        let model = BpModel::from_bp_entities(&BlueprintEntities::from_blueprint(&bp), &dict);
+        // the curves extend 4 tiles from their center lengthwise, past their 4x4 collision box
+        assert_eq!(
+            model.overlapping_pairs(|entity| entity.prototype.is_pole()),
+            vec![(EntityId(1), EntityId(3)), (EntityId(2), EntityId(4))]
+        );
+    }
+
+    #[test]
+    fn pole_networks() {
+        let mut model = BpModel::new();
+        let poles = model.add_test_poles(&[point2(0, 0), point2(3, 0), point2(20, 0)]);
+        model.add_cable_connection(poles[0], poles[1]);
+        assert_eq!(
+            model.pole_networks(),
+            vec![vec![poles[0], poles[1]], vec![poles[2]]]
+        );
+    }
 }
@@ -0,0 +1,639 @@
+use std::cell::Cell;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use base64::engine::GeneralPurpose;
+use base64::prelude::*;
+use base64::read::DecoderReader;
+use base64::write::EncoderWriter;
+use factorio_blueprint::objects::{BlueprintBookBlueprintValue, Entity};
+use factorio_blueprint::Container;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, Deserializer, Error as _, MapAccess, SeqAccess,
+    Visitor,
+};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::bp_compat;
+use crate::bp_io::{self, BlueprintFormat};
+use crate::error::OptimizerError;
+
+thread_local! {
+    static LOW_MEMORY: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Makes [bp_io::decode] use [decode_streaming] (`--low-memory`).
+pub fn use_low_memory(low_memory: bool) {
+    LOW_MEMORY.with(|cell| cell.set(low_memory));
+}
+
+/// If [use_low_memory] is on, in this thread.
+pub fn low_memory() -> bool {
+    LOW_MEMORY.with(|cell| cell.get())
+}
+
+/// Drops ASCII whitespace, which base64 decoding doesn't accept, e.g. a trailing newline.
+struct SkipWhitespace<R> {
+    inner: R,
+    scratch: Vec<u8>,
+}
+
+impl<R: Read> Read for SkipWhitespace<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.scratch.resize(buf.len(), 0);
+        loop {
+            let read = self.inner.read(&mut self.scratch)?;
+            if read == 0 {
+                return Ok(0);
+            }
+            let bytes = self.scratch[..read]
+                .iter()
+                .filter(|byte| !byte.is_ascii_whitespace());
+            let kept = buf
+                .iter_mut()
+                .zip(bytes)
+                .map(|(slot, byte)| *slot = *byte)
+                .count();
+            if kept > 0 {
+                return Ok(kept);
+            }
+        }
+    }
+}
+
+/// The JSON in a blueprint string or JSON file, decoded as it is read.
+fn json_reader<'a>(reader: impl Read + 'a) -> Result<Box<dyn Read + 'a>, OptimizerError> {
+    let mut reader = BufReader::new(reader);
+    let first = loop {
+        let Some(&byte) = reader.fill_buf()?.first() else {
+            return Err(OptimizerError::Decode("The input is empty".into()));
+        };
+        if !byte.is_ascii_whitespace() {
+            break byte;
+        }
+        reader.consume(1);
+    };
+    match first {
+        b'{' => Ok(Box::new(reader)),
+        b'0' => {
+            reader.consume(1);
+            let base64 = SkipWhitespace {
+                inner: reader,
+                scratch: vec![],
+            };
+            Ok(Box::new(ZlibDecoder::new(DecoderReader::new(
+                base64,
+                &BASE64_STANDARD,
+            ))))
+        }
+        other => Err(OptimizerError::Decode(format!(
+            "Unknown blueprint string version {:?}; expected '0'",
+            other as char
+        ))),
+    }
+}
+
+/// Fixes applied and unsupported constructs found so far, as in [bp_io::decode].
+#[derive(Default)]
+struct Findings {
+    fixes: Vec<String>,
+    unsupported: Vec<String>,
+}
+
+impl Findings {
+    /// Normalizes and reads a container-like object: a container, or an entry of a book.
+    fn read<T: DeserializeOwned>(&mut self, mut value: Value) -> Result<T, String> {
+        self.fixes.extend(bp_compat::normalize(&mut value));
+        self.unsupported
+            .extend(bp_compat::unsupported_constructs(&value));
+        T::deserialize(&value).map_err(|err| err.to_string())
+    }
+
+    /// Reads a blueprint from its fields other than `entities`, and its entities, already read.
+    fn read_blueprint(
+        &mut self,
+        fields: Map<String, Value>,
+        entities: Vec<Entity>,
+    ) -> Result<Container, String> {
+        let mut container = self.read(json!({ "blueprint": fields }))?;
+        if let Container::Blueprint(bp) = &mut container {
+            bp.entities = entities;
+        }
+        Ok(container)
+    }
+}
+
+/// Called on each entry of a book as soon as it is read.
+type OnEntry<'a> = dyn FnMut(BlueprintBookBlueprintValue) -> Result<(), OptimizerError> + 'a;
+
+/// Shared by the visitors while reading.
+struct Reading<'a> {
+    findings: Findings,
+    on_entry: &'a mut OnEntry<'a>,
+    /// Why `on_entry` failed; serde only passes on its message.
+    entry_error: Option<OptimizerError>,
+}
+
+/// Decodes like [bp_io::decode], but without holding the whole input, its JSON, or a book's JSON in memory:
+/// the input is decoded as it is read, each entry of a book is read into a blueprint on its own,
+/// and each entity of a blueprint is read on its own. Slower.
+pub fn decode_streaming(reader: impl Read) -> Result<Container, OptimizerError> {
+    let mut entries = vec![];
+    let mut container = decode_book_streaming(reader, &mut |entry| {
+        entries.push(entry);
+        Ok(())
+    })?;
+    if let Container::BlueprintBook(book) = &mut container {
+        book.blueprints = entries;
+    }
+    Ok(container)
+}
+
+/// Decodes like [decode_streaming], but instead of keeping a book's entries, hands each to `on_entry` as soon as it is read,
+/// so only one is in memory at once. Returns the book without its entries, or the blueprint if the input isn't a book.
+/// Fixes and unsupported constructs are reported once the whole input is read.
+pub fn decode_book_streaming<'a>(
+    reader: impl Read,
+    on_entry: &'a mut OnEntry<'a>,
+) -> Result<Container, OptimizerError> {
+    let mut reading = Reading {
+        findings: Findings::default(),
+        on_entry,
+        entry_error: None,
+    };
+    let mut deserializer = serde_json::Deserializer::from_reader(json_reader(reader)?);
+    let result = (&mut deserializer)
+        .deserialize_map(ContainerVisitor {
+            reading: &mut reading,
+        })
+        .and_then(|container| deserializer.end().map(|_| container));
+    if let Some(err) = reading.entry_error {
+        return Err(err);
+    }
+    let Findings {
+        fixes,
+        mut unsupported,
+    } = reading.findings;
+    unsupported.sort();
+    unsupported.dedup();
+    bp_io::report_findings(&fixes, &unsupported, result.map_err(|err| err.to_string()))
+}
+
+struct ContainerVisitor<'r, 'a> {
+    reading: &'r mut Reading<'a>,
+}
+
+impl<'de> Visitor<'de> for ContainerVisitor<'_, '_> {
+    type Value = Container;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a blueprint, or a blueprint book")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Container, A::Error> {
+        let mut container = None;
+        // fields of a bare blueprint, not wrapped in a container
+        let mut bare = Map::new();
+        let mut bare_entities = vec![];
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "blueprint" => {
+                    container = Some(map.next_value_seed(BlueprintSeed {
+                        findings: &mut self.reading.findings,
+                    })?);
+                }
+                "blueprint_book" => {
+                    container = Some(map.next_value_seed(BookSeed {
+                        reading: &mut *self.reading,
+                    })?);
+                }
+                "entities" => {
+                    bare_entities = map.next_value_seed(EntitiesSeed {
+                        findings: &mut self.reading.findings,
+                    })?;
+                }
+                _ => {
+                    bare.insert(key, map.next_value()?);
+                }
+            }
+        }
+        match container {
+            Some(container) => Ok(container),
+            None => self
+                .reading
+                .findings
+                .read_blueprint(bare, bare_entities)
+                .map_err(de::Error::custom),
+        }
+    }
+}
+
+/// Reads a blueprint, with its entities one at a time.
+struct BlueprintSeed<'a> {
+    findings: &'a mut Findings,
+}
+
+impl<'de> DeserializeSeed<'de> for BlueprintSeed<'_> {
+    type Value = Container;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Container, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for BlueprintSeed<'_> {
+    type Value = Container;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a blueprint")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Container, A::Error> {
+        let mut fields = Map::new();
+        let mut entities = vec![];
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "entities" {
+                entities = map.next_value_seed(EntitiesSeed {
+                    findings: &mut *self.findings,
+                })?;
+            } else {
+                fields.insert(key, map.next_value()?);
+            }
+        }
+        self.findings
+            .read_blueprint(fields, entities)
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Reads a blueprint's entities one at a time, fixing each as [bp_compat::normalize] would.
+struct EntitiesSeed<'a> {
+    findings: &'a mut Findings,
+}
+
+impl<'de> DeserializeSeed<'de> for EntitiesSeed<'_> {
+    type Value = Vec<Entity>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Vec<Entity>, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for EntitiesSeed<'_> {
+    type Value = Vec<Entity>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a list of entities")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<Entity>, A::Error> {
+        let mut entities = vec![];
+        // whether to number entities, decided by the first: all or none must have an entity_number
+        let mut numbering = None;
+        let mut num_connections = 0;
+        while let Some(mut entity) = seq.next_element::<Value>()? {
+            let unnumbered = entity.get("entity_number").is_none();
+            if unnumbered != *numbering.get_or_insert(unnumbered) {
+                return Err(de::Error::custom(
+                    "only some entities have an entity_number",
+                ));
+            }
+            if let (true, Some(fields)) = (unnumbered, entity.as_object_mut()) {
+                fields.insert("entity_number".into(), json!(entities.len() + 1));
+            }
+            num_connections += bp_compat::normalize_entity(&mut entity);
+            bp_compat::entity_unsupported(&entity, &mut self.findings.unsupported);
+            entities.push(Entity::deserialize(&entity).map_err(A::Error::custom)?);
+        }
+        if numbering == Some(true) {
+            self.findings
+                .fixes
+                .push("numbered entities without entity_number".into());
+        }
+        if num_connections > 0 {
+            self.findings.fixes.push(format!(
+                "converted {} circuit connection points from arrays to objects",
+                num_connections
+            ));
+        }
+        Ok(entities)
+    }
+}
+
+struct BookSeed<'r, 'a> {
+    reading: &'r mut Reading<'a>,
+}
+
+impl<'de> DeserializeSeed<'de> for BookSeed<'_, '_> {
+    type Value = Container;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Container, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for BookSeed<'_, '_> {
+    type Value = Container;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a blueprint book")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Container, A::Error> {
+        let mut book = Map::new();
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "blueprints" {
+                map.next_value_seed(EntriesSeed {
+                    reading: &mut *self.reading,
+                })?;
+            } else {
+                book.insert(key, map.next_value()?);
+            }
+        }
+        book.insert("blueprints".into(), json!([]));
+        self.reading
+            .findings
+            .read(json!({ "blueprint_book": book }))
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Reads a book's entries one at a time, leaving out upgrade and deconstruction planners,
+/// and hands each to [Reading::on_entry].
+struct EntriesSeed<'r, 'a> {
+    reading: &'r mut Reading<'a>,
+}
+
+impl<'de> DeserializeSeed<'de> for EntriesSeed<'_, '_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for EntriesSeed<'_, '_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a list of blueprint book entries")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        let mut planners = 0;
+        while let Some(entry) = seq.next_element_seed(EntrySeed {
+            findings: &mut self.reading.findings,
+        })? {
+            let Some(entry) = entry else {
+                planners += 1;
+                continue;
+            };
+            if let Err(err) = (self.reading.on_entry)(entry) {
+                let message = err.to_string();
+                self.reading.entry_error = Some(err);
+                return Err(de::Error::custom(message));
+            }
+        }
+        if planners > 0 {
+            self.reading.findings.fixes.push(format!(
+                "removed {} upgrade/deconstruction planners from book",
+                planners
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Reads an entry of a book; None for an upgrade or deconstruction planner.
+/// A nested book is read whole.
+struct EntrySeed<'a> {
+    findings: &'a mut Findings,
+}
+
+impl<'de> DeserializeSeed<'de> for EntrySeed<'_> {
+    type Value = Option<BlueprintBookBlueprintValue>;
+
+    fn deserialize<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Option<BlueprintBookBlueprintValue>, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for EntrySeed<'_> {
+    type Value = Option<BlueprintBookBlueprintValue>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a blueprint book entry")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(
+        self,
+        mut map: A,
+    ) -> Result<Option<BlueprintBookBlueprintValue>, A::Error> {
+        let mut index = None;
+        let mut item = None;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "index" => index = Some(map.next_value()?),
+                "blueprint" => {
+                    item = Some(map.next_value_seed(BlueprintSeed {
+                        findings: &mut *self.findings,
+                    })?);
+                }
+                "blueprint_book" => {
+                    let book = map.next_value::<Value>()?;
+                    let read = self.findings.read(json!({ "blueprint_book": book }));
+                    item = Some(read.map_err(A::Error::custom)?);
+                }
+                _ => {
+                    map.next_value::<de::IgnoredAny>()?;
+                }
+            }
+        }
+        let Some(item) = item else {
+            return Ok(None);
+        };
+        let index = index.ok_or_else(|| A::Error::missing_field("index"))?;
+        Ok(Some(BlueprintBookBlueprintValue { index, item }))
+    }
+}
+
+/// Where [BookWriter] writes the book's JSON.
+enum BookOutput<W: Write> {
+    // boxed, as the encoder's buffers are large
+    String(Box<ZlibEncoder<EncoderWriter<'static, GeneralPurpose, W>>>),
+    Json(W),
+}
+
+/// Writes a blueprint book as [bp_io::encode] would, but an entry at a time, so the book needn't be in memory.
+/// The entries are written before the book's other fields, and JSON is written compact.
+pub struct BookWriter<W: Write> {
+    out: BookOutput<W>,
+    num_entries: usize,
+}
+
+impl<W: Write> BookWriter<W> {
+    pub fn new(mut writer: W, format: BlueprintFormat) -> Result<Self, OptimizerError> {
+        let mut out = match format {
+            BlueprintFormat::String => {
+                writer.write_all(b"0")?;
+                BookOutput::String(Box::new(ZlibEncoder::new(
+                    EncoderWriter::new(writer, &BASE64_STANDARD),
+                    Compression::best(),
+                )))
+            }
+            BlueprintFormat::Json => BookOutput::Json(writer),
+        };
+        out.write_all(br#"{"blueprint_book":{"blueprints":["#)?;
+        Ok(BookWriter {
+            out,
+            num_entries: 0,
+        })
+    }
+
+    pub fn write_entry(
+        &mut self,
+        entry: &BlueprintBookBlueprintValue,
+    ) -> Result<(), OptimizerError> {
+        if self.num_entries > 0 {
+            self.out.write_all(b",")?;
+        }
+        serde_json::to_writer(&mut self.out, entry)?;
+        self.num_entries += 1;
+        Ok(())
+    }
+
+    /// Writes the fields of `book` other than its entries, and finishes the output.
+    pub fn finish(mut self, book: &Container) -> Result<(), OptimizerError> {
+        let Value::Object(mut fields) = serde_json::to_value(book)?["blueprint_book"].take() else {
+            return Err("Expected a blueprint book".into());
+        };
+        fields.remove("blueprints");
+        self.out.write_all(b"]")?;
+        for (key, value) in &fields {
+            self.out.write_all(b",")?;
+            serde_json::to_writer(&mut self.out, key)?;
+            self.out.write_all(b":")?;
+            serde_json::to_writer(&mut self.out, value)?;
+        }
+        self.out.write_all(b"}}")?;
+        let mut writer = match self.out {
+            BookOutput::String(encoder) => encoder.finish()?.finish()?,
+            BookOutput::Json(writer) => writer,
+        };
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for BookOutput<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            BookOutput::String(encoder) => encoder.write(buf),
+            BookOutput::Json(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            BookOutput::String(encoder) => encoder.flush(),
+            BookOutput::Json(writer) => writer.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+    use crate::bp_io::{blueprints_mut, encode, make_book};
+
+    #[test]
+    fn test_decode_streaming() {
+        let content = std::fs::read("test-data/bigtest.txt").unwrap();
+        let Container::Blueprint(bp) = bp_io::decode(content.as_slice()).unwrap() else {
+            panic!("not a blueprint");
+        };
+        let mut with_newline = content.clone();
+        with_newline.extend(b"\n");
+        let Container::Blueprint(streamed) = decode_streaming(with_newline.as_slice()).unwrap()
+        else {
+            panic!("not a blueprint");
+        };
+        assert_eq!(streamed.entities.len(), bp.entities.len());
+
+        let book = make_book("both", &[bp.clone(), bp.clone()]).unwrap();
+        let mut encoded = vec![];
+        encode(&mut encoded, &book, BlueprintFormat::String).unwrap();
+        let mut decoded = decode_streaming(encoded.as_slice()).unwrap();
+        let blueprints = blueprints_mut(&mut decoded);
+        assert_eq!(blueprints.len(), 2);
+        assert_eq!(blueprints[1].entities.len(), bp.entities.len());
+
+        let bare_json = serde_json::to_vec(&bp).unwrap();
+        assert!(matches!(
+            decode_streaming(bare_json.as_slice()).unwrap(),
+            Container::Blueprint(_)
+        ));
+        assert!(decode_streaming(File::open("Cargo.toml").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_book_planners_removed() {
+        let json =
+            br#"{"blueprint_book": {"item": "blueprint-book", "label": "", "active_index": 0,
+            "version": 0, "blueprints": [
+                {"index": 0, "upgrade_planner": {"item": "upgrade-planner"}},
+                {"index": 1, "blueprint": {"item": "blueprint", "entities": []}}]}}"#;
+        let mut container = decode_streaming(json.as_slice()).unwrap();
+        assert_eq!(blueprints_mut(&mut container).len(), 1);
+    }
+
+    #[test]
+    fn test_entities_fixed_one_at_a_time() {
+        let json = br#"{"blueprint": {"item": "blueprint", "entities": [
+            {"name": "small-lamp", "position": {"x": 0.5, "y": 0.5}},
+            {"name": "small-lamp", "position": {"x": 2.5, "y": 0.5}, "connections": [{"red": []}]}]}}"#;
+        let mut container = decode_streaming(json.as_slice()).unwrap();
+        let blueprints = blueprints_mut(&mut container);
+        let numbers = blueprints[0]
+            .entities
+            .iter()
+            .map(|entity| entity.entity_number.get())
+            .collect::<Vec<_>>();
+        assert_eq!(numbers, vec![1, 2]);
+
+        let mixed = br#"{"blueprint": {"item": "blueprint", "entities": [
+            {"name": "small-lamp", "position": {"x": 0.5, "y": 0.5}},
+            {"entity_number": 2, "name": "small-lamp", "position": {"x": 2.5, "y": 0.5}}]}}"#;
+        assert!(decode_streaming(mixed.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_book_writer() {
+        let Container::Blueprint(bp) =
+            bp_io::decode(File::open("test-data/bigtest.txt").unwrap()).unwrap()
+        else {
+            panic!("not a blueprint");
+        };
+        let book = make_book("both", &[bp.clone(), bp]).unwrap();
+        let mut encoded = vec![];
+        encode(&mut encoded, &book, BlueprintFormat::String).unwrap();
+        for format in [BlueprintFormat::String, BlueprintFormat::Json] {
+            let mut written = vec![];
+            let mut writer = BookWriter::new(&mut written, format).unwrap();
+            let header =
+                decode_book_streaming(encoded.as_slice(), &mut |entry| writer.write_entry(&entry))
+                    .unwrap();
+            writer.finish(&header).unwrap();
+            assert_eq!(bp_io::decode(written.as_slice()).unwrap(), book);
+        }
+    }
+}
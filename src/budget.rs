@@ -0,0 +1,63 @@
+use std::time::{Duration, Instant};
+
+/// Share of the remaining budget for candidate generation; solving and polishing share the rest.
+pub const CANDIDATES_SHARE: f64 = 0.2;
+/// Share of the remaining budget, after candidate generation, for solving; polishing gets the rest.
+pub const SOLVE_SHARE: f64 = 0.75;
+/// Below this, the solver is skipped for a greedy solution.
+pub const MIN_SOLVE_TIME: Duration = Duration::from_millis(500);
+
+/// Rough candidate generation speed, in tiles per second for one pole type.
+const CANDIDATE_TILES_PER_SECOND: f64 = 200_000.0;
+const MAX_CANDIDATE_STEP: i32 = 4;
+
+/// A soft limit on the total run time (`--budget`), split between pipeline stages.
+/// Time a stage doesn't use is left for the later ones.
+#[derive(Debug, Clone, Copy)]
+pub struct TimeBudget {
+    start: Instant,
+    total: Duration,
+}
+
+impl TimeBudget {
+    pub fn new(total: Duration) -> Self {
+        TimeBudget {
+            start: Instant::now(),
+            total,
+        }
+    }
+
+    pub fn remaining(&self) -> Duration {
+        self.total.saturating_sub(self.start.elapsed())
+    }
+
+    /// Time for a stage that gets `share` of what is left.
+    pub fn share(&self, share: f64) -> Duration {
+        self.remaining().mul_f64(share)
+    }
+
+    /// Grid step for candidate poles, so that generating them takes about [CANDIDATES_SHARE] of what is left.
+    /// See [crate::bp_model::BpModel::with_coarse_candidate_poles].
+    pub fn candidate_step(&self, num_tiles: usize, num_pole_types: usize) -> i32 {
+        let seconds = self.share(CANDIDATES_SHARE).as_secs_f64();
+        let tiles_in_time = seconds * CANDIDATE_TILES_PER_SECOND;
+        let work = (num_tiles * num_pole_types) as f64;
+        let step = (work / tiles_in_time.max(1.0)).sqrt().ceil() as i32;
+        step.clamp(1, MAX_CANDIDATE_STEP)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_candidate_step() {
+        let budget = TimeBudget::new(Duration::from_secs(100));
+        assert_eq!(budget.candidate_step(10_000, 2), 1);
+        assert!(budget.candidate_step(10_000_000, 2) > 1);
+        let budget = TimeBudget::new(Duration::ZERO);
+        assert_eq!(budget.remaining(), Duration::ZERO);
+        assert_eq!(budget.candidate_step(10_000_000, 2), MAX_CANDIDATE_STEP);
+    }
+}
@@ -0,0 +1,100 @@
+use std::cell::RefCell;
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Lets another thread stop a running job. Clones share the same flag.
+///
+/// The pipeline checks this between stages, candidate generation between its steps, and long-running
+/// loops (like polishing) between iterations. ILP solves are interrupted through a HiGHS callback;
+/// see [crate::highs_solve::solve_interruptible].
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// An error if cancelled, for `stage` to return early with.
+    pub fn check(&self, stage: &'static str) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            Err(Cancelled { stage })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The flag itself, for callbacks from C that can't hold a clone.
+    pub fn flag(&self) -> &AtomicBool {
+        &self.0
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<CancellationToken>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` with `token` as this thread's [current] token, so solvers deep in the stack can stop early
+/// without every caller passing it down.
+pub fn with_token<R>(token: &CancellationToken, f: impl FnOnce() -> R) -> R {
+    let prev = CURRENT.with(|current| current.replace(Some(token.clone())));
+    let result = f();
+    CURRENT.with(|current| *current.borrow_mut() = prev);
+    result
+}
+
+/// The token of the job running on this thread, from [with_token]. Threads a job spawns must set it again.
+pub fn current() -> Option<CancellationToken> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Returned when a job is stopped with a [CancellationToken].
+#[derive(Debug, Clone)]
+pub struct Cancelled {
+    /// The stage that was running, or would have run next.
+    pub stage: &'static str,
+}
+
+impl Display for Cancelled {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Cancelled at stage '{}'", self.stage)
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clones_share_flag() {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+        assert!(!clone.is_cancelled());
+        assert!(clone.check("a").is_ok());
+        token.cancel();
+        assert!(clone.is_cancelled());
+        assert_eq!(clone.check("a").unwrap_err().stage, "a");
+    }
+
+    #[test]
+    fn test_current_token() {
+        assert!(current().is_none());
+        let token = CancellationToken::new();
+        with_token(&token, || {
+            current().unwrap().cancel();
+        });
+        assert!(token.is_cancelled());
+        assert!(current().is_none());
+    }
+}
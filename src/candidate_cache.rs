@@ -0,0 +1,109 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use hashbrown::HashSet;
+use itertools::Itertools;
+use petgraph::prelude::*;
+
+use crate::certificate::{fnv1a, FNV_OFFSET_BASIS};
+use crate::error::OptimizerError;
+use crate::pole_graph::CandPoleGraph;
+use crate::prototype_data::{with_serde_prototypes, EntityPrototypeDict};
+
+/// Bump when the candidate generation or the format below changes, to invalidate old cache files.
+const CACHE_VERSION: u32 = 2;
+
+/// Hashes everything the candidate graph depends on, with FNV-1a so keys stay the same across builds.
+/// `inputs` should be the serialized blueprint (and context), and `options` every option affecting candidates.
+pub fn cache_key(inputs: &[&[u8]], options: &[&str]) -> u64 {
+    // each part is prefixed by its length, so moving bytes between parts changes the key
+    let add = |hash: u64, part: &[u8]| fnv1a(fnv1a(hash, &(part.len() as u64).to_le_bytes()), part);
+    let hash = add(FNV_OFFSET_BASIS, &CACHE_VERSION.to_le_bytes());
+    let hash = inputs.iter().fold(hash, |hash, input| add(hash, input));
+    options
+        .iter()
+        .fold(hash, |hash, option| add(hash, option.as_bytes()))
+}
+
+pub fn cache_path(cache_dir: &Path, key: u64) -> PathBuf {
+    cache_dir.join(format!("candidates-{:016x}.json", key))
+}
+
+pub fn save(
+    path: &Path,
+    graph: &CandPoleGraph,
+    fixed_poles: &HashSet<NodeIndex>,
+) -> Result<(), OptimizerError> {
+    let fixed_poles = fixed_poles.iter().copied().sorted().collect_vec();
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    serde_json::to_writer(BufWriter::new(File::create(path)?), &(graph, fixed_poles))?;
+    Ok(())
+}
+
+/// Returns None if there is no cache file at `path`.
+pub fn load(
+    path: &Path,
+    prototypes: &EntityPrototypeDict,
+) -> Result<Option<(CandPoleGraph, HashSet<NodeIndex>)>, OptimizerError> {
+    let Ok(file) = File::open(path) else {
+        return Ok(None);
+    };
+    let (graph, fixed_poles): (CandPoleGraph, Vec<NodeIndex>) =
+        with_serde_prototypes(prototypes, || serde_json::from_reader(BufReader::new(file)))?;
+    Ok(Some((graph, fixed_poles.into_iter().collect())))
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::point2;
+
+    use crate::bp_model::test_util::small_pole_prototype;
+    use crate::bp_model::BpModel;
+    use crate::pole_graph::ToCandidatePoleGraph;
+    use crate::prototype_data::EntityPrototypeDict;
+
+    use super::*;
+
+    #[test]
+    fn test_cache_roundtrip() {
+        let mut model = BpModel::new();
+        model.add_test_powerable(point2(0, 0));
+        model.add_test_powerable(point2(4, 0));
+        let prototype = small_pole_prototype();
+        let graph = model
+            .with_all_candidate_poles(model.get_bounding_box(), &[&prototype])
+            .get_maximally_connected_pole_graph()
+            .0
+            .to_cand_pole_graph(&model);
+        let fixed_poles = HashSet::from([NodeIndex::new(0)]);
+
+        let path = std::env::temp_dir().join("candidate_cache_test.json");
+        save(&path, &graph, &fixed_poles).unwrap();
+        let dict = EntityPrototypeDict(std::sync::Arc::new(std::collections::HashMap::from([(
+            "test".to_string(),
+            prototype,
+        )])));
+        let (loaded, loaded_fixed) = load(&path, &dict).unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.node_count(), graph.node_count());
+        assert_eq!(loaded.edge_count(), graph.edge_count());
+        assert_eq!(loaded_fixed, fixed_poles);
+        for idx in graph.node_indices() {
+            assert_eq!(loaded[idx].entity, graph[idx].entity);
+            assert_eq!(loaded[idx].powered_entities, graph[idx].powered_entities);
+        }
+    }
+
+    #[test]
+    fn test_cache_key() {
+        let key = |input: &str, option: &str| cache_key(&[input.as_bytes()], &[option]);
+        assert_eq!(key("a", "s"), key("a", "s"));
+        assert_ne!(key("a", "s"), key("a", "m"));
+        assert_ne!(key("a", "s"), key("b", "s"));
+        assert_ne!(key("ab", "c"), key("a", "bc"));
+    }
+}
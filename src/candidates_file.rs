@@ -0,0 +1,158 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use euclid::point2;
+use hashbrown::HashSet;
+use itertools::Itertools;
+use serde::Deserialize;
+
+use crate::bp_model::{BpModel, WorldEntity};
+use crate::error::OptimizerError;
+use crate::position::{BoundingBoxExt, IterTiles, MapPositionExt};
+use crate::prototype_data::EntityPrototypeDict;
+use crate::require_prototype;
+
+/// Most problems listed in an error.
+const MAX_LISTED: usize = 5;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CandidateSpec {
+    name: String,
+    position: SpecPosition,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SpecPosition {
+    x: f64,
+    y: f64,
+}
+
+fn describe(entity: &WorldEntity) -> String {
+    format!(
+        "{} at ({}, {})",
+        entity.prototype.name, entity.position.x, entity.position.y
+    )
+}
+
+fn list(problems: &[String]) -> String {
+    let mut listed = problems.iter().take(MAX_LISTED).join("; ");
+    if problems.len() > MAX_LISTED {
+        listed += &format!("; and {} more", problems.len() - MAX_LISTED);
+    }
+    listed
+}
+
+/// Reads candidate poles for `--candidates`. The file is a JSON array of poles, in blueprint coordinates:
+/// `[{"name": "medium-electric-pole", "position": {"x": 0.5, "y": 2.5}}, ...]`.
+/// Names can use aliases: s, m, b, t. Each pole must be a pole, centered on its tiles like in a blueprint, and listed once.
+pub fn load(path: &Path, dict: &EntityPrototypeDict) -> Result<Vec<WorldEntity>, OptimizerError> {
+    let specs: Vec<CandidateSpec> = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    let mut problems = vec![];
+    let mut seen = HashSet::new();
+    let mut candidates = vec![];
+    for spec in specs {
+        let prototype = require_prototype(&spec.name, dict)?;
+        let entity = WorldEntity {
+            position: point2(spec.position.x, spec.position.y),
+            direction: 0,
+            prototype,
+        };
+        let (width, height) = entity.prototype.footprint();
+        let left = entity.position.x - width as f64 / 2.0;
+        let top = entity.position.y - height as f64 / 2.0;
+        if !entity.prototype.is_pole() {
+            problems.push(format!("{} is not a pole", describe(&entity)));
+        } else if left.fract() != 0.0 || top.fract() != 0.0 {
+            problems.push(format!("{} is not aligned to tiles", describe(&entity)));
+        } else if !seen.insert((spec.name, entity.position.tile_pos())) {
+            problems.push(format!("{} is listed twice", describe(&entity)));
+        } else {
+            candidates.push(entity);
+        }
+    }
+    if !problems.is_empty() {
+        return Err(format!("Invalid --candidates file: {}", list(&problems)).into());
+    }
+    Ok(candidates)
+}
+
+/// `model` with `candidates` added, like [BpModel::with_all_candidate_poles] but at exactly these positions.
+/// Candidates may overlap each other, but not anything in `model`, nor reserved tiles.
+pub fn place(model: &BpModel, candidates: &[WorldEntity]) -> Result<BpModel, OptimizerError> {
+    let problems = candidates
+        .iter()
+        .filter(|entity| !model.can_place(entity))
+        .map(|entity| {
+            let obstacle = entity
+                .world_bbox()
+                .round_out_to_tiles()
+                .iter_tiles()
+                .find_map(|tile| model.get_at_tile(tile).next())
+                .map_or("an avoided tile".to_string(), |other| {
+                    describe(&other.entity)
+                });
+            format!("{} collides with {}", describe(entity), obstacle)
+        })
+        .collect_vec();
+    if !problems.is_empty() {
+        return Err(format!("Can't place --candidates poles: {}", list(&problems)).into());
+    }
+    let mut candidate_model = model.clone();
+    for entity in candidates {
+        candidate_model.add_overlap(entity.clone());
+    }
+    Ok(candidate_model)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prototype_data;
+
+    use super::*;
+
+    fn write_candidates(name: &str, json: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, json).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_load_and_place() {
+        let dict = prototype_data::load_prototype_data().unwrap();
+        let mut model = BpModel::new();
+        model.add_test_powerable(point2(0, 0));
+
+        let path = write_candidates(
+            "candidates_test.json",
+            r#"[{"name": "s", "position": {"x": 1.5, "y": 0.5}},
+                {"name": "big-electric-pole", "position": {"x": 3, "y": 1}}]"#,
+        );
+        let candidates = load(&path, &dict).unwrap();
+        assert_eq!(candidates.len(), 2);
+        let candidate_model = place(&model, &candidates).unwrap();
+        assert_eq!(candidate_model.all_entities().count(), 3);
+
+        let colliding = write_candidates(
+            "candidates_test_colliding.json",
+            r#"[{"name": "s", "position": {"x": 0.5, "y": 0.5}}]"#,
+        );
+        let error = place(&model, &load(&colliding, &dict).unwrap()).unwrap_err();
+        assert!(error.to_string().contains("collides with"), "{}", error);
+
+        let invalid = write_candidates(
+            "candidates_test_invalid.json",
+            r#"[{"name": "s", "position": {"x": 1, "y": 0.5}},
+                {"name": "stone-furnace", "position": {"x": 5, "y": 5}}]"#,
+        );
+        let error = load(&invalid, &dict).unwrap_err().to_string();
+        assert!(error.contains("not aligned"), "{}", error);
+        assert!(error.contains("not a pole"), "{}", error);
+
+        for path in [path, colliding, invalid] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use std::io::Write;
+
+use factorio_blueprint::Container;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::bp_io::{self, BlueprintFormat};
+use crate::error::OptimizerError;
+use crate::report::OptimizationReport;
+
+/// Starts the certificate's line in the description; the rest of the line is its JSON.
+pub const CERTIFICATE_PREFIX: &str = "factorio-opti-poles certificate: ";
+
+/// How a blueprint was optimized, for `--certificate`: written to its description,
+/// so anyone receiving it can see what produced it, and how close to optimal it is.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Certificate {
+    pub version: String,
+    /// [options_hash] of the options used.
+    pub options: String,
+    pub poles: usize,
+    pub unpowered: usize,
+    /// The MIP solver's status, e.g. "Optimal"; absent if the last solve was not a HiGHS MIP.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    /// The relative gap from optimal, in percent; absent if unknown.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gap: Option<f64>,
+}
+
+/// Starting value for [fnv1a].
+pub const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+
+/// Continues a 64-bit FNV-1a hash with `bytes`. Unlike the std hashers, it is the same across runs and builds.
+pub fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Hash of the options, stable across runs and builds (64-bit FNV-1a of their debug form), in hex.
+pub fn options_hash(options: &impl std::fmt::Debug) -> String {
+    let hash = fnv1a(FNV_OFFSET_BASIS, format!("{:?}", options).as_bytes());
+    format!("{:016x}", hash)
+}
+
+impl Certificate {
+    pub fn new(options_hash: String, report: &OptimizationReport) -> Self {
+        let mip_stats = report.mip_stats.as_ref();
+        Certificate {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            options: options_hash,
+            poles: report.poles_after.values().sum(),
+            unpowered: report.unpowered_after,
+            status: mip_stats.map(|stats| stats.status.clone()),
+            gap: mip_stats.and_then(|stats| stats.gap),
+        }
+    }
+
+    pub fn line(&self) -> String {
+        format!(
+            "{}{}",
+            CERTIFICATE_PREFIX,
+            serde_json::to_string(self).unwrap()
+        )
+    }
+
+    /// The certificate in a blueprint description, if it has one.
+    #[allow(dead_code)]
+    pub fn find(description: &str) -> Option<Certificate> {
+        description
+            .lines()
+            .find_map(|line| line.strip_prefix(CERTIFICATE_PREFIX))
+            .and_then(|json| serde_json::from_str(json).ok())
+    }
+
+    /// Adds the certificate to the description of the blueprint or book, replacing any earlier one.
+    pub fn add_to(&self, container: &mut Value) {
+        let Some(object) = container
+            .as_object_mut()
+            .and_then(|container| container.values_mut().next())
+            .and_then(Value::as_object_mut)
+        else {
+            return;
+        };
+        let description = object
+            .get("description")
+            .and_then(Value::as_str)
+            .unwrap_or("");
+        let mut lines = description
+            .lines()
+            .filter(|line| !line.starts_with(CERTIFICATE_PREFIX))
+            .collect::<Vec<_>>();
+        let line = self.line();
+        lines.push(&line);
+        let description = lines.join("\n");
+        object.insert("description".into(), description.into());
+    }
+
+    /// Encodes `container` like [bp_io::encode], with the certificate in its description.
+    pub fn encode(
+        &self,
+        writer: impl Write,
+        container: &Container,
+        format: BlueprintFormat,
+    ) -> Result<(), OptimizerError> {
+        let mut value = serde_json::to_value(container)?;
+        self.add_to(&mut value);
+        bp_io::encode_value(writer, &value, format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::mip_stats::MipStats;
+    use crate::report::EntityCounts;
+
+    #[test]
+    fn test_certificate() {
+        let report = OptimizationReport {
+            poles_after: EntityCounts::from([("small".to_string(), 10), ("medium".to_string(), 2)]),
+            mip_stats: Some(MipStats {
+                status: "Optimal".to_string(),
+                gap: Some(0.0),
+                ..MipStats::default()
+            }),
+            ..OptimizationReport::default()
+        };
+        let hash = options_hash(&("a", 1));
+        assert_eq!(hash, options_hash(&("a", 1)));
+        assert_ne!(hash, options_hash(&("a", 2)));
+        let certificate = Certificate::new(hash, &report);
+        assert_eq!(certificate.poles, 12);
+
+        let mut value =
+            json!({"blueprint": {"description": "Smelting\nfactorio-opti-poles certificate: {}"}});
+        certificate.add_to(&mut value);
+        let description = value["blueprint"]["description"].as_str().unwrap();
+        assert!(description.starts_with("Smelting\n"));
+        assert_eq!(description.lines().count(), 2);
+        assert_eq!(Certificate::find(description), Some(certificate.clone()));
+
+        let mut value = json!({"blueprint_book": {"label": "book"}});
+        certificate.add_to(&mut value);
+        assert_eq!(value["blueprint_book"]["description"], certificate.line());
+    }
+}
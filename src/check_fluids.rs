@@ -0,0 +1,133 @@
+use factorio_blueprint::objects::Blueprint;
+
+use crate::better_bp::{BlueprintEntities, EntityId};
+use crate::bp_model::BpModel;
+use crate::error::OptimizerError;
+use crate::fluid_graph::FluidGraph;
+use crate::position::MapPosition;
+use crate::prototype_data;
+use crate::report::count_by_name;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FluidProblemKind {
+    /// A pipe connected to fewer than two entities, or an underground pipe with an open end.
+    DeadEnd,
+    /// An entity that needs fluid, with none of its pipe connections connected.
+    Disconnected,
+}
+
+#[derive(Debug, Clone)]
+pub struct FluidProblem {
+    pub id: EntityId,
+    pub name: String,
+    pub position: MapPosition,
+    pub kind: FluidProblemKind,
+}
+
+/// Finds dead-ended pipes, and fluid entities with nothing connected.
+/// Entities whose fluid boxes are only used by some recipes, like assembling machines, are not reported.
+pub fn find_fluid_problems(model: &BpModel, graph: &FluidGraph) -> Vec<FluidProblem> {
+    let mut problems = vec![];
+    for entity in model.all_entities_grid_order() {
+        let Some(fluid_data) = &entity.prototype.fluid_data else {
+            continue;
+        };
+        let connected = &graph.connections[&entity.id()];
+        let kind = match entity.prototype.type_.as_str() {
+            "pipe" if graph.num_neighbors(entity.id()) < 2 => FluidProblemKind::DeadEnd,
+            "pipe-to-ground" if connected.contains(&None) => FluidProblemKind::DeadEnd,
+            "pipe" | "pipe-to-ground" => continue,
+            _ if !fluid_data.optional && connected.iter().all(Option::is_none) => {
+                FluidProblemKind::Disconnected
+            }
+            _ => continue,
+        };
+        problems.push(FluidProblem {
+            id: entity.id(),
+            name: entity.prototype.name.clone(),
+            position: entity.position,
+            kind,
+        });
+    }
+    problems
+}
+
+/// Prints dead-ended pipes and disconnected fluid entities. Gives an error if there are any.
+pub fn run_check_fluids(bp: &Blueprint) -> Result<(), OptimizerError> {
+    let prototype_data = prototype_data::load_prototype_data()?;
+    let entities = BlueprintEntities::from_blueprint(bp);
+    let model = BpModel::from_bp_entities(&entities, &prototype_data);
+    let graph = FluidGraph::new(&model);
+    println!(
+        "Checked {} entities with fluid boxes, in {} fluid networks",
+        graph.connections.len(),
+        petgraph::algo::connected_components(&graph.to_graph())
+    );
+    let problems = find_fluid_problems(&model, &graph);
+    if problems.is_empty() {
+        println!("No dead-ended pipes or disconnected fluid entities");
+        return Ok(());
+    }
+    for problem in &problems {
+        println!(
+            "  {} (entity {}) at ({}, {}): {}",
+            problem.name,
+            problem.id.0,
+            problem.position.x,
+            problem.position.y,
+            match problem.kind {
+                FluidProblemKind::DeadEnd => "dead end",
+                FluidProblemKind::Disconnected => "no pipes connected",
+            }
+        );
+    }
+    println!("Problems by entity type:");
+    for (name, count) in count_by_name(problems.iter().map(|problem| problem.name.as_str())) {
+        println!("  {:>6} {}", count, name);
+    }
+    Err(format!("{} fluid problems found", problems.len()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::point2;
+
+    use crate::better_bp::BlueprintEntityData;
+
+    use super::*;
+
+    #[test]
+    fn test_find_fluid_problems() {
+        let dict = prototype_data::load_prototype_data().unwrap();
+        let mut entities = BlueprintEntities::new();
+        let mut add = |name: &str, x: f64, y: f64| {
+            entities.add_entity(BlueprintEntityData::new(
+                name.to_string(),
+                point2(x, y),
+                None,
+            ))
+        };
+        // pump facing north: input at the south, output at the north
+        add("pump", 0.5, 0.0);
+        add("pipe", 0.5, 1.5);
+        let end = add("pipe", 0.5, 2.5);
+        add("pipe", 0.5, -1.5);
+        add("storage-tank", -0.5, -3.5);
+        let tank = add("storage-tank", 20.5, 0.5);
+        add("assembling-machine-2", 30.5, 0.5);
+        let model = BpModel::from_bp_entities(&entities, &dict);
+
+        let problems = find_fluid_problems(&model, &FluidGraph::new(&model));
+        let found = problems
+            .iter()
+            .map(|problem| (problem.id, problem.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                (end, FluidProblemKind::DeadEnd),
+                (tank, FluidProblemKind::Disconnected),
+            ]
+        );
+    }
+}
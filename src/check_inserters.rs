@@ -0,0 +1,148 @@
+use factorio_blueprint::objects::Blueprint;
+
+use crate::better_bp::{BlueprintEntities, BlueprintEntityData, EntityId};
+use crate::bp_model::{BpModel, WorldEntity};
+use crate::error::OptimizerError;
+use crate::position::{CardinalDirection, MapPosition, MapPositionExt, Rotate, TilePosition};
+use crate::prototype_data;
+use crate::report::count_by_name;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InserterEnd {
+    Pickup,
+    Drop,
+}
+
+/// An inserter whose pickup or drop position has no entity.
+#[derive(Debug, Clone)]
+pub struct InserterGap {
+    pub inserter: EntityId,
+    pub name: String,
+    pub position: MapPosition,
+    pub end: InserterEnd,
+    pub target: TilePosition,
+}
+
+/// Pickup and drop positions of an inserter, in world coordinates.
+/// Custom vectors in the blueprint (from mods) are relative to the inserter, and already rotated.
+fn inserter_targets(
+    entity: &WorldEntity,
+    data: &BlueprintEntityData,
+) -> Option<(MapPosition, MapPosition)> {
+    let inserter = entity.prototype.inserter_data?;
+    let direction = CardinalDirection::from_u8_rounding(entity.direction);
+    let pickup = data
+        .pickup_position
+        .unwrap_or_else(|| inserter.pickup_position.rotate(direction));
+    let drop = data
+        .drop_position
+        .unwrap_or_else(|| inserter.insert_position.rotate(direction));
+    Some((
+        entity.position + pickup.to_vector(),
+        entity.position + drop.to_vector(),
+    ))
+}
+
+/// Finds inserters that pick up from, or drop to, a tile with no entity.
+pub fn find_inserter_gaps(entities: &BlueprintEntities, model: &BpModel) -> Vec<InserterGap> {
+    let mut gaps = vec![];
+    for entity in model.all_entities_grid_order() {
+        let Some(data) = entities.get(entity.id()) else {
+            continue;
+        };
+        let Some((pickup, drop)) = inserter_targets(entity, data) else {
+            continue;
+        };
+        for (end, pos) in [(InserterEnd::Pickup, pickup), (InserterEnd::Drop, drop)] {
+            let target = pos.tile_pos();
+            if model
+                .get_at_tile(target)
+                .all(|other| other.id() == entity.id())
+            {
+                gaps.push(InserterGap {
+                    inserter: entity.id(),
+                    name: entity.prototype.name.clone(),
+                    position: entity.position,
+                    end,
+                    target,
+                });
+            }
+        }
+    }
+    gaps
+}
+
+/// Prints inserters with nothing to pick up from or drop to. Gives an error if there are any.
+pub fn run_check_inserters(bp: &Blueprint) -> Result<(), OptimizerError> {
+    let prototype_data = prototype_data::load_prototype_data()?;
+    let entities = BlueprintEntities::from_blueprint(bp);
+    let model = BpModel::from_bp_entities(&entities, &prototype_data);
+    let num_inserters = model
+        .all_entities()
+        .filter(|entity| entity.prototype.inserter_data.is_some())
+        .count();
+    let gaps = find_inserter_gaps(&entities, &model);
+    println!("Checked {} inserters", num_inserters);
+    if gaps.is_empty() {
+        println!("All inserters have something to pick up from and drop to");
+        return Ok(());
+    }
+    for gap in &gaps {
+        println!(
+            "  {} (entity {}) at ({}, {}): nothing to {} at tile ({}, {})",
+            gap.name,
+            gap.inserter.0,
+            gap.position.x,
+            gap.position.y,
+            match gap.end {
+                InserterEnd::Pickup => "pick up from",
+                InserterEnd::Drop => "drop to",
+            },
+            gap.target.x,
+            gap.target.y
+        );
+    }
+    println!("Gaps by inserter type:");
+    for (name, count) in count_by_name(gaps.iter().map(|gap| gap.name.as_str())) {
+        println!("  {:>6} {}", count, name);
+    }
+    Err(format!("{} inserter gaps found", gaps.len()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::point2;
+
+    use super::*;
+
+    #[test]
+    fn test_find_inserter_gaps() {
+        let dict = prototype_data::load_prototype_data().unwrap();
+        let mut entities = BlueprintEntities::new();
+        let inserter = entities.add_entity(BlueprintEntityData::new(
+            "inserter".to_string(),
+            point2(0.5, 0.5),
+            None,
+        ));
+        entities.add_entity(BlueprintEntityData::new(
+            "wooden-chest".to_string(),
+            point2(0.5, -0.5),
+            None,
+        ));
+        let model = BpModel::from_bp_entities(&entities, &dict);
+
+        let gaps = find_inserter_gaps(&entities, &model);
+        assert_eq!(gaps.len(), 1);
+        assert_eq!(gaps[0].inserter, inserter);
+        assert_eq!(gaps[0].end, InserterEnd::Drop);
+        assert_eq!(gaps[0].target, point2(0, 1));
+
+        // facing south, the chest is at the drop position, and the pickup position is empty
+        entities.get_mut(inserter).unwrap().data.direction = Some(4);
+        let model = BpModel::from_bp_entities(&entities, &dict);
+        let gaps = find_inserter_gaps(&entities, &model);
+        assert_eq!(gaps.len(), 1);
+        assert_eq!(gaps[0].end, InserterEnd::Pickup);
+        assert_eq!(gaps[0].target, point2(0, 1));
+    }
+}
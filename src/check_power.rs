@@ -0,0 +1,190 @@
+use factorio_blueprint::objects::Blueprint;
+use itertools::Itertools;
+
+use crate::better_bp::{BlueprintEntities, EntityId};
+use crate::bp_model::BpModel;
+use crate::error::OptimizerError;
+use crate::prototype_data;
+
+/// Poles connected by copper wire, and the power use of the entities they supply.
+#[derive(Debug, Clone)]
+pub struct ElectricNetwork {
+    pub poles: Vec<EntityId>,
+    /// Entities in the supply area of any of the poles, sorted by id.
+    pub entities: Vec<EntityId>,
+    /// Entities that draw power; those without energy data are counted too.
+    pub num_consumers: usize,
+    /// Max total draw, in watts.
+    pub consumption: f64,
+    /// Max total output, in watts.
+    pub production: f64,
+    /// Energy stored in accumulators, in joules.
+    pub buffer: f64,
+}
+
+impl ElectricNetwork {
+    pub fn has_source(&self) -> bool {
+        self.production > 0.0 || self.buffer > 0.0
+    }
+}
+
+/// Electric networks in the model, from pole wires and supply areas.
+/// An entity in the supply area of poles from two networks is counted in both, like in game.
+pub fn electric_networks(model: &BpModel) -> Vec<ElectricNetwork> {
+    model
+        .pole_networks()
+        .into_iter()
+        .map(|poles| {
+            let entities = poles
+                .iter()
+                .flat_map(|&id| {
+                    let pole = model.get(id).unwrap();
+                    model.powered_entities(pole.position, pole.pole_data().unwrap().0)
+                })
+                .map(|entity| entity.id())
+                .sorted()
+                .dedup()
+                .collect_vec();
+            let mut network = ElectricNetwork {
+                poles,
+                entities: vec![],
+                num_consumers: 0,
+                consumption: 0.0,
+                production: 0.0,
+                buffer: 0.0,
+            };
+            for &id in &entities {
+                let energy = model.get(id).unwrap().prototype.energy_data;
+                if !energy.is_some_and(|energy| energy.is_source()) {
+                    network.num_consumers += 1;
+                }
+                let energy = energy.unwrap_or_default();
+                network.consumption += energy.consumption;
+                network.production += energy.production;
+                network.buffer += energy.buffer;
+            }
+            network.entities = entities;
+            network
+        })
+        .collect()
+}
+
+/// Formats watts as e.g. "1.5 MW".
+pub fn format_power(watts: f64) -> String {
+    if watts >= 1e9 {
+        format!("{:.1} GW", watts / 1e9)
+    } else if watts >= 1e6 {
+        format!("{:.1} MW", watts / 1e6)
+    } else {
+        format!("{:.1} kW", watts / 1e3)
+    }
+}
+
+/// Prints the power use of each electric network. Gives an error if a network has consumers, but no
+/// generator, solar panel, or accumulator, while other networks do.
+/// If the blueprint has no power source at all, it is assumed to connect to an existing grid.
+pub fn run_check_power(bp: &Blueprint) -> Result<(), OptimizerError> {
+    let prototype_data = prototype_data::load_prototype_data()?;
+    let entities = BlueprintEntities::from_blueprint(bp);
+    let model = BpModel::from_bp_entities(&entities, &prototype_data);
+    let networks = electric_networks(&model);
+    println!("Found {} electric networks", networks.len());
+    for (i, network) in networks.iter().enumerate() {
+        println!(
+            "  Network {}: {} poles, {} consumers using up to {}, producing up to {}{}",
+            i + 1,
+            network.poles.len(),
+            network.num_consumers,
+            format_power(network.consumption),
+            format_power(network.production),
+            if network.buffer > 0.0 {
+                format!(", {:.0} MJ in accumulators", network.buffer / 1e6)
+            } else {
+                String::new()
+            }
+        );
+    }
+    let num_unpowered = model.unpowered_entities().count();
+    if num_unpowered > 0 {
+        println!("Warning: {} entities are not near any pole", num_unpowered);
+    }
+
+    if !networks.iter().any(|network| network.has_source()) {
+        println!(
+            "No generators or accumulators; assuming the blueprint connects to an existing grid"
+        );
+        return Ok(());
+    }
+    let without_source = networks
+        .iter()
+        .enumerate()
+        .filter(|(_, network)| network.num_consumers > 0 && !network.has_source())
+        .collect_vec();
+    if without_source.is_empty() {
+        println!("All networks with consumers have a power source");
+        return Ok(());
+    }
+    for (i, network) in &without_source {
+        let pole = model.get(network.poles[0]).unwrap();
+        println!(
+            "Warning: network {} (pole at ({}, {})) has {} consumers, but no generator, solar panel, or accumulator",
+            i + 1,
+            pole.position.x,
+            pole.position.y,
+            network.num_consumers
+        );
+    }
+    Err(format!(
+        "{} electric networks have no power source",
+        without_source.len()
+    )
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::point2;
+
+    use crate::better_bp::BlueprintEntityData;
+
+    use super::*;
+
+    #[test]
+    fn test_electric_networks() {
+        let dict = prototype_data::load_prototype_data().unwrap();
+        let mut entities = BlueprintEntities::new();
+        let mut add = |name: &str, x: f64, y: f64| {
+            entities.add_entity(BlueprintEntityData::new(
+                name.to_string(),
+                point2(x, y),
+                None,
+            ))
+        };
+        let pole1 = add("small-electric-pole", 0.5, 0.5);
+        let pole2 = add("small-electric-pole", 5.5, 0.5);
+        add("solar-panel", 2.5, 2.5);
+        // a separate network, with no power source
+        let pole3 = add("small-electric-pole", 20.5, 0.5);
+        add("assembling-machine-1", 22.5, 0.5);
+        entities.add_cable_connection(pole1, pole2);
+        let model = BpModel::from_bp_entities(&entities, &dict);
+
+        let networks = electric_networks(&model);
+        assert_eq!(networks.len(), 2);
+        let (first, second) = networks
+            .iter()
+            .partition::<Vec<_>, _>(|network| network.poles.contains(&pole1));
+        assert_eq!(first[0].poles.len(), 2);
+        assert_eq!(first[0].production, 60e3);
+        assert_eq!(first[0].num_consumers, 0);
+        assert_eq!(second[0].poles, vec![pole3]);
+        assert_eq!(second[0].consumption, 75e3);
+        assert!(!second[0].has_source());
+    }
+
+    #[test]
+    fn test_format_power() {
+        assert_eq!(format_power(75e3), "75.0 kW");
+        assert_eq!(format_power(5.82e6), "5.8 MW");
+    }
+}
@@ -0,0 +1,253 @@
+use factorio_blueprint::objects::Blueprint;
+use hashbrown::HashMap;
+use itertools::Itertools;
+use petgraph::unionfind::UnionFind;
+
+use crate::better_bp::{BlueprintEntities, EntityId};
+use crate::bp_model::{BpModel, ModelEntity, RailEnd};
+use crate::error::OptimizerError;
+use crate::position::{direction8_vector, MapPosition, MapPositionExt};
+use crate::prototype_data;
+use crate::report::count_by_name;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RailProblemKind {
+    /// A rail end with no rail joining it, away from the edge of the blueprint.
+    OpenEnd,
+    /// A signal or train stop with no rail beside it, in the direction it faces.
+    NoRail,
+    /// A signal or train stop on the left of the rail; trains only see them on their right.
+    WrongSide,
+}
+
+#[derive(Debug, Clone)]
+pub struct RailProblem {
+    pub id: EntityId,
+    pub name: String,
+    pub position: MapPosition,
+    pub kind: RailProblemKind,
+}
+
+/// Rails, grouped into connected tracks.
+pub fn rail_networks(model: &BpModel) -> Vec<Vec<EntityId>> {
+    let rails = model
+        .all_entities()
+        .filter_map(|entity| Some((entity.id(), entity.rail_ends()?)))
+        .sorted_by_key(|(id, _)| *id)
+        .collect_vec();
+    let mut by_end: HashMap<(i32, i32), Vec<(usize, RailEnd)>> = HashMap::new();
+    for (i, (_, ends)) in rails.iter().enumerate() {
+        for end in ends {
+            by_end
+                .entry(end.half_tile_position)
+                .or_default()
+                .push((i, *end));
+        }
+    }
+    let mut union_find = UnionFind::new(rails.len());
+    for ends in by_end.values() {
+        for ((i, a), (j, b)) in ends.iter().tuple_combinations() {
+            if a.joins(b) {
+                union_find.union(*i, *j);
+            }
+        }
+    }
+    rails
+        .iter()
+        .enumerate()
+        .into_group_map_by(|(i, _)| union_find.find(*i))
+        .into_values()
+        .map(|network| network.into_iter().map(|(_, (id, _))| *id).collect_vec())
+        .sorted_by_key(|network| network[0])
+        .collect()
+}
+
+/// Rail ends with no other rail joining them, excluding those on the edge of the blueprint,
+/// which are usually meant to join the next copy of a tiled blueprint.
+fn open_rail_ends(model: &BpModel) -> Vec<(EntityId, RailEnd)> {
+    let ends = model
+        .all_entities()
+        .filter_map(|entity| Some((entity.id(), entity.rail_ends()?)))
+        .flat_map(|(id, ends)| ends.map(|end| (id, end)))
+        .collect_vec();
+    let by_position = ends
+        .iter()
+        .map(|(_, end)| (end.half_tile_position, end))
+        .into_group_map();
+    let area = model.get_bounding_box();
+    ends.iter()
+        .filter(|(_, end)| {
+            !by_position[&end.half_tile_position]
+                .iter()
+                .any(|other| end.joins(other))
+        })
+        .filter(|(_, end)| {
+            let tile = end.position().tile_pos();
+            tile.x > area.min.x + 1
+                && tile.y > area.min.y + 1
+                && tile.x < area.max.x - 2
+                && tile.y < area.max.y - 2
+        })
+        .copied()
+        .collect()
+}
+
+/// If there is a rail at `position` running along `direction`, either way.
+fn has_rail_along(model: &BpModel, position: MapPosition, direction: u8) -> bool {
+    model.get_at_tile(position.tile_pos()).any(|entity| {
+        entity
+            .rail_ends()
+            .is_some_and(|ends| ends.iter().any(|end| end.direction % 4 == direction % 4))
+    })
+}
+
+/// Checks that a signal or stop facing the direction trains travel has the rail on its left,
+/// `distance` away from its center.
+fn check_side(model: &BpModel, entity: &ModelEntity, distance: f64) -> Option<RailProblemKind> {
+    let direction = entity.direction % 8;
+    let left = direction8_vector(direction + 6) * distance;
+    if has_rail_along(model, entity.position + left, direction) {
+        None
+    } else if has_rail_along(model, entity.position - left, direction) {
+        Some(RailProblemKind::WrongSide)
+    } else {
+        Some(RailProblemKind::NoRail)
+    }
+}
+
+/// Finds open rail ends, and signals and train stops that are missing a rail or on the wrong side of it.
+/// Signal positions beside diagonal rails are approximate.
+pub fn find_rail_problems(model: &BpModel) -> Vec<RailProblem> {
+    let mut problems = open_rail_ends(model)
+        .into_iter()
+        .map(|(id, end)| RailProblem {
+            id,
+            name: model.get(id).unwrap().prototype.name.clone(),
+            position: end.position(),
+            kind: RailProblemKind::OpenEnd,
+        })
+        .collect_vec();
+    for entity in model.all_entities_grid_order() {
+        let problem = match entity.prototype.type_.as_str() {
+            "rail-signal" | "rail-chain-signal" => {
+                let distance = if entity.direction % 2 == 0 { 1.5 } else { 1.0 };
+                check_side(model, entity, distance)
+            }
+            "train-stop" => check_side(model, entity, 2.0),
+            _ => None,
+        };
+        if let Some(kind) = problem {
+            problems.push(RailProblem {
+                id: entity.id(),
+                name: entity.prototype.name.clone(),
+                position: entity.position,
+                kind,
+            });
+        }
+    }
+    problems
+}
+
+/// Prints rail problems. Gives an error if there are any.
+pub fn run_check_rails(bp: &Blueprint) -> Result<(), OptimizerError> {
+    let prototype_data = prototype_data::load_prototype_data()?;
+    let entities = BlueprintEntities::from_blueprint(bp);
+    let model = BpModel::from_bp_entities(&entities, &prototype_data);
+    let networks = rail_networks(&model);
+    println!(
+        "Checked {} rails in {} separate tracks",
+        networks.iter().map(Vec::len).sum::<usize>(),
+        networks.len()
+    );
+    let problems = find_rail_problems(&model);
+    if problems.is_empty() {
+        println!("No open rail ends, or misplaced signals or train stops");
+        return Ok(());
+    }
+    for problem in &problems {
+        println!(
+            "  {} (entity {}) at ({}, {}): {}",
+            problem.name,
+            problem.id.0,
+            problem.position.x,
+            problem.position.y,
+            match problem.kind {
+                RailProblemKind::OpenEnd => "rail ends here",
+                RailProblemKind::NoRail => "no rail beside it",
+                RailProblemKind::WrongSide => "on the left side of the rail; trains won't see it",
+            }
+        );
+    }
+    println!("Problems by entity type:");
+    for (name, count) in count_by_name(problems.iter().map(|problem| problem.name.as_str())) {
+        println!("  {:>6} {}", count, name);
+    }
+    Err(format!("{} rail problems found", problems.len()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::point2;
+
+    use crate::better_bp::BlueprintEntityData;
+
+    use super::*;
+
+    fn add(
+        entities: &mut BlueprintEntities,
+        name: &str,
+        x: f64,
+        y: f64,
+        direction: u8,
+    ) -> EntityId {
+        entities.add_entity(BlueprintEntityData::new(
+            name.to_string(),
+            point2(x, y),
+            Some(direction),
+        ))
+    }
+
+    #[test]
+    fn test_rail_networks() {
+        let dict = prototype_data::load_prototype_data().unwrap();
+        let mut entities = BlueprintEntities::new();
+        // a vertical track, then a curve to the north-west, then a diagonal piece
+        let rails = [
+            add(&mut entities, "straight-rail", 1.0, 7.0, 0),
+            add(&mut entities, "straight-rail", 1.0, 5.0, 0),
+            add(&mut entities, "curved-rail", 0.0, 0.0, 0),
+            add(&mut entities, "straight-rail", -3.0, -3.0, 1),
+        ];
+        let separate = add(&mut entities, "straight-rail", 21.0, 1.0, 2);
+        let model = BpModel::from_bp_entities(&entities, &dict);
+
+        assert_eq!(rail_networks(&model), vec![rails.to_vec(), vec![separate]]);
+    }
+
+    #[test]
+    fn test_signals_and_stops() {
+        let dict = prototype_data::load_prototype_data().unwrap();
+        let mut entities = BlueprintEntities::new();
+        for y in (-9..=9).step_by(2) {
+            add(&mut entities, "straight-rail", 1.0, y as f64, 0);
+        }
+        // trains going north see things on the east
+        add(&mut entities, "rail-signal", 2.5, 0.5, 0);
+        let wrong_side = add(&mut entities, "rail-chain-signal", -0.5, 0.5, 0);
+        add(&mut entities, "train-stop", 3.0, 5.0, 0);
+        let no_rail = add(&mut entities, "train-stop", 7.0, 5.0, 0);
+        let model = BpModel::from_bp_entities(&entities, &dict);
+
+        let problems = find_rail_problems(&model)
+            .into_iter()
+            .map(|problem| (problem.id, problem.kind))
+            .collect_vec();
+        assert_eq!(
+            problems,
+            vec![
+                (wrong_side, RailProblemKind::WrongSide),
+                (no_rail, RailProblemKind::NoRail),
+            ]
+        );
+    }
+}
@@ -0,0 +1,95 @@
+use clap::ValueEnum;
+
+use crate::bp_model::WorldEntity;
+use crate::error::OptimizerError;
+use crate::parse_tuple;
+
+/// How strongly to align big poles and substations to the chunk grid.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChunkAlign {
+    /// Aligned poles cost a little less; see [MISALIGNED_COST].
+    Prefer,
+    /// Only aligned candidates are placed. Existing poles are still candidates.
+    Require,
+}
+
+/// Added to the cost of a misaligned pole; less than a pole, so alignment never costs an extra pole.
+pub const MISALIGNED_COST: f64 = 0.05;
+
+const CHUNK_SIZE: i32 = 32;
+
+/// Positions repeating every `period` tiles, which divides the 32-tile chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkGrid {
+    pub period: i32,
+    /// Tile position of the top-left corner of aligned poles, relative to a chunk corner.
+    pub offset: (i32, i32),
+}
+
+impl ChunkGrid {
+    /// From `--chunk-period` and `--chunk-offset`.
+    pub fn parse(period: i32, offset: &str) -> Result<ChunkGrid, OptimizerError> {
+        if period <= 0 || CHUNK_SIZE % period != 0 {
+            return Err(
+                format!("--chunk-period must divide {}, got {}", CHUNK_SIZE, period).into(),
+            );
+        }
+        let (x, y) = parse_tuple(offset)?;
+        Ok(ChunkGrid {
+            period,
+            offset: (x.round() as i32, y.round() as i32),
+        })
+    }
+
+    /// Only poles 2 tiles or wider (big poles, substations) are aligned.
+    pub fn applies_to(&self, entity: &WorldEntity) -> bool {
+        entity.prototype.is_pole() && entity.prototype.footprint().0 >= 2
+    }
+
+    /// If the pole's top-left tile is on the grid. Blueprint coordinates are taken as world coordinates,
+    /// as when the blueprint is placed with absolute grid snapping.
+    pub fn is_aligned(&self, entity: &WorldEntity) -> bool {
+        let (width, height) = entity.prototype.footprint();
+        let left = (entity.position.x - width as f64 / 2.0).round() as i32;
+        let top = (entity.position.y - height as f64 / 2.0).round() as i32;
+        (left - self.offset.0).rem_euclid(self.period) == 0
+            && (top - self.offset.1).rem_euclid(self.period) == 0
+    }
+
+    /// If the entity is a pole this applies to, and is not on the grid.
+    pub fn is_misaligned(&self, entity: &WorldEntity) -> bool {
+        self.applies_to(entity) && !self.is_aligned(entity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::point2;
+
+    use crate::prototype_data;
+
+    use super::*;
+
+    #[test]
+    fn test_is_aligned() {
+        let dict = prototype_data::load_prototype_data().unwrap();
+        let grid = ChunkGrid::parse(16, "1,1").unwrap();
+        let substation = |x: f64, y: f64| WorldEntity {
+            prototype: dict["substation"].clone(),
+            position: point2(x, y),
+            direction: 0,
+        };
+        assert!(grid.is_aligned(&substation(2.0, 2.0)));
+        assert!(grid.is_aligned(&substation(-14.0, 34.0)));
+        assert!(grid.is_misaligned(&substation(3.0, 2.0)));
+
+        let small_pole = WorldEntity {
+            prototype: dict["small-electric-pole"].clone(),
+            position: point2(3.5, 2.5),
+            direction: 0,
+        };
+        assert!(!grid.is_misaligned(&small_pole));
+
+        assert!(ChunkGrid::parse(18, "0,0").is_err());
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::{HashMap, HashSet};
+
+use clap::ValueEnum;
+use itertools::Itertools;
+use petgraph::unionfind::UnionFind;
+
+use crate::better_bp::{BlueprintEntities, ConnectionPointId, EntityId, WireColor};
+use crate::prototype_data::EntityPrototypeDict;
+
+/// Which circuit network wires to carry over from the input poles to the new poles.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CarryCircuit {
+    Red,
+    Green,
+    Both,
+}
+
+impl CarryCircuit {
+    pub fn colors(self) -> &'static [WireColor] {
+        match self {
+            CarryCircuit::Red => &[WireColor::Red],
+            CarryCircuit::Green => &[WireColor::Green],
+            CarryCircuit::Both => &[WireColor::Red, WireColor::Green],
+        }
+    }
+}
+
+/// Circuit wires on poles, recorded before the poles are replaced.
+#[derive(Debug, Default)]
+pub struct PoleCircuitWires {
+    /// Colors of wires that connected poles to each other.
+    pub colors: HashSet<WireColor>,
+    /// Connection points of other entities that were wired to a pole.
+    pub attached: Vec<(ConnectionPointId, WireColor)>,
+}
+
+/// Finds circuit wires of the given colors on `poles`.
+pub fn pole_circuit_wires(
+    entities: &BlueprintEntities,
+    poles: &HashSet<EntityId>,
+    colors: &[WireColor],
+) -> PoleCircuitWires {
+    let mut result = PoleCircuitWires::default();
+    for pole in poles.iter().sorted() {
+        let Some(entity) = entities.get(*pole) else {
+            continue;
+        };
+        for connection in entity.connection_pt(false).iter() {
+            if !colors.contains(&connection.color) {
+                continue;
+            }
+            if poles.contains(&connection.dest.entity_id) {
+                result.colors.insert(connection.color);
+            } else {
+                result.attached.push((connection.dest, connection.color));
+            }
+        }
+    }
+    result
+        .attached
+        .sort_by_key(|(pt, color)| (pt.entity_id, pt.circuit_id, *color == WireColor::Green));
+    result.attached.dedup();
+    result
+}
+
+/// Recreates `wires` on `new_poles`: poles are wired along a minimum spanning tree of their cable connections,
+/// and attached entities are wired to the closest pole in reach.
+/// Returns the number of attached entities that could not be reconnected.
+pub fn restore_pole_circuit_wires(
+    entities: &mut BlueprintEntities,
+    new_poles: &[EntityId],
+    wires: &PoleCircuitWires,
+    dict: &EntityPrototypeDict,
+) -> usize {
+    let pole_point = |entity_id| ConnectionPointId {
+        entity_id,
+        circuit_id: false,
+    };
+    let position = |entities: &BlueprintEntities, id: EntityId| entities.get(id).unwrap().position;
+    let index = new_poles
+        .iter()
+        .enumerate()
+        .map(|(i, id)| (*id, i))
+        .collect::<HashMap<_, _>>();
+
+    let bp: &BlueprintEntities = entities;
+    let index_ref = &index;
+    let edges = new_poles
+        .iter()
+        .flat_map(|&id| {
+            bp.get(id)
+                .unwrap()
+                .neighbours
+                .iter()
+                .flatten()
+                .filter(move |&&other| id < other && index_ref.contains_key(&other))
+                .map(move |&other| (id, other))
+        })
+        .sorted_by(|&(a1, b1), &(a2, b2)| {
+            let len1 = (position(bp, a1) - position(bp, b1)).square_length();
+            let len2 = (position(bp, a2) - position(bp, b2)).square_length();
+            len1.total_cmp(&len2).then((a1, b1).cmp(&(a2, b2)))
+        })
+        .collect_vec();
+    let mut union_find = UnionFind::new(new_poles.len());
+    for (a, b) in edges {
+        if union_find.union(index[&a], index[&b]) {
+            for color in wires
+                .colors
+                .iter()
+                .sorted_by_key(|c| **c == WireColor::Green)
+            {
+                entities.add_wire_connection(pole_point(a), pole_point(b), *color);
+            }
+        }
+    }
+
+    let mut num_unconnected = 0;
+    for &(point, color) in &wires.attached {
+        let Some(entity_pos) = entities.get(point.entity_id).map(|e| e.position) else {
+            continue;
+        };
+        let closest = new_poles
+            .iter()
+            .map(|&pole| (pole, (position(entities, pole) - entity_pos).length()))
+            .filter(|&(pole, dist)| {
+                let name = entities.get(pole).unwrap().name.as_str();
+                dict[name]
+                    .pole_data
+                    .is_some_and(|data| dist <= data.wire_distance)
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+        match closest {
+            Some((pole, _)) => {
+                entities.add_wire_connection(point, pole_point(pole), color);
+            }
+            None => num_unconnected += 1,
+        }
+    }
+    num_unconnected
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::point2;
+
+    use crate::better_bp::BlueprintEntityData;
+    use crate::prototype_data;
+
+    use super::*;
+
+    #[test]
+    fn test_carry_circuit() {
+        let dict = prototype_data::load_prototype_data().unwrap();
+        let mut entities = BlueprintEntities::new();
+        let mut add = |name: &str, x: f64| {
+            entities.add_entity(BlueprintEntityData::new(
+                name.to_string(),
+                point2(x, 0.5),
+                None,
+            ))
+        };
+        let old1 = add("small-electric-pole", 0.5);
+        let old2 = add("small-electric-pole", 5.5);
+        let lamp = add("small-lamp", 7.5);
+        let wire = |entity_id| ConnectionPointId {
+            entity_id,
+            circuit_id: false,
+        };
+        entities.add_cable_connection(old1, old2);
+        entities.add_wire_connection(wire(old1), wire(old2), WireColor::Red);
+        entities.add_wire_connection(wire(old2), wire(lamp), WireColor::Red);
+
+        let wires = pole_circuit_wires(
+            &entities,
+            &HashSet::from([old1, old2]),
+            CarryCircuit::Both.colors(),
+        );
+        assert_eq!(wires.colors, HashSet::from([WireColor::Red]));
+        assert_eq!(wires.attached, [(wire(lamp), WireColor::Red)]);
+
+        entities.retain(|entity| entity.id() != old1 && entity.id() != old2);
+        let new_poles = [1.5, 4.5, 8.5].map(|x| {
+            entities.add_entity(BlueprintEntityData::new(
+                "small-electric-pole".to_string(),
+                point2(x, 0.5),
+                None,
+            ))
+        });
+        for (a, b) in new_poles.iter().tuple_windows() {
+            entities.add_cable_connection(*a, *b);
+        }
+        let unconnected = restore_pole_circuit_wires(&mut entities, &new_poles, &wires, &dict);
+        assert_eq!(unconnected, 0);
+
+        let wired_to = |id| {
+            entities
+                .get(id)
+                .unwrap()
+                .connection_pt(false)
+                .iter()
+                .map(|connection| connection.dest.entity_id)
+                .sorted()
+                .collect_vec()
+        };
+        assert_eq!(wired_to(new_poles[0]), [new_poles[1]]);
+        assert_eq!(wired_to(new_poles[1]), [new_poles[0], new_poles[2]]);
+        assert_eq!(wired_to(new_poles[2]), [lamp, new_poles[1]]);
+        assert_eq!(wired_to(lamp), [new_poles[2]]);
+    }
+}
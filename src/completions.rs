@@ -0,0 +1,74 @@
+use std::io::Write;
+
+use clap::builder::{PossibleValue, PossibleValuesParser};
+use clap::{Arg, CommandFactory, Parser};
+use clap_complete::Shell;
+use itertools::Itertools;
+
+use crate::error::OptimizerError;
+use crate::prototype_data::{self, EntityPrototypeDict};
+use crate::{Args, POLE_NAME_ALIASES};
+
+#[derive(Parser, Debug)]
+pub struct CompletionsArgs {
+    #[arg(value_enum, help = "Shell to generate the completion script for")]
+    shell: Shell,
+}
+
+/// Pole names in `dict`, and their aliases, as completion candidates.
+fn pole_names(dict: &EntityPrototypeDict) -> Vec<PossibleValue> {
+    let aliases = POLE_NAME_ALIASES
+        .iter()
+        .filter(|(_, name)| dict.0.contains_key(**name))
+        .sorted()
+        .map(|(alias, name)| PossibleValue::new(*alias).help(*name));
+    let names = dict
+        .0
+        .values()
+        .filter(|prototype| prototype.is_pole())
+        .map(|prototype| prototype.name.clone())
+        .sorted()
+        .map(PossibleValue::new);
+    aliases.chain(names).collect()
+}
+
+/// Writes the completion script for `shell`, completing pole names for arguments that take them.
+/// Only the generated command is changed; parsing still accepts anything.
+pub fn write_completions(
+    shell: Shell,
+    dict: &EntityPrototypeDict,
+    out: &mut impl Write,
+) -> Result<(), OptimizerError> {
+    let poles = pole_names(dict);
+    let complete_poles = |arg: Arg| arg.value_parser(PossibleValuesParser::new(poles.clone()));
+    let mut command = Args::command().mut_subcommand("optimize", |optimize| {
+        optimize
+            .mut_arg("POLES", complete_poles)
+            .mut_arg("keep_input_poles", complete_poles)
+            .mut_arg("trunk", complete_poles)
+    });
+    let name = command.get_name().to_string();
+    clap_complete::generate(shell, &mut command, name, out);
+    Ok(())
+}
+
+/// Prints the completion script, using pole names from the loaded prototype data, including `--mod-pack`.
+pub fn run_completions(args: &CompletionsArgs) -> Result<(), OptimizerError> {
+    let dict = prototype_data::load_prototype_data()?;
+    write_completions(args.shell, &dict, &mut std::io::stdout())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_completes_pole_names() {
+        let dict = prototype_data::load_prototype_data().unwrap();
+        let mut out = vec![];
+        write_completions(Shell::Bash, &dict, &mut out).unwrap();
+        let script = String::from_utf8(out).unwrap();
+        assert!(script.contains("check-power"));
+        assert!(script.contains("substation"));
+    }
+}
@@ -0,0 +1,230 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use euclid::point2;
+use factorio_blueprint::objects::Blueprint;
+use factorio_blueprint::Container;
+use itertools::Itertools;
+
+use crate::better_bp::{BlueprintEntities, BlueprintTiles};
+use crate::bp_io;
+use crate::error::OptimizerError;
+use crate::roundtrip;
+
+#[derive(Parser, Debug)]
+pub struct DedupArgs {
+    #[arg(
+        name = "DIR",
+        help = "Directory of blueprint files (.txt or .json), searched recursively"
+    )]
+    dir: PathBuf,
+}
+
+/// Hashes of a blueprint's contents, moved so its entities start at the origin,
+/// so copies placed elsewhere, renumbered, or relabeled hash the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Fingerprint {
+    /// Entities with all their settings and wires, and tiles.
+    pub full: u64,
+    /// Only entity names, directions, and positions.
+    pub layout: u64,
+}
+
+fn hash(value: impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn fingerprint(bp: &Blueprint) -> Result<Fingerprint, OptimizerError> {
+    let mut entities = BlueprintEntities::from_blueprint(bp);
+    let min = entities
+        .entities
+        .values()
+        .map(|entity| entity.position)
+        .reduce(|a, b| a.min(b));
+    if let Some(min) = min {
+        entities.translate(-min.to_vector());
+    }
+    let mut moved = bp.clone();
+    entities.write_to_blueprint(&mut moved);
+    // bp_io::decode already normalized older formats, so equal blueprints have equal JSON
+    let (canonical_entities, _) = roundtrip::canonical(&moved, |_| false)?;
+
+    let tiles = BlueprintTiles::from_blueprint(bp);
+    let tile_min = tiles
+        .iter()
+        .map(|(_, position)| position)
+        .reduce(|a, b| a.min(b))
+        .unwrap_or(point2(0, 0));
+    let tiles = tiles
+        .iter()
+        .map(|(name, position)| (name, (position - tile_min).to_tuple()))
+        .sorted()
+        .collect_vec();
+
+    let layout = entities
+        .entities
+        .values()
+        .map(|entity| {
+            (
+                entity.name.as_str(),
+                entity.direction,
+                (entity.position.x * 2.0).round() as i64,
+                (entity.position.y * 2.0).round() as i64,
+            )
+        })
+        .sorted()
+        .collect_vec();
+    Ok(Fingerprint {
+        full: hash((canonical_entities, tiles)),
+        layout: hash(layout),
+    })
+}
+
+/// Groups of names with the same full fingerprint, then groups with the same layout
+/// that aren't all the same; each group sorted, and with more than one name.
+fn duplicate_groups(fingerprints: &[(String, Fingerprint)]) -> (Vec<Vec<&str>>, Vec<Vec<&str>>) {
+    fn names(group: Vec<(&str, u64)>) -> Vec<&str> {
+        group.into_iter().map(|(name, _)| name).sorted().collect()
+    }
+    let groups = |key: &dyn Fn(&Fingerprint) -> u64| {
+        fingerprints
+            .iter()
+            .map(|(name, fingerprint)| (key(fingerprint), (name.as_str(), fingerprint.full)))
+            .into_group_map()
+            .into_values()
+            .filter(|group| group.len() > 1)
+            .collect_vec()
+    };
+    let exact = groups(&|fingerprint| fingerprint.full)
+        .into_iter()
+        .map(names)
+        .sorted()
+        .collect();
+    let near = groups(&|fingerprint| fingerprint.layout)
+        .into_iter()
+        .filter(|group| group.iter().map(|(_, full)| full).unique().count() > 1)
+        .map(names)
+        .sorted()
+        .collect();
+    (exact, near)
+}
+
+fn blueprint_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), OptimizerError> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            blueprint_files(&path, files)?;
+        } else if matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("txt" | "json")
+        ) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Fingerprints every blueprint in `dir`, including those in books,
+/// and prints duplicates, and blueprints with the same layout but different settings.
+pub fn run_dedup(args: &DedupArgs) -> Result<(), OptimizerError> {
+    let mut files = vec![];
+    blueprint_files(&args.dir, &mut files)?;
+    files.sort();
+    let mut fingerprints = vec![];
+    for file in &files {
+        let mut container = match bp_io::decode(BufReader::new(File::open(file)?)) {
+            Ok(container) => container,
+            Err(err) => {
+                println!("Warning: skipping {}: {}", file.display(), err);
+                continue;
+            }
+        };
+        let is_book = matches!(container, Container::BlueprintBook(_));
+        for (i, bp) in bp_io::blueprints_mut(&mut container)
+            .into_iter()
+            .enumerate()
+        {
+            let name = if is_book {
+                format!("{} (blueprint {})", file.display(), i + 1)
+            } else {
+                file.display().to_string()
+            };
+            fingerprints.push((name, fingerprint(bp)?));
+        }
+    }
+    println!(
+        "Read {} blueprints from {} files",
+        fingerprints.len(),
+        files.len()
+    );
+
+    let (exact, near) = duplicate_groups(&fingerprints);
+    if exact.is_empty() && near.is_empty() {
+        println!("No duplicates found");
+    }
+    for group in exact {
+        println!("Duplicates:");
+        for name in group {
+            println!("  {}", name);
+        }
+    }
+    for group in near {
+        println!("Same layout, different settings, wires, or tiles:");
+        for name in group {
+            println!("  {}", name);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::vec2;
+
+    use crate::read_blueprint;
+
+    use super::*;
+
+    #[test]
+    fn test_fingerprint() {
+        let bp = read_blueprint(&PathBuf::from("test-data/bigtest.txt")).unwrap();
+        let original = fingerprint(&bp).unwrap();
+
+        let mut entities = BlueprintEntities::from_blueprint(&bp);
+        entities.translate(vec2(10.0, -4.0));
+        let mut moved = bp.clone();
+        entities.write_to_blueprint(&mut moved);
+        moved.label = "copy".to_string();
+        assert_eq!(fingerprint(&moved).unwrap(), original);
+
+        let entity = entities.entities.values_mut().next().unwrap();
+        entity.data.recipe = Some("not-a-recipe".to_string());
+        entities.write_to_blueprint(&mut moved);
+        let changed = fingerprint(&moved).unwrap();
+        assert_eq!(changed.layout, original.layout);
+        assert_ne!(changed.full, original.full);
+    }
+
+    #[test]
+    fn test_duplicate_groups() {
+        let fingerprint = |full, layout| Fingerprint { full, layout };
+        let fingerprints = [
+            ("a", fingerprint(1, 1)),
+            ("b", fingerprint(1, 1)),
+            ("c", fingerprint(2, 1)),
+            ("d", fingerprint(3, 3)),
+            ("e", fingerprint(4, 4)),
+            ("f", fingerprint(4, 4)),
+        ]
+        .map(|(name, fingerprint)| (name.to_string(), fingerprint));
+        let (exact, near) = duplicate_groups(&fingerprints);
+        assert_eq!(exact, vec![vec!["a", "b"], vec!["e", "f"]]);
+        assert_eq!(near, vec![vec!["a", "b", "c"]]);
+    }
+}
@@ -0,0 +1,149 @@
+use std::fmt::{Display, Formatter};
+
+use euclid::vec2;
+use hashbrown::HashSet;
+use itertools::Itertools;
+
+use crate::better_bp::EntityId;
+use crate::bp_model::{BpModel, WorldEntity};
+use crate::pole_graph::CandPoleGraph;
+use crate::position::{BoundingBoxExt, IterTiles, TileBoundingBox, TileSpaceExt};
+use crate::prototype_data::EntityPrototypeRef;
+
+/// Why no candidate pole can power an entity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UncoverableReason {
+    /// No pole of any of the given types fits anywhere with the entity in its supply area.
+    NoFreeTile,
+    /// A pole fits with the entity in its supply area, but only outside the area candidates are placed in.
+    OutsideCandidateArea,
+}
+
+impl Display for UncoverableReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UncoverableReason::NoFreeTile => write!(f, "no free tile for a pole within supply range"),
+            UncoverableReason::OutsideCandidateArea => write!(
+                f,
+                "free tiles in supply range are only outside the candidate area; try a larger --expand"
+            ),
+        }
+    }
+}
+
+/// An entity that uses power, but is not in the supply area of any candidate pole.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Uncoverable {
+    pub id: EntityId,
+    pub reason: UncoverableReason,
+}
+
+/// Entities in `model` that use power, but that no pole in `candidates` powers.
+/// A set cover with these entities is infeasible.
+pub fn uncoverable_entities(
+    model: &BpModel,
+    candidates: &CandPoleGraph,
+    pole_types: &[EntityPrototypeRef],
+) -> Vec<Uncoverable> {
+    let covered = candidates
+        .node_weights()
+        .flat_map(|node| node.powered_entities.iter().copied())
+        .collect::<HashSet<_>>();
+    model
+        .all_entities()
+        .filter(|entity| entity.uses_power() && !covered.contains(&entity.id()))
+        .map(|entity| Uncoverable {
+            id: entity.id(),
+            reason: if pole_types
+                .iter()
+                .any(|pole| pole_fits_in_range(model, pole, entity.id()))
+            {
+                UncoverableReason::OutsideCandidateArea
+            } else {
+                UncoverableReason::NoFreeTile
+            },
+        })
+        .sorted_by_key(|uncoverable| uncoverable.id)
+        .collect()
+}
+
+/// If a `pole` can be placed anywhere with the entity `id` in its supply area.
+fn pole_fits_in_range(model: &BpModel, pole: &EntityPrototypeRef, id: EntityId) -> bool {
+    let pole_data = pole.pole_data.unwrap();
+    let width = pole.tile_width;
+    let reach = pole_data.supply_radius.ceil() as i32 + width as i32;
+    let area: TileBoundingBox = model
+        .get(id)
+        .unwrap()
+        .world_bbox()
+        .round_out_to_tiles()
+        .inflate(reach, reach);
+    area.iter_tiles().any(|top_left| {
+        let candidate = WorldEntity {
+            position: top_left.corner_map_pos() + vec2(width as f64 / 2.0, width as f64 / 2.0),
+            direction: 0,
+            prototype: pole.clone(),
+        };
+        model.can_place(&candidate)
+            && model
+                .powered_entities(candidate.position, pole_data)
+                .any(|entity| entity.id() == id)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::point2;
+
+    use crate::bp_model::test_util::small_pole_prototype;
+    use crate::pole_graph::ToCandidatePoleGraph;
+
+    use super::*;
+
+    #[test]
+    fn test_uncoverable_entities() {
+        let mut model = BpModel::new();
+        // a 5x5 block; small poles have supply radius 2.5
+        let mut center = None;
+        for x in -2..=2 {
+            for y in -2..=2 {
+                let id = model.add_test_powerable(point2(x, y));
+                if x == 0 && y == 0 {
+                    center = Some(id);
+                }
+            }
+        }
+        let pole = small_pole_prototype();
+        let candidates = model
+            .with_all_candidate_poles(model.get_bounding_box(), &[&pole])
+            .get_maximally_connected_pole_graph()
+            .0
+            .to_cand_pole_graph(&model);
+        assert_eq!(candidates.node_count(), 0);
+
+        let uncoverable = uncoverable_entities(&model, &candidates, &[pole]);
+        assert_eq!(uncoverable.len(), 25);
+        for entity in uncoverable {
+            let expected = if Some(entity.id) == center {
+                UncoverableReason::NoFreeTile
+            } else {
+                UncoverableReason::OutsideCandidateArea
+            };
+            assert_eq!(entity.reason, expected);
+        }
+    }
+
+    #[test]
+    fn test_no_uncoverable_with_room() {
+        let mut model = BpModel::new();
+        model.add_test_powerable(point2(0, 0));
+        model.add_test_powerable(point2(3, 0));
+        let pole = small_pole_prototype();
+        let candidates = model
+            .with_all_candidate_poles(model.get_bounding_box(), &[&pole])
+            .get_maximally_connected_pole_graph()
+            .0
+            .to_cand_pole_graph(&model);
+        assert!(uncoverable_entities(&model, &candidates, &[pole]).is_empty());
+    }
+}
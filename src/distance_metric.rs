@@ -0,0 +1,87 @@
+use euclid::point2;
+
+use crate::error::OptimizerError;
+use crate::position::MapPosition;
+
+/// How `--distance-cost` measures how far a pole is, for `--distance-metric`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DistanceMetric {
+    /// Euclidean distance from the center.
+    L2,
+    /// Manhattan distance from the center.
+    L1,
+    /// Chebyshev distance from the center: the larger of the x and y distances.
+    Linf,
+    /// Distance from the line through two points, e.g. along a main bus. The center isn't used.
+    Line(MapPosition, MapPosition),
+}
+
+impl DistanceMetric {
+    /// From `l1`, `l2`, `linf`, or `line:x1,y1,x2,y2`.
+    pub fn parse(input: &str) -> Result<DistanceMetric, OptimizerError> {
+        match input.trim() {
+            "l2" => return Ok(DistanceMetric::L2),
+            "l1" => return Ok(DistanceMetric::L1),
+            "linf" => return Ok(DistanceMetric::Linf),
+            _ => {}
+        }
+        let error = || {
+            OptimizerError::from(format!(
+                "Expected distance metric l1, l2, linf, or line:x1,y1,x2,y2, got '{}'",
+                input
+            ))
+        };
+        let coordinates = input
+            .trim()
+            .strip_prefix("line:")
+            .ok_or_else(error)?
+            .split(',')
+            .map(|part| part.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| error())?;
+        let [x1, y1, x2, y2] = coordinates[..] else {
+            return Err(error());
+        };
+        Ok(DistanceMetric::Line(point2(x1, y1), point2(x2, y2)))
+    }
+
+    pub fn distance(&self, position: MapPosition, center: MapPosition) -> f64 {
+        let offset = position - center;
+        match *self {
+            DistanceMetric::L2 => offset.length(),
+            DistanceMetric::L1 => offset.x.abs() + offset.y.abs(),
+            DistanceMetric::Linf => offset.x.abs().max(offset.y.abs()),
+            DistanceMetric::Line(start, end) => {
+                let direction = end - start;
+                if direction.square_length() == 0.0 {
+                    return (position - start).length();
+                }
+                direction.cross(position - start).abs() / direction.length()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distance() {
+        let center = point2(1.0, 1.0);
+        let position = point2(4.0, 5.0);
+        let distance = |metric: &str| {
+            DistanceMetric::parse(metric)
+                .unwrap()
+                .distance(position, center)
+        };
+        assert_eq!(distance("l2"), 5.0);
+        assert_eq!(distance("l1"), 7.0);
+        assert_eq!(distance("linf"), 4.0);
+        // a vertical bus at x = 2
+        assert_eq!(distance("line:2,0,2,10"), 2.0);
+        assert_eq!(distance("line: 0,0, 0,0"), position.to_vector().length());
+        assert!(DistanceMetric::parse("line:1,2,3").is_err());
+        assert!(DistanceMetric::parse("l3").is_err());
+    }
+}
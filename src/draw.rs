@@ -1,19 +1,232 @@
+use std::collections::{HashMap, HashSet};
 use std::ops::{Add, Mul};
+use std::path::Path;
 
 use euclid::{vec2, Vector2D};
 use petgraph::prelude::*;
 use plotters::coord::Shift;
 use plotters::prelude::*;
+use serde::Deserialize;
 
 use crate::bp_model::{BpModel, WorldEntity};
+use crate::error::OptimizerError;
 use crate::pole_graph::WithPosition;
 use crate::position::*;
 
-static POLE_COLOR: HSLColor = HSLColor(0.02, 0.95, 0.4);
-static BLOCKER_COLOR: RGBColor = RGBColor(0, (0.38 * 255.0) as u8, (0.57 * 255.0) as u8);
-static POWERABLE_COLOR: HSLColor = HSLColor(0.3, 0.8, 0.35);
-static BACKGROUND_COLOR: RGBColor = RGBColor(80, 80, 90);
-static POLE_GRAPH_COLOR: RGBColor = RGBColor(20, 212, 255);
+/// Colors of a visualization.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Theme {
+    pub background: RGBColor,
+    pub pole: RGBColor,
+    /// Entities that use power.
+    pub powerable: RGBColor,
+    /// Other entities.
+    pub blocker: RGBColor,
+    pub outline: RGBColor,
+    pub pole_graph: RGBColor,
+    pub highlight: RGBColor,
+    /// Poles only in the output, for `--vis-compare`.
+    pub added_pole: RGBColor,
+    /// Poles only in the input, for `--vis-compare`; drawn faded.
+    pub removed_pole: RGBColor,
+    /// Colors by prototype type, e.g. "assembling-machine", overriding the above.
+    pub entity_types: HashMap<String, RGBColor>,
+}
+
+pub const BUILTIN_THEMES: [&str; 4] = ["default", "dark", "light", "colorblind"];
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            background: RGBColor(80, 80, 90),
+            pole: RGBColor(199, 28, 5),
+            powerable: RGBColor(46, 161, 18),
+            blocker: RGBColor(0, 96, 145),
+            outline: BLACK,
+            pole_graph: RGBColor(20, 212, 255),
+            highlight: RGBColor(255, 0, 0),
+            added_pole: RGBColor(255, 220, 0),
+            removed_pole: RGBColor(230, 230, 230),
+            entity_types: HashMap::new(),
+        }
+    }
+}
+
+/// A theme in a TOML file. Colors not given are taken from the `base` theme.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct ThemeFile {
+    base: Option<String>,
+    background: Option<String>,
+    pole: Option<String>,
+    powerable: Option<String>,
+    blocker: Option<String>,
+    outline: Option<String>,
+    pole_graph: Option<String>,
+    highlight: Option<String>,
+    added_pole: Option<String>,
+    removed_pole: Option<String>,
+    #[serde(default)]
+    entity_types: HashMap<String, String>,
+}
+
+/// Parses a color like "#1e90ff".
+fn parse_color(input: &str) -> Result<RGBColor, OptimizerError> {
+    let hex = input.strip_prefix('#').unwrap_or(input);
+    let channel = |i: usize| {
+        hex.get(i..i + 2)
+            .and_then(|c| u8::from_str_radix(c, 16).ok())
+    };
+    match (hex.len(), channel(0), channel(2), channel(4)) {
+        (6, Some(r), Some(g), Some(b)) => Ok(RGBColor(r, g, b)),
+        _ => Err(format!("Invalid color {:?}; expected e.g. \"#1e90ff\"", input).into()),
+    }
+}
+
+impl Theme {
+    pub fn builtin(name: &str) -> Option<Theme> {
+        let default = Theme::default();
+        match name {
+            "default" => Some(default),
+            "dark" => Some(Theme {
+                background: RGBColor(18, 18, 22),
+                pole: RGBColor(230, 80, 40),
+                powerable: RGBColor(40, 120, 30),
+                blocker: RGBColor(50, 60, 75),
+                outline: RGBColor(5, 5, 5),
+                pole_graph: RGBColor(0, 200, 255),
+                ..default
+            }),
+            "light" => Some(Theme {
+                background: RGBColor(240, 240, 236),
+                pole: RGBColor(210, 50, 20),
+                powerable: RGBColor(120, 200, 110),
+                blocker: RGBColor(165, 180, 195),
+                outline: RGBColor(90, 90, 90),
+                pole_graph: RGBColor(0, 90, 200),
+                added_pole: RGBColor(200, 150, 0),
+                removed_pole: RGBColor(40, 40, 40),
+                ..default
+            }),
+            // Okabe-Ito palette, distinguishable with the common kinds of color blindness
+            "colorblind" => Some(Theme {
+                background: RGBColor(60, 60, 60),
+                pole: RGBColor(230, 159, 0),
+                powerable: RGBColor(0, 114, 178),
+                blocker: RGBColor(150, 150, 150),
+                pole_graph: RGBColor(240, 228, 66),
+                highlight: RGBColor(204, 121, 167),
+                added_pole: RGBColor(0, 158, 115),
+                removed_pole: RGBColor(86, 180, 233),
+                ..default
+            }),
+            _ => None,
+        }
+    }
+
+    /// A built-in theme by name, or a theme from a TOML file.
+    pub fn load(name_or_path: &str) -> Result<Theme, OptimizerError> {
+        if let Some(theme) = Theme::builtin(name_or_path) {
+            return Ok(theme);
+        }
+        let path = Path::new(name_or_path);
+        if !path.exists() {
+            return Err(format!(
+                "Unknown theme {:?}; expected one of {}, or a TOML file",
+                name_or_path,
+                BUILTIN_THEMES.join(", ")
+            )
+            .into());
+        }
+        Theme::from_toml(&std::fs::read_to_string(path)?)
+            .map_err(|err| format!("Invalid theme file {:?}: {}", path, err).into())
+    }
+
+    fn from_toml(content: &str) -> Result<Theme, OptimizerError> {
+        let file: ThemeFile = toml::from_str(content).map_err(|err| err.to_string())?;
+        let base = file.base.as_deref().unwrap_or("default");
+        let mut theme =
+            Theme::builtin(base).ok_or_else(|| format!("Unknown base theme {:?}", base))?;
+        for (color, value) in [
+            (&mut theme.background, &file.background),
+            (&mut theme.pole, &file.pole),
+            (&mut theme.powerable, &file.powerable),
+            (&mut theme.blocker, &file.blocker),
+            (&mut theme.outline, &file.outline),
+            (&mut theme.pole_graph, &file.pole_graph),
+            (&mut theme.highlight, &file.highlight),
+            (&mut theme.added_pole, &file.added_pole),
+            (&mut theme.removed_pole, &file.removed_pole),
+        ] {
+            if let Some(value) = value {
+                *color = parse_color(value)?;
+            }
+        }
+        for (type_, value) in &file.entity_types {
+            theme
+                .entity_types
+                .insert(type_.clone(), parse_color(value)?);
+        }
+        Ok(theme)
+    }
+
+    fn entity_color(&self, entity: &WorldEntity) -> RGBColor {
+        if let Some(color) = self.entity_types.get(&entity.prototype.type_) {
+            *color
+        } else if entity.prototype.pole_data.is_some() {
+            self.pole
+        } else if entity.uses_power() {
+            self.powerable
+        } else {
+            self.blocker
+        }
+    }
+}
+
+/// How a pole changed from the input to the output, for `--vis-compare`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoleChange {
+    Kept,
+    Added,
+    Removed,
+}
+
+/// Classifies the poles of the input and the output; poles of the same name and position are kept.
+pub fn pole_changes<'a>(
+    input_poles: &'a [WorldEntity],
+    output_poles: &'a [WorldEntity],
+) -> Vec<(&'a WorldEntity, PoleChange)> {
+    let key = |entity: &WorldEntity| {
+        (
+            entity.prototype.name.clone(),
+            (entity.position.x * 2.0).round() as i64,
+            (entity.position.y * 2.0).round() as i64,
+        )
+    };
+    let input_keys = input_poles.iter().map(key).collect::<HashSet<_>>();
+    let output_keys = output_poles.iter().map(key).collect::<HashSet<_>>();
+    let removed = input_poles
+        .iter()
+        .filter(|pole| !output_keys.contains(&key(pole)))
+        .map(|pole| (pole, PoleChange::Removed));
+    let output = output_poles.iter().map(|pole| {
+        let change = if input_keys.contains(&key(pole)) {
+            PoleChange::Kept
+        } else {
+            PoleChange::Added
+        };
+        (pole, change)
+    });
+    removed.chain(output).collect()
+}
+
+/// `area` grown to include the tiles of `markers`, so none are cut off.
+pub fn area_with_markers(area: TileBoundingBox, markers: &[MapPosition]) -> TileBoundingBox {
+    markers.iter().fold(area, |area, marker| {
+        let tile = marker.tile_pos();
+        area.union(&TileBoundingBox::new(tile, tile + vec2(1, 1)))
+    })
+}
 
 pub struct Drawing<'a> {
     pub area: DrawingArea<BitMapBackend<'a>, Shift>,
@@ -21,6 +234,7 @@ pub struct Drawing<'a> {
     tile_shift: Vector2D<f64, MapSpace>,
     scale: i32,
     padding: i32,
+    theme: Theme,
 }
 
 impl<'a> Drawing<'a> {
@@ -34,16 +248,25 @@ impl<'a> Drawing<'a> {
         let size = (area.size() * pixels_per_tile).to_vector() + vec2(padding, padding) * 2;
         let dim = size.to_u32().to_tuple();
         let root = BitMapBackend::<'a, _>::new(name, dim).into_drawing_area();
-        root.fill(&BACKGROUND_COLOR)?;
+        let theme = Theme::default();
+        root.fill(&theme.background)?;
 
         Ok(Drawing {
             area: root,
             tile_shift,
             scale: pixels_per_tile,
             padding,
+            theme,
         })
     }
 
+    /// Uses the theme's colors; call before drawing anything.
+    pub fn with_theme(mut self, theme: Theme) -> Result<Self, Box<dyn std::error::Error>> {
+        self.area.fill(&theme.background)?;
+        self.theme = theme;
+        Ok(self)
+    }
+
     pub fn map_pos(&self, pt: MapPosition) -> (i32, i32) {
         pt.add(-self.tile_shift)
             .mul(self.scale as f64)
@@ -59,20 +282,25 @@ impl<'a> Drawing<'a> {
     pub fn draw_entity(&self, entity: &WorldEntity) -> Result<(), Box<dyn std::error::Error>> {
         let bounds = self.map_bbox(entity.world_bbox().round_out());
 
-        let color = match entity.prototype.pole_data {
-            Some(_) => POLE_COLOR.to_rgba(),
-            None => {
-                if entity.uses_power() {
-                    POWERABLE_COLOR.to_rgba()
-                } else {
-                    BLOCKER_COLOR.to_rgba()
-                }
-            }
-        };
+        let color = self.theme.entity_color(entity);
         self.area.draw(&Rectangle::new(bounds, color.filled()))?;
         self.area.draw(&Rectangle::new(
             bounds,
-            BLACK.stroke_width((0.1 * self.scale as f64).ceil() as u32),
+            self.theme
+                .outline
+                .stroke_width((0.1 * self.scale as f64).ceil() as u32),
+        ))?;
+        Ok(())
+    }
+
+    /// Draws a thick outline around the entity, e.g. to mark a problem.
+    pub fn highlight_entity(&self, entity: &WorldEntity) -> Result<(), Box<dyn std::error::Error>> {
+        let bounds = self.map_bbox(entity.world_bbox().round_out());
+        self.area.draw(&Rectangle::new(
+            bounds,
+            self.theme
+                .highlight
+                .stroke_width((0.3 * self.scale as f64).ceil() as u32),
         ))?;
         Ok(())
     }
@@ -98,7 +326,9 @@ impl<'a> Drawing<'a> {
                 graph[from].position(),
                 graph[to].position(),
                 ShapeStyle::from(
-                    POLE_GRAPH_COLOR.stroke_width((width * self.scale as f64).ceil() as u32),
+                    self.theme
+                        .pole_graph
+                        .stroke_width((width * self.scale as f64).ceil() as u32),
                 ),
             )?;
         }
@@ -122,7 +352,157 @@ impl<'a> Drawing<'a> {
         Ok(())
     }
 
+    /// Draws the output's entities and wires, with its poles marked by how they changed from the input,
+    /// and the input's removed poles faded. Returns the numbers of kept, added, and removed poles.
+    pub fn draw_comparison(
+        &self,
+        input: &BpModel,
+        output: &BpModel,
+    ) -> Result<[usize; 3], Box<dyn std::error::Error>> {
+        let poles = |model: &BpModel| {
+            model
+                .poles()
+                .map(|entity| entity.entity.clone())
+                .collect::<Vec<_>>()
+        };
+        let (input_poles, output_poles) = (poles(input), poles(output));
+        self.draw_all_entities(
+            output
+                .all_entities()
+                .filter(|entity| !entity.prototype.is_pole())
+                .map(|entity| &entity.entity),
+        )?;
+        self.draw_pole_graph(&output.get_current_pole_graph().0, 0.2)?;
+        let mut counts = [0; 3];
+        for (pole, change) in pole_changes(&input_poles, &output_poles) {
+            let bounds = self.map_bbox(pole.world_bbox().round_out());
+            let style = match change {
+                PoleChange::Kept => self.theme.pole.filled(),
+                PoleChange::Added => self.theme.added_pole.filled(),
+                PoleChange::Removed => self.theme.removed_pole.mix(0.4).filled(),
+            };
+            self.area.draw(&Rectangle::new(bounds, style))?;
+            if change != PoleChange::Removed {
+                self.area.draw(&Rectangle::new(
+                    bounds,
+                    self.theme
+                        .outline
+                        .stroke_width((0.1 * self.scale as f64).ceil() as u32),
+                ))?;
+            }
+            counts[change as usize] += 1;
+        }
+        Ok(counts)
+    }
+
+    /// Draws a crosshair at `position`, labelled with its coordinates, for `--vis-marker`.
+    pub fn draw_marker(&self, position: MapPosition) -> Result<(), Box<dyn std::error::Error>> {
+        let (x, y) = self.map_pos(position);
+        let size = 2 * self.scale;
+        let style = self
+            .theme
+            .highlight
+            .stroke_width((0.2 * self.scale as f64).ceil() as u32);
+        self.area
+            .draw(&PathElement::new(vec![(x - size, y), (x + size, y)], style))?;
+        self.area
+            .draw(&PathElement::new(vec![(x, y - size), (x, y + size)], style))?;
+        self.area.draw(&Circle::new((x, y), self.scale, style))?;
+        let font = ("sans-serif", 3 * self.scale)
+            .into_font()
+            .color(&self.theme.highlight);
+        self.area.draw(&Text::new(
+            format!("({}, {})", position.x, position.y),
+            (x + size / 2, y + size / 2),
+            font,
+        ))?;
+        Ok(())
+    }
+
     pub fn show(&self) -> Result<(), Box<dyn std::error::Error>> {
         self.area.present().map_err(Into::into)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use euclid::point2;
+
+    use super::*;
+
+    #[test]
+    fn test_builtin_themes() {
+        for name in BUILTIN_THEMES {
+            assert!(Theme::load(name).is_ok(), "{}", name);
+        }
+        assert!(Theme::load("no-such-theme").is_err());
+    }
+
+    #[test]
+    fn test_pole_changes() {
+        let pole = |x: f64| WorldEntity {
+            position: euclid::point2(x, 0.5),
+            direction: 0,
+            prototype: crate::bp_model::test_util::small_pole_prototype(),
+        };
+        let input = [pole(0.5), pole(5.5)];
+        let output = [pole(5.5), pole(10.5)];
+        let changes = pole_changes(&input, &output)
+            .into_iter()
+            .map(|(pole, change)| (pole.position.x, change))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            changes,
+            [
+                (0.5, PoleChange::Removed),
+                (5.5, PoleChange::Kept),
+                (10.5, PoleChange::Added)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_draw_marker() {
+        let path = std::env::temp_dir().join("draw_marker_test.png");
+        let marker = point2(3.5, -2.5);
+        let area = area_with_markers(TileBoundingBox::new(point2(0, 0), point2(2, 2)), &[marker]);
+        assert_eq!(area, TileBoundingBox::new(point2(0, -3), point2(4, 2)));
+        let drawing = Drawing::on_area(&path, area, 5, 10).unwrap();
+        drawing.draw_marker(marker).unwrap();
+        let (x, y) = drawing.map_pos(marker);
+        drawing.show().unwrap();
+        drop(drawing);
+
+        let image = image::open(&path).unwrap().to_rgb8();
+        let highlight = Theme::default().highlight;
+        assert_eq!(
+            image.get_pixel(x as u32 + 3, y as u32).0,
+            [highlight.0, highlight.1, highlight.2]
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_theme_from_toml() {
+        let theme = Theme::from_toml(
+            r##"
+            base = "dark"
+            pole = "#ff8000"
+
+            [entity-types]
+            assembling-machine = "#102030"
+            "##,
+        )
+        .unwrap();
+        let dark = Theme::builtin("dark").unwrap();
+        assert_eq!(theme.pole, RGBColor(255, 128, 0));
+        assert_eq!(theme.background, dark.background);
+        assert_eq!(
+            theme.entity_types["assembling-machine"],
+            RGBColor(16, 32, 48)
+        );
+
+        assert!(Theme::from_toml("pole = \"orange\"").is_err());
+        assert!(Theme::from_toml("polee = \"#000000\"").is_err());
+    }
+}
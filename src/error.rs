@@ -0,0 +1,127 @@
+use std::error::Error;
+use std::time::Duration;
+
+use good_lp::ResolutionError;
+use thiserror::Error;
+
+use crate::cancel::Cancelled;
+
+/// Errors from reading blueprints, looking up prototypes, and solving.
+///
+/// Specific failures have their own variant, so callers can react to them,
+/// e.g. retry with a longer time limit on [OptimizerError::SolverTimeout].
+#[derive(Debug, Error)]
+pub enum OptimizerError {
+    #[error("Could not read blueprint: {0}")]
+    Decode(String),
+    #[error("Unknown entity type: {0}")]
+    UnknownPrototype(String),
+    #[error("No solution exists: {0}")]
+    Infeasible(String),
+    #[error("Solver found no solution within {elapsed:.1?}; try a longer --time-limit")]
+    SolverTimeout { elapsed: Duration },
+    #[error("Solver failed: {0}")]
+    Solver(String),
+    #[error(transparent)]
+    Cancelled(#[from] Cancelled),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error("Output changed more than poles: {0}")]
+    RoundtripMismatch(String),
+    #[error("{0}")]
+    Other(String),
+}
+
+impl OptimizerError {
+    /// Process exit code for this error; 2 is left for invalid arguments, as used by clap.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            OptimizerError::Decode(_) | OptimizerError::Json(_) => 3,
+            OptimizerError::UnknownPrototype(_) => 4,
+            OptimizerError::Infeasible(_) => 5,
+            OptimizerError::SolverTimeout { .. } => 6,
+            OptimizerError::Io(_) => 7,
+            OptimizerError::Cancelled(_) => 8,
+            OptimizerError::RoundtripMismatch(_) => 9,
+            OptimizerError::Solver(_) | OptimizerError::Other(_) => 1,
+        }
+    }
+}
+
+impl From<ResolutionError> for OptimizerError {
+    fn from(err: ResolutionError) -> Self {
+        match err {
+            ResolutionError::Infeasible => {
+                OptimizerError::Infeasible("the constraints can't all be satisfied".into())
+            }
+            err => OptimizerError::Solver(err.to_string()),
+        }
+    }
+}
+
+impl From<String> for OptimizerError {
+    fn from(message: String) -> Self {
+        OptimizerError::Other(message)
+    }
+}
+
+impl From<&str> for OptimizerError {
+    fn from(message: &str) -> Self {
+        OptimizerError::Other(message.to_string())
+    }
+}
+
+impl From<std::num::ParseFloatError> for OptimizerError {
+    fn from(err: std::num::ParseFloatError) -> Self {
+        OptimizerError::Other(err.to_string())
+    }
+}
+
+impl From<std::num::ParseIntError> for OptimizerError {
+    fn from(err: std::num::ParseIntError) -> Self {
+        OptimizerError::Other(err.to_string())
+    }
+}
+
+/// For errors from dependencies without their own variant, e.g. drawing.
+impl From<Box<dyn Error>> for OptimizerError {
+    fn from(err: Box<dyn Error>) -> Self {
+        OptimizerError::Other(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolution_error() {
+        let err = OptimizerError::from(ResolutionError::Infeasible);
+        assert!(matches!(err, OptimizerError::Infeasible(_)));
+        assert_eq!(err.exit_code(), 5);
+        let err = OptimizerError::from(ResolutionError::Unbounded);
+        assert!(matches!(err, OptimizerError::Solver(_)));
+    }
+
+    #[test]
+    fn test_exit_codes_distinct() {
+        let errors = [
+            OptimizerError::Decode(String::new()),
+            OptimizerError::UnknownPrototype(String::new()),
+            OptimizerError::Infeasible(String::new()),
+            OptimizerError::SolverTimeout {
+                elapsed: Duration::ZERO,
+            },
+            OptimizerError::Io(std::io::Error::other("")),
+            OptimizerError::Cancelled(Cancelled { stage: "" }),
+            OptimizerError::RoundtripMismatch(String::new()),
+        ];
+        let codes = errors.iter().map(|err| err.exit_code()).collect::<Vec<_>>();
+        let mut unique = codes.clone();
+        unique.dedup();
+        assert_eq!(codes, unique);
+        assert!(codes.iter().all(|&code| code > 2));
+    }
+}
@@ -0,0 +1,123 @@
+use clap::Parser;
+use factorio_blueprint::objects::Blueprint;
+use itertools::Itertools;
+
+use crate::better_bp::BlueprintEntities;
+use crate::bp_model::BpModel;
+use crate::error::OptimizerError;
+use crate::output_book::is_pole;
+use crate::pipeline::{self, PipelineState};
+use crate::prototype_data::{self, EntityPrototypeDict};
+use crate::OptimizePoles;
+
+#[derive(Parser, Debug)]
+pub struct FixPowerArgs {
+    #[arg(
+        default_value = "m",
+        help = "Poles to add, separated by commas. Can use aliases: s, m, b, t",
+        name = "POLES"
+    )]
+    use_poles: Vec<String>,
+
+    #[arg(
+        short = 't',
+        long,
+        default_value_t = 120.0,
+        help = "Time limit for ILP solver"
+    )]
+    time_limit: f64,
+
+    #[arg(short, long, help = "Don't output stuff from ILP solver")]
+    quiet: bool,
+}
+
+/// Names of the pole types in `entities`, to keep all its poles.
+fn input_pole_names(entities: &BlueprintEntities, dict: &EntityPrototypeDict) -> Vec<String> {
+    entities
+        .entities
+        .values()
+        .filter(|entity| is_pole(dict, entity))
+        .map(|entity| entity.name.to_string())
+        .unique()
+        .sorted()
+        .collect()
+}
+
+/// The `optimize` options for `fix-power`: the input poles are kept, and must end up connected.
+fn optimize_args(keep: &[String], args: &FixPowerArgs) -> Result<OptimizePoles, OptimizerError> {
+    let mut command = vec![
+        "optimize".to_string(),
+        "--connect-kept-poles".to_string(),
+        "--time-limit".to_string(),
+        args.time_limit.to_string(),
+    ];
+    command.extend(args.use_poles.iter().cloned());
+    if !keep.is_empty() {
+        command.extend(["--keep-input-poles".to_string(), keep.join(",")]);
+    }
+    if args.quiet {
+        command.push("--quiet".to_string());
+    }
+    OptimizePoles::try_parse_from(command).map_err(|err| OptimizerError::from(err.to_string()))
+}
+
+/// Adds poles to `bp` until everything is powered and connected, keeping all of its poles.
+/// Returns None if there is nothing to fix.
+pub fn run_fix_power(
+    bp: Blueprint,
+    args: &FixPowerArgs,
+) -> Result<Option<PipelineState>, OptimizerError> {
+    let dict = prototype_data::load_prototype_data()?;
+    let entities = BlueprintEntities::from_blueprint(&bp);
+    let model = BpModel::from_bp_entities(&entities, &dict);
+    let unpowered = model.unpowered_entities().count();
+    let networks = model.count_pole_networks();
+    if unpowered == 0 && networks <= 1 {
+        println!("Nothing to fix: every entity is powered, and the poles are connected");
+        return Ok(None);
+    }
+    println!(
+        "Fixing {} unpowered entities and {} separate groups of poles",
+        unpowered, networks
+    );
+    let opt = optimize_args(&input_pole_names(&entities, &dict), args)?;
+    pipeline::run_fix_power_pipeline(bp, &opt).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::output_book::pole_key;
+
+    #[test]
+    fn test_fix_power_keeps_poles() {
+        let mut bp = crate::read_blueprint(&PathBuf::from("test-data/bigtest_out.txt")).unwrap();
+        let dict = prototype_data::load_prototype_data().unwrap();
+        // remove every other pole, as if part of the build was pasted over
+        let mut entities = BlueprintEntities::from_blueprint(&bp);
+        let mut count = 0;
+        entities.retain(|entity| {
+            if !is_pole(&dict, entity) {
+                return true;
+            }
+            count += 1;
+            count % 2 == 0
+        });
+        let kept = entities
+            .entities
+            .values()
+            .filter(|entity| is_pole(&dict, entity))
+            .map(pole_key)
+            .collect_vec();
+        entities.write_to_blueprint(&mut bp);
+
+        let args = FixPowerArgs::try_parse_from(["fix-power", "m", "--quiet"]).unwrap();
+        let state = run_fix_power(bp, &args).unwrap().unwrap();
+        assert_eq!(state.report.unpowered_after, 0);
+        assert_eq!(state.model.count_pole_networks(), 1);
+        let after = state.entities.entities.values().map(pole_key).collect_vec();
+        assert!(kept.iter().all(|pole| after.contains(pole)));
+    }
+}
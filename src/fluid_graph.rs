@@ -0,0 +1,182 @@
+use std::collections::BTreeMap;
+
+use euclid::{vec2, Vector2D};
+use hashbrown::HashMap;
+use petgraph::prelude::*;
+
+use crate::better_bp::EntityId;
+use crate::bp_model::{BpModel, ModelEntity, WorldEntity};
+use crate::position::{CardinalDirection, MapPositionExt, TilePosition, TileSpace};
+use crate::prototype_data::FluidConnection;
+
+/// What the pipe connections of each entity with fluid boxes are connected to.
+#[derive(Debug, Clone, Default)]
+pub struct FluidGraph {
+    /// Indexed like [crate::prototype_data::FluidData::connections]; `None` if nothing is connected.
+    pub connections: BTreeMap<EntityId, Vec<Option<EntityId>>>,
+}
+
+fn direction(entity: &WorldEntity) -> CardinalDirection {
+    CardinalDirection::from_u8_rounding(entity.direction)
+}
+
+/// The tile a connection points to, just outside the entity.
+fn target_tile(entity: &WorldEntity, connection: &FluidConnection) -> TilePosition {
+    (entity.position + connection.position(direction(entity)).to_vector()).tile_pos()
+}
+
+/// Direction an underground connection goes in, as a unit vector.
+fn underground_step(
+    entity: &WorldEntity,
+    connection: &FluidConnection,
+) -> Vector2D<i32, TileSpace> {
+    let offset = connection.position(direction(entity));
+    vec2(offset.x.round() as i32, offset.y.round() as i32)
+}
+
+/// The entity on the target tile, if it has a connection pointing back at this entity.
+fn above_ground_neighbor(
+    model: &BpModel,
+    entity: &ModelEntity,
+    connection: &FluidConnection,
+) -> Option<EntityId> {
+    let target = target_tile(entity, connection);
+    model
+        .get_at_tile(target)
+        .filter(|other| other.id() != entity.id())
+        .find(|other| {
+            let Some(fluid_data) = &other.prototype.fluid_data else {
+                return false;
+            };
+            fluid_data
+                .connections
+                .iter()
+                .filter(|back| back.max_underground_distance.is_none())
+                .any(|back| {
+                    let back_tile = target_tile(other, back);
+                    let offset = back_tile - target;
+                    offset.x.abs() + offset.y.abs() == 1
+                        && model
+                            .get_at_tile(back_tile)
+                            .any(|this| this.id() == entity.id())
+                })
+        })
+        .map(|other| other.id())
+}
+
+/// The nearest underground pipe of the same type in line, if it faces back at this one.
+/// One facing the same way blocks the connection.
+fn underground_neighbor(
+    model: &BpModel,
+    entity: &ModelEntity,
+    connection: &FluidConnection,
+    max_distance: u32,
+) -> Option<EntityId> {
+    let step = underground_step(entity, connection);
+    let start = entity.position.tile_pos();
+    for distance in 1..=max_distance as i32 {
+        for other in model.get_at_tile(start + step * distance) {
+            if other.prototype != entity.prototype {
+                continue;
+            }
+            let other_step = other
+                .prototype
+                .fluid_data
+                .iter()
+                .flat_map(|fluid_data| &fluid_data.connections)
+                .find(|back| back.max_underground_distance.is_some())
+                .map(|back| underground_step(other, back));
+            if other_step == Some(-step) {
+                return Some(other.id());
+            }
+            if other_step == Some(step) {
+                return None;
+            }
+        }
+    }
+    None
+}
+
+impl FluidGraph {
+    pub fn new(model: &BpModel) -> Self {
+        let connections = model
+            .all_entities()
+            .filter_map(|entity| {
+                let fluid_data = entity.prototype.fluid_data.as_ref()?;
+                let connected = fluid_data
+                    .connections
+                    .iter()
+                    .map(|connection| match connection.max_underground_distance {
+                        Some(distance) => underground_neighbor(model, entity, connection, distance),
+                        None => above_ground_neighbor(model, entity, connection),
+                    })
+                    .collect();
+                Some((entity.id(), connected))
+            })
+            .collect();
+        FluidGraph { connections }
+    }
+
+    /// Entities with fluid boxes, with an edge for each pair connected by a pipe connection.
+    pub fn to_graph(&self) -> UnGraph<EntityId, ()> {
+        let mut graph = UnGraph::new_undirected();
+        let nodes = self
+            .connections
+            .keys()
+            .map(|&id| (id, graph.add_node(id)))
+            .collect::<HashMap<_, _>>();
+        for (id, connected) in &self.connections {
+            for other in connected.iter().flatten() {
+                if id < other {
+                    graph.update_edge(nodes[id], nodes[other], ());
+                }
+            }
+        }
+        graph
+    }
+
+    /// Number of distinct entities connected to `id`.
+    pub fn num_neighbors(&self, id: EntityId) -> usize {
+        let mut neighbors = self.connections[&id].iter().flatten().collect::<Vec<_>>();
+        neighbors.sort();
+        neighbors.dedup();
+        neighbors.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::point2;
+
+    use crate::better_bp::{BlueprintEntities, BlueprintEntityData};
+    use crate::prototype_data;
+
+    use super::*;
+
+    #[test]
+    fn test_fluid_graph() {
+        let dict = prototype_data::load_prototype_data().unwrap();
+        let mut entities = BlueprintEntities::new();
+        let mut add = |name: &str, y: f64, direction: Option<u8>| {
+            entities.add_entity(BlueprintEntityData::new(
+                name.to_string(),
+                point2(0.5, y),
+                direction,
+            ))
+        };
+        let pipe = add("pipe", -0.5, None);
+        // facing north: above ground to the north, underground to the south
+        let under1 = add("pipe-to-ground", 0.5, None);
+        let under2 = add("pipe-to-ground", 5.5, Some(4));
+        // facing south, but out of range
+        let under3 = add("pipe-to-ground", 20.5, Some(4));
+        let model = BpModel::from_bp_entities(&entities, &dict);
+
+        let graph = FluidGraph::new(&model);
+        assert_eq!(graph.connections[&under1], vec![Some(pipe), Some(under2)]);
+        assert_eq!(graph.connections[&under2], vec![None, Some(under1)]);
+        assert_eq!(graph.connections[&under3], vec![None, None]);
+        assert_eq!(graph.num_neighbors(pipe), 1);
+        assert_eq!(petgraph::algo::connected_components(&graph.to_graph()), 2);
+    }
+}
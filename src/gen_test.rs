@@ -0,0 +1,324 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+
+use clap::Parser;
+use euclid::point2;
+use factorio_blueprint::objects::Blueprint;
+use factorio_blueprint::Container;
+use rand::prelude::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::better_bp::{BlueprintEntities, BlueprintEntityData, ConnectionPointId, WireColor};
+use crate::bp_io::{self, BlueprintFormat};
+use crate::bp_model::{BpModel, WorldEntity};
+use crate::error::OptimizerError;
+use crate::pipeline;
+use crate::prototype_data::{self, EntityPrototypeDict};
+use crate::roundtrip;
+use crate::OptimizePoles;
+
+/// Entities other than poles that are generated; powered and unpowered, of different sizes.
+const ENTITIES: &[&str] = &[
+    "assembling-machine-1",
+    "inserter",
+    "small-lamp",
+    "wooden-chest",
+    "transport-belt",
+];
+const POLES: &[&str] = &["small-electric-pole", "medium-electric-pole"];
+/// Chance that a generated entity is a pole.
+const POLE_CHANCE: f64 = 0.15;
+/// Chances that two poles in reach of each other get a copper, red, or green wire.
+const COPPER_CHANCE: f64 = 0.5;
+const CIRCUIT_CHANCE: f64 = 0.2;
+
+#[derive(Parser, Debug)]
+pub struct GenTestArgs {
+    #[arg(
+        short = 'n',
+        long,
+        default_value_t = 20,
+        help = "Number of blueprints to generate"
+    )]
+    count: u64,
+
+    #[arg(
+        long,
+        default_value_t = 24,
+        help = "Side of the square area entities are placed in, in tiles"
+    )]
+    size: i32,
+
+    #[arg(
+        long,
+        default_value_t = 0.3,
+        help = "Chance of trying to place an entity on each tile"
+    )]
+    density: f64,
+
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "Seed of the first blueprint; each next one uses the next seed"
+    )]
+    seed: u64,
+
+    #[arg(
+        short = 't',
+        long,
+        default_value_t = 10.0,
+        help = "Time limit for ILP solver, for each blueprint"
+    )]
+    time_limit: f64,
+
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Write each blueprint that fails to DIR, as gen-test-SEED.txt"
+    )]
+    save_failures: Option<PathBuf>,
+}
+
+fn empty_blueprint() -> Blueprint {
+    let value = json!({
+        "blueprint": {"item": "blueprint", "label": "gen-test", "icons": [], "entities": [],
+            "version": 281479275675648u64}
+    });
+    match Container::deserialize(&value).unwrap() {
+        Container::Blueprint(bp) => bp,
+        _ => unreachable!(),
+    }
+}
+
+/// A random blueprint of entities that don't overlap in a `size` x `size` area, with poles,
+/// and copper and circuit wires between entities in reach of each other.
+pub fn random_blueprint(
+    seed: u64,
+    size: i32,
+    density: f64,
+    dict: &EntityPrototypeDict,
+) -> Blueprint {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut model = BpModel::new();
+    let mut entities = BlueprintEntities::new();
+    for (y, x) in itertools::iproduct!(0..size, 0..size) {
+        if !rng.gen_bool(density) {
+            continue;
+        }
+        let names = if rng.gen_bool(POLE_CHANCE) {
+            POLES
+        } else {
+            ENTITIES
+        };
+        let prototype = dict[names[rng.gen_range(0..names.len())]].clone();
+        let direction = rng.gen_range(0..4) * 2;
+        let (width, height) = prototype.footprint();
+        let entity = WorldEntity {
+            position: point2(
+                x as f64 + width as f64 / 2.0,
+                y as f64 + height as f64 / 2.0,
+            ),
+            direction,
+            prototype,
+        };
+        if !model.can_place(&entity) {
+            continue;
+        }
+        entities.add_entity(BlueprintEntityData::new(
+            entity.prototype.name.clone(),
+            entity.position,
+            Some(direction).filter(|&direction| direction != 0),
+        ));
+        model.add_overlap(entity);
+    }
+
+    let reach = |name: &str| dict[name].pole_data.map(|pole| pole.wire_distance);
+    let mut wired = entities
+        .entities
+        .values()
+        .map(|entity| (entity.id(), entity.name.to_string(), entity.position))
+        .collect::<Vec<_>>();
+    wired.sort_by_key(|(id, _, _)| *id);
+    let point = |entity_id| ConnectionPointId {
+        entity_id,
+        circuit_id: false,
+    };
+    for (i, (pole, name, position)) in wired.iter().enumerate() {
+        let Some(pole_reach) = reach(name) else {
+            continue;
+        };
+        for (other, other_name, other_position) in &wired[i + 1..] {
+            let other_reach = reach(other_name);
+            let max_distance = other_reach.map_or(pole_reach, |other| other.min(pole_reach));
+            if position.distance_to(*other_position) > max_distance {
+                continue;
+            }
+            if other_reach.is_some() && rng.gen_bool(COPPER_CHANCE) {
+                entities.add_cable_connection(*pole, *other);
+            }
+            for color in [WireColor::Red, WireColor::Green] {
+                let lamp_or_pole = other_reach.is_some() || other_name == "small-lamp";
+                if lamp_or_pole && rng.gen_bool(CIRCUIT_CHANCE) {
+                    entities.add_wire_connection(point(*pole), point(*other), color);
+                }
+            }
+        }
+    }
+
+    let mut bp = empty_blueprint();
+    entities.write_to_blueprint(&mut bp);
+    bp
+}
+
+/// Problems if `bp` changes when encoded and decoded again.
+fn roundtrip_problems(bp: &Blueprint, what: &str) -> Result<Vec<String>, OptimizerError> {
+    let mut encoded = vec![];
+    bp_io::encode(
+        &mut encoded,
+        &Container::Blueprint(bp.clone()),
+        BlueprintFormat::String,
+    )?;
+    let Container::Blueprint(decoded) = bp_io::decode(encoded.as_slice())? else {
+        return Ok(vec![format!("{} decoded as a book", what)]);
+    };
+    let canonical = |bp: &Blueprint| roundtrip::canonical(bp, |_| false);
+    Ok(if canonical(bp)? == canonical(&decoded)? {
+        vec![]
+    } else {
+        vec![format!("{} changed when encoded and decoded again", what)]
+    })
+}
+
+/// Optimizes `bp`, and returns the invariants the result breaks.
+fn check_case(
+    bp: &Blueprint,
+    time_limit: f64,
+    dict: &EntityPrototypeDict,
+) -> Result<Vec<String>, OptimizerError> {
+    let mut problems = roundtrip_problems(bp, "input")?;
+    let input = BpModel::from_bp_entities(&BlueprintEntities::from_blueprint(bp), dict);
+    let opt = OptimizePoles::try_parse_from([
+        "optimize",
+        "s,m",
+        "--quiet",
+        "--allow-unpowered",
+        "--time-limit",
+        &time_limit.to_string(),
+    ])
+    .map_err(|err| err.to_string())?;
+    let state = match pipeline::run_optimize_pipeline(bp.clone(), &opt) {
+        Ok(state) => state,
+        Err(err) => {
+            problems.push(format!("optimize failed: {}", err));
+            return Ok(problems);
+        }
+    };
+    if let Some(differences) = roundtrip::differences(bp, &state.blueprint, dict)? {
+        problems.push(format!(
+            "entities other than poles changed: {}",
+            differences
+        ));
+    }
+    let unpowered_before = input.unpowered_entities().count();
+    if state.report.unpowered_after > unpowered_before {
+        problems.push(format!(
+            "{} entities unpowered, but only {} in the input",
+            state.report.unpowered_after, unpowered_before
+        ));
+    }
+    if state.model.count_pole_networks() > 1 {
+        problems.push(format!(
+            "poles form {} separate networks",
+            state.model.count_pole_networks()
+        ));
+    }
+    problems.extend(roundtrip_problems(&state.blueprint, "output")?);
+    Ok(problems)
+}
+
+/// Generates random blueprints and optimizes them, checking invariants of the result, and that nothing panics.
+pub fn run_gen_test(args: &GenTestArgs) -> Result<(), OptimizerError> {
+    let dict = prototype_data::load_prototype_data()?;
+    let mut failed = vec![];
+    for seed in args.seed..args.seed + args.count {
+        let bp = random_blueprint(seed, args.size, args.density, &dict);
+        println!("Seed {}: {} entities", seed, bp.entities.len());
+        let result =
+            panic::catch_unwind(AssertUnwindSafe(|| check_case(&bp, args.time_limit, &dict)));
+        let problems = match result {
+            Ok(Ok(problems)) => problems,
+            Ok(Err(err)) => vec![format!("error: {}", err)],
+            Err(_) => vec!["panicked".to_string()],
+        };
+        if problems.is_empty() {
+            println!("  ok");
+            continue;
+        }
+        for problem in &problems {
+            println!("  FAILED: {}", problem);
+        }
+        failed.push(seed);
+        if let Some(dir) = &args.save_failures {
+            std::fs::create_dir_all(dir)?;
+            let path = dir.join(format!("gen-test-{}.txt", seed));
+            bp_io::encode(
+                BufWriter::new(File::create(&path)?),
+                &Container::Blueprint(bp),
+                BlueprintFormat::String,
+            )?;
+            println!("  wrote {:?}", path);
+        }
+    }
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "gen-test failed for seeds {}; rerun one with --seed SEED -n 1",
+            failed
+                .iter()
+                .map(u64::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+        .into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// Random blueprints, as [random_blueprint] generates them.
+    fn blueprints(dict: EntityPrototypeDict) -> impl Strategy<Value = Blueprint> {
+        (any::<u64>(), 4..16i32, 0.1..0.6f64)
+            .prop_map(move |(seed, size, density)| random_blueprint(seed, size, density, &dict))
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+        #[test]
+        fn test_random_blueprint_roundtrip(
+            bp in blueprints(prototype_data::load_prototype_data().unwrap())
+        ) {
+            prop_assert_eq!(roundtrip_problems(&bp, "input").unwrap(), Vec::<String>::new());
+        }
+    }
+
+    #[test]
+    fn test_random_blueprint() {
+        let dict = prototype_data::load_prototype_data().unwrap();
+        let bp = random_blueprint(1, 12, 0.3, &dict);
+        assert!(!bp.entities.is_empty());
+        assert_eq!(
+            bp.entities.len(),
+            random_blueprint(1, 12, 0.3, &dict).entities.len()
+        );
+        assert_eq!(check_case(&bp, 10.0, &dict).unwrap(), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,236 @@
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use petgraph::prelude::*;
+
+use crate::bp_model::{BpModel, WorldEntity};
+use crate::error::OptimizerError;
+use crate::pole_graph::CandPoleNode;
+
+/// A pole graph node that can be exported to external graph formats.
+pub trait ExportNode {
+    /// Extra attributes for each node, as (name, GraphML type) pairs.
+    const EXTRA_ATTRIBUTES: &'static [(&'static str, &'static str)] = &[];
+
+    fn entity(&self) -> &WorldEntity;
+    /// Values for [Self::EXTRA_ATTRIBUTES], in the same order.
+    fn extra_values(&self) -> Vec<String> {
+        vec![]
+    }
+}
+
+impl ExportNode for WorldEntity {
+    fn entity(&self) -> &WorldEntity {
+        self
+    }
+}
+
+impl ExportNode for CandPoleNode {
+    const EXTRA_ATTRIBUTES: &'static [(&'static str, &'static str)] =
+        &[("powered_entities", "int")];
+
+    fn entity(&self) -> &WorldEntity {
+        &self.entity
+    }
+    fn extra_values(&self) -> Vec<String> {
+        vec![self.powered_entities.len().to_string()]
+    }
+}
+
+fn escape_xml(str: &str) -> String {
+    let mut result = String::with_capacity(str.len());
+    for c in str.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+pub fn to_graphml<N: ExportNode>(graph: &UnGraph<N, f64>) -> String {
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str("<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n");
+    let node_keys = [("name", "string"), ("x", "double"), ("y", "double")];
+    for (name, type_) in node_keys.iter().chain(N::EXTRA_ATTRIBUTES) {
+        writeln!(
+            out,
+            "  <key id=\"{name}\" for=\"node\" attr.name=\"{name}\" attr.type=\"{type_}\"/>"
+        )
+        .unwrap();
+    }
+    out.push_str("  <key id=\"weight\" for=\"edge\" attr.name=\"weight\" attr.type=\"double\"/>\n");
+    out.push_str("  <graph id=\"G\" edgedefault=\"undirected\">\n");
+    for idx in graph.node_indices() {
+        let node = &graph[idx];
+        let entity = node.entity();
+        writeln!(out, "    <node id=\"n{}\">", idx.index()).unwrap();
+        let values = [
+            escape_xml(&entity.prototype.name),
+            entity.position.x.to_string(),
+            entity.position.y.to_string(),
+        ];
+        for ((key, _), value) in node_keys
+            .iter()
+            .chain(N::EXTRA_ATTRIBUTES)
+            .zip(values.into_iter().chain(node.extra_values()))
+        {
+            writeln!(out, "      <data key=\"{key}\">{value}</data>").unwrap();
+        }
+        out.push_str("    </node>\n");
+    }
+    for edge in graph.edge_references() {
+        writeln!(
+            out,
+            "    <edge source=\"n{}\" target=\"n{}\"><data key=\"weight\">{}</data></edge>",
+            edge.source().index(),
+            edge.target().index(),
+            edge.weight()
+        )
+        .unwrap();
+    }
+    out.push_str("  </graph>\n</graphml>\n");
+    out
+}
+
+/// Graphviz format. Positions are pinned, for use with `neato -n` or `fdp`;
+/// y is flipped since Graphviz has +y up.
+pub fn to_dot<N: ExportNode>(graph: &UnGraph<N, f64>) -> String {
+    let mut out = String::new();
+    out.push_str("graph poles {\n");
+    for idx in graph.node_indices() {
+        let node = &graph[idx];
+        let entity = node.entity();
+        write!(
+            out,
+            "  n{} [label=\"{}\", pos=\"{},{}!\"",
+            idx.index(),
+            entity.prototype.name.replace('"', "\\\""),
+            entity.position.x,
+            -entity.position.y
+        )
+        .unwrap();
+        for ((key, _), value) in N::EXTRA_ATTRIBUTES.iter().zip(node.extra_values()) {
+            write!(out, ", {key}=\"{value}\"").unwrap();
+        }
+        out.push_str("];\n");
+    }
+    for edge in graph.edge_references() {
+        writeln!(
+            out,
+            "  n{} -- n{} [len={}];",
+            edge.source().index(),
+            edge.target().index(),
+            edge.weight()
+        )
+        .unwrap();
+    }
+    out.push_str("}\n");
+    out
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    GraphMl,
+    Dot,
+}
+
+impl GraphFormat {
+    pub fn from_path(path: &Path) -> Result<Self, String> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("graphml") | Some("xml") => Ok(GraphFormat::GraphMl),
+            Some("dot") | Some("gv") => Ok(GraphFormat::Dot),
+            _ => Err(format!(
+                "Unknown graph format for {:?}; use a .graphml or .dot extension",
+                path
+            )),
+        }
+    }
+}
+
+pub fn write_graph<N: ExportNode>(
+    graph: &UnGraph<N, f64>,
+    format: GraphFormat,
+    writer: &mut impl Write,
+) -> std::io::Result<()> {
+    let str = match format {
+        GraphFormat::GraphMl => to_graphml(graph),
+        GraphFormat::Dot => to_dot(graph),
+    };
+    writer.write_all(str.as_bytes())
+}
+
+/// Writes the graph to a file, with the format chosen by the file extension.
+pub fn export_graph_file<N: ExportNode>(
+    graph: &UnGraph<N, f64>,
+    path: &Path,
+) -> Result<(), OptimizerError> {
+    let format = GraphFormat::from_path(path)?;
+    write_graph(graph, format, &mut BufWriter::new(File::create(path)?))?;
+    Ok(())
+}
+
+impl BpModel {
+    /// Writes the current poles and connections to a GraphML or DOT file, depending on the extension.
+    pub fn export_pole_graph(&self, path: &Path) -> Result<(), OptimizerError> {
+        export_graph_file(&self.get_current_pole_graph().0, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::point2;
+
+    use crate::bp_model::BpModel;
+
+    use super::*;
+
+    #[test]
+    fn test_to_graphml() {
+        let mut model = BpModel::new();
+        let p1 = model.add_test_pole(point2(0, 0));
+        let p2 = model.add_test_pole(point2(3, 0));
+        model.add_cable_connection(p1, p2);
+        let (graph, _) = model.get_current_pole_graph();
+        let graphml = to_graphml(&graph);
+        assert_eq!(graphml.matches("<node ").count(), 2);
+        assert_eq!(graphml.matches("<edge ").count(), 1);
+        assert!(graphml.contains("<data key=\"name\">test</data>"));
+        assert!(graphml.contains("<data key=\"weight\">3</data>"));
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let mut model = BpModel::new();
+        let p1 = model.add_test_pole(point2(0, 0));
+        let p2 = model.add_test_pole(point2(3, 0));
+        model.add_cable_connection(p1, p2);
+        let (graph, map) = model.get_current_pole_graph();
+        let dot = to_dot(&graph);
+        assert!(dot.starts_with("graph poles {"));
+        assert!(dot.contains(&format!(
+            "n{} [label=\"test\", pos=\"0.5,-0.5!\"];",
+            map[&p1].index()
+        )));
+        assert_eq!(dot.matches(" -- ").count(), 1);
+    }
+
+    #[test]
+    fn test_graph_format_from_path() {
+        assert_eq!(
+            GraphFormat::from_path(Path::new("a.graphml")),
+            Ok(GraphFormat::GraphMl)
+        );
+        assert_eq!(
+            GraphFormat::from_path(Path::new("a.dot")),
+            Ok(GraphFormat::Dot)
+        );
+        assert!(GraphFormat::from_path(Path::new("a.png")).is_err());
+    }
+}
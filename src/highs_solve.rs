@@ -0,0 +1,243 @@
+use std::ffi::{c_char, c_int, c_void};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+use good_lp::solvers::highs::{HighsOptionValue, HighsProblem};
+use good_lp::ResolutionError;
+use highs::{HighsModelStatus, HighsSolutionStatus};
+use highs_sys::{
+    kHighsCallbackIpmInterrupt, kHighsCallbackMipInterrupt, kHighsCallbackSimplexInterrupt,
+    HighsCallbackDataIn, HighsCallbackDataOut, HighsInt,
+};
+
+use crate::cancel::{CancellationToken, Cancelled};
+use crate::error::OptimizerError;
+
+/// The HiGHS callbacks that can interrupt a solve.
+const INTERRUPT_CALLBACKS: [HighsInt; 3] = [
+    kHighsCallbackSimplexInterrupt,
+    kHighsCallbackIpmInterrupt,
+    kHighsCallbackMipInterrupt,
+];
+
+/// HiGHS options for a solve. Kept apart from the problem, as good_lp doesn't give them back:
+/// they are applied to a good_lp problem with [HighsOptions::apply],
+/// or to the HiGHS model itself by [solve_interruptible].
+#[derive(Debug, Clone, Default)]
+pub struct HighsOptions {
+    verbose: bool,
+    options: Vec<(String, HighsOptionValue)>,
+}
+
+fn check_gap(name: &str, gap: f32) -> Result<f32, OptimizerError> {
+    if gap.is_sign_negative() || gap.is_infinite() {
+        return Err(format!("{} must be a non-negative number, got {}", name, gap).into());
+    }
+    Ok(gap)
+}
+
+impl HighsOptions {
+    pub fn set_verbose(&mut self, verbose: bool) {
+        self.verbose = verbose;
+    }
+
+    pub fn set_option(mut self, key: &str, value: impl Into<HighsOptionValue>) -> Self {
+        self.options.push((key.to_string(), value.into()));
+        self
+    }
+
+    pub fn set_mip_rel_gap(self, gap: f32) -> Result<Self, OptimizerError> {
+        let gap = check_gap("--mip-rel-gap", gap)?;
+        Ok(self.set_option("mip_rel_gap", gap as f64))
+    }
+
+    pub fn set_mip_abs_gap(self, gap: f32) -> Result<Self, OptimizerError> {
+        let gap = check_gap("--mip-abs-gap", gap)?;
+        Ok(self.set_option("mip_abs_gap", gap as f64))
+    }
+
+    pub fn set_time_limit(self, seconds: f64) -> Self {
+        self.set_option("time_limit", seconds)
+    }
+
+    /// Sets the options on a problem to solve through good_lp.
+    pub fn apply(&self, mut problem: HighsProblem) -> HighsProblem {
+        problem.set_verbose(self.verbose);
+        self.options.iter().fold(problem, |problem, (key, value)| {
+            problem.set_option(key.as_str(), value.clone())
+        })
+    }
+
+    /// Sets the options on a HiGHS model, as good_lp would when solving.
+    fn apply_to_model(&self, model: &mut highs::Model) -> Result<(), OptimizerError> {
+        let verbosity = [
+            (
+                "output_flag".to_string(),
+                HighsOptionValue::Bool(self.verbose),
+            ),
+            (
+                "log_to_console".to_string(),
+                HighsOptionValue::Bool(self.verbose),
+            ),
+        ];
+        for (key, value) in verbosity.iter().chain(&self.options) {
+            let result = match value {
+                HighsOptionValue::String(value) => {
+                    model.try_set_option(key.as_str(), value.as_str())
+                }
+                HighsOptionValue::Bool(value) => model.try_set_option(key.as_str(), *value),
+                HighsOptionValue::Int(value) => model.try_set_option(key.as_str(), *value),
+                HighsOptionValue::Float(value) => model.try_set_option(key.as_str(), *value),
+            };
+            result.map_err(|_| OptimizerError::Solver(format!("Invalid HiGHS option {}", key)))?;
+        }
+        Ok(())
+    }
+}
+
+/// Called by HiGHS during a solve; tells it to stop once the [CancellationToken] flag in `user_data` is set.
+unsafe extern "C" fn interrupt_callback(
+    _callback_type: c_int,
+    _message: *const c_char,
+    _data_out: *const HighsCallbackDataOut,
+    data_in: *mut HighsCallbackDataIn,
+    user_data: *mut c_void,
+) {
+    let cancelled = &*(user_data as *const AtomicBool);
+    if !data_in.is_null() && cancelled.load(Ordering::Relaxed) {
+        (*data_in).user_interrupt = 1;
+    }
+}
+
+/// Makes HiGHS check `token` while solving `model`. The token must outlive the solve.
+fn attach_token(model: &mut highs::Model, token: &CancellationToken) -> Result<(), OptimizerError> {
+    let highs = model.as_mut_ptr();
+    let flag = token.flag() as *const AtomicBool as *mut c_void;
+    // SAFETY: `highs` is a live model, and the caller keeps the flag alive until the solve is done.
+    let failed = unsafe {
+        highs_sys::Highs_setCallback(highs, Some(interrupt_callback), flag) != 0
+            || INTERRUPT_CALLBACKS
+                .iter()
+                .any(|&callback| highs_sys::Highs_startCallback(highs, callback) != 0)
+    };
+    if failed {
+        return Err(OptimizerError::Solver(
+            "Could not set the HiGHS interrupt callback".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Solves `problem` with HiGHS directly, instead of through good_lp, so that it stops early,
+/// with [Cancelled], when `token` is cancelled. Returns the value of each column, in the order the variables were added.
+/// `initial_solution` is a value for each column, to start from.
+pub fn solve_interruptible(
+    problem: HighsProblem,
+    options: &HighsOptions,
+    initial_solution: Option<&[f64]>,
+    token: &CancellationToken,
+) -> Result<Vec<f64>, OptimizerError> {
+    let mut model = problem.try_into_inner()?;
+    options.apply_to_model(&mut model)?;
+    if let Some(values) = initial_solution {
+        model
+            .try_set_solution(Some(values), None, None, None)
+            .map_err(|status| {
+                OptimizerError::Solver(format!("Could not set the initial solution: {:?}", status))
+            })?;
+    }
+    attach_token(&mut model, token)?;
+    let start = Instant::now();
+    let solved = model
+        .try_solve()
+        .map_err(|status| OptimizerError::Solver(format!("HiGHS failed: {:?}", status)))?;
+    match solved.status() {
+        HighsModelStatus::ReachedInterrupt if token.is_cancelled() => {
+            Err(Cancelled { stage: "solve" }.into())
+        }
+        HighsModelStatus::Infeasible | HighsModelStatus::UnboundedOrInfeasible => {
+            Err(ResolutionError::Infeasible.into())
+        }
+        HighsModelStatus::Unbounded => Err(ResolutionError::Unbounded.into()),
+        status @ (HighsModelStatus::NotSet
+        | HighsModelStatus::LoadError
+        | HighsModelStatus::ModelError
+        | HighsModelStatus::PresolveError
+        | HighsModelStatus::SolveError
+        | HighsModelStatus::PostsolveError
+        | HighsModelStatus::ModelEmpty) => Err(OptimizerError::Solver(format!(
+            "HiGHS failed: {:?}",
+            status
+        ))),
+        _ if solved.primal_solution_status() == HighsSolutionStatus::Feasible => {
+            // on a time limit, the best solution so far
+            Ok(solved.get_solution().columns().to_vec())
+        }
+        HighsModelStatus::ReachedTimeLimit => Err(OptimizerError::SolverTimeout {
+            elapsed: start.elapsed(),
+        }),
+        status => Err(OptimizerError::Solver(format!(
+            "HiGHS found no solution: {:?}",
+            status
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use good_lp::*;
+
+    use super::*;
+
+    fn problem() -> (HighsProblem, Variable, Variable) {
+        let mut vars = ProblemVariables::new();
+        let a = vars.add(variable().binary());
+        let b = vars.add(variable().binary());
+        let mut problem = highs(vars.minimise(2 * a + 3 * b));
+        problem.add_constraint(constraint!(a + b >= 1));
+        (problem, a, b)
+    }
+
+    #[test]
+    fn test_solve_interruptible() {
+        let (problem, _, _) = problem();
+        let options = HighsOptions::default().set_time_limit(10.0);
+        let values =
+            solve_interruptible(problem, &options, None, &CancellationToken::new()).unwrap();
+        assert_eq!(values.len(), 2);
+        assert!(values[0] > 0.5 && values[1] < 0.5);
+    }
+
+    #[test]
+    fn test_timeout_without_solution() {
+        // too many variables for HiGHS to find a solution without any time
+        let mut vars = ProblemVariables::new();
+        let xs = vars.add_vector(variable().binary(), 200);
+        let objective: Expression = xs
+            .iter()
+            .enumerate()
+            .map(|(i, x)| (i % 7 + 1) as f64 * *x)
+            .sum();
+        let mut problem = highs(vars.minimise(objective));
+        for i in 0..xs.len() {
+            let sum: Expression = (0..5).map(|j| xs[(i * 13 + j * 31) % xs.len()]).sum();
+            problem.add_constraint(constraint!(sum >= 2));
+        }
+        let options = HighsOptions::default().set_time_limit(0.0);
+        let result = solve_interruptible(problem, &options, None, &CancellationToken::new());
+        assert!(matches!(result, Err(OptimizerError::SolverTimeout { .. })));
+    }
+
+    #[test]
+    fn test_apply_options() {
+        let mut options = HighsOptions::default()
+            .set_mip_rel_gap(0.0)
+            .unwrap()
+            .set_time_limit(10.0);
+        options.set_verbose(false);
+        let (problem, a, b) = problem();
+        let solution = options.apply(problem).solve().unwrap();
+        assert_eq!((solution.value(a), solution.value(b)), (1.0, 0.0));
+        assert!(HighsOptions::default().set_mip_abs_gap(-1.0).is_err());
+    }
+}
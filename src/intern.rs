@@ -0,0 +1,134 @@
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::fmt::{Debug, Display};
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use once_cell::sync::Lazy;
+
+/// Names from [Name::intern], i.e. of known prototypes; these are never freed.
+/// Other names, e.g. from blueprints with entities of unknown mods, aren't kept,
+/// so a long-running server doesn't keep every name it was sent.
+static NAMES: Lazy<Mutex<HashSet<Arc<str>>>> = Lazy::new(Default::default);
+
+/// A string, e.g. a prototype name, that shares one allocation with all equal interned names, across threads,
+/// so blueprints with many of the same entity don't each hold a copy, and cloning doesn't allocate.
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Name(Arc<str>);
+
+impl Name {
+    /// The interned name if there is one, otherwise a new allocation.
+    pub fn new(name: &str) -> Self {
+        match NAMES.lock().unwrap().get(name) {
+            Some(name) => Name(name.clone()),
+            None => Name(Arc::from(name)),
+        }
+    }
+
+    /// Interns `names`, so [Name::new] shares them from now on. Only for a bounded set of names, like prototype names.
+    pub fn intern<'a>(names: impl IntoIterator<Item = &'a str>) {
+        let mut interned = NAMES.lock().unwrap();
+        for name in names {
+            if !interned.contains(name) {
+                interned.insert(Arc::from(name));
+            }
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Deref for Name {
+    type Target = str;
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for Name {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Name {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for Name {
+    fn from(name: &str) -> Self {
+        Name::new(name)
+    }
+}
+
+impl From<String> for Name {
+    fn from(name: String) -> Self {
+        Name::new(&name)
+    }
+}
+
+impl From<&String> for Name {
+    fn from(name: &String) -> Self {
+        Name::new(name)
+    }
+}
+
+impl PartialEq<str> for Name {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0 == other
+    }
+}
+
+impl PartialEq<&str> for Name {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.0 == *other
+    }
+}
+
+impl PartialEq<String> for Name {
+    fn eq(&self, other: &String) -> bool {
+        &*self.0 == other.as_str()
+    }
+}
+
+impl Display for Name {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&*self.0, f)
+    }
+}
+
+impl Debug for Name {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&*self.0, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interned() {
+        Name::intern(["small-electric-pole"]);
+        let a = Name::new("small-electric-pole");
+        let b = Name::from("small-electric-pole".to_string());
+        assert_eq!(a, b);
+        assert!(Arc::ptr_eq(&a.0, &b.0));
+        assert_eq!(a, "small-electric-pole");
+        assert_ne!(a, Name::new("big-electric-pole"));
+        assert_eq!(format!("{:?}", a), "\"small-electric-pole\"");
+    }
+
+    #[test]
+    fn test_unknown_names_not_kept() {
+        let a = Name::new("test-unknown-entity");
+        let b = Name::new("test-unknown-entity");
+        assert_eq!(a, b);
+        assert!(!Arc::ptr_eq(&a.0, &b.0));
+        assert!(!NAMES.lock().unwrap().contains("test-unknown-entity"));
+    }
+}
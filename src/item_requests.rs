@@ -0,0 +1,137 @@
+use std::collections::HashSet;
+
+use factorio_blueprint::objects::ItemRequest;
+use itertools::Itertools;
+
+use crate::better_bp::{BlueprintEntities, EntityId};
+use crate::intern::Name;
+use crate::position::MapPosition;
+
+/// An item request on a pole, e.g. modules for a modded pole, recorded before the poles are replaced.
+#[derive(Debug, Clone)]
+pub struct PoleItemRequest {
+    pub name: Name,
+    pub position: MapPosition,
+    pub items: ItemRequest,
+}
+
+/// Item requests of `poles`, in order of position.
+pub fn pole_item_requests(
+    entities: &BlueprintEntities,
+    poles: &HashSet<EntityId>,
+) -> Vec<PoleItemRequest> {
+    poles
+        .iter()
+        .filter_map(|id| entities.get(*id))
+        .filter_map(|entity| {
+            Some(PoleItemRequest {
+                name: entity.name.clone(),
+                position: entity.position,
+                items: entity.items.clone()?,
+            })
+        })
+        .sorted_by(|a, b| {
+            (a.position.y, a.position.x)
+                .partial_cmp(&(b.position.y, b.position.x))
+                .unwrap()
+        })
+        .collect()
+}
+
+/// Gives each request to a new pole of the same type: the one at the same position if there is one,
+/// otherwise the nearest one without a request yet.
+/// Returns the number of requests that couldn't be given to any pole.
+pub fn restore_pole_item_requests(
+    entities: &mut BlueprintEntities,
+    new_poles: &[EntityId],
+    requests: &[PoleItemRequest],
+) -> usize {
+    let mut free = new_poles.iter().copied().collect::<HashSet<_>>();
+    let mut unmatched = vec![];
+    for request in requests {
+        let same_place = free.iter().copied().find(|id| {
+            let pole = entities.get(*id).unwrap();
+            pole.name == request.name && pole.position == request.position
+        });
+        match same_place {
+            Some(id) => {
+                free.remove(&id);
+                entities.get_mut(id).unwrap().data.items = Some(request.items.clone());
+            }
+            None => unmatched.push(request),
+        }
+    }
+    let mut dropped = 0;
+    for request in unmatched {
+        let nearest = free
+            .iter()
+            .copied()
+            .filter(|id| entities.get(*id).unwrap().name == request.name)
+            .min_by(|a, b| {
+                let distance = |id: &EntityId| {
+                    let position = entities.get(*id).unwrap().position;
+                    ((position - request.position).square_length(), *id)
+                };
+                distance(a).partial_cmp(&distance(b)).unwrap()
+            });
+        match nearest {
+            Some(id) => {
+                free.remove(&id);
+                entities.get_mut(id).unwrap().data.items = Some(request.items.clone());
+            }
+            None => dropped += 1,
+        }
+    }
+    dropped
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::point2;
+    use serde_json::json;
+
+    use crate::better_bp::BlueprintEntityData;
+
+    use super::*;
+
+    fn add(
+        entities: &mut BlueprintEntities,
+        name: &str,
+        x: f64,
+        items: Option<ItemRequest>,
+    ) -> EntityId {
+        let mut data = BlueprintEntityData::new(name.to_string(), point2(x, 0.5), None);
+        data.items = items;
+        entities.add_entity(data)
+    }
+
+    fn modules(count: u32) -> Option<ItemRequest> {
+        Some(serde_json::from_value(json!({ "speed-module": count })).unwrap())
+    }
+
+    #[test]
+    fn test_carry_item_requests() {
+        let mut entities = BlueprintEntities::new();
+        let old = [
+            add(&mut entities, "modded-pole", 0.5, modules(1)),
+            add(&mut entities, "modded-pole", 10.5, modules(2)),
+            add(&mut entities, "small-electric-pole", 20.5, modules(3)),
+            add(&mut entities, "modded-pole", 30.5, None),
+        ];
+        let requests = pole_item_requests(&entities, &HashSet::from(old));
+        assert_eq!(requests.len(), 3);
+
+        entities.retain(|entity| !old.contains(&entity.id()));
+        let new_poles = [
+            add(&mut entities, "modded-pole", 1.5, None),
+            add(&mut entities, "modded-pole", 10.5, None),
+            add(&mut entities, "medium-electric-pole", 20.5, None),
+        ];
+        let dropped = restore_pole_item_requests(&mut entities, &new_poles, &requests);
+        assert_eq!(dropped, 1);
+        let items = |id: EntityId| entities.get(id).unwrap().items.clone();
+        assert_eq!(items(new_poles[0]), modules(1));
+        assert_eq!(items(new_poles[1]), modules(2));
+        assert_eq!(items(new_poles[2]), None);
+    }
+}
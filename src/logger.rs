@@ -0,0 +1,35 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::pipeline;
+
+/// Prints warnings and errors from `log` to the console, hiding the running stage's spinner while printing.
+/// Only this crate's; the solver crates warn about e.g. reaching the time limit, which is reported anyway.
+struct ConsoleLogger;
+
+impl Log for ConsoleLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Warn && metadata.target().starts_with(env!("CARGO_CRATE_NAME"))
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let prefix = match record.level() {
+            Level::Error => "Error",
+            _ => "Warning",
+        };
+        pipeline::suspend_progress(|| println!("{}: {}", prefix, record.args()));
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: ConsoleLogger = ConsoleLogger;
+
+/// Sets the global logger; call before anything logs.
+pub fn init() {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(LevelFilter::Warn);
+    }
+}
@@ -0,0 +1,119 @@
+use std::fmt::Write;
+
+use factorio_blueprint::objects::Blueprint;
+use hashbrown::HashSet;
+use itertools::Itertools;
+
+use crate::better_bp::{BlueprintEntities, BlueprintEntity};
+use crate::output_book::{is_pole, pole_key};
+use crate::prototype_data::EntityPrototypeDict;
+
+/// Poles in `from` that aren't in `to`, sorted by name then position.
+fn poles_not_in<'a>(
+    dict: &EntityPrototypeDict,
+    from: &'a BlueprintEntities,
+    to: &BlueprintEntities,
+) -> Vec<&'a BlueprintEntity> {
+    let to_keys = to
+        .entities
+        .values()
+        .filter(|entity| is_pole(dict, entity))
+        .map(pole_key)
+        .collect::<HashSet<_>>();
+    from.entities
+        .values()
+        .filter(|entity| is_pole(dict, entity) && !to_keys.contains(&pole_key(entity)))
+        .sorted_by_key(|entity| pole_key(entity))
+        .collect()
+}
+
+/// For `--lua-script`: a `/c` console command that removes the poles `optimized` removed from `original`,
+/// and creates the ones it added, in the player's surface and force.
+/// `anchor` is the map position the blueprint's origin was pasted at.
+/// Poles built by script connect to nearby poles as if built by hand, so wires may differ from the blueprint's.
+pub fn pole_changes_script(
+    dict: &EntityPrototypeDict,
+    original: &Blueprint,
+    optimized: &Blueprint,
+    anchor: (f64, f64),
+) -> String {
+    let original = BlueprintEntities::from_blueprint(original);
+    let optimized = BlueprintEntities::from_blueprint(optimized);
+    let position = |entity: &BlueprintEntity| {
+        format!(
+            "{{{}, {}}}",
+            entity.position.x + anchor.0,
+            entity.position.y + anchor.1
+        )
+    };
+
+    let mut script = "/c local s = game.player.surface local f = game.player.force".to_string();
+    for entity in poles_not_in(dict, &original, &optimized) {
+        write!(
+            script,
+            " for _, e in pairs(s.find_entities_filtered{{name = {:?}, position = {}}}) do e.destroy() end",
+            entity.name,
+            position(entity)
+        )
+        .unwrap();
+    }
+    for entity in poles_not_in(dict, &optimized, &original) {
+        write!(
+            script,
+            " s.create_entity{{name = {:?}, position = {}, force = f}}",
+            entity.name,
+            position(entity)
+        )
+        .unwrap();
+    }
+    script.push('\n');
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use euclid::point2;
+
+    use crate::better_bp::BlueprintEntityData;
+    use crate::{prototype_data, read_blueprint};
+
+    use super::*;
+
+    #[test]
+    fn test_pole_changes_script() {
+        let dict = prototype_data::load_prototype_data().unwrap();
+        let original = read_blueprint(&PathBuf::from("test-data/bigtest.txt")).unwrap();
+        let mut entities = BlueprintEntities::from_blueprint(&original);
+        let mut removed = None;
+        entities.retain(|entity| {
+            if !is_pole(&dict, entity) || removed.is_some() {
+                return true;
+            }
+            removed = Some(entity.position);
+            false
+        });
+        let removed = removed.unwrap();
+        entities.add_entity(BlueprintEntityData::new(
+            "medium-electric-pole".to_string(),
+            point2(1000.5, 1000.5),
+            None,
+        ));
+        let mut optimized = original.clone();
+        entities.write_to_blueprint(&mut optimized);
+
+        let script = pole_changes_script(&dict, &original, &optimized, (100.0, -10.0));
+        assert!(script.starts_with("/c "));
+        assert_eq!(script.lines().count(), 1);
+        assert_eq!(script.matches("e.destroy()").count(), 1);
+        assert!(script.contains(&format!(
+            "position = {{{}, {}}}}}",
+            removed.x + 100.0,
+            removed.y - 10.0
+        )));
+        assert!(script.contains(
+            "s.create_entity{name = \"medium-electric-pole\", position = {1100.5, 990.5}, force = f}"
+        ));
+    }
+}
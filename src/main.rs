@@ -1,40 +1,104 @@
 mod algorithms;
+mod auto_poles;
 mod better_bp;
+mod book;
+mod bp_compat;
+mod bp_io;
 mod bp_model;
+mod bp_stream;
+mod budget;
+mod cancel;
+mod candidate_cache;
+mod candidates_file;
+mod certificate;
+mod check_fluids;
+mod check_inserters;
+mod check_power;
+mod check_rails;
+mod chunk_align;
+mod circuit;
+mod completions;
+mod dedup;
+mod diagnose;
+mod distance_metric;
 mod draw;
+mod error;
+mod fix_power;
+mod fluid_graph;
+mod gen_test;
+mod graph_export;
+mod highs_solve;
+mod intern;
+mod item_requests;
+mod logger;
+mod lua_script;
+mod mip_stats;
+mod notify;
+mod output_book;
+mod pareto;
 mod pole_graph;
+mod pipeline;
 mod pole_windows;
 mod position;
+mod presets;
 mod prototype_data;
+mod prune;
+mod radars;
+mod radius_query;
+mod rail_poles;
 mod rcid;
+mod recenter;
+mod report;
+mod rewire;
+mod roundtrip;
+mod self_test;
+mod serve_http;
+mod solver_state;
+mod stats;
+mod term_vis;
+mod trace;
+mod turrets;
+mod upgrade;
+mod verify;
+mod water_mask;
 
 use std::collections::HashMap;
-use std::error::Error;
 use std::fmt::Debug;
 use std::fs::File;
 use std::io::{BufReader, BufWriter};
 use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
 use clap::*;
+use euclid::point2;
 use factorio_blueprint::objects::Blueprint;
-use factorio_blueprint::{BlueprintCodec, Container};
-use good_lp::highs;
+use factorio_blueprint::Container;
 use once_cell::sync::Lazy;
-use petgraph::graph::NodeIndex;
 
-use algorithms::*;
 use better_bp::BlueprintEntities;
-use bp_model::BpModel;
-use pole_graph::*;
+use bp_io::BlueprintFormat;
+use bp_model::{BpModel, WorldEntity};
+use error::OptimizerError;
 
-use crate::position::{BoundingBoxExt, TileBoundingBox};
+use crate::certificate::Certificate;
+use crate::position::{BoundingBox, MapPosition, TileBoundingBox};
 use crate::prototype_data::{EntityPrototypeDict, EntityPrototypeRef};
+use crate::report::OptimizationReport;
 
 #[derive(Parser, Debug)]
-#[command(version, about, subcommand_required = true, next_line_help = true)]
+#[command(
+    version,
+    about,
+    subcommand_required = true,
+    next_line_help = true,
+    after_help = "Pole aliases: s = small-electric-pole, m = medium-electric-pole, b = big-electric-pole, t = substation.\nRun `<command> --help` for the options of each command; see `completions` for shell completion."
+)]
 struct Args {
-    #[arg(name = "INPUT_FILE", help = "Input blueprint txt file")]
-    input: PathBuf,
+    #[arg(
+        name = "INPUT_FILE",
+        help = "Input blueprint file; either a blueprint string or blueprint JSON"
+    )]
+    input: Option<PathBuf>,
 
     #[arg(
         short,
@@ -43,20 +107,167 @@ struct Args {
     )]
     output: Option<PathBuf>,
 
+    #[arg(
+        long,
+        value_enum,
+        default_value = "string",
+        help = "Format of the output file"
+    )]
+    output_format: BlueprintFormat,
+
     #[command(subcommand)]
     command: Command,
 
     #[arg(short, long="vis", help = "also output a png visualization of the solution", action=ArgAction::SetTrue)]
     visualize: bool,
+
+    #[arg(
+        long,
+        value_name = "THEME",
+        default_value = "default",
+        help = "Colors of the --vis png: default, dark, light, colorblind, or a TOML file of colors"
+    )]
+    vis_theme: String,
+
+    #[arg(
+        long,
+        help = "Also output a png comparing the input and output poles, named like the output with .compare.png: kept poles in the usual color, added poles highlighted, and removed poles faded",
+        action = ArgAction::SetTrue
+    )]
+    vis_compare: bool,
+
+    #[arg(
+        long,
+        value_name = "X,Y",
+        help = "Mark this position on the --vis and --vis-compare pngs with a labelled crosshair, e.g. to find an entity mentioned in the report. Can be given multiple times"
+    )]
+    vis_marker: Vec<String>,
+
+    #[arg(
+        long,
+        help = "Also print a text map of the solution, with poles and unpowered entities marked, scaled to fit the terminal",
+        action = ArgAction::SetTrue
+    )]
+    vis_term: bool,
+
+    #[arg(
+        long,
+        help = "Run as usual and print a summary of changes, but don't write any output files",
+        action = ArgAction::SetTrue
+    )]
+    dry_run: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "Also use entity data for this overhaul mod; the data file must be in data/mod-packs"
+    )]
+    mod_pack: Option<prototype_data::ModPack>,
+
+    #[arg(
+        long,
+        help = "Keep entity numbers from the input, and number new entities after them, so unchanged entities give clean text diffs. A new pole identical to a removed one gets its number back",
+        action = ArgAction::SetTrue
+    )]
+    stable_numbering: bool,
+
+    #[arg(
+        long,
+        help = "Decode the input as it is read, and each blueprint in a book separately, instead of all at once; optimize reads, optimizes and writes a book one entry at a time. Slower, but uses much less memory on very large books",
+        action = ArgAction::SetTrue
+    )]
+    low_memory: bool,
+
+    #[arg(
+        long,
+        help = "Check wire reach with the game's fixed point positions, in 1/256 tiles, instead of floats with a small tolerance. May become the default once validated against the game",
+        action = ArgAction::SetTrue
+    )]
+    exact_game_math: bool,
+
+    #[arg(
+        long,
+        value_name = "URL",
+        help = "When done, or on failure, POST a summary and the report as JSON to this webhook, e.g. a Discord or Slack webhook URL"
+    )]
+    notify_url: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Write a Chrome trace of the time spent in each stage, e.g. trace.json, to open in Perfetto or chrome://tracing"
+    )]
+    trace: Option<PathBuf>,
 }
 
 #[derive(Subcommand, Debug)]
 enum Command {
     #[command(about = "Optimize poles in a blueprint")]
-    Optimize(OptimizePoles),
+    Optimize(Box<OptimizePoles>),
+    #[command(about = "Run the optimizer on bundled blueprints, and compare results to golden files")]
+    SelfTest(self_test::SelfTestArgs),
+    #[command(
+        about = "Developer tool: generate random blueprints, optimize them, and check that nothing panics and the results keep invariants"
+    )]
+    GenTest(gen_test::GenTestArgs),
+    #[command(
+        about = "Replace entities with other entities of the same size, in a blueprint or book"
+    )]
+    Upgrade(upgrade::UpgradeArgs),
+    #[command(about = "Check that every inserter has an entity at its pickup and drop positions")]
+    CheckInserters,
+    #[command(
+        about = "Check that every electric network with consumers has a generator, solar panel, or accumulator"
+    )]
+    CheckPower,
+    #[command(about = "Check for dead-ended pipes, and fluid entities with no pipes connected")]
+    CheckFluids,
+    #[command(
+        about = "Check for open rail ends, and signals and train stops with no rail, or on the wrong side of it"
+    )]
+    CheckRails,
+    #[command(
+        about = "Print entity counts, area covered, item cost, and size of a blueprint, or each blueprint in a book"
+    )]
+    Stats,
+    #[command(
+        about = "Move all entities so the blueprint is centered at the origin, or another anchor, in a blueprint or book"
+    )]
+    Recenter(recenter::RecenterArgs),
+    #[command(
+        about = "Add radars so that every entity in the blueprint is continuously revealed; useful for walls and perimeters"
+    )]
+    Radars(radars::RadarsArgs),
+    #[command(about = "Check or add turrets so that every wall is in range of at least N turrets")]
+    Turrets(turrets::TurretsArgs),
+    #[command(
+        about = "Add the fewest poles to power every unpowered entity and reconnect separate groups of poles, without moving or removing anything; e.g. after pasting over part of a build"
+    )]
+    FixPower(fix_power::FixPowerArgs),
+    #[command(
+        about = "Remove redundant poles, keeping everything powered and the poles connected, without adding or moving any; e.g. to clean up a base built over time"
+    )]
+    Prune(prune::PruneArgs),
+    #[command(
+        about = "Replace the copper wires between poles with tidy new ones, keeping every pole where it is; e.g. to clean up auto-connected wires in a hand-built blueprint"
+    )]
+    Rewire(rewire::RewireArgs),
+    #[command(
+        about = "Print a shell completion script, including pole names from the entity data",
+        after_help = "For example, for bash: source <(factorio-opti-poles completions bash)"
+    )]
+    Completions(completions::CompletionsArgs),
+    #[command(
+        about = "Serve POST /optimize over HTTP, taking a blueprint string and options as JSON, and returning the result and report"
+    )]
+    ServeHttp(serve_http::ServeHttpArgs),
+    #[command(
+        about = "Find duplicate blueprints in a directory, including ones moved, renumbered, or relabeled, and ones with the same layout but different settings"
+    )]
+    Dedup(dedup::DedupArgs),
 }
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 struct OptimizePoles {
     #[arg(
         help = "Candidate poles to use, separated by commas. Can use aliases: s, m, b, t. If none specified, only uses a subset of existing poles",
@@ -64,12 +275,216 @@ struct OptimizePoles {
     )]
     use_poles: Vec<String>,
 
+    #[arg(
+        long,
+        help = "Bundle of options for a kind of build: megabase, outpost, compact, or pretty; or one from --preset-file. Options given explicitly take precedence"
+    )]
+    preset: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "JSON file of more presets, or overriding built-in ones, e.g. '{\"mine\": {\"poles\": [\"m\"], \"distance-cost\": 5}}'. Options: poles, pole-costs, distance-cost, distance-metric, connectivity, center-pos, wire-axis, polish"
+    )]
+    preset_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "If no POLES are given, choose candidate pole types based on how dense the blueprint is",
+        action = ArgAction::SetTrue
+    )]
+    auto_poles: bool,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["POLES", "auto_poles", "cache_dir"],
+        help = "Use exactly these candidate poles instead of trying every tile, e.g. from another tool or picked by hand. JSON array in blueprint coordinates: '[{\"name\": \"m\", \"position\": {\"x\": 0.5, \"y\": 2.5}}]'. It's an error if one collides with an entity"
+    )]
+    candidates: Option<PathBuf>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["auto_poles", "candidates"],
+        help = "Use only the input's poles as candidates, so poles are only removed, never added or moved. POLES are ignored"
+    )]
+    only_input_poles: bool,
+
     #[arg(
         short = 'r',
         long,
-        help = "Poles to remove from input blueprint before optimization; allows candidate poles to be placed in their place. Only useful if existing poles are not candidate poles"
+        visible_alias = "remove-poles",
+        help = "Entities to remove from input blueprint before optimization, separated by commas; allows candidate poles to be placed in their place. Useful for poles that are not candidate poles, lamps, etc. Can use aliases: s, m, b, t"
+    )]
+    remove_entities: Vec<String>,
+
+    #[arg(
+        long,
+        help = "Prefer placing poles where --remove-entities removed poles, to keep changes small: there is no distance cost at those positions, and they are always candidates",
+        action = ArgAction::SetTrue
+    )]
+    replace_in_place: bool,
+
+    #[arg(
+        long,
+        value_name = "NAMES",
+        help = "Entities that still block poles, but need no power, separated by commas; e.g. entities you plan to replace. Can be given multiple times"
     )]
-    remove_poles: Vec<String>,
+    treat_as_obstacle: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "NAMES",
+        help = "Entities to ignore entirely, separated by commas: they need no power, and poles may be placed over them. They are still in the output. Can be given multiple times"
+    )]
+    treat_as_free: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        help = "Only write the output if the pole cost is lower than the input's by at least this many percent. Whether or not this is given, nothing is written if the input is already optimal"
+    )]
+    only_if_better: Option<f64>,
+
+    #[arg(
+        long,
+        help = "Write a blueprint book with the original and the optimized blueprint, to compare them in-game"
+    )]
+    output_book: bool,
+
+    #[arg(
+        long,
+        requires = "output_book",
+        help = "With --output-book, also add a blueprint with only the poles that were added"
+    )]
+    book_diff: bool,
+
+    #[arg(
+        long,
+        help = "After writing, decode the output again and fail if anything other than poles changed. Entities removed by --remove-entities count as changed"
+    )]
+    verify_roundtrip: bool,
+
+    #[arg(
+        long,
+        help = "Write a line to the output's description with the tool version, a hash of the options, the pole count, and how close to optimal the solution is proven to be, so anyone receiving it can tell how it was made"
+    )]
+    certificate: bool,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Also write a /c console command that removes and creates poles to apply the result to a base already built, without bots"
+    )]
+    lua_script: Option<PathBuf>,
+
+    #[arg(
+        long,
+        default_value = "0,0",
+        requires = "lua_script",
+        help = "With --lua-script, the map position the blueprint's origin is at in the base. Format: 'x,y'"
+    )]
+    paste_anchor: String,
+
+    #[command(flatten)]
+    book_select: book::BookSelectArgs,
+
+    #[arg(
+        long,
+        default_value = "1",
+        help = "Number of blueprints in a book to optimize at once"
+    )]
+    jobs: usize,
+
+    #[arg(
+        short = 'k',
+        long,
+        help = "Existing poles to always keep, even if they power nothing, separated by commas. Kept poles have no cost, and can still power entities and connect other poles. Can use aliases: s, m, b, t"
+    )]
+    keep_input_poles: Vec<String>,
+
+    #[arg(
+        long,
+        help = "Always keep existing poles inside this area, like --keep-input-poles. Format: 'x1,y1,x2,y2'. Can be given multiple times"
+    )]
+    keep_poles_in: Vec<String>,
+
+    #[arg(
+        long,
+        conflicts_with = "no_connectivity",
+        help = "Also connect groups of kept poles to each other, even if they are only connected to other kept poles, e.g. to reconnect islands of existing poles"
+    )]
+    connect_kept_poles: bool,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Tiles of the map that are water, where poles can only go on the blueprint's landfill. A JSON array of tile positions, '[[x, y], ...]', or an image with one pixel per tile, where blue pixels are water"
+    )]
+    water_mask: Option<PathBuf>,
+
+    #[arg(
+        long,
+        default_value = "0,0",
+        help = "Tile position of the top-left pixel of a --water-mask image, in blueprint coordinates. Format: 'x,y'"
+    )]
+    water_mask_origin: String,
+
+    #[arg(
+        long,
+        requires = "water_mask",
+        help = "Allow poles on --water-mask water without landfill, and add landfill under them to the output"
+    )]
+    allow_landfill: bool,
+
+    #[arg(
+        long,
+        default_value_t = 0.1,
+        help = "Cost of each tile of landfill --allow-landfill adds, in the same units as --pole-costs"
+    )]
+    landfill_cost: f64,
+
+    #[arg(
+        long,
+        value_name = "TILES",
+        help = "Never place poles on the blueprint's tiles with these names, separated by commas, e.g. 'hazard-concrete-left,hazard-concrete-right' for marked walkways"
+    )]
+    avoid_tiles: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Reference blueprint of an existing build. Its entities are treated as obstacles and need power, and its poles are kept, but none of it is written to the output"
+    )]
+    context: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "TILES",
+        help = "Assume entities within this many tiles of the blueprint's edge are already powered by an existing base, so poles don't need to cover them"
+    )]
+    assume_powered_border: Option<i32>,
+
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Cache candidate pole graphs in this directory, keyed by the blueprint and options affecting candidates. Speeds up re-running with different solver options"
+    )]
+    cache_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Save the solution's poles in this directory, for --load-solver-state"
+    )]
+    save_solver_state: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Start the ILP solver from the solution saved by --save-solver-state, if any. Poles are matched by name and position, so this helps after small changes to the blueprint"
+    )]
+    load_solver_state: Option<PathBuf>,
 
     #[arg(
         short = 'c',
@@ -78,6 +493,41 @@ struct OptimizePoles {
     )]
     pole_costs: Option<String>,
 
+    #[arg(
+        long,
+        value_enum,
+        help = "Connect the new poles with circuit wires of these colors, if the input poles had them"
+    )]
+    carry_circuit: Option<circuit::CarryCircuit>,
+
+    #[arg(
+        long,
+        help = "Maximum number of each pole type in the result; format: 'name=count' separated by commas. Can use aliases: s, m, b, t"
+    )]
+    max_count: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "K",
+        help = "Use at most K distinct pole types in the result, including kept poles"
+    )]
+    max_pole_types: Option<usize>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Each pole may power at most N entities; entities in range of several poles count toward only one. Adds a variable per entity and candidate pole, so solving is slower"
+    )]
+    max_load: Option<usize>,
+
+    #[arg(
+        long,
+        value_name = "MW",
+        conflicts_with = "max_load",
+        help = "Like --max-load, but each pole may supply at most MW megawatts, counting the max power draw of each entity"
+    )]
+    max_load_mw: Option<f64>,
+
     #[arg(
         short = 'E',
         long,
@@ -94,9 +544,52 @@ struct OptimizePoles {
     )]
     expand: i32,
 
+    #[arg(
+        long,
+        help = "Leave entities that no candidate pole can power unpowered, instead of giving an error",
+        action = ArgAction::SetTrue
+    )]
+    allow_unpowered: bool,
+
+    #[arg(
+        long,
+        value_name = "SPEC",
+        help = "Which entities must be powered; format: 'class=tier' separated by commas, where class is an entity type like 'beacon', an entity name, or '*' for all others, and tier is 'required' or 'soft'. Soft entities may be left unpowered if limits like --max-count don't allow powering everything. Default: all required"
+    )]
+    priority: Option<String>,
+
+    #[arg(
+        long,
+        default_value_t = 10.0,
+        help = "Cost of leaving an entity with soft --priority unpowered, in the same units as --pole-costs"
+    )]
+    unpowered_cost: f64,
+
     #[arg(long, visible_alias = "--no-c", help = "Do not require that poles are connected; may be faster", action = ArgAction::SetFalse)]
     no_connectivity: bool,
 
+    #[arg(
+        long,
+        help = "Don't check the output from scratch for unpowered entities and disconnected poles before writing it",
+        action = ArgAction::SetTrue
+    )]
+    no_verify: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with_all = ["no_connectivity", "trunk"],
+        help = "Every pole must be within N wire hops of the root (the center, --anchor, or --roots), for shallower networks that survive partial deconstruction better. Adds a variable per candidate pole and hop count"
+    )]
+    max_hops: Option<usize>,
+
+    #[arg(
+        long,
+        value_name = "X,Y[,POLE]",
+        help = "Existing pole, possibly outside the blueprint, that the poles must connect to; e.g. where power arrives. Used as the connectivity root instead of the center, and poles are added to reach it if needed. POLE defaults to medium-electric-pole; can use aliases: s, m, b, t"
+    )]
+    anchor: Option<String>,
+
     #[arg(
         short = 'P',
         long,
@@ -105,14 +598,71 @@ struct OptimizePoles {
     )]
     center_pos: String,
 
+    #[arg(
+        long,
+        value_enum,
+        conflicts_with = "center_pos",
+        help = "Compute the \"center\" from the powered entities, instead of using --center-pos; better for L-shaped or lopsided blueprints"
+    )]
+    center: Option<CenterMode>,
+
+    #[arg(
+        long,
+        value_name = "X,Y;X,Y;...",
+        help = "Connectivity roots; each pole connects toward the nearest one. Helps on long blueprints, like train unloading arrays. Format: 'x1,y1;x2,y2'"
+    )]
+    roots: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "TILES",
+        help = "Add connectivity roots every this many tiles along the longer side of the blueprint, through the center"
+    )]
+    root_spacing: Option<f64>,
+
     #[arg(
         short = 'D',
         long,
-        help = "Cost factor for distance from center, per 10000 tiles. Helps prettify the solution. Set to 0 to disable",
+        help = "Cost factor for distance from center, or as --distance-metric measures it, per 10000 tiles. Helps prettify the solution. Set to 0 to disable",
         default_value_t = 1.0
     )]
     distance_cost: f64,
 
+    #[arg(
+        long,
+        default_value = "l2",
+        help = "How --distance-cost measures distance: 'l2' (straight line from the center), 'l1' (Manhattan), 'linf' (the larger of the x and y distances), or 'line:x1,y1,x2,y2' for the distance from the line through two points, e.g. to keep poles near a main bus"
+    )]
+    distance_metric: String,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "Align big poles and substations to a grid repeating every --chunk-period tiles, as with absolute grid snapping. 'prefer' makes misaligned poles cost a little more; 'require' only places aligned ones"
+    )]
+    chunk_align: Option<chunk_align::ChunkAlign>,
+
+    #[arg(
+        long,
+        default_value_t = 32,
+        help = "Period of the --chunk-align grid, in tiles; must divide 32"
+    )]
+    chunk_period: i32,
+
+    #[arg(
+        long,
+        default_value = "0,0",
+        help = "Offset of aligned poles' top-left tile from a chunk corner, for --chunk-align. Format: 'x,y'"
+    )]
+    chunk_offset: String,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "Place big poles and substations in the gap between parallel rails 4 tiles apart, every 2 rail segments, as is conventional in rail blueprints. 'prefer' makes other big poles cost a little more; 'require' only places them between rails. Does nothing if the blueprint has no parallel rails"
+    )]
+    rail_poles: Option<rail_poles::RailPoles>,
+
     #[arg(
         short = 't',
         long,
@@ -136,8 +686,179 @@ struct OptimizePoles {
     )]
     mip_abs_gap: f32,
 
+    #[arg(
+        long,
+        help = "Add coverage and connectivity constraints to the ILP only when a solution violates them, re-solving until none are. Uses less memory on huge blueprints",
+        action = ArgAction::SetTrue
+    )]
+    lazy_constraints: bool,
+
     #[arg(short, long, help = "Don't output stuff from ILP solver", action = ArgAction::SetTrue)]
     quiet: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "ilp",
+        help = "Solver used to select poles"
+    )]
+    solver: SolverKind,
+
+    #[arg(
+        long,
+        help = "Number of randomized rounding rounds for --solver lp-round. Defaults to about ln(number of entities)"
+    )]
+    rounding_rounds: Option<usize>,
+
+    #[arg(
+        long,
+        default_value = "200",
+        help = "Max candidate poles added per round of --solver column-gen"
+    )]
+    columns_per_round: usize,
+
+    #[arg(
+        long,
+        default_value = "128",
+        help = "Width and height in tiles of the regions solved separately by --solver split"
+    )]
+    split_size: f64,
+
+    #[arg(
+        long,
+        default_value = "16",
+        help = "For --solver split, how far past its region each region's candidates extend; poles this close to a region border are chosen when stitching the regions"
+    )]
+    split_overlap: f64,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Random seed for --solver lp-round and --polish"
+    )]
+    seed: u64,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        help = "After solving, spend this many seconds moving/removing poles with local search, to shorten wires and align poles"
+    )]
+    polish: Option<f64>,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        help = "Soft limit on the total run time, split between candidate generation, solving, and polishing with whatever is left. With a small budget, candidate poles are placed on a coarser grid, and a greedy solution is used if the solver runs out of time. --time-limit and --polish still cap their stages"
+    )]
+    budget: Option<f64>,
+
+    #[arg(
+        long,
+        value_name = "POLE",
+        help = "Two tiers: first cover entities with POLES without connecting them, then add poles of this type, e.g. big-electric-pole or b, to connect them. Use --pole-costs to weigh the trunk poles"
+    )]
+    trunk: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "auto",
+        help = "Prefer wires between poles to run along this axis. By default, the longer side of the blueprint, if it is at least twice as long as the other"
+    )]
+    wire_axis: algorithms::WireAxis,
+
+    #[arg(
+        long,
+        help = "Connect poles in the style of the input's wires: as many wires per pole, so a tree stays a tree, only horizontal and vertical wires if it has almost only those, and along its main axis unless --wire-axis is given"
+    )]
+    match_wire_style: bool,
+
+    #[arg(
+        long,
+        help = "Make the longest wire as short as it can be while still connecting the poles, then the total length as usual. Gives more uniform networks, with fewer long diagonal wires"
+    )]
+    minimize_longest_wire: bool,
+
+    #[arg(
+        long,
+        help = "Only build the problem and solve its LP relaxation; prints problem size, a lower bound on the pole count, and a solve time guess. The guess is only a heuristic, the LP time scaled by the number of fractional variables, and can be far off. Does not write any output",
+        action = ArgAction::SetTrue
+    )]
+    estimate: bool,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["estimate", "pareto", "import_solution"],
+        help = "Only build the ILP and write it to FILE in LP (.lp) or MPS (.mps) format, to solve with another solver like Gurobi or CPLEX. Does not write any output"
+    )]
+    export_model: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["estimate", "pareto"],
+        help = "Use another solver's solution to the model from --export-model instead of solving, given the same blueprint and options. Reads plain text solutions with a variable name and value on each line, as from Gurobi, SCIP, or CBC"
+    )]
+    import_solution: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(2..),
+        help = "Solve N times, scaling the cost of all pole types after the first by ratios from 1/4 to 4. Writes each result to OUTPUT_FILE with a _paretoI suffix, and prints a trade-off table. Needs at least 2 pole types"
+    )]
+    pareto: Option<usize>,
+
+    #[arg(
+        long,
+        help = "Write the result of a pipeline stage to a file, for debugging. Format: 'NAME=path'. Stages: model, solve, trunk, polish, emit (JSON); candidates, connect (pole graph, as GraphML or Graphviz by the file extension: .graphml or .dot). Can be given multiple times"
+    )]
+    dump_stage: Vec<String>,
+
+    #[arg(
+        long,
+        help = "Export a pole graph to a GraphML (.graphml) or Graphviz (.dot) file, with positions and wire lengths"
+    )]
+    export_graph: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "solution",
+        help = "Which pole graph to write with --export-graph"
+    )]
+    export_graph_kind: ExportGraphKind,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum CenterMode {
+    /// Mean position of the entities that use power
+    Auto,
+    /// The entity using power with the least total distance to the others; always on an entity
+    Medoid,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum SolverKind {
+    /// Exact (up to the MIP gap) set cover ILP
+    Ilp,
+    /// LP relaxation with randomized rounding; approximate, but fast on very large blueprints
+    LpRound,
+    /// Column generation from a greedy cover, then the ILP over the generated poles; for huge candidate sets
+    ColumnGen,
+    /// The ILP on each region of the blueprint separately, then stitched together; for blueprints too big for one ILP
+    Split,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ExportGraphKind {
+    /// Existing poles and connections, after removing entities
+    Current,
+    /// All candidate poles, with all possible connections
+    Candidates,
+    /// Poles and connections in the result
+    Solution,
 }
 
 fn sep_commas(input: &[String]) -> impl Iterator<Item = String> + '_ {
@@ -145,12 +866,36 @@ fn sep_commas(input: &[String]) -> impl Iterator<Item = String> + '_ {
         .iter()
         .flat_map(|s| s.split(',').map(|s| s.to_string()))
 }
-fn parse_tuple(input: &str) -> Result<(f64, f64), Box<dyn Error>> {
+fn parse_tuple(input: &str) -> Result<(f64, f64), OptimizerError> {
     let mut parts = input.split(',');
     let x = parts.next().ok_or("Missing x")?.parse()?;
     let y = parts.next().ok_or("Missing y")?.parse()?;
     Ok((x, y))
 }
+/// Parses `x,y[,prototype]` into a pole entity at that position.
+fn parse_anchor(input: &str, dict: &EntityPrototypeDict) -> Result<WorldEntity, OptimizerError> {
+    let (x, y) = parse_tuple(input)?;
+    let name = input.split(',').nth(2).unwrap_or("medium-electric-pole");
+    let prototype = require_prototype(name, dict)?;
+    if !prototype.is_pole() {
+        return Err(format!("Anchor {} is not a pole", name).into());
+    }
+    Ok(WorldEntity {
+        position: point2(x, y),
+        direction: 0,
+        prototype,
+    })
+}
+fn parse_area(input: &str) -> Result<BoundingBox, OptimizerError> {
+    let parts = input
+        .split(',')
+        .map(|part| part.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()?;
+    let [x1, y1, x2, y2] = parts[..] else {
+        return Err(format!("Expected area as 'x1,y1,x2,y2', got '{}'", input).into());
+    };
+    Ok(BoundingBox::from_points([point2(x1, y1), point2(x2, y2)]))
+}
 
 static POLE_NAME_ALIASES: Lazy<HashMap<&'static str, &'static str>> = Lazy::new(|| {
     HashMap::from([
@@ -161,148 +906,115 @@ static POLE_NAME_ALIASES: Lazy<HashMap<&'static str, &'static str>> = Lazy::new(
     ])
 });
 
-fn get_pole_prototype(name: &str, dict: &EntityPrototypeDict) -> Option<EntityPrototypeRef> {
+fn get_prototype(name: &str, dict: &EntityPrototypeDict) -> Option<EntityPrototypeRef> {
     let real_name = POLE_NAME_ALIASES.get(name).copied().unwrap_or(name);
     dict.0.get(real_name).cloned()
 }
 
-fn get_pole_prototypes(
+/// Like [get_prototype], but an unknown name is an error.
+fn require_prototype(
+    name: &str,
+    dict: &EntityPrototypeDict,
+) -> Result<EntityPrototypeRef, OptimizerError> {
+    get_prototype(name, dict).ok_or_else(|| OptimizerError::UnknownPrototype(name.to_string()))
+}
+
+fn get_prototypes(
     names: &[String],
     dict: &EntityPrototypeDict,
-) -> Result<Vec<EntityPrototypeRef>, Box<dyn Error>> {
-    Ok(sep_commas(names)
-        .map(|name| {
-            get_pole_prototype(&name, dict).ok_or_else(|| format!("Unknown pole type: {}", name))
-        })
-        .collect::<Result<Vec<_>, _>>()?)
+) -> Result<Vec<EntityPrototypeRef>, OptimizerError> {
+    sep_commas(names)
+        .map(|name| require_prototype(&name, dict))
+        .collect()
 }
 
-fn parse_pole_costs(input: &str) -> Result<HashMap<EntityPrototypeRef, f64>, Box<dyn Error>> {
+fn parse_pole_costs(input: &str) -> Result<HashMap<EntityPrototypeRef, f64>, OptimizerError> {
     input
         .split(',')
         .map(|part| {
             let mut parts = part.split('=');
             let name = parts.next().ok_or("Missing name")?;
             let cost = parts.next().ok_or("Missing cost")?.parse()?;
-            let prototype = get_pole_prototype(name, &prototype_data::load_prototype_data()?)
-                .ok_or_else(|| format!("Unknown pole type: {}", name))?;
+            let prototype = require_prototype(name, &prototype_data::load_prototype_data()?)?;
             Ok((prototype, cost))
         })
         .collect::<Result<HashMap<_, _>, _>>()
 }
 
+fn parse_max_counts(input: &str) -> Result<HashMap<EntityPrototypeRef, usize>, OptimizerError> {
+    input
+        .split(',')
+        .map(|part| {
+            let (name, count) = part
+                .split_once('=')
+                .ok_or_else(|| format!("Expected 'name=count', got '{}'", part))?;
+            let prototype = require_prototype(name, &prototype_data::load_prototype_data()?)?;
+            Ok((prototype, count.parse()?))
+        })
+        .collect()
+}
+
 struct BlueprintProcessResult {
     blueprint: Blueprint,
     model: BpModel,
     bounding_box: TileBoundingBox,
+    report: OptimizationReport,
 }
 
-fn optimize_poles(
-    mut bp: Blueprint,
-    args: &OptimizePoles,
-) -> Result<BlueprintProcessResult, Box<dyn Error>> {
-    let prototype_data = prototype_data::load_prototype_data()?;
-
-    // todo: consolidate these 2 representations??
-    let mut bp2 = BlueprintEntities::from_blueprint(&bp);
-    let mut model = BpModel::from_bp_entities(&bp2, &prototype_data);
-
-    if !args.remove_poles.is_empty() {
-        let pole_prototypes = get_pole_prototypes(&args.remove_poles, &prototype_data)?;
-        model.retain(|entity| !pole_prototypes.contains(&entity.prototype));
-    }
-
-    let poles_to_use = get_pole_prototypes(&args.use_poles, &prototype_data)?;
-    let mut pole_costs = prototype_data
-        .0
-        .iter()
-        .filter(|(_, prototype)| prototype.type_ == "electric-pole")
-        .map(|(_, prototype)| (prototype.clone(), 1.0))
-        .collect::<HashMap<_, _>>();
-
-    if let Some(arg_pole_costs) = &args.pole_costs {
-        pole_costs.extend(parse_pole_costs(arg_pole_costs)?);
-    }
-
-    let bounding_box = {
-        if args.expand == 0 {
-            model.get_bounding_box()
-        } else {
-            model.get_bounding_box().inflate(args.expand, args.expand)
+impl From<pipeline::PipelineState> for BlueprintProcessResult {
+    fn from(state: pipeline::PipelineState) -> Self {
+        BlueprintProcessResult {
+            blueprint: state.blueprint,
+            model: state.model,
+            bounding_box: state.bounding_box,
+            report: state.report,
         }
-    };
-
-    let cand_graph: CandPoleGraph = model
-        .with_all_candidate_poles(bounding_box, &poles_to_use)
-        .get_maximally_connected_pole_graph()
-        .0
-        .to_cand_pole_graph(&model);
-
-    let center_rel_pos = parse_tuple(&args.center_pos)?;
-
-    let center = bounding_box
-        .to_f64()
-        .cast_unit()
-        .relative_pt_at(center_rel_pos);
-
-    let cost_fn = |graph: &CandPoleGraph, idx: NodeIndex| {
-        let entity = &graph[idx].entity;
-        let score = pole_costs[&entity.prototype];
-        score + (entity.position - center).length() / 10000.0 * args.distance_cost
-    };
-
-    println!("Solving ILP");
-    let solver = SetCoverILPSolver {
-        solver: &highs,
-        config: &|mut model| {
-            model.set_verbose(!args.quiet);
-            Ok(model
-                .set_mip_rel_gap(args.mip_rel_gap)?
-                .set_mip_abs_gap(args.mip_abs_gap)?
-                .set_time_limit(args.time_limit))
-        },
-        cost: &cost_fn,
-        connectivity: if args.no_connectivity {
-            Some(DistanceConnectivity { center_rel_pos })
-        } else {
-            None
-        },
-    };
-
-    let sol_poles = solver.solve(&cand_graph)?;
-    let sol_graph = PrettyPoleConnector::default().connect_poles(&sol_poles);
+    }
+}
 
-    println!("Result has {} poles", sol_graph.node_count());
+fn optimize_poles(
+    bp: Blueprint,
+    args: &OptimizePoles,
+) -> Result<BlueprintProcessResult, OptimizerError> {
+    Ok(pipeline::run_optimize_pipeline(bp, args)?.into())
+}
 
-    model.remove_all_poles();
-    model.add_from_pole_graph(&sol_graph);
+/// Whether `optimize` needs a single blueprint, rather than optimizing each in a book.
+fn needs_blueprint(opt: &OptimizePoles) -> bool {
+    opt.estimate || opt.export_model.is_some() || opt.pareto.is_some()
+}
 
-    bp2.entities
-        .retain(|_, entity| prototype_data[&entity.name].type_ != "electric-pole");
-    bp2.add_poles_from(&model);
+fn read_blueprint(path: &PathBuf) -> Result<Blueprint, OptimizerError> {
+    into_blueprint(read_container(path)?)
+}
 
-    bp.entities = bp2.to_blueprint_entities();
-    Ok(BlueprintProcessResult {
-        blueprint: bp,
-        model,
-        bounding_box,
-    })
+fn read_container(path: &PathBuf) -> Result<Container, OptimizerError> {
+    bp_io::decode(BufReader::new(File::open(path)?))
 }
 
-fn read_blueprint(path: &PathBuf) -> Result<Blueprint, Box<dyn Error>> {
-    let file = File::open(path)?;
-    match BlueprintCodec::decode(BufReader::new(file))? {
+fn into_blueprint(container: Container) -> Result<Blueprint, OptimizerError> {
+    match container {
         Container::Blueprint(bp) => Ok(bp),
-        _ => Err("Expected input to be a blueprint, got something else".into()),
+        _ => Err(OptimizerError::Decode(
+            "Expected input to be a blueprint, got something else".into(),
+        )),
     }
 }
 
 // need to take ownership then return it... for reasons...
 // the borrow checker giveth, and the borrow checker taketh away
-fn write_blueprint(bp: Blueprint, path: &PathBuf) -> Result<Blueprint, Box<dyn Error>> {
-    let file = File::create(path)?;
+fn write_blueprint(
+    bp: Blueprint,
+    path: &PathBuf,
+    format: BlueprintFormat,
+    certificate: Option<&Certificate>,
+) -> Result<Blueprint, OptimizerError> {
+    let file = BufWriter::new(File::create(path)?);
     let container = Container::Blueprint(bp);
-    BlueprintCodec::encode(BufWriter::new(file), &container)?;
+    match certificate {
+        Some(certificate) => certificate.encode(file, &container, format)?,
+        None => bp_io::encode(file, &container, format)?,
+    }
     Ok(match container {
         Container::Blueprint(bp) => bp,
         _ => unreachable!(),
@@ -312,39 +1024,284 @@ fn write_blueprint(bp: Blueprint, path: &PathBuf) -> Result<Blueprint, Box<dyn E
 fn visualize_blueprint(
     result_bp: &BlueprintProcessResult,
     out_file: &Path,
-) -> Result<(), Box<dyn Error>> {
+    theme: draw::Theme,
+    markers: &[MapPosition],
+) -> Result<(), OptimizerError> {
     println!("visualizing");
     let png_file = out_file.with_extension("png");
-    let bbox = result_bp.bounding_box;
-    let drawing = draw::Drawing::on_area(&png_file, bbox, 5, 10)?;
+    let bbox = draw::area_with_markers(result_bp.bounding_box, markers);
+    let drawing = draw::Drawing::on_area(&png_file, bbox, 5, 10)?.with_theme(theme)?;
     drawing.draw_model(&result_bp.model)?;
+    for marker in markers {
+        drawing.draw_marker(*marker)?;
+    }
+
+    drawing.show()?;
+    Ok(())
+}
 
+/// For `--vis-compare`.
+fn visualize_comparison(
+    original: &Blueprint,
+    result_bp: &BlueprintProcessResult,
+    out_file: &Path,
+    theme: draw::Theme,
+    markers: &[MapPosition],
+) -> Result<(), OptimizerError> {
+    let dict = prototype_data::load_prototype_data()?;
+    let input = BpModel::from_bp_entities(&BlueprintEntities::from_blueprint(original), &dict);
+    let png_file = out_file.with_extension("compare.png");
+    let bbox = result_bp.bounding_box.union(&input.get_bounding_box());
+    let bbox = draw::area_with_markers(bbox, markers);
+    let drawing = draw::Drawing::on_area(&png_file, bbox, 5, 10)?.with_theme(theme)?;
+    let [kept, added, removed] = drawing.draw_comparison(&input, &result_bp.model)?;
+    for marker in markers {
+        drawing.draw_marker(*marker)?;
+    }
     drawing.show()?;
+    println!(
+        "Wrote comparison to {:?}: {} poles kept, {} added, {} removed",
+        png_file, kept, added, removed
+    );
     Ok(())
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let args = Args::parse();
+fn main() -> ExitCode {
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    let notify_url = args.notify_url.clone();
+    let input = args.input.clone();
+    let start = std::time::Instant::now();
+    logger::init();
+    // written when dropped, after the run
+    let _trace = args.trace.as_deref().map(trace::start);
+    let mut report = None;
+    let result = run(args, &matches, &mut report);
+    if let Some(url) = notify_url {
+        let payload =
+            notify::notification(input.as_deref(), start.elapsed(), &result, report.as_ref());
+        notify::notify(&url, &payload);
+    }
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            ExitCode::from(err.exit_code())
+        }
+    }
+}
+
+/// `report` is set to the optimization report, if there is one.
+fn run(
+    mut args: Args,
+    matches: &ArgMatches,
+    report: &mut Option<OptimizationReport>,
+) -> Result<(), OptimizerError> {
+    if let Some(mod_pack) = args.mod_pack {
+        prototype_data::use_mod_pack(mod_pack)?;
+    }
+    if let Command::Optimize(opt) = &mut args.command {
+        presets::apply_preset(opt, matches.subcommand_matches("optimize").unwrap())?;
+    }
+    better_bp::use_stable_numbering(args.stable_numbering);
+    bp_stream::use_low_memory(args.low_memory);
+    position::use_exact_game_math(args.exact_game_math);
+
+    if let Command::SelfTest(self_test_args) = &args.command {
+        return self_test::run_self_test(self_test_args);
+    }
+    if let Command::GenTest(gen_test_args) = &args.command {
+        return gen_test::run_gen_test(gen_test_args);
+    }
+    if let Command::Completions(completions_args) = &args.command {
+        return completions::run_completions(completions_args);
+    }
+    if let Command::ServeHttp(serve_args) = &args.command {
+        return serve_http::run_serve_http(serve_args);
+    }
+    if let Command::Dedup(dedup_args) = &args.command {
+        return dedup::run_dedup(dedup_args);
+    }
 
-    let in_file = &args.input;
+    let in_file = args.input.as_ref().ok_or("INPUT_FILE is required")?;
+    // before optimizing, so a bad theme fails fast
+    let vis_theme = draw::Theme::load(&args.vis_theme)?;
+    let vis_markers = args
+        .vis_marker
+        .iter()
+        .map(|marker| parse_tuple(marker).map(|(x, y)| point2(x, y)))
+        .collect::<Result<Vec<_>, _>>()?;
     let out_file = args.output.unwrap_or_else(|| {
         let file = in_file.with_extension("");
         file.with_file_name(file.file_name().unwrap().to_str().unwrap().to_string() + "_out")
-            .with_extension("txt")
+            .with_extension(args.output_format.extension())
     });
 
+    if let Command::Upgrade(upgrade_args) = &args.command {
+        let out_file = Some(out_file.as_path()).filter(|_| !args.dry_run);
+        return upgrade::run_upgrade(upgrade_args, in_file, out_file, args.output_format);
+    }
+    if let Command::Stats = &args.command {
+        return stats::run_stats(in_file);
+    }
+    if let Command::Recenter(recenter_args) = &args.command {
+        let out_file = Some(out_file.as_path()).filter(|_| !args.dry_run);
+        return recenter::run_recenter(recenter_args, in_file, out_file, args.output_format);
+    }
+
     println!("Reading from {:?}", in_file);
-    let bp = read_blueprint(in_file)?;
+    let mut container = match &args.command {
+        Command::Optimize(opt) if args.low_memory && !needs_blueprint(opt) => {
+            let out_file = Some(out_file.as_path()).filter(|_| !args.dry_run);
+            match book::run_optimize_book_streaming(in_file, opt, out_file, args.output_format)? {
+                Some(container) => container,
+                None => return Ok(()),
+            }
+        }
+        _ => read_container(in_file)?,
+    };
+    if let Command::Optimize(opt) = &args.command {
+        if matches!(container, Container::BlueprintBook(_)) && !needs_blueprint(opt) {
+            book::warn_unused_options(opt);
+            let out_file = Some(out_file.as_path()).filter(|_| !args.dry_run);
+            let inputs = opt.verify_roundtrip.then(|| {
+                bp_io::blueprints_mut(&mut container)
+                    .into_iter()
+                    .map(|bp| bp.clone())
+                    .collect::<Vec<_>>()
+            });
+            book::run_optimize_book(container, opt, out_file, args.output_format)?;
+            if let (Some(inputs), Some(out_file)) = (inputs, out_file) {
+                let dict = prototype_data::load_prototype_data()?;
+                roundtrip::verify_roundtrip(&inputs, out_file, 0, &dict)?;
+            }
+            return Ok(());
+        }
+        if !opt.book_select.is_empty() {
+            log::warn!("--select and --index do nothing, as the input is not a book");
+        }
+    }
+    let bp = into_blueprint(container)?;
     println!("Read blueprint with {} entities", bp.entities.len());
+    // the original and whether to add a diff, for --output-book
+    let book_original = match &args.command {
+        Command::Optimize(opt) if opt.output_book => Some((bp.clone(), opt.book_diff)),
+        _ => None,
+    };
+    let verify_input = match &args.command {
+        Command::Optimize(opt) if opt.verify_roundtrip => Some(bp.clone()),
+        _ => None,
+    };
+    // the original, the script file, and the anchor, for --lua-script
+    let lua_script = match &args.command {
+        Command::Optimize(opt) => match &opt.lua_script {
+            Some(file) => Some((bp.clone(), file.clone(), parse_tuple(&opt.paste_anchor)?)),
+            None => None,
+        },
+        _ => None,
+    };
+    // the options hash, for --certificate
+    let certify = match &args.command {
+        Command::Optimize(opt) if opt.certificate => Some(certificate::options_hash(opt)),
+        _ => None,
+    };
+    let compare_original = args.vis_compare.then(|| bp.clone());
+    // in the --output-book book, the optimized blueprint comes after the original
+    let verify_skip = usize::from(book_original.is_some());
 
     let mut result = match args.command {
+        Command::CheckInserters => return check_inserters::run_check_inserters(&bp),
+        Command::CheckPower => return check_power::run_check_power(&bp),
+        Command::CheckFluids => return check_fluids::run_check_fluids(&bp),
+        Command::CheckRails => return check_rails::run_check_rails(&bp),
+        Command::Optimize(opt) if opt.estimate => return pipeline::run_estimate_pipeline(bp, &opt),
+        Command::Optimize(opt) if opt.export_model.is_some() => {
+            let path = opt.export_model.clone().unwrap();
+            return pipeline::run_export_model_pipeline(bp, &opt, &path);
+        }
+        Command::Optimize(opt) if opt.pareto.is_some() => {
+            let out_file = Some(out_file.as_path()).filter(|_| !args.dry_run);
+            return pareto::run_pareto(bp, &opt, opt.pareto.unwrap(), out_file, args.output_format);
+        }
+        Command::Radars(radar_args) => {
+            let out_file = Some(out_file.as_path()).filter(|_| !args.dry_run);
+            return radars::run_radars(bp, &radar_args, out_file, args.output_format);
+        }
+        Command::Turrets(turret_args) => {
+            let out_file = Some(out_file.as_path()).filter(|_| !args.dry_run);
+            return turrets::run_turrets(bp, &turret_args, out_file, args.output_format);
+        }
         Command::Optimize(opt) => optimize_poles(bp, &opt)?,
+        Command::FixPower(fix_args) => match fix_power::run_fix_power(bp, &fix_args)? {
+            Some(state) => state.into(),
+            None => return Ok(()),
+        },
+        Command::Prune(prune_args) => match prune::run_prune(bp, &prune_args)? {
+            Some(state) => state.into(),
+            None => return Ok(()),
+        },
+        Command::Rewire(rewire_args) => match rewire::run_rewire(bp, &rewire_args)? {
+            Some(state) => state.into(),
+            None => return Ok(()),
+        },
+        Command::SelfTest(_)
+        | Command::GenTest(_)
+        | Command::Upgrade(_)
+        | Command::Recenter(_)
+        | Command::Stats
+        | Command::Completions(_)
+        | Command::ServeHttp(_)
+        | Command::Dedup(_) => unreachable!(),
     };
 
-    result.blueprint = write_blueprint(result.blueprint, &out_file)?;
+    result.report.print();
+    *report = Some(result.report.clone());
+    if args.vis_term {
+        term_vis::print_model(&result.model);
+    }
+    if result.report.kept_input {
+        println!("Not writing {:?}, as the input is unchanged", out_file);
+        return Ok(());
+    }
+    if args.dry_run {
+        println!("Dry run; not writing {:?}", out_file);
+        return Ok(());
+    }
+    let certificate = certify.map(|hash| Certificate::new(hash, &result.report));
+    match book_original {
+        Some((original, with_diff)) => {
+            let book = output_book::comparison_book(original, result.blueprint.clone(), with_diff)?;
+            let writer = BufWriter::new(File::create(&out_file)?);
+            match &certificate {
+                Some(certificate) => certificate.encode(writer, &book, args.output_format)?,
+                None => bp_io::encode(writer, &book, args.output_format)?,
+            }
+        }
+        None => {
+            result.blueprint = write_blueprint(
+                result.blueprint,
+                &out_file,
+                args.output_format,
+                certificate.as_ref(),
+            )?;
+        }
+    }
+    if let Some((original, file, anchor)) = lua_script {
+        let dict = prototype_data::load_prototype_data()?;
+        let script = lua_script::pole_changes_script(&dict, &original, &result.blueprint, anchor);
+        std::fs::write(&file, script)?;
+        println!("Wrote console command to {:?}", file);
+    }
+    if let Some(input) = verify_input {
+        let dict = prototype_data::load_prototype_data()?;
+        roundtrip::verify_roundtrip(&[input], &out_file, verify_skip, &dict)?;
+    }
 
     if args.visualize {
-        visualize_blueprint(&result, &out_file)?;
+        visualize_blueprint(&result, &out_file, vis_theme.clone(), &vis_markers)?;
+    }
+    if let Some(original) = compare_original {
+        visualize_comparison(&original, &result, &out_file, vis_theme, &vis_markers)?;
     }
 
     Ok(())
@@ -0,0 +1,184 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::highs_solve::HighsOptions;
+
+/// Statistics of a MIP solve, from the "Solving report" HiGHS writes to its log.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MipStats {
+    /// e.g. "Optimal", or "Time limit reached".
+    pub status: String,
+    /// Objective of the best solution found.
+    pub primal_bound: Option<f64>,
+    /// Lower bound on the objective of any solution.
+    pub dual_bound: Option<f64>,
+    /// Relative gap between the bounds, in percent.
+    pub gap: Option<f64>,
+    pub nodes: Option<u64>,
+    pub lp_iterations: Option<u64>,
+    /// Total solve time, in seconds.
+    pub time: Option<f64>,
+    /// Rows of the MIP, before presolve.
+    pub constraints: Option<u64>,
+}
+
+impl MipStats {
+    pub fn is_optimal(&self) -> bool {
+        self.status == "Optimal"
+    }
+
+    pub fn print(&self) {
+        let mut parts = vec![];
+        if let Some(constraints) = self.constraints {
+            parts.push(format!("{} constraints", constraints));
+        }
+        if let Some(primal) = self.primal_bound {
+            parts.push(format!("cost {}", primal));
+        }
+        if let Some(dual) = self.dual_bound {
+            parts.push(format!("bound {}", dual));
+        }
+        if let Some(gap) = self.gap {
+            parts.push(format!("gap {}%", gap));
+        }
+        if let Some(nodes) = self.nodes {
+            parts.push(format!("{} nodes", nodes));
+        }
+        if let Some(iterations) = self.lp_iterations {
+            parts.push(format!("{} LP iterations", iterations));
+        }
+        if let Some(time) = self.time {
+            parts.push(format!("{:.2}s", time));
+        }
+        println!("Solver: {}; {}", self.status, parts.join(", "));
+        if !self.is_optimal() {
+            println!("The solution is not proven optimal; a longer --time-limit may improve it");
+        }
+    }
+}
+
+/// First number in `text`, ignoring a trailing '%'.
+fn leading_number<T: std::str::FromStr>(text: &str) -> Option<T> {
+    text.split_whitespace()
+        .next()?
+        .trim_end_matches('%')
+        .parse()
+        .ok()
+}
+
+/// Parses the last "Solving report" in a HiGHS log. `None` if there is none, e.g. for an LP.
+pub fn parse_highs_log(log: &str) -> Option<MipStats> {
+    let start = log.rfind("Solving report")?;
+    let report = &log[start..];
+    // e.g. "MIP has 120 rows; 40 cols; ..."
+    let constraints = log[..start]
+        .rfind("MIP has ")
+        .and_then(|i| leading_number(&log[i + "MIP has ".len()..]));
+    let mut stats = MipStats {
+        constraints,
+        ..MipStats::default()
+    };
+    for line in report.lines() {
+        let line = line.trim();
+        let value = |key: &str| line.strip_prefix(key).map(str::trim);
+        if let Some(status) = value("Status") {
+            stats.status = status.to_string();
+        } else if let Some(primal) = value("Primal bound") {
+            stats.primal_bound = leading_number(primal);
+        } else if let Some(dual) = value("Dual bound") {
+            stats.dual_bound = leading_number(dual);
+        } else if let Some(gap) = value("Gap") {
+            stats.gap = leading_number(gap);
+        } else if let Some(time) = value("Timing") {
+            stats.time = leading_number(time);
+        } else if let Some(nodes) = value("Nodes") {
+            stats.nodes = leading_number(nodes);
+        } else if let Some(iterations) = value("LP iterations") {
+            stats.lp_iterations = leading_number(iterations);
+        }
+    }
+    Some(stats)
+}
+
+/// A HiGHS log file for one solve, read back afterward for [MipStats].
+pub struct HighsLog {
+    path: PathBuf,
+}
+
+/// Numbers log files, so concurrent solves (`--jobs`) don't share one.
+static NEXT_LOG: AtomicUsize = AtomicUsize::new(0);
+
+impl Default for HighsLog {
+    fn default() -> Self {
+        let n = NEXT_LOG.fetch_add(1, Ordering::Relaxed);
+        HighsLog {
+            path: std::env::temp_dir().join(format!(
+                "factorio-opti-poles-{}-{}.log",
+                std::process::id(),
+                n
+            )),
+        }
+    }
+}
+
+impl HighsLog {
+    /// Makes HiGHS also write its log to this file. Output to the console is still controlled by `verbose`.
+    pub fn attach(&self, mut options: HighsOptions, verbose: bool) -> HighsOptions {
+        options.set_verbose(true);
+        options
+            .set_option("log_to_console", verbose)
+            .set_option("log_file", self.path.to_string_lossy().as_ref())
+    }
+
+    /// Reads and removes the log. `None` if nothing was solved, or the last solve was not a MIP.
+    pub fn stats(self) -> Option<MipStats> {
+        let log = std::fs::read_to_string(&self.path).ok();
+        let _ = std::fs::remove_file(&self.path);
+        parse_highs_log(&log?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_highs_log() {
+        let log = "\
+Running HiGHS 1.7.0
+MIP has 150 rows; 40 cols; 600 nonzeros; 40 integer variables (40 binary)
+Solving MIP model with:
+   120 rows
+
+Solving report
+  Status            Time limit reached
+  Primal bound      14.0002
+  Dual bound        12.5
+  Gap               12% (tolerance: 0.01%)
+  Solution status   feasible
+                    14.0002 (objective)
+  Timing            10.01 (total)
+                    0.02 (presolve)
+  Nodes             350
+  Repair LPs        0 (0 feasible; 0 iterations)
+  LP iterations     20441 (total)
+                    0 (strong br.)
+";
+        let stats = parse_highs_log(log).unwrap();
+        assert_eq!(
+            stats,
+            MipStats {
+                status: "Time limit reached".to_string(),
+                primal_bound: Some(14.0002),
+                dual_bound: Some(12.5),
+                gap: Some(12.0),
+                nodes: Some(350),
+                lp_iterations: Some(20441),
+                time: Some(10.01),
+                constraints: Some(150),
+            }
+        );
+        assert!(!stats.is_optimal());
+        assert_eq!(parse_highs_log("Model status: Optimal"), None);
+    }
+}
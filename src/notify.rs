@@ -0,0 +1,93 @@
+use std::path::Path;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::error::OptimizerError;
+use crate::report::OptimizationReport;
+
+/// How long to wait for the webhook, so a dead endpoint doesn't hold up exiting.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Body of the `--notify-url` POST. `content` and `text` hold the same one line summary,
+/// as Discord and Slack webhooks each show one of them; the rest is for other services.
+pub fn notification(
+    input: Option<&Path>,
+    elapsed: Duration,
+    result: &Result<(), OptimizerError>,
+    report: Option<&OptimizationReport>,
+) -> Value {
+    let name = input
+        .and_then(|input| input.file_name())
+        .map_or("blueprint".into(), |name| name.to_string_lossy());
+    let mut summary = match result {
+        Ok(()) => format!("Finished optimizing {} in {:.0?}", name, elapsed),
+        Err(err) => format!("Failed optimizing {} after {:.0?}: {}", name, elapsed, err),
+    };
+    if let Some(report) = report {
+        let changes = report.pole_changes();
+        if report.kept_input {
+            summary += "; kept the input poles";
+        } else if !changes.is_empty() {
+            summary += &format!("; {}", changes.join(", "));
+        }
+        if report.unpowered_after > 0 {
+            summary += &format!("; {} entities unpowered", report.unpowered_after);
+        }
+    }
+    json!({
+        "content": summary,
+        "text": summary,
+        "success": result.is_ok(),
+        "error": result.as_ref().err().map(ToString::to_string),
+        "elapsed_seconds": elapsed.as_secs_f64(),
+        "report": report.map(OptimizationReport::to_json),
+    })
+}
+
+/// POSTs `payload` as JSON to `url`. Failing to notify only warns, as the run itself is done.
+pub fn notify(url: &str, payload: &Value) {
+    let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).build();
+    let request = agent.post(url).set("Content-Type", "application/json");
+    match request.send_string(&payload.to_string()) {
+        Ok(_) => println!("Sent notification to {}", url),
+        Err(err) => println!("Warning: could not send notification to {}: {}", url, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::EntityCounts;
+
+    #[test]
+    fn test_notification() {
+        let report = OptimizationReport {
+            poles_before: EntityCounts::from([("small-electric-pole".to_string(), 10)]),
+            poles_after: EntityCounts::from([("small-electric-pole".to_string(), 7)]),
+            ..Default::default()
+        };
+        let payload = notification(
+            Some(Path::new("dir/base.txt")),
+            Duration::from_secs(90),
+            &Ok(()),
+            Some(&report),
+        );
+        assert_eq!(payload["success"], true);
+        assert_eq!(
+            payload["content"],
+            "Finished optimizing base.txt in 90s; removed 3 small-electric-pole"
+        );
+        assert_eq!(payload["report"]["poles_after"]["small-electric-pole"], 7);
+
+        let payload = notification(
+            None,
+            Duration::from_secs(5),
+            &Err("out of memory".into()),
+            None,
+        );
+        assert_eq!(payload["success"], false);
+        assert_eq!(payload["error"], "out of memory");
+        assert!(payload["report"].is_null());
+    }
+}
@@ -0,0 +1,101 @@
+use factorio_blueprint::objects::Blueprint;
+use factorio_blueprint::Container;
+use hashbrown::HashSet;
+
+use crate::better_bp::{BlueprintEntities, BlueprintEntity};
+use crate::bp_io;
+use crate::error::OptimizerError;
+use crate::prototype_data::{self, EntityPrototypeDict};
+
+pub fn is_pole(dict: &EntityPrototypeDict, entity: &BlueprintEntity) -> bool {
+    dict.0
+        .get(entity.name.as_str())
+        .is_some_and(|prototype| prototype.is_pole())
+}
+
+/// Name and position in half tiles, to match poles between blueprints.
+pub fn pole_key(entity: &BlueprintEntity) -> (String, i64, i64) {
+    (
+        entity.name.to_string(),
+        (entity.position.x * 2.0).round() as i64,
+        (entity.position.y * 2.0).round() as i64,
+    )
+}
+
+/// The poles in `optimized` that aren't in `original`, with their connections to each other; no other entities or tiles.
+fn new_poles(
+    dict: &EntityPrototypeDict,
+    original: &Blueprint,
+    optimized: &Blueprint,
+) -> Result<Blueprint, OptimizerError> {
+    let original_poles = BlueprintEntities::from_blueprint(original)
+        .retain(|entity| !is_pole(dict, entity))
+        .iter()
+        .map(pole_key)
+        .collect::<HashSet<_>>();
+    let mut entities = BlueprintEntities::from_blueprint(optimized);
+    entities.retain(|entity| is_pole(dict, entity) && !original_poles.contains(&pole_key(entity)));
+    let mut diff = optimized.clone();
+    entities.write_to_blueprint(&mut diff);
+    let mut value = serde_json::to_value(&diff)?;
+    if let Some(bp) = value.as_object_mut() {
+        bp.remove("tiles");
+    }
+    Ok(serde_json::from_value(value)?)
+}
+
+/// For `--output-book`: a book with the original blueprint, then the optimized one,
+/// then if `with_diff`, one with only the poles that were added.
+pub fn comparison_book(
+    mut original: Blueprint,
+    mut optimized: Blueprint,
+    with_diff: bool,
+) -> Result<Container, OptimizerError> {
+    let name = if original.label.is_empty() {
+        "Blueprint".to_string()
+    } else {
+        original.label.clone()
+    };
+    let mut blueprints = vec![];
+    if with_diff {
+        let dict = prototype_data::load_prototype_data()?;
+        let mut diff = new_poles(&dict, &original, &optimized)?;
+        println!("Diff blueprint has {} new poles", diff.entities.len());
+        diff.label = format!("{} (new poles)", name);
+        blueprints.push(diff);
+    }
+    original.label = format!("{} (original)", name);
+    optimized.label = format!("{} (optimized)", name);
+    blueprints.splice(0..0, [original, optimized]);
+    bp_io::make_book(&format!("{} comparison", name), &blueprints)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+
+    #[test]
+    fn test_comparison_book() {
+        let Container::Blueprint(mut original) =
+            bp_io::decode(File::open("test-data/bigtest.txt").unwrap()).unwrap()
+        else {
+            panic!("not a blueprint");
+        };
+        let dict = prototype_data::load_prototype_data().unwrap();
+        // as if all the poles were added
+        let optimized = original.clone();
+        let mut without_poles = BlueprintEntities::from_blueprint(&original);
+        let num_poles = without_poles.retain(|entity| !is_pole(&dict, entity)).len();
+        without_poles.write_to_blueprint(&mut original);
+
+        let mut book = comparison_book(original, optimized, true).unwrap();
+        let blueprints = bp_io::blueprints_mut(&mut book);
+        assert_eq!(blueprints.len(), 3);
+        assert!(blueprints[0].label.ends_with("(original)"));
+        assert!(blueprints[1].label.ends_with("(optimized)"));
+        assert_eq!(blueprints[2].entities.len(), num_poles);
+        assert!(num_poles > 0);
+    }
+}
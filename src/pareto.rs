@@ -0,0 +1,185 @@
+use std::path::{Path, PathBuf};
+
+use factorio_blueprint::objects::Blueprint;
+use itertools::Itertools;
+
+use crate::bp_io::BlueprintFormat;
+use crate::error::OptimizerError;
+use crate::pipeline::*;
+use crate::prototype_data;
+use crate::report::{count_poles, EntityCounts};
+use crate::{parse_pole_costs, read_blueprint, write_blueprint, OptimizePoles};
+
+/// Largest cost ratio between the other pole types and the first, in either direction.
+const MAX_COST_RATIO: f64 = 4.0;
+
+/// `n` cost ratios, geometrically spaced from `1/MAX_COST_RATIO` to `MAX_COST_RATIO`.
+fn cost_ratios(n: usize) -> Vec<f64> {
+    if n <= 1 {
+        return vec![1.0];
+    }
+    (0..n)
+        .map(|i| MAX_COST_RATIO.powf(2.0 * i as f64 / (n - 1) as f64 - 1.0))
+        .collect()
+}
+
+/// If `a` uses at most as many of every pole type as `b`, and is not the same.
+fn dominates(a: &EntityCounts, b: &EntityCounts) -> bool {
+    a != b
+        && a.keys()
+            .chain(b.keys())
+            .all(|name| a.get(name).unwrap_or(&0) <= b.get(name).unwrap_or(&0))
+}
+
+struct ParetoPoint {
+    ratio: f64,
+    poles: EntityCounts,
+    path: Option<PathBuf>,
+}
+
+fn print_table(points: &[ParetoPoint]) {
+    let names = points
+        .iter()
+        .flat_map(|point| point.poles.keys())
+        .sorted()
+        .dedup()
+        .collect_vec();
+    print!("{:>3} {:>8}", "#", "ratio");
+    for name in &names {
+        print!(" {:>20}", name);
+    }
+    println!(" {:>6}  file", "total");
+    for (i, point) in points.iter().enumerate() {
+        print!("{:>3} {:>8.3}", i, point.ratio);
+        for name in &names {
+            print!(" {:>20}", point.poles.get(*name).unwrap_or(&0));
+        }
+        let dominated = points
+            .iter()
+            .any(|other| dominates(&other.poles, &point.poles));
+        println!(
+            " {:>6}  {}{}",
+            point.poles.values().sum::<usize>(),
+            point
+                .path
+                .as_ref()
+                .map_or("-".to_string(), |path| path.display().to_string()),
+            if dominated { " (dominated)" } else { "" }
+        );
+    }
+}
+
+/// Solves `--pareto N` times, scaling the cost of all pole types but the first by a varying ratio.
+/// Writes one blueprint per solution next to `out_file` if given, and prints a summary table.
+pub fn run_pareto(
+    blueprint: Blueprint,
+    args: &OptimizePoles,
+    num_points: usize,
+    out_file: Option<&Path>,
+    format: BlueprintFormat,
+) -> Result<(), OptimizerError> {
+    let mut state = PipelineState::new(blueprint, prototype_data::load_prototype_data()?);
+    if let Some(path) = &args.context {
+        state.context = Some(read_blueprint(path)?);
+    }
+    let base_costs = match &args.pole_costs {
+        Some(costs) => parse_pole_costs(costs)?,
+        None => Default::default(),
+    };
+
+    Pipeline::new()
+        .then(DecodeStage)
+        .then(ModelStage { args })
+        .then(CandidatesStage { args })
+        .run(&mut state)?;
+    let pole_types = state.pole_types.clone();
+    if pole_types.len() < 2 {
+        return Err("--pareto needs at least 2 pole types".into());
+    }
+    let model = state.model.clone();
+    let entities = state.entities.clone();
+
+    let mut points = vec![];
+    for (i, ratio) in cost_ratios(num_points).into_iter().enumerate() {
+        println!(
+            "Pareto point {}/{}: cost ratio {:.3}",
+            i + 1,
+            num_points,
+            ratio
+        );
+        let pole_costs = pole_types
+            .iter()
+            .enumerate()
+            .map(|(j, prototype)| {
+                let base = base_costs.get(prototype).copied().unwrap_or(1.0);
+                let cost = if j == 0 { base } else { base * ratio };
+                format!("{}={}", prototype.name, cost)
+            })
+            .join(",");
+        let point_args = OptimizePoles {
+            pole_costs: Some(pole_costs),
+            ..args.clone()
+        };
+
+        state.model = model.clone();
+        state.entities = entities.clone();
+        Pipeline::new()
+            .then(SolveStage { args: &point_args })
+            .then(PolishStage { args: &point_args })
+            .then(ConnectStage::new(args))
+            .then(EmitStage {
+                carry_circuit: args.carry_circuit,
+            })
+            .then(VerifyStage { args: &point_args })
+            .run(&mut state)?;
+
+        let path = out_file.map(|out_file| {
+            let stem = out_file.file_stem().unwrap().to_string_lossy();
+            out_file.with_file_name(format!("{}_pareto{}.{}", stem, i, format.extension()))
+        });
+        if let Some(path) = &path {
+            state.blueprint = write_blueprint(state.blueprint, path, format, None)?;
+        }
+        points.push(ParetoPoint {
+            ratio,
+            poles: count_poles(&state.model),
+            path,
+        });
+    }
+
+    print_table(&points);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cost_ratios() {
+        assert_eq!(cost_ratios(1), [1.0]);
+        let ratios = cost_ratios(3);
+        assert!((ratios[0] - 0.25).abs() < 1e-9);
+        assert!((ratios[1] - 1.0).abs() < 1e-9);
+        assert!((ratios[2] - 4.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_pareto_needs_two_points() {
+        use clap::Parser;
+        assert!(OptimizePoles::try_parse_from(["optimize", "--pareto", "1"]).is_err());
+        let opt = OptimizePoles::try_parse_from(["optimize", "--pareto", "2"]).unwrap();
+        assert_eq!(opt.pareto, Some(2));
+    }
+
+    #[test]
+    fn test_dominates() {
+        let a = EntityCounts::from([("small".to_string(), 5), ("medium".to_string(), 2)]);
+        let b = EntityCounts::from([("small".to_string(), 6), ("medium".to_string(), 2)]);
+        let c = EntityCounts::from([("small".to_string(), 4), ("medium".to_string(), 3)]);
+        assert!(dominates(&a, &b));
+        assert!(!dominates(&b, &a));
+        assert!(!dominates(&a, &c));
+        assert!(!dominates(&a, &a));
+    }
+}
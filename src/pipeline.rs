@@ -0,0 +1,2271 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use euclid::{point2, vec2};
+use factorio_blueprint::objects::Blueprint;
+use good_lp::highs;
+use indicatif::{ProgressBar, ProgressStyle};
+use itertools::Itertools;
+use log::warn;
+use petgraph::graph::NodeIndex;
+use serde::Serialize;
+
+use crate::algorithms::*;
+use crate::auto_poles::{choose_pole_types, ModelStats};
+use crate::better_bp::{BlueprintEntities, BlueprintTiles, EntityId};
+use crate::bp_model::{BpModel, WorldEntity, LANDFILL};
+use crate::budget::{self, TimeBudget};
+use crate::cancel::{self, CancellationToken};
+use crate::candidate_cache;
+use crate::candidates_file;
+use crate::chunk_align::{ChunkAlign, ChunkGrid, MISALIGNED_COST};
+use crate::circuit::{self, CarryCircuit};
+use crate::diagnose::{self, Uncoverable};
+use crate::distance_metric::DistanceMetric;
+use crate::draw;
+use crate::error::OptimizerError;
+use crate::graph_export::{export_graph_file, GraphFormat};
+use crate::highs_solve::HighsOptions;
+use crate::item_requests;
+use crate::mip_stats::HighsLog;
+use crate::pole_graph::*;
+use crate::position::{
+    exact_game_math, BoundingBox, BoundingBoxExt, IterTiles, MapPosition, MapPositionExt,
+    TileBoundingBox,
+};
+use crate::prototype_data::{self, EntityPrototypeDict, EntityPrototypeRef};
+use crate::rail_poles::{RailPoleGrid, RailPoles, OFF_RAIL_GRID_COST};
+use crate::report::{self, OptimizationReport};
+use crate::solver_state::SolverState;
+use crate::trace;
+use crate::verify::{self, Violation};
+use crate::water_mask;
+use crate::{
+    get_prototypes, parse_anchor, parse_area, parse_max_counts, parse_pole_costs, parse_tuple,
+    read_blueprint, require_prototype, sep_commas, CenterMode, ExportGraphKind, OptimizePoles,
+    SolverKind,
+};
+
+thread_local! {
+    static STAGE_BAR: RefCell<Option<ProgressBar>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` with the spinner of the stage running on this thread hidden, so its output doesn't garble the spinner,
+/// e.g. while HiGHS writes its log to the console.
+pub fn suspend_progress<R>(f: impl FnOnce() -> R) -> R {
+    // taken out while suspended, as suspending it again from `f` would deadlock
+    match STAGE_BAR.with(|bar| bar.borrow_mut().take()) {
+        Some(bar) => {
+            let result = bar.suspend(f);
+            STAGE_BAR.with(|current| *current.borrow_mut() = Some(bar));
+            result
+        }
+        None => f(),
+    }
+}
+
+/// Like `println!`, but hides the stage's spinner while printing.
+macro_rules! stage_println {
+    ($($arg:tt)*) => {
+        suspend_progress(|| println!($($arg)*))
+    };
+}
+
+/// Intermediate results passed between pipeline stages.
+pub struct PipelineState {
+    pub prototype_data: EntityPrototypeDict,
+    pub blueprint: Blueprint,
+    /// Reference blueprint from `--context`. Its entities are obstacles and need power, but are not output.
+    pub context: Option<Blueprint>,
+    /// Ids of entities from [Self::context] in [Self::model].
+    pub context_entities: hashbrown::HashSet<EntityId>,
+    /// Entities the solution doesn't need to power: near the edge from `--assume-powered-border`,
+    /// or from `--treat-as-obstacle`.
+    pub externally_powered: hashbrown::HashSet<EntityId>,
+    /// Names of entities left out of [Self::model], from `--treat-as-free`; poles may be placed over them.
+    pub free_entities: HashSet<String>,
+    pub entities: BlueprintEntities,
+    pub tiles: BlueprintTiles,
+    pub model: BpModel,
+    pub bounding_box: TileBoundingBox,
+    /// Pole types used for candidate poles.
+    pub pole_types: Vec<EntityPrototypeRef>,
+    /// Input poles removed by `--remove-entities`.
+    pub removed_poles: Vec<WorldEntity>,
+    pub candidates: CandPoleGraph,
+    /// Nodes in [Self::candidates] that must be kept.
+    pub fixed_poles: hashbrown::HashSet<NodeIndex>,
+    pub solution: CandPoleGraph,
+    pub report: OptimizationReport,
+    /// Checked between stages; cancel it from another thread to stop the pipeline.
+    pub cancel: CancellationToken,
+    /// Names of stages that have finished, for diagnostics if the pipeline stops early.
+    pub completed_stages: Vec<&'static str>,
+    /// From `--budget`; stages scale down their work to fit in it.
+    pub budget: Option<TimeBudget>,
+}
+
+impl PipelineState {
+    pub fn new(blueprint: Blueprint, prototype_data: EntityPrototypeDict) -> Self {
+        PipelineState {
+            prototype_data,
+            blueprint,
+            context: None,
+            context_entities: Default::default(),
+            externally_powered: Default::default(),
+            free_entities: HashSet::new(),
+            entities: BlueprintEntities::new(),
+            tiles: BlueprintTiles::default(),
+            model: BpModel::new(),
+            bounding_box: TileBoundingBox::zero(),
+            pole_types: vec![],
+            removed_poles: vec![],
+            candidates: CandPoleGraph::default(),
+            fixed_poles: Default::default(),
+            solution: CandPoleGraph::default(),
+            report: OptimizationReport::default(),
+            cancel: CancellationToken::new(),
+            completed_stages: vec![],
+            budget: None,
+        }
+    }
+}
+
+/// A single step of the optimization pipeline.
+pub trait PipelineStage {
+    fn name(&self) -> &'static str;
+
+    fn run(&self, state: &mut PipelineState) -> Result<(), OptimizerError>;
+
+    /// Amount of work done by this stage and its unit, e.g. `(1000, "entities")`, to show a rate in the progress display.
+    fn work_done(&self, _state: &PipelineState) -> Option<(usize, &'static str)> {
+        None
+    }
+
+    /// More to show in the progress display after the stage finishes, e.g. the size of the ILP it solved.
+    fn details(&self, _state: &PipelineState) -> Option<String> {
+        None
+    }
+
+    /// Writes the artifact produced by this stage to a file, for debugging.
+    fn dump(&self, _state: &PipelineState, _path: &Path) -> Result<(), OptimizerError> {
+        Err(format!("Stage '{}' has nothing to dump", self.name()).into())
+    }
+}
+
+type StageHook<'a> =
+    Box<dyn Fn(&dyn PipelineStage, &PipelineState) -> Result<(), OptimizerError> + 'a>;
+
+/// An ordered list of stages, run one after the other on the same [PipelineState].
+pub struct Pipeline<'a> {
+    stages: Vec<Box<dyn PipelineStage + 'a>>,
+    after_stage: Vec<StageHook<'a>>,
+    progress: bool,
+}
+
+impl<'a> Pipeline<'a> {
+    pub fn new() -> Self {
+        Pipeline {
+            stages: vec![],
+            after_stage: vec![],
+            progress: false,
+        }
+    }
+
+    /// decode → model → candidates → solve → trunk → polish → compare → connect → emit → verify.
+    /// Trunk and polish only do anything if enabled.
+    pub fn standard(args: &'a OptimizePoles) -> Self {
+        Pipeline::new()
+            .then(DecodeStage)
+            .then(ModelStage { args })
+            .then(CandidatesStage { args })
+            .then(SolveStage { args })
+            .then(TrunkStage { args })
+            .then(PolishStage { args })
+            .then(CompareStage { args })
+            .then(ConnectStage::new(args))
+            .then(EmitStage {
+                carry_circuit: args.carry_circuit,
+            })
+            .then(VerifyStage { args })
+            .with_progress(!args.quiet)
+    }
+
+    /// decode → model → candidates → solve → connect → emit → verify, for `fix-power`.
+    /// There is no compare stage, as the solution always costs more than the input it adds to.
+    pub fn fix_power(args: &'a OptimizePoles) -> Self {
+        Pipeline::new()
+            .then(DecodeStage)
+            .then(ModelStage { args })
+            .then(CandidatesStage { args })
+            .then(SolveStage { args })
+            .then(ConnectStage::new(args))
+            .then(EmitStage {
+                carry_circuit: args.carry_circuit,
+            })
+            .then(VerifyStage { args })
+            .with_progress(!args.quiet)
+    }
+
+    /// decode → model → candidates → keep-all → connect → emit → verify, for `rewire`.
+    /// `args` should make the input poles the only candidates, so they all stay and only get new wires.
+    pub fn rewire(args: &'a OptimizePoles) -> Self {
+        Pipeline::new()
+            .then(DecodeStage)
+            .then(ModelStage { args })
+            .then(CandidatesStage { args })
+            .then(KeepAllStage)
+            .then(ConnectStage::new(args))
+            .then(EmitStage {
+                carry_circuit: args.carry_circuit,
+            })
+            .then(VerifyStage { args })
+            .with_progress(!args.quiet)
+    }
+
+    /// decode → model → candidates → estimate; does not solve the full ILP.
+    pub fn estimate(args: &'a OptimizePoles) -> Self {
+        Pipeline::new()
+            .then(DecodeStage)
+            .then(ModelStage { args })
+            .then(CandidatesStage { args })
+            .then(EstimateStage { args })
+            .with_progress(!args.quiet)
+    }
+
+    /// decode → model → candidates → export-model; writes the ILP to `path` instead of solving it.
+    pub fn export_model(args: &'a OptimizePoles, path: &'a Path) -> Self {
+        Pipeline::new()
+            .then(DecodeStage)
+            .then(ModelStage { args })
+            .then(CandidatesStage { args })
+            .then(ExportModelStage { args, path })
+            .with_progress(!args.quiet)
+    }
+
+    pub fn then(mut self, stage: impl PipelineStage + 'a) -> Self {
+        self.stages.push(Box::new(stage));
+        self
+    }
+
+    pub fn stage_names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.stages.iter().map(|stage| stage.name())
+    }
+
+    /// Replaces the stage with the same name. Returns false if there is no such stage.
+    #[allow(dead_code)]
+    pub fn replace_stage(&mut self, stage: impl PipelineStage + 'a) -> bool {
+        let Some(index) = self.stage_names().position(|name| name == stage.name()) else {
+            return false;
+        };
+        self.stages[index] = Box::new(stage);
+        true
+    }
+
+    /// Shows a spinner while each stage runs, and its time and rate afterward.
+    pub fn with_progress(mut self, progress: bool) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Adds a function called after every stage.
+    pub fn after_stage(
+        mut self,
+        hook: impl Fn(&dyn PipelineStage, &PipelineState) -> Result<(), OptimizerError> + 'a,
+    ) -> Self {
+        self.after_stage.push(Box::new(hook));
+        self
+    }
+
+    /// Dumps the artifact of the given stage after it runs.
+    pub fn dump_stage(self, name: &str, path: PathBuf) -> Result<Self, OptimizerError> {
+        if !self.stage_names().any(|stage_name| stage_name == name) {
+            return Err(format!(
+                "Unknown stage '{}'; stages are: {}",
+                name,
+                self.stage_names().collect::<Vec<_>>().join(", ")
+            )
+            .into());
+        }
+        let name = name.to_string();
+        Ok(self.after_stage(move |stage, state| {
+            if stage.name() == name {
+                println!("Writing {} stage to {:?}", name, path);
+                stage.dump(state, &path)?;
+            }
+            Ok(())
+        }))
+    }
+
+    fn stage_progress_bar(&self, stage: &dyn PipelineStage) -> ProgressBar {
+        if !self.progress {
+            return ProgressBar::hidden();
+        }
+        let bar = ProgressBar::new_spinner()
+            .with_style(ProgressStyle::with_template("{spinner} {msg} [{elapsed}]").unwrap())
+            .with_message(stage.name());
+        bar.enable_steady_tick(Duration::from_millis(100));
+        bar
+    }
+
+    pub fn run(&self, state: &mut PipelineState) -> Result<(), OptimizerError> {
+        let start = Instant::now();
+        let token = state.cancel.clone();
+        for stage in &self.stages {
+            token.check(stage.name())?;
+            let bar = self.stage_progress_bar(stage.as_ref());
+            let stage_start = Instant::now();
+            let prev_bar = STAGE_BAR.with(|current| current.replace(Some(bar.clone())));
+            let result = cancel::with_token(&token, || {
+                trace::stage_span(stage.name()).in_scope(|| stage.run(state))
+            });
+            STAGE_BAR.with(|current| *current.borrow_mut() = prev_bar);
+            let elapsed = stage_start.elapsed();
+            if result.is_err() {
+                bar.abandon_with_message(format!("{}: failed after {:.2?}", stage.name(), elapsed));
+            } else {
+                let mut parts = vec![];
+                if let Some((amount, unit)) = stage.work_done(state) {
+                    parts.push(format!(
+                        "{} {}, {:.0} {}/s",
+                        amount,
+                        unit,
+                        amount as f64 / elapsed.as_secs_f64().max(1e-9),
+                        unit
+                    ));
+                }
+                parts.extend(stage.details(state));
+                parts.push(format!("{:.2?}", elapsed));
+                bar.finish_with_message(format!("{}: {}", stage.name(), parts.join(", ")));
+            }
+            result?;
+            state.completed_stages.push(stage.name());
+            for hook in &self.after_stage {
+                hook(stage.as_ref(), state)?;
+            }
+        }
+        if self.progress {
+            println!("Finished in {:.2?}", start.elapsed());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct EntityJson<'a> {
+    name: &'a str,
+    x: f64,
+    y: f64,
+    direction: u8,
+}
+
+fn write_entities_json<'a>(
+    path: &Path,
+    entities: impl Iterator<Item = &'a WorldEntity>,
+) -> Result<(), OptimizerError> {
+    let entities = entities
+        .map(|entity| EntityJson {
+            name: &entity.prototype.name,
+            x: entity.position.x,
+            y: entity.position.y,
+            direction: entity.direction,
+        })
+        .collect::<Vec<_>>();
+    serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), &entities)?;
+    Ok(())
+}
+
+/// Converts the blueprint into [BlueprintEntities] and [BlueprintTiles].
+pub struct DecodeStage;
+impl PipelineStage for DecodeStage {
+    fn name(&self) -> &'static str {
+        "decode"
+    }
+    fn work_done(&self, state: &PipelineState) -> Option<(usize, &'static str)> {
+        Some((state.entities.entities.len(), "entities"))
+    }
+    fn run(&self, state: &mut PipelineState) -> Result<(), OptimizerError> {
+        state.entities = BlueprintEntities::from_blueprint(&state.blueprint);
+        state.tiles = BlueprintTiles::from_blueprint(&state.blueprint);
+        Ok(())
+    }
+}
+
+/// Builds the [BpModel], and removes entities that should not be in the output.
+pub struct ModelStage<'a> {
+    pub args: &'a OptimizePoles,
+}
+impl PipelineStage for ModelStage<'_> {
+    fn name(&self) -> &'static str {
+        "model"
+    }
+    fn work_done(&self, state: &PipelineState) -> Option<(usize, &'static str)> {
+        Some((state.model.all_entities().count(), "entities"))
+    }
+    fn run(&self, state: &mut PipelineState) -> Result<(), OptimizerError> {
+        let args = self.args;
+        // todo: consolidate these 2 representations??
+        state.model = BpModel::from_bp_entities(&state.entities, &state.prototype_data);
+        state.model.add_floor_tiles(state.tiles.iter());
+        state.report.poles_before = report::count_poles(&state.model);
+        state.report.wire_length_before = report::wire_length(&state.model);
+
+        if !args.remove_entities.is_empty() {
+            let to_remove = get_prototypes(&args.remove_entities, &state.prototype_data)?
+                .into_iter()
+                .map(|prototype| prototype.name.clone())
+                .collect::<HashSet<_>>();
+            let removed = state
+                .entities
+                .retain(|entity| !to_remove.contains(entity.name.as_str()));
+            state.removed_poles = state
+                .model
+                .poles()
+                .filter(|entity| to_remove.contains(&entity.prototype.name))
+                .map(|entity| entity.entity.clone())
+                .collect();
+            state
+                .model
+                .retain(|entity| !to_remove.contains(&entity.prototype.name));
+            state.report.removed_entities =
+                report::count_by_name(removed.iter().map(|entity| entity.name.as_str()));
+        }
+        if !args.treat_as_free.is_empty() {
+            state.free_entities = get_prototypes(&args.treat_as_free, &state.prototype_data)?
+                .into_iter()
+                .map(|prototype| prototype.name.clone())
+                .collect();
+            let free = &state.free_entities;
+            let before = state.model.all_entities().count();
+            state
+                .model
+                .retain(|entity| !free.contains(&entity.prototype.name));
+            stage_println!(
+                "Ignoring {} entities, as --treat-as-free",
+                before - state.model.all_entities().count()
+            );
+        }
+        if args.replace_in_place && state.removed_poles.is_empty() {
+            warn!("--replace-in-place does nothing, as --remove-entities removed no poles");
+        }
+
+        if !args.avoid_tiles.is_empty() {
+            let names = sep_commas(&args.avoid_tiles).collect_vec();
+            let tiles = state.model.floor_tiles_named(&names).collect_vec();
+            if tiles.is_empty() {
+                warn!("the blueprint has no {} tiles to avoid", names.join(" or "));
+            } else {
+                stage_println!("Avoiding {} tiles", tiles.len());
+            }
+            state.model.reserve_tiles(tiles);
+        }
+        if let Some(path) = &args.water_mask {
+            let (x, y) = parse_tuple(&args.water_mask_origin)?;
+            let water = water_mask::load(path, (x.round() as i32, y.round() as i32))?;
+            let count = state.model.set_water_tiles(water, args.allow_landfill);
+            stage_println!("{} water tiles have no landfill", count);
+        }
+
+        // before adding context, so the edge is the blueprint's own
+        if let Some(border) = args.assume_powered_border {
+            let inner = state.model.get_bounding_box().inflate(-border, -border);
+            state.externally_powered = state
+                .model
+                .all_entities()
+                .filter(|entity| {
+                    entity.uses_power()
+                        && !inner.contains_box(&entity.world_bbox().round_out_to_tiles())
+                })
+                .map(|entity| entity.id())
+                .collect();
+            stage_println!(
+                "Assuming {} entities within {} tiles of the edge are powered externally",
+                state.externally_powered.len(),
+                border
+            );
+        }
+        if !args.treat_as_obstacle.is_empty() {
+            let obstacles = get_prototypes(&args.treat_as_obstacle, &state.prototype_data)?;
+            let ids = state
+                .model
+                .all_entities()
+                .filter(|entity| entity.uses_power() && obstacles.contains(&entity.prototype))
+                .map(|entity| entity.id())
+                .collect_vec();
+            stage_println!(
+                "Treating {} entities as obstacles that need no power",
+                ids.len()
+            );
+            state.externally_powered.extend(ids);
+        }
+
+        if let Some(context) = &state.context {
+            let context_entities = BlueprintEntities::from_blueprint(context);
+            state.context_entities = state
+                .model
+                .add_bp_entities(&context_entities, &state.prototype_data)
+                .into_iter()
+                .collect();
+            stage_println!(
+                "Added {} entities from context",
+                state.context_entities.len()
+            );
+        }
+
+        // like a context pole: fixed, and not written to the output
+        if let Some(anchor) = &args.anchor {
+            let anchor = parse_anchor(anchor, &state.prototype_data)?;
+            stage_println!(
+                "Connecting to {} at ({}, {})",
+                anchor.prototype.name,
+                anchor.position.x,
+                anchor.position.y
+            );
+            let id = state.model.add_overlap(anchor);
+            state.context_entities.insert(id);
+        }
+
+        state.bounding_box = if args.expand == 0 {
+            state.model.get_bounding_box()
+        } else {
+            state
+                .model
+                .get_bounding_box()
+                .inflate(args.expand, args.expand)
+        };
+        Ok(())
+    }
+    fn dump(&self, state: &PipelineState, path: &Path) -> Result<(), OptimizerError> {
+        serde_json::to_writer(BufWriter::new(File::create(path)?), &state.model)?;
+        Ok(())
+    }
+}
+
+/// Generates the candidate pole graph.
+pub struct CandidatesStage<'a> {
+    pub args: &'a OptimizePoles,
+}
+impl PipelineStage for CandidatesStage<'_> {
+    fn name(&self) -> &'static str {
+        "candidates"
+    }
+    fn work_done(&self, state: &PipelineState) -> Option<(usize, &'static str)> {
+        Some((state.bounding_box.area().max(0) as usize, "tiles"))
+    }
+    fn run(&self, state: &mut PipelineState) -> Result<(), OptimizerError> {
+        if let Some(path) = &self.args.candidates {
+            let candidates = candidates_file::load(path, &state.prototype_data)?;
+            stage_println!("Using {} candidate poles from {:?}", candidates.len(), path);
+            state.pole_types = candidates
+                .iter()
+                .map(|entity| entity.prototype.clone())
+                .unique()
+                .collect();
+            let candidate_model = candidates_file::place(&state.model, &candidates)?;
+            return self.connect_candidates(state, candidate_model);
+        }
+        if self.args.only_input_poles {
+            state.pole_types = state
+                .model
+                .poles()
+                .map(|entity| entity.prototype.clone())
+                .unique()
+                .sorted_by(|a, b| a.name.cmp(&b.name))
+                .collect();
+            stage_println!(
+                "Using the {} input poles as the only candidates",
+                state.model.poles().count()
+            );
+            let candidate_model = state.model.clone();
+            return self.connect_candidates(state, candidate_model);
+        }
+        state.pole_types = self.pole_types(state)?;
+        let step = match state.budget {
+            Some(budget) => budget.candidate_step(
+                state.bounding_box.area().max(0) as usize,
+                state.pole_types.len(),
+            ),
+            None => 1,
+        };
+        if step > 1 {
+            stage_println!(
+                "Placing candidate poles every {} tiles, to fit in --budget",
+                step
+            );
+        }
+        let cache_path = match &self.args.cache_dir {
+            Some(dir) => Some(candidate_cache::cache_path(
+                dir,
+                self.cache_key(state, step)?,
+            )),
+            None => None,
+        };
+        if let Some(path) = &cache_path {
+            if let Some((candidates, fixed_poles)) =
+                candidate_cache::load(path, &state.prototype_data)?
+            {
+                stage_println!("Loaded candidate poles from cache {:?}", path);
+                state.candidates = candidates;
+                state.fixed_poles = fixed_poles;
+                return Ok(());
+            }
+        }
+        self.generate(state, step)?;
+        if let Some(path) = &cache_path {
+            candidate_cache::save(path, &state.candidates, &state.fixed_poles)?;
+        }
+        Ok(())
+    }
+    fn dump(&self, state: &PipelineState, path: &Path) -> Result<(), OptimizerError> {
+        export_graph_file(&state.candidates, path)
+    }
+}
+
+impl CandidatesStage<'_> {
+    fn pole_types(&self, state: &PipelineState) -> Result<Vec<EntityPrototypeRef>, OptimizerError> {
+        let args = self.args;
+        if !args.use_poles.is_empty() || !args.auto_poles {
+            return get_prototypes(&args.use_poles, &state.prototype_data);
+        }
+        let stats = ModelStats::of_model(&state.model);
+        let (names, reason) = choose_pole_types(&stats);
+        stage_println!(
+            "Using poles {} ({}; {:.0}% of tiles occupied, {:.1} powered entities per 100 tiles)",
+            names.join(", "),
+            reason,
+            stats.density() * 100.0,
+            stats.power_density()
+        );
+        Ok(names
+            .iter()
+            .map(|name| state.prototype_data[name].clone())
+            .collect())
+    }
+
+    fn cache_key(&self, state: &PipelineState, step: i32) -> Result<u64, OptimizerError> {
+        let args = self.args;
+        let blueprint = serde_json::to_vec(&state.blueprint)?;
+        let context = match &state.context {
+            Some(context) => serde_json::to_vec(context)?,
+            None => vec![],
+        };
+        let water_mask = match &args.water_mask {
+            Some(path) => std::fs::read(path)?,
+            None => vec![],
+        };
+        let prototype_data = prototype_data::prototype_data_files()
+            .iter()
+            .map(std::fs::read)
+            .collect::<Result<Vec<_>, _>>()?
+            .concat();
+        let options = [
+            args.use_poles.join(","),
+            args.remove_entities.join(","),
+            args.keep_input_poles.join(","),
+            args.keep_poles_in.join(";"),
+            args.avoid_tiles.join(","),
+            format!("{:?}", args.assume_powered_border),
+            args.expand.to_string(),
+            args.auto_poles.to_string(),
+            args.anchor.clone().unwrap_or_default(),
+            step.to_string(),
+            args.replace_in_place.to_string(),
+            exact_game_math().to_string(),
+            format!(
+                "{:?},{},{}",
+                args.chunk_align, args.chunk_period, args.chunk_offset
+            ),
+            format!("{:?}", args.rail_poles),
+            format!("{},{}", args.water_mask_origin, args.allow_landfill),
+            args.treat_as_free.join(","),
+            args.treat_as_obstacle.join(","),
+        ];
+        Ok(candidate_cache::cache_key(
+            &[&blueprint, &context, &water_mask, &prototype_data],
+            &options.iter().map(String::as_str).collect::<Vec<_>>(),
+        ))
+    }
+
+    /// Places candidate poles on every `step`th tile; see [BpModel::with_coarse_candidate_poles].
+    fn generate(&self, state: &mut PipelineState, step: i32) -> Result<(), OptimizerError> {
+        let args = self.args;
+        let model = &state.model;
+        let mut candidate_model =
+            model.with_coarse_candidate_poles(state.bounding_box, &state.pole_types, step);
+        state.cancel.check(self.name())?;
+        // a coarse grid may skip the removed poles' positions
+        if args.replace_in_place && step > 1 {
+            for pole in &state.removed_poles {
+                let placed = candidate_model
+                    .get_at_tile(pole.position.tile_pos())
+                    .any(|entity| entity.entity == *pole);
+                if state.pole_types.contains(&pole.prototype) && !placed && model.can_place(pole) {
+                    candidate_model.add_overlap(pole.clone());
+                }
+            }
+        }
+        if let Some(rail_poles) = args.rail_poles {
+            let grid = RailPoleGrid::from_model(model);
+            if grid.is_empty() {
+                warn!("--rail-poles does nothing, as the blueprint has no parallel rails");
+            } else if rail_poles == RailPoles::Require {
+                candidate_model
+                    .retain(|entity| model.get(entity.id()).is_some() || !grid.is_off_grid(entity));
+            }
+            // a coarse grid may skip the positions between rails
+            if step > 1 {
+                add_rail_grid_candidates(&mut candidate_model, model, &grid, &state.pole_types);
+            }
+        }
+        if args.chunk_align == Some(ChunkAlign::Require) {
+            let grid = chunk_grid(args)?;
+            // existing entities keep their ids in the candidate model
+            candidate_model
+                .retain(|entity| model.get(entity.id()).is_some() || !grid.is_misaligned(entity));
+        }
+        state.cancel.check(self.name())?;
+        self.connect_candidates(state, candidate_model)
+    }
+
+    /// Builds the candidate graph from a model with the candidate poles added, and finds the poles to keep.
+    fn connect_candidates(
+        &self,
+        state: &mut PipelineState,
+        candidate_model: BpModel,
+    ) -> Result<(), OptimizerError> {
+        let args = self.args;
+        let model = &state.model;
+        let (pole_graph, id_map) = candidate_model.get_maximally_connected_pole_graph();
+        state.cancel.check(self.name())?;
+        state.candidates = pole_graph.to_cand_pole_graph(model);
+        remove_externally_powered(&mut state.candidates, &state.externally_powered);
+
+        let keep_prototypes = get_prototypes(&args.keep_input_poles, &state.prototype_data)?;
+        let keep_areas = args
+            .keep_poles_in
+            .iter()
+            .map(|area| parse_area(area))
+            .collect::<Result<Vec<_>, _>>()?;
+        state.fixed_poles = model
+            .poles()
+            .filter(|entity| {
+                keep_prototypes.contains(&entity.prototype)
+                    || keep_areas.iter().any(|area| area.contains(entity.position))
+            })
+            .map(|entity| id_map[&entity.id()])
+            .collect();
+        // context poles already exist, so they must be kept
+        state.fixed_poles.extend(
+            state
+                .context_entities
+                .iter()
+                .filter(|id| model.get(**id).unwrap().prototype.is_pole())
+                .map(|id| id_map[id]),
+        );
+        if !state.fixed_poles.is_empty() {
+            stage_println!("Keeping {} existing poles", state.fixed_poles.len());
+        }
+        Ok(())
+    }
+}
+
+/// Adds candidates of the pole types [RailPoleGrid] applies to at its positions, where they aren't already.
+fn add_rail_grid_candidates(
+    candidate_model: &mut BpModel,
+    model: &BpModel,
+    grid: &RailPoleGrid,
+    pole_types: &[EntityPrototypeRef],
+) {
+    for prototype in pole_types {
+        for (x, y) in grid.positions() {
+            let pole = WorldEntity {
+                position: point2(x, y),
+                direction: 0,
+                prototype: prototype.clone(),
+            };
+            let placed = candidate_model
+                .get_at_tile(pole.position.tile_pos())
+                .any(|entity| entity.entity == pole);
+            if grid.applies_to(&pole) && !placed && model.can_place(&pole) {
+                candidate_model.add_overlap(pole);
+            }
+        }
+    }
+}
+
+/// Removes `externally_powered` entities from what each candidate powers, so solvers don't need to cover them.
+fn remove_externally_powered(
+    candidates: &mut CandPoleGraph,
+    externally_powered: &hashbrown::HashSet<EntityId>,
+) {
+    if externally_powered.is_empty() {
+        return;
+    }
+    for node in candidates.node_weights_mut() {
+        node.powered_entities
+            .retain(|id| !externally_powered.contains(id));
+    }
+}
+
+/// Entities that need power but have none, not counting externally powered ones.
+fn count_unpowered(state: &PipelineState) -> usize {
+    state
+        .model
+        .unpowered_entities()
+        .filter(|entity| !state.externally_powered.contains(&entity.id()))
+        .count()
+}
+
+fn configure_solver(
+    args: &OptimizePoles,
+    time_limit: f64,
+) -> impl Fn(HighsOptions) -> Result<HighsOptions, OptimizerError> + '_ {
+    move |mut options| {
+        options.set_verbose(!args.quiet);
+        Ok(options
+            .set_mip_rel_gap(args.mip_rel_gap)?
+            .set_mip_abs_gap(args.mip_abs_gap)?
+            .set_time_limit(time_limit))
+    }
+}
+
+fn connectivity(
+    state: &PipelineState,
+    args: &OptimizePoles,
+) -> Result<Option<DistanceConnectivity>, OptimizerError> {
+    if !args.no_connectivity {
+        return Ok(None);
+    }
+    let center_rel_pos = parse_tuple(&args.center_pos)?;
+    let mut roots = vec![];
+    if let Some(anchor) = &args.anchor {
+        let (x, y) = parse_tuple(anchor)?;
+        roots.push(point2(x, y));
+    }
+    if let Some(root_list) = &args.roots {
+        for root in root_list.split(';') {
+            let (x, y) = parse_tuple(root)?;
+            roots.push(point2(x, y));
+        }
+    }
+    let center = args.center.and_then(|mode| auto_center(&state.model, mode));
+    if let Some(spacing) = args.root_spacing {
+        if spacing <= 0.0 {
+            return Err("--root-spacing must be positive".into());
+        }
+        let bbox = state.bounding_box.to_f64().cast_unit();
+        let center = center.unwrap_or_else(|| bbox.relative_pt_at(center_rel_pos));
+        roots.extend(seeded_roots(bbox, spacing, center));
+    }
+    if roots.is_empty() {
+        roots.extend(center);
+    }
+    Ok(Some(DistanceConnectivity {
+        center_rel_pos,
+        roots,
+        max_hops: args.max_hops,
+        connect_fixed: args.connect_kept_poles,
+    }))
+}
+
+/// Roots every `spacing` tiles along the longer axis of `bbox`, in line with `center`, for `--root-spacing`.
+fn seeded_roots(bbox: BoundingBox, spacing: f64, center: MapPosition) -> Vec<MapPosition> {
+    let horizontal = bbox.width() >= bbox.height();
+    let (start, length) = if horizontal {
+        (bbox.min.x, bbox.width())
+    } else {
+        (bbox.min.y, bbox.height())
+    };
+    let count = (length / spacing).ceil().max(1.0) as usize;
+    // centered, so the ends are equally far from a root
+    let offset = (length - spacing * (count - 1) as f64) / 2.0;
+    (0..count)
+        .map(|i| start + offset + spacing * i as f64)
+        .map(|along| {
+            if horizontal {
+                point2(along, center.y)
+            } else {
+                point2(center.x, along)
+            }
+        })
+        .collect()
+}
+
+/// Most entities to compute a medoid of; more are sampled evenly, as it takes quadratic time.
+const MAX_MEDOID_ENTITIES: usize = 2000;
+
+/// Center computed from the entities that use power, for `--center`. None if there are none.
+fn auto_center(model: &BpModel, mode: CenterMode) -> Option<MapPosition> {
+    let positions = model
+        .all_entities_grid_order()
+        .filter(|entity| entity.uses_power())
+        .map(|entity| entity.position)
+        .collect_vec();
+    if positions.is_empty() {
+        return None;
+    }
+    match mode {
+        CenterMode::Auto => {
+            let sum = positions
+                .iter()
+                .fold(vec2(0.0, 0.0), |sum, pos| sum + pos.to_vector());
+            Some((sum / positions.len() as f64).to_point())
+        }
+        CenterMode::Medoid => {
+            let step = positions.len().div_ceil(MAX_MEDOID_ENTITIES);
+            let sample = positions.into_iter().step_by(step).collect_vec();
+            let total_distance =
+                |a: MapPosition| -> f64 { sample.iter().map(|b| (a - *b).length()).sum() };
+            sample
+                .iter()
+                .copied()
+                .min_by(|a, b| total_distance(*a).total_cmp(&total_distance(*b)))
+        }
+    }
+}
+
+fn max_count(
+    args: &OptimizePoles,
+) -> Result<hashbrown::HashMap<EntityPrototypeRef, usize>, OptimizerError> {
+    Ok(match &args.max_count {
+        Some(max_count) => parse_max_counts(max_count)?.into_iter().collect(),
+        None => Default::default(),
+    })
+}
+
+/// Limit on the entities each pole supplies, from `--max-load` or `--max-load-mw`.
+fn load_limit(
+    state: &PipelineState,
+    args: &OptimizePoles,
+) -> Result<Option<LoadLimit>, OptimizerError> {
+    if let Some(max) = args.max_load {
+        return Ok(Some(LoadLimit::count(max)));
+    }
+    let Some(megawatts) = args.max_load_mw else {
+        return Ok(None);
+    };
+    let max = megawatts * 1e6;
+    let weights = state
+        .model
+        .all_entities()
+        .filter_map(|entity| Some((entity.id(), entity.prototype.energy_data?.consumption)))
+        .collect::<hashbrown::HashMap<_, _>>();
+    if let Some((id, _)) = weights.iter().find(|(_, watts)| **watts > max) {
+        let entity = state.model.get(*id).unwrap();
+        return Err(format!(
+            "{} at ({}, {}) draws more than --max-load-mw on its own",
+            entity.prototype.name, entity.position.x, entity.position.y
+        )
+        .into());
+    }
+    Ok(Some(LoadLimit {
+        max,
+        weights: Some(weights),
+    }))
+}
+
+/// Whether entities of a class must be powered, from `--priority`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Priority {
+    Required,
+    Soft,
+}
+
+/// Parses `--priority` into the tier of each class: an entity type, an entity name, or `*`.
+fn parse_priorities(spec: &str) -> Result<HashMap<String, Priority>, OptimizerError> {
+    spec.split(',')
+        .map(|part| {
+            let (class, tier) = part
+                .split_once('=')
+                .ok_or_else(|| format!("Expected 'class=tier', got '{}'", part))?;
+            let tier = match tier {
+                "required" => Priority::Required,
+                "soft" => Priority::Soft,
+                _ => {
+                    return Err(format!(
+                        "Unknown priority '{}'; expected 'required' or 'soft'",
+                        tier
+                    )
+                    .into())
+                }
+            };
+            Ok((class.to_string(), tier))
+        })
+        .collect()
+}
+
+/// Entities the solution may leave unpowered, with the cost of doing so, from `--priority`.
+/// An entity's name takes precedence over its type, which takes precedence over `*`.
+fn soft_entities(
+    state: &PipelineState,
+    args: &OptimizePoles,
+) -> Result<hashbrown::HashMap<EntityId, f64>, OptimizerError> {
+    let Some(spec) = &args.priority else {
+        return Ok(Default::default());
+    };
+    let priorities = parse_priorities(spec)?;
+    Ok(state
+        .model
+        .all_entities()
+        .filter(|entity| {
+            let prototype = &entity.prototype;
+            let priority = [prototype.name.as_str(), prototype.type_.as_str(), "*"]
+                .into_iter()
+                .find_map(|class| priorities.get(class));
+            entity.uses_power() && priority == Some(&Priority::Soft)
+        })
+        .map(|entity| (entity.id(), args.unpowered_cost))
+        .collect())
+}
+
+fn chunk_grid(args: &OptimizePoles) -> Result<ChunkGrid, OptimizerError> {
+    ChunkGrid::parse(args.chunk_period, &args.chunk_offset)
+}
+
+/// Candidates matching the poles saved in `dir` by `--save-solver-state`.
+fn initial_solution(
+    dir: &Path,
+    candidates: &CandPoleGraph,
+) -> Result<hashbrown::HashSet<NodeIndex>, OptimizerError> {
+    let Some(saved) = SolverState::load(dir)? else {
+        warn!("no solver state saved in {:?} yet", dir);
+        return Ok(Default::default());
+    };
+    let (matched, missing) = saved.match_candidates(candidates);
+    stage_println!(
+        "Starting from {} saved poles{}",
+        matched.len(),
+        if missing > 0 {
+            format!("; {} are no longer candidates", missing)
+        } else {
+            String::new()
+        }
+    );
+    Ok(matched)
+}
+
+/// The candidates selected in a solution from another solver, to a model from `--export-model`.
+fn import_solution(
+    path: &Path,
+    candidates: &CandPoleGraph,
+) -> Result<CandPoleGraph, OptimizerError> {
+    let columns = candidates
+        .node_indices()
+        .map(|idx| (pole_column_name(&candidates[idx].entity), idx))
+        .collect::<HashMap<_, _>>();
+    let values = read_solution(path, |name| columns.contains_key(name))?;
+    if values.is_empty() {
+        return Err(format!(
+            "No candidate poles in the solution {:?}; was the model exported with the same blueprint and options?",
+            path
+        )
+        .into());
+    }
+    let selected = values
+        .iter()
+        .filter(|(_, value)| **value > 0.5)
+        .map(|(name, _)| columns[name])
+        .collect::<hashbrown::HashSet<_>>();
+    stage_println!("Imported {} poles from {:?}", selected.len(), path);
+    let unpowered = get_pole_coverage_dict(candidates)
+        .values()
+        .filter(|poles| poles.is_disjoint(&selected))
+        .count();
+    if unpowered > 0 {
+        warn!(
+            "the imported solution leaves {} entities unpowered",
+            unpowered
+        );
+    }
+    Ok(candidates.filter_map(
+        |idx, node| selected.contains(&idx).then(|| node.clone()),
+        |_, w| Some(*w),
+    ))
+}
+
+/// Cost of each candidate pole, from `--pole-costs`, `--distance-cost`, `--chunk-align`, `--rail-poles`,
+/// and landfill from `--allow-landfill`.
+fn pole_cost_fn<'a>(
+    state: &PipelineState,
+    args: &'a OptimizePoles,
+) -> Result<impl Fn(&CandPoleGraph, NodeIndex) -> f64 + 'a, OptimizerError> {
+    let mut pole_costs = state
+        .prototype_data
+        .0
+        .iter()
+        .filter(|(_, prototype)| prototype.type_ == "electric-pole")
+        .map(|(_, prototype)| (prototype.clone(), 1.0))
+        .collect::<HashMap<_, _>>();
+
+    if let Some(arg_pole_costs) = &args.pole_costs {
+        pole_costs.extend(parse_pole_costs(arg_pole_costs)?);
+    }
+
+    let center = match args.center.and_then(|mode| auto_center(&state.model, mode)) {
+        Some(center) => center,
+        None => state
+            .bounding_box
+            .to_f64()
+            .cast_unit()
+            .relative_pt_at(parse_tuple(&args.center_pos)?),
+    };
+    // with --replace-in-place, no distance cost where a pole was removed, so poles tend to stay put
+    let position_key = |position: MapPosition| {
+        (
+            (position.x * 2.0).round() as i64,
+            (position.y * 2.0).round() as i64,
+        )
+    };
+    let in_place = if args.replace_in_place {
+        state
+            .removed_poles
+            .iter()
+            .map(|pole| position_key(pole.position))
+            .collect::<HashSet<_>>()
+    } else {
+        HashSet::new()
+    };
+
+    let chunk_grid = match args.chunk_align {
+        Some(_) => Some(chunk_grid(args)?),
+        None => None,
+    };
+    let rail_grid = args
+        .rail_poles
+        .map(|_| RailPoleGrid::from_model(&state.model))
+        .filter(|grid| !grid.is_empty());
+    let metric = DistanceMetric::parse(&args.distance_metric)?;
+    let water_tiles = if args.allow_landfill {
+        state.model.water_tiles().clone()
+    } else {
+        Default::default()
+    };
+
+    Ok(move |graph: &CandPoleGraph, idx: NodeIndex| {
+        let entity = &graph[idx].entity;
+        let mut score = pole_costs[&entity.prototype];
+        if chunk_grid.is_some_and(|grid| grid.is_misaligned(entity)) {
+            score += MISALIGNED_COST;
+        }
+        if rail_grid
+            .as_ref()
+            .is_some_and(|grid| grid.is_off_grid(entity))
+        {
+            score += OFF_RAIL_GRID_COST;
+        }
+        let landfill = entity
+            .world_bbox()
+            .iter_tiles()
+            .filter(|tile| water_tiles.contains(tile))
+            .count();
+        score += landfill as f64 * args.landfill_cost;
+        if in_place.contains(&position_key(entity.position)) {
+            return score;
+        }
+        score + metric.distance(entity.position, center) / 10000.0 * args.distance_cost
+    })
+}
+
+const MAX_COLUMN_GENERATION_ROUNDS: usize = 1000;
+
+/// Selects a subset of the candidate poles, with the solver chosen by `--solver`.
+pub struct SolveStage<'a> {
+    pub args: &'a OptimizePoles,
+}
+impl PipelineStage for SolveStage<'_> {
+    fn name(&self) -> &'static str {
+        "solve"
+    }
+    // solve time doesn't grow in proportion to the candidates, so there is no rate
+    fn details(&self, state: &PipelineState) -> Option<String> {
+        let mut parts = vec![format!("{} candidate poles", state.candidates.node_count())];
+        if let Some(stats) = &state.report.mip_stats {
+            parts.extend(
+                stats
+                    .constraints
+                    .map(|rows| format!("{} constraints", rows)),
+            );
+            parts.extend(stats.gap.map(|gap| format!("gap {}%", gap)));
+        }
+        Some(parts.join(", "))
+    }
+    fn run(&self, state: &mut PipelineState) -> Result<(), OptimizerError> {
+        let args = self.args;
+        if let Some(path) = &args.import_solution {
+            state.solution = import_solution(path, &state.candidates)?;
+            return Ok(());
+        }
+        let mut uncoverable =
+            diagnose::uncoverable_entities(&state.model, &state.candidates, &state.pole_types);
+        uncoverable.retain(|entity| !state.externally_powered.contains(&entity.id));
+        let soft_entities = soft_entities(state, args)?;
+        if !soft_entities.is_empty() {
+            let num_uncoverable = uncoverable
+                .iter()
+                .filter(|entity| soft_entities.contains_key(&entity.id))
+                .count();
+            stage_println!(
+                "{} entities may be left unpowered ({} can't be powered by any candidate pole)",
+                soft_entities.len(),
+                num_uncoverable
+            );
+            uncoverable.retain(|entity| !soft_entities.contains_key(&entity.id));
+        }
+        check_uncoverable(&state.model, &uncoverable, args.allow_unpowered)?;
+        let cost_fn = pole_cost_fn(state, args)?;
+        let time_limit = match state.budget {
+            Some(budget) => budget
+                .share(budget::SOLVE_SHARE)
+                .min(Duration::from_secs_f64(args.time_limit)),
+            None => Duration::from_secs_f64(args.time_limit),
+        };
+        let highs_config = configure_solver(args, time_limit.as_secs_f64());
+        let log = HighsLog::default();
+        let config = |options: HighsOptions| -> Result<HighsOptions, OptimizerError> {
+            Ok(log.attach(highs_config(options)?, !args.quiet))
+        };
+        // with --trunk, the trunk stage connects the poles
+        let connectivity = match args.trunk {
+            Some(_) => None,
+            None => connectivity(state, args)?,
+        };
+        let max_count = max_count(args)?;
+        let max_load = load_limit(state, args)?;
+        let initial_solution = match &args.load_solver_state {
+            Some(dir) => initial_solution(dir, &state.candidates)?,
+            None => Default::default(),
+        };
+        let ilp = || SetCoverILPSolver {
+            solver: &highs,
+            config: &config,
+            cost: &cost_fn,
+            connectivity: connectivity.clone(),
+            fixed_poles: state.fixed_poles.clone(),
+            max_count: max_count.clone(),
+            lazy: args.lazy_constraints,
+            max_pole_types: args.max_pole_types,
+            max_load: max_load.clone(),
+            initial_solution: initial_solution.clone(),
+            soft_entities: soft_entities.clone(),
+        };
+
+        if args.solver == SolverKind::Split {
+            if args.max_count.is_some() || args.max_pole_types.is_some() {
+                return Err(
+                    "--solver split doesn't support --max-count or --max-pole-types".into(),
+                );
+            }
+            if args.split_overlap <= 0.0 || args.split_size <= 2.0 * args.split_overlap {
+                return Err(
+                    "--split-size must be more than twice --split-overlap, which must be positive"
+                        .into(),
+                );
+            }
+        }
+
+        let result = if state.budget.is_some() && time_limit < budget::MIN_SOLVE_TIME {
+            Err(OptimizerError::SolverTimeout {
+                elapsed: Duration::ZERO,
+            })
+        } else {
+            // HiGHS writes its log to the console unless --quiet
+            suspend_progress(|| match args.solver {
+                SolverKind::Ilp => {
+                    stage_println!("Solving ILP");
+                    ilp().solve(&state.candidates)
+                }
+                SolverKind::LpRound => {
+                    stage_println!("Solving LP relaxation, with randomized rounding");
+                    LpRoundingSolver {
+                        lp: ilp(),
+                        rounds: args.rounding_rounds,
+                        seed: args.seed,
+                    }
+                    .solve(&state.candidates)
+                }
+                SolverKind::ColumnGen => {
+                    stage_println!("Solving with column generation");
+                    ColumnGenerationSolver {
+                        ilp: ilp(),
+                        columns_per_round: args.columns_per_round,
+                        max_rounds: MAX_COLUMN_GENERATION_ROUNDS,
+                    }
+                    .solve(&state.candidates)
+                }
+                SolverKind::Split => {
+                    stage_println!("Solving ILP by regions");
+                    SpatialSplitSolver {
+                        ilp: ilp(),
+                        region_size: args.split_size,
+                        overlap: args.split_overlap,
+                        threads: std::thread::available_parallelism().map_or(1, usize::from),
+                    }
+                    .solve(&state.candidates)
+                }
+            })
+        };
+        state.solution = match result {
+            Err(OptimizerError::SolverTimeout { .. }) if state.budget.is_some() => {
+                stage_println!("Out of --budget for solving; using a greedy solution instead");
+                LpRoundingSolver {
+                    lp: ilp(),
+                    rounds: None,
+                    seed: args.seed,
+                }
+                .solve_greedy(&state.candidates)?
+            }
+            result => result?,
+        };
+        state.report.mip_stats = log.stats();
+        if let Some(dir) = &args.save_solver_state {
+            SolverState::from_solution(&state.solution).save(dir)?;
+        }
+        Ok(())
+    }
+    fn dump(&self, state: &PipelineState, path: &Path) -> Result<(), OptimizerError> {
+        write_entities_json(path, state.solution.node_weights().map(|n| &n.entity))
+    }
+}
+
+/// With `--trunk`, adds poles of the trunk type to connect the poles chosen by [SolveStage].
+///
+/// The trunk candidates are placed around the solution poles, which are kept fixed;
+/// the ILP then only needs to satisfy connectivity, at the cost of the trunk poles.
+/// Afterward, the candidates are the trunk candidates, so later stages can only move trunk poles.
+pub struct TrunkStage<'a> {
+    pub args: &'a OptimizePoles,
+}
+impl PipelineStage for TrunkStage<'_> {
+    fn name(&self) -> &'static str {
+        "trunk"
+    }
+    fn details(&self, state: &PipelineState) -> Option<String> {
+        // without --trunk, the candidates are still the solve stage's
+        self.args.trunk.as_ref()?;
+        Some(format!(
+            "{} trunk candidates",
+            state.candidates.node_count() - state.fixed_poles.len()
+        ))
+    }
+    fn run(&self, state: &mut PipelineState) -> Result<(), OptimizerError> {
+        let args = self.args;
+        let Some(trunk) = &args.trunk else {
+            return Ok(());
+        };
+        let trunk = require_prototype(trunk, &state.prototype_data)?;
+        let Some(mut connectivity) = connectivity(state, args)? else {
+            return Err("--trunk only adds poles to connect others; it can't be used with --no-connectivity".into());
+        };
+        // the covering poles are all fixed, and connecting them is the point
+        connectivity.connect_fixed = true;
+
+        let mut model = state.model.clone();
+        model.remove_all_poles();
+        model.add_from_pole_graph(&state.solution);
+        let (pole_graph, id_map) = model
+            .with_all_candidate_poles(state.bounding_box, &[&trunk])
+            .get_maximally_connected_pole_graph();
+        let mut candidates = pole_graph.to_cand_pole_graph(&model);
+        remove_externally_powered(&mut candidates, &state.externally_powered);
+        let fixed_poles = model
+            .poles()
+            .map(|entity| id_map[&entity.id()])
+            .collect::<hashbrown::HashSet<_>>();
+        stage_println!(
+            "Connecting {} poles with {} trunk candidates",
+            fixed_poles.len(),
+            candidates.node_count() - fixed_poles.len()
+        );
+
+        let cost_fn = pole_cost_fn(state, args)?;
+        let solver = SetCoverILPSolver {
+            solver: &highs,
+            config: &configure_solver(args, args.time_limit),
+            cost: &cost_fn,
+            connectivity: Some(connectivity),
+            fixed_poles: fixed_poles.clone(),
+            max_count: max_count(args)?,
+            lazy: args.lazy_constraints,
+            max_pole_types: None,
+            max_load: None,
+            initial_solution: Default::default(),
+            soft_entities: soft_entities(state, args)?,
+        };
+        state.solution = suspend_progress(|| solver.solve(&candidates))?;
+        stage_println!(
+            "Added {} {}",
+            state.solution.node_count() - fixed_poles.len(),
+            trunk.name
+        );
+        state.candidates = candidates;
+        state.fixed_poles = fixed_poles;
+        Ok(())
+    }
+    fn dump(&self, state: &PipelineState, path: &Path) -> Result<(), OptimizerError> {
+        write_entities_json(path, state.solution.node_weights().map(|n| &n.entity))
+    }
+}
+
+/// Improves the solution with local search, for `--polish` seconds, or what is left of `--budget`.
+pub struct PolishStage<'a> {
+    pub args: &'a OptimizePoles,
+}
+impl PipelineStage for PolishStage<'_> {
+    fn name(&self) -> &'static str {
+        "polish"
+    }
+    fn run(&self, state: &mut PipelineState) -> Result<(), OptimizerError> {
+        let seconds = match state.budget {
+            // polish with whatever is left of the budget
+            Some(budget) => {
+                let left = budget.remaining().as_secs_f64();
+                Some(self.args.polish.map_or(left, |seconds| seconds.min(left)))
+            }
+            None => self.args.polish,
+        };
+        let Some(seconds) = seconds.filter(|seconds| *seconds > 0.0) else {
+            return Ok(());
+        };
+        let cost_fn = pole_cost_fn(state, self.args)?;
+        let max_count = max_count(self.args)?;
+        let connectivity = connectivity(state, self.args)?;
+        let chunk_grid = match self.args.chunk_align {
+            Some(ChunkAlign::Require) => Some(chunk_grid(self.args)?),
+            _ => None,
+        };
+        let can_add = |graph: &CandPoleGraph, idx: NodeIndex| {
+            chunk_grid.is_none_or(|grid| !grid.is_misaligned(&graph[idx].entity))
+        };
+        let load_limit = load_limit(state, self.args)?;
+        let polisher = LocalSearchPolisher {
+            time_limit: Duration::from_secs_f64(seconds),
+            seed: self.args.seed,
+            cost: &cost_fn,
+            fixed_poles: &state.fixed_poles,
+            move_radius: 2.0,
+            cancel: &state.cancel,
+            max_count: &max_count,
+            max_pole_types: self.args.max_pole_types,
+            connectivity: connectivity.as_ref(),
+            can_add: &can_add,
+            max_load: load_limit.as_ref(),
+        };
+        stage_println!("Polishing solution for {}s", seconds);
+        state.solution = polisher.optimize(&state.candidates, &state.solution)?;
+        Ok(())
+    }
+    fn dump(&self, state: &PipelineState, path: &Path) -> Result<(), OptimizerError> {
+        write_entities_json(path, state.solution.node_weights().map(|n| &n.entity))
+    }
+}
+
+/// Compares the solution's cost to the input poles', and keeps the input if the solution is no better,
+/// or not better by `--only-if-better` percent.
+///
+/// Only done when every input pole is a candidate and the input powers everything, so the input is a
+/// solution of the same problem.
+pub struct CompareStage<'a> {
+    pub args: &'a OptimizePoles,
+}
+
+/// Costs within this are equal.
+const COST_EPSILON: f64 = 1e-6;
+
+impl PipelineStage for CompareStage<'_> {
+    fn name(&self) -> &'static str {
+        "compare"
+    }
+    fn run(&self, state: &mut PipelineState) -> Result<(), OptimizerError> {
+        let input_poles = state
+            .model
+            .poles()
+            .filter(|entity| !state.context_entities.contains(&entity.id()))
+            .map(|entity| entity.entity.clone())
+            .collect_vec();
+        if input_poles.is_empty() || count_unpowered(state) > 0 {
+            return Ok(());
+        }
+        let input_nodes = state
+            .candidates
+            .node_indices()
+            .filter(|idx| input_poles.contains(&state.candidates[*idx].entity))
+            .collect_vec();
+        if input_nodes.len() != input_poles.len() {
+            return Ok(());
+        }
+        let cost_fn = pole_cost_fn(state, self.args)?;
+        let cost = |graph: &CandPoleGraph, nodes: Vec<NodeIndex>| -> f64 {
+            nodes
+                .into_iter()
+                .filter(|idx| !state.fixed_poles.contains(idx))
+                .map(|idx| cost_fn(graph, idx))
+                .sum()
+        };
+        let input_cost = cost(&state.candidates, input_nodes);
+        let solution_nodes = candidate_indices(&state.candidates, &state.solution);
+        let solution_cost = cost(&state.candidates, solution_nodes.into_iter().collect());
+
+        if solution_cost >= input_cost - COST_EPSILON {
+            stage_println!("Input poles are already optimal; keeping them");
+            state.report.kept_input = true;
+            return Ok(());
+        }
+        let improvement = (input_cost - solution_cost) / input_cost * 100.0;
+        match self.args.only_if_better {
+            Some(threshold) if improvement < threshold => {
+                stage_println!(
+                    "Solution is only {:.1}% better than the input, less than --only-if-better; keeping the input",
+                    improvement
+                );
+                state.report.kept_input = true;
+            }
+            _ => stage_println!("Solution is {:.1}% better than the input", improvement),
+        }
+        Ok(())
+    }
+}
+
+/// Solves only the LP relaxation, and prints problem size and a lower bound on the pole count.
+pub struct EstimateStage<'a> {
+    pub args: &'a OptimizePoles,
+}
+impl PipelineStage for EstimateStage<'_> {
+    fn name(&self) -> &'static str {
+        "estimate"
+    }
+    fn run(&self, state: &mut PipelineState) -> Result<(), OptimizerError> {
+        let solver = SetCoverILPSolver {
+            solver: &highs,
+            config: &configure_solver(self.args, self.args.time_limit),
+            cost: &|_, _| 1.0,
+            connectivity: connectivity(state, self.args)?,
+            fixed_poles: state.fixed_poles.clone(),
+            max_count: max_count(self.args)?,
+            lazy: false,
+            max_pole_types: self.args.max_pole_types,
+            max_load: load_limit(state, self.args)?,
+            initial_solution: Default::default(),
+            soft_entities: Default::default(),
+        };
+        let estimate = solver.estimate(&state.candidates)?;
+        stage_println!("Variables (candidate poles): {}", estimate.num_variables);
+        stage_println!("Constraints: {}", estimate.num_constraints);
+        stage_println!(
+            "LP relaxation: at least {} poles; solved in {:.2?}",
+            (estimate.min_poles - 1e-6).ceil(),
+            estimate.lp_time
+        );
+        stage_println!(
+            "Fractional variables: {}; MIP solve time, by a rough heuristic: {:.1?}",
+            estimate.num_fractional,
+            estimate.rough_mip_time()
+        );
+        Ok(())
+    }
+}
+
+/// Writes the ILP [SolveStage] would solve to a file, for `--export-model`, without solving it.
+pub struct ExportModelStage<'a> {
+    pub args: &'a OptimizePoles,
+    pub path: &'a Path,
+}
+impl PipelineStage for ExportModelStage<'_> {
+    fn name(&self) -> &'static str {
+        "export-model"
+    }
+    fn run(&self, state: &mut PipelineState) -> Result<(), OptimizerError> {
+        let args = self.args;
+        let cost_fn = pole_cost_fn(state, args)?;
+        // as in SolveStage, with --trunk, the trunk stage connects the poles
+        let connectivity = match args.trunk {
+            Some(_) => None,
+            None => connectivity(state, args)?,
+        };
+        let solver = SetCoverILPSolver {
+            solver: &highs,
+            config: &Ok,
+            cost: &cost_fn,
+            connectivity,
+            fixed_poles: state.fixed_poles.clone(),
+            max_count: max_count(args)?,
+            lazy: false,
+            max_pole_types: args.max_pole_types,
+            max_load: load_limit(state, args)?,
+            initial_solution: Default::default(),
+            soft_entities: soft_entities(state, args)?,
+        };
+        solver.export_model(&state.candidates, self.path)?;
+        stage_println!(
+            "To finish, solve it, then run again with the same options and --import-solution"
+        );
+        Ok(())
+    }
+}
+
+/// Chooses which poles in the solution are connected with wires.
+pub struct ConnectStage {
+    pub connector: PrettyPoleConnector,
+    /// Mimic the style of the input's wires, with `--match-wire-style`.
+    pub match_wire_style: bool,
+}
+impl ConnectStage {
+    pub fn new(args: &OptimizePoles) -> Self {
+        ConnectStage {
+            connector: PrettyPoleConnector {
+                minimize_longest_wire: args.minimize_longest_wire,
+                ..PrettyPoleConnector::with_wire_axis(args.wire_axis)
+            },
+            match_wire_style: args.match_wire_style,
+        }
+    }
+}
+impl PipelineStage for ConnectStage {
+    fn name(&self) -> &'static str {
+        "connect"
+    }
+    fn work_done(&self, state: &PipelineState) -> Option<(usize, &'static str)> {
+        Some((state.solution.node_count(), "poles"))
+    }
+    fn run(&self, state: &mut PipelineState) -> Result<(), OptimizerError> {
+        // the model still has the input poles
+        let style = self
+            .match_wire_style
+            .then(|| WireStyle::detect(&state.model.get_current_pole_graph().0))
+            .flatten();
+        match style {
+            Some(style) => stage_println!(
+                "Matching input wires: {:.1} wires per pole, {:.0}% along axes",
+                style.mean_degree,
+                style.axis_aligned * 100.0
+            ),
+            None if self.match_wire_style => {
+                warn!("the input has no wires to match the style of")
+            }
+            None => {}
+        }
+        let bucketed = state.solution.node_count() >= BUCKETED_MIN_POLES;
+        if bucketed {
+            stage_println!("Connecting poles one area at a time, as there are many");
+        }
+        let connector = PrettyPoleConnector {
+            style,
+            bucketed: self.connector.bucketed || bucketed,
+            ..self.connector.clone()
+        };
+        state.solution = connector.connect_poles(&state.solution);
+        stage_println!("Result has {} poles", state.solution.node_count());
+        Ok(())
+    }
+    fn dump(&self, state: &PipelineState, path: &Path) -> Result<(), OptimizerError> {
+        export_graph_file(&state.solution, path)
+    }
+}
+
+/// Uses every candidate as the solution, instead of solving; for `rewire`.
+pub struct KeepAllStage;
+impl PipelineStage for KeepAllStage {
+    fn name(&self) -> &'static str {
+        "keep-all"
+    }
+    fn run(&self, state: &mut PipelineState) -> Result<(), OptimizerError> {
+        state.solution = state.candidates.clone();
+        Ok(())
+    }
+}
+
+/// Replaces the poles in the model and blueprint with the solution.
+pub struct EmitStage {
+    pub carry_circuit: Option<CarryCircuit>,
+}
+impl PipelineStage for EmitStage {
+    fn name(&self) -> &'static str {
+        "emit"
+    }
+    fn run(&self, state: &mut PipelineState) -> Result<(), OptimizerError> {
+        if state.report.kept_input {
+            state.report.poles_after = state.report.poles_before.clone();
+            state.report.wire_length_after = state.report.wire_length_before;
+            state.report.unpowered_after = count_unpowered(state);
+            return Ok(());
+        }
+        let prototype_data = &state.prototype_data;
+        let context_poles = state
+            .context_entities
+            .iter()
+            .map(|id| state.model.get(*id).unwrap())
+            .filter(|entity| entity.prototype.is_pole())
+            .map(|entity| entity.entity.clone())
+            .collect::<Vec<_>>();
+        state.model.remove_all_poles();
+        state.model.add_from_pole_graph(&state.solution);
+        let landfill = state
+            .solution
+            .node_weights()
+            .flat_map(|node| state.model.landfill_needed(&node.entity))
+            .unique()
+            .sorted_by_key(|tile| (tile.y, tile.x))
+            .collect_vec();
+        if !landfill.is_empty() {
+            stage_println!("Adding {} landfill tiles under new poles", landfill.len());
+        }
+        for tile in landfill {
+            state.tiles.add(LANDFILL, tile);
+        }
+        // context entities are not part of the output
+        let context_entities = &state.context_entities;
+        state.model.retain(|entity| {
+            !context_entities.contains(&entity.id()) && !context_poles.contains(&entity.entity)
+        });
+
+        let is_pole = |name: &str| prototype_data[name].type_ == "electric-pole";
+        let old_poles = state
+            .entities
+            .entities
+            .values()
+            .filter(|entity| is_pole(&entity.name))
+            .map(|entity| entity.id())
+            .collect::<HashSet<_>>();
+        let circuit_wires = self
+            .carry_circuit
+            .map(|carry| circuit::pole_circuit_wires(&state.entities, &old_poles, carry.colors()));
+        let item_requests = item_requests::pole_item_requests(&state.entities, &old_poles);
+        state.entities.retain(|entity| !is_pole(&entity.name));
+        let id_map = state.entities.add_poles_from(&state.model);
+        if !item_requests.is_empty() {
+            let new_poles = id_map.values().copied().sorted().collect_vec();
+            let dropped = item_requests::restore_pole_item_requests(
+                &mut state.entities,
+                &new_poles,
+                &item_requests,
+            );
+            stage_println!(
+                "Carried over item requests (e.g. modules) of {} poles",
+                item_requests.len() - dropped
+            );
+            if dropped > 0 {
+                warn!(
+                    "{} poles' item requests were dropped, as no new pole of the same type replaces them",
+                    dropped
+                );
+            }
+        }
+        if let Some(wires) = circuit_wires {
+            if wires.colors.is_empty() && wires.attached.is_empty() {
+                stage_println!("Input poles had no circuit wires to carry over");
+            } else {
+                let new_poles = id_map.values().copied().sorted().collect_vec();
+                let unconnected = circuit::restore_pole_circuit_wires(
+                    &mut state.entities,
+                    &new_poles,
+                    &wires,
+                    prototype_data,
+                );
+                stage_println!(
+                    "Carried over circuit network to new poles; reconnected {} entities",
+                    wires.attached.len() - unconnected
+                );
+                if unconnected > 0 {
+                    warn!(
+                        "{} entities wired to poles are not in reach of a new pole",
+                        unconnected
+                    );
+                }
+            }
+        }
+        state.report.poles_after = report::count_poles(&state.model);
+        state.report.wire_length_after = report::wire_length(&state.model);
+        state.report.unpowered_after = count_unpowered(state);
+
+        check_no_overlaps(&state.entities, &state.free_entities, prototype_data)?;
+        state.entities.write_to_blueprint(&mut state.blueprint);
+        state.blueprint.tiles = state.tiles.to_blueprint_tiles();
+        Ok(())
+    }
+    fn dump(&self, state: &PipelineState, path: &Path) -> Result<(), OptimizerError> {
+        serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), &state.blueprint)?;
+        Ok(())
+    }
+}
+
+/// Checks the emitted blueprint with [verify::verify_solution], so a bug can't silently give a bad blueprint.
+/// Entities the optimizer was allowed to leave unpowered, and separate networks it was allowed to make, are not violations.
+pub struct VerifyStage<'a> {
+    pub args: &'a OptimizePoles,
+}
+impl PipelineStage for VerifyStage<'_> {
+    fn name(&self) -> &'static str {
+        "verify"
+    }
+    fn run(&self, state: &mut PipelineState) -> Result<(), OptimizerError> {
+        let args = self.args;
+        if args.no_verify || state.report.kept_input {
+            return Ok(());
+        }
+        let key = |name: &str, position: MapPosition| {
+            let half_tiles = (position * 2.0).round().to_i32();
+            (name.to_string(), half_tiles.x, half_tiles.y)
+        };
+        let may_be_unpowered =
+            args.allow_unpowered || args.priority.is_some() || state.context.is_some();
+        let allowed_unpowered = state
+            .model
+            .unpowered_entities()
+            .filter(|entity| may_be_unpowered || state.externally_powered.contains(&entity.id()))
+            .map(|entity| key(&entity.prototype.name, entity.position))
+            .collect::<HashSet<_>>();
+        // with these, poles are only connected towards the nearest root, or may stay apart
+        let one_network = args.no_connectivity
+            && state.context.is_none()
+            && args.roots.is_none()
+            && args.root_spacing.is_none()
+            && (state.fixed_poles.is_empty() || args.connect_kept_poles);
+
+        let model = output_model(&state.entities, &state.free_entities, &state.prototype_data);
+        let violations = verify::verify_solution(&model)
+            .into_iter()
+            .filter(|violation| match violation {
+                Violation::Unpowered { name, position } => {
+                    !allowed_unpowered.contains(&key(name, *position))
+                }
+                Violation::Disconnected { .. } => one_network,
+            })
+            .collect_vec();
+        if violations.is_empty() {
+            return Ok(());
+        }
+        Err(format!(
+            "Output fails verification, which is a bug: {}{}; use --no-verify to write it anyway",
+            violations.iter().take(5).join(", "),
+            if violations.len() > 5 {
+                format!(" and {} more", violations.len() - 5)
+            } else {
+                String::new()
+            }
+        )
+        .into())
+    }
+}
+
+const UNCOVERABLE_PNG: &str = "uncoverable_entities.png";
+
+/// Gives an error listing entities that no candidate pole can power, and marks them in [UNCOVERABLE_PNG].
+/// If `allow`, only warns; the entities are left unpowered.
+fn check_uncoverable(
+    model: &BpModel,
+    uncoverable: &[Uncoverable],
+    allow: bool,
+) -> Result<(), OptimizerError> {
+    if uncoverable.is_empty() {
+        return Ok(());
+    }
+    let describe = |uncoverable: &Uncoverable| {
+        let entity = model.get(uncoverable.id).unwrap();
+        format!(
+            "{} at ({}, {}): {}",
+            entity.prototype.name, entity.position.x, entity.position.y, uncoverable.reason
+        )
+    };
+    let mut details = uncoverable.iter().take(10).map(describe).join("\n  ");
+    if uncoverable.len() > 10 {
+        details += &format!("\n  and {} more", uncoverable.len() - 10);
+    }
+    if allow {
+        warn!(
+            "{} entities can't be powered by any candidate pole, and will be left unpowered:\n  {}",
+            uncoverable.len(),
+            details
+        );
+        return Ok(());
+    }
+    let drawing = draw::Drawing::on_area(&UNCOVERABLE_PNG, model.get_bounding_box(), 5, 10)?;
+    drawing.draw_model(model)?;
+    for entity in uncoverable {
+        drawing.highlight_entity(model.get(entity.id).unwrap())?;
+    }
+    drawing.show()?;
+    Err(OptimizerError::Infeasible(format!(
+        "{} entities can't be powered by any candidate pole; see {}, or use --allow-unpowered to leave them unpowered:\n  {}",
+        uncoverable.len(),
+        UNCOVERABLE_PNG,
+        details
+    )))
+}
+
+/// A model of the output, without `free_entities` (`--treat-as-free`), to check it.
+fn output_model(
+    entities: &BlueprintEntities,
+    free_entities: &HashSet<String>,
+    prototype_data: &EntityPrototypeDict,
+) -> BpModel {
+    if free_entities.is_empty() {
+        return BpModel::from_bp_entities(entities, prototype_data);
+    }
+    let mut entities = entities.clone();
+    entities.retain(|entity| !free_entities.contains(entity.name.as_str()));
+    BpModel::from_bp_entities(&entities, prototype_data)
+}
+
+const OVERLAP_PNG: &str = "overlapping_entities.png";
+
+/// Gives an error, and draws the collisions to [OVERLAP_PNG], if any entities in the output overlap.
+/// Only poles are placed by the optimizer, so only overlaps with poles are checked;
+/// the input may have entities that can overlap in game, like crossing rails.
+fn check_no_overlaps(
+    entities: &BlueprintEntities,
+    free_entities: &HashSet<String>,
+    prototype_data: &EntityPrototypeDict,
+) -> Result<(), OptimizerError> {
+    let model = output_model(entities, free_entities, prototype_data);
+    let overlaps = model.overlapping_pairs(|entity| entity.prototype.is_pole());
+    if overlaps.is_empty() {
+        return Ok(());
+    }
+    let drawing = draw::Drawing::on_area(&OVERLAP_PNG, model.get_bounding_box(), 5, 10)?;
+    drawing.draw_model(&model)?;
+    for id in overlaps.iter().flat_map(|(a, b)| [a, b]) {
+        drawing.highlight_entity(model.get(*id).unwrap())?;
+    }
+    drawing.show()?;
+
+    let describe = |id| {
+        let entity = model.get(id).unwrap();
+        format!(
+            "{} at ({}, {})",
+            entity.prototype.name, entity.position.x, entity.position.y
+        )
+    };
+    Err(format!(
+        "Output has {} pairs of overlapping entities, which is a bug: {}; see {}",
+        overlaps.len(),
+        overlaps
+            .iter()
+            .take(5)
+            .map(|(a, b)| format!("{} and {}", describe(*a), describe(*b)))
+            .join(", "),
+        OVERLAP_PNG
+    )
+    .into())
+}
+
+/// Runs the standard pipeline, with stages dumped as requested by `--dump-stage`.
+pub fn run_optimize_pipeline(
+    blueprint: Blueprint,
+    args: &OptimizePoles,
+) -> Result<PipelineState, OptimizerError> {
+    run_cancellable_optimize_pipeline(blueprint, args, CancellationToken::new())
+}
+
+/// Like [run_optimize_pipeline], but stops with [OptimizerError::Cancelled] once `cancel` is cancelled,
+/// e.g. from another thread.
+pub fn run_cancellable_optimize_pipeline(
+    blueprint: Blueprint,
+    args: &OptimizePoles,
+    cancel: CancellationToken,
+) -> Result<PipelineState, OptimizerError> {
+    run_with_output(blueprint, args, Pipeline::standard(args), cancel)
+}
+
+/// Runs the `fix-power` pipeline; `args` should keep all input poles.
+pub fn run_fix_power_pipeline(
+    blueprint: Blueprint,
+    args: &OptimizePoles,
+) -> Result<PipelineState, OptimizerError> {
+    run_with_output(
+        blueprint,
+        args,
+        Pipeline::fix_power(args),
+        CancellationToken::new(),
+    )
+}
+
+/// Runs the `rewire` pipeline; see [Pipeline::rewire].
+pub fn run_rewire_pipeline(
+    blueprint: Blueprint,
+    args: &OptimizePoles,
+) -> Result<PipelineState, OptimizerError> {
+    run_with_output(
+        blueprint,
+        args,
+        Pipeline::rewire(args),
+        CancellationToken::new(),
+    )
+}
+
+fn run_with_output<'a>(
+    blueprint: Blueprint,
+    args: &'a OptimizePoles,
+    mut pipeline: Pipeline<'a>,
+    cancel: CancellationToken,
+) -> Result<PipelineState, OptimizerError> {
+    for dump in &args.dump_stage {
+        let (name, path) = dump
+            .split_once('=')
+            .ok_or_else(|| format!("Expected --dump-stage as NAME=path, got '{}'", dump))?;
+        pipeline = pipeline.dump_stage(name, PathBuf::from(path))?;
+    }
+    if let Some(path) = &args.export_graph {
+        GraphFormat::from_path(path)?;
+        let kind = args.export_graph_kind;
+        pipeline = pipeline.after_stage(move |stage, state| match (kind, stage.name()) {
+            (ExportGraphKind::Current, "model") => state.model.export_pole_graph(path),
+            (ExportGraphKind::Candidates, "candidates") => {
+                export_graph_file(&state.candidates, path)
+            }
+            (ExportGraphKind::Solution, "connect") => export_graph_file(&state.solution, path),
+            _ => Ok(()),
+        });
+    }
+    let mut state = PipelineState::new(blueprint, prototype_data::load_prototype_data()?);
+    state.cancel = cancel;
+    state.budget = args
+        .budget
+        .map(|seconds| TimeBudget::new(Duration::from_secs_f64(seconds)));
+    if let Some(path) = &args.context {
+        state.context = Some(read_blueprint(path)?);
+    }
+    pipeline.run(&mut state)?;
+    Ok(state)
+}
+
+/// Runs a pipeline that doesn't produce a blueprint.
+fn run_without_output(
+    blueprint: Blueprint,
+    args: &OptimizePoles,
+    pipeline: Pipeline,
+) -> Result<(), OptimizerError> {
+    let mut state = PipelineState::new(blueprint, prototype_data::load_prototype_data()?);
+    if let Some(path) = &args.context {
+        state.context = Some(read_blueprint(path)?);
+    }
+    pipeline.run(&mut state)
+}
+
+/// Runs the pipeline up to solving the LP relaxation, printing an estimate of problem difficulty.
+pub fn run_estimate_pipeline(
+    blueprint: Blueprint,
+    args: &OptimizePoles,
+) -> Result<(), OptimizerError> {
+    run_without_output(blueprint, args, Pipeline::estimate(args))
+}
+
+/// Runs the pipeline up to building the ILP, and writes it to `path` for `--export-model`.
+pub fn run_export_model_pipeline(
+    blueprint: Blueprint,
+    args: &OptimizePoles,
+    path: &Path,
+) -> Result<(), OptimizerError> {
+    run_without_output(blueprint, args, Pipeline::export_model(args, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn test_seeded_roots() {
+        let bbox = BoundingBox::new(point2(0.0, 0.0), point2(10.0, 100.0));
+        assert_eq!(
+            seeded_roots(bbox, 40.0, point2(5.0, 50.0)),
+            vec![point2(5.0, 10.0), point2(5.0, 50.0), point2(5.0, 90.0)]
+        );
+        assert_eq!(
+            seeded_roots(bbox, 200.0, point2(3.0, 50.0)),
+            vec![point2(3.0, 50.0)]
+        );
+    }
+
+    #[test]
+    fn test_auto_center() {
+        let mut model = BpModel::new();
+        for (x, y) in [(0, 0), (1, 0), (2, 0), (0, 10)] {
+            model.add_test_powerable(point2(x, y));
+        }
+        assert_eq!(
+            auto_center(&model, CenterMode::Auto),
+            Some(point2(1.25, 3.0))
+        );
+        assert_eq!(
+            auto_center(&model, CenterMode::Medoid),
+            Some(point2(1.5, 0.5))
+        );
+        assert_eq!(auto_center(&BpModel::new(), CenterMode::Auto), None);
+    }
+
+    struct NamedStage(&'static str);
+    impl PipelineStage for NamedStage {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+        fn run(&self, _state: &mut PipelineState) -> Result<(), OptimizerError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_replace_stage() {
+        let mut pipeline = Pipeline::new().then(NamedStage("a")).then(NamedStage("b"));
+        assert!(pipeline.replace_stage(NamedStage("b")));
+        assert!(!pipeline.replace_stage(NamedStage("c")));
+        assert_eq!(pipeline.stage_names().collect::<Vec<_>>(), ["a", "b"]);
+        assert!(pipeline.dump_stage("c", PathBuf::from("c.json")).is_err());
+    }
+
+    #[test]
+    fn test_after_stage_hook() {
+        let ran = RefCell::new(vec![]);
+        let pipeline = Pipeline::new()
+            .then(NamedStage("a"))
+            .then(NamedStage("b"))
+            .after_stage(|stage, _| {
+                ran.borrow_mut().push(stage.name());
+                Ok(())
+            });
+        let bp = crate::read_blueprint(&PathBuf::from("test-data/bigtest.txt")).unwrap();
+        let mut state = PipelineState::new(bp, prototype_data::load_prototype_data().unwrap());
+        pipeline.run(&mut state).unwrap();
+        assert_eq!(*ran.borrow(), ["a", "b"]);
+    }
+
+    #[test]
+    fn test_cancel() {
+        let pipeline = Pipeline::new()
+            .then(NamedStage("a"))
+            .then(NamedStage("b"))
+            .after_stage(|_, state| {
+                state.cancel.cancel();
+                Ok(())
+            });
+        let bp = crate::read_blueprint(&PathBuf::from("test-data/bigtest.txt")).unwrap();
+        let mut state = PipelineState::new(bp, prototype_data::load_prototype_data().unwrap());
+        let err = pipeline.run(&mut state).unwrap_err();
+        let OptimizerError::Cancelled(cancelled) = &err else {
+            panic!("expected Cancelled, got {}", err);
+        };
+        assert_eq!(cancelled.stage, "b");
+        assert_eq!(state.completed_stages, ["a"]);
+    }
+
+    #[test]
+    fn test_context_entities_added_to_model() {
+        let args = OptimizePoles::try_parse_from(["optimize", "s"]).unwrap();
+        let path = PathBuf::from("test-data/bigtest.txt");
+        let mut state = PipelineState::new(
+            crate::read_blueprint(&path).unwrap(),
+            prototype_data::load_prototype_data().unwrap(),
+        );
+        state.context = Some(crate::read_blueprint(&path).unwrap());
+        Pipeline::new()
+            .then(DecodeStage)
+            .then(ModelStage { args: &args })
+            .run(&mut state)
+            .unwrap();
+        assert_eq!(state.context_entities.len(), state.entities.entities.len());
+        assert_eq!(
+            state.model.all_entities().count(),
+            2 * state.entities.entities.len()
+        );
+    }
+
+    #[test]
+    fn test_assume_powered_border() {
+        let args = OptimizePoles::try_parse_from(["optimize", "s", "--assume-powered-border", "4"])
+            .unwrap();
+        let mut state = PipelineState::new(
+            crate::read_blueprint(&PathBuf::from("test-data/bigtest.txt")).unwrap(),
+            prototype_data::load_prototype_data().unwrap(),
+        );
+        Pipeline::new()
+            .then(DecodeStage)
+            .then(ModelStage { args: &args })
+            .then(CandidatesStage { args: &args })
+            .run(&mut state)
+            .unwrap();
+        let bbox = state.model.get_bounding_box();
+        assert!(!state.externally_powered.is_empty());
+        for id in &state.externally_powered {
+            let tiles = state
+                .model
+                .get(*id)
+                .unwrap()
+                .world_bbox()
+                .round_out_to_tiles();
+            assert!(!bbox.inflate(-4, -4).contains_box(&tiles));
+        }
+        assert!(state
+            .candidates
+            .node_weights()
+            .all(|node| node.powered_entities.is_disjoint(&state.externally_powered)));
+    }
+
+    #[test]
+    fn test_treat_as_obstacle_and_free() {
+        let args = OptimizePoles::try_parse_from([
+            "optimize",
+            "s",
+            "--treat-as-obstacle",
+            "assembling-machine-2",
+            "--treat-as-free",
+            "transport-belt,pipe",
+        ])
+        .unwrap();
+        let mut state = PipelineState::new(
+            crate::read_blueprint(&PathBuf::from("test-data/bigtest.txt")).unwrap(),
+            prototype_data::load_prototype_data().unwrap(),
+        );
+        Pipeline::new()
+            .then(DecodeStage)
+            .then(ModelStage { args: &args })
+            .run(&mut state)
+            .unwrap();
+        let names = |ids: &hashbrown::HashSet<EntityId>| {
+            ids.iter()
+                .map(|id| state.model.get(*id).unwrap().prototype.name.as_str())
+                .collect::<HashSet<_>>()
+        };
+        assert_eq!(
+            names(&state.externally_powered),
+            HashSet::from(["assembling-machine-2"])
+        );
+        assert!(state.model.all_entities().all(|entity| {
+            !["transport-belt", "pipe"].contains(&entity.prototype.name.as_str())
+        }));
+        assert!(state
+            .entities
+            .entities
+            .values()
+            .any(|entity| entity.name.as_str() == "pipe"));
+    }
+
+    #[test]
+    fn test_trunk_connects_poles() {
+        let args =
+            OptimizePoles::try_parse_from(["optimize", "s", "--trunk", "m", "--quiet"]).unwrap();
+        let mut state = PipelineState::new(
+            crate::read_blueprint(&PathBuf::from("test-data/bigtest_out.txt")).unwrap(),
+            prototype_data::load_prototype_data().unwrap(),
+        );
+        Pipeline::new()
+            .then(DecodeStage)
+            .then(ModelStage { args: &args })
+            .then(CandidatesStage { args: &args })
+            .then(SolveStage { args: &args })
+            .then(TrunkStage { args: &args })
+            .run(&mut state)
+            .unwrap();
+        let names = state
+            .solution
+            .node_weights()
+            .map(|node| node.entity.prototype.name.as_str())
+            .collect::<HashSet<_>>();
+        assert!(names.contains("medium-electric-pole"));
+        assert_eq!(petgraph::algo::connected_components(&state.solution), 1);
+    }
+}
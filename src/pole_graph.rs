@@ -3,15 +3,16 @@ use std::borrow::Borrow;
 use euclid::vec2;
 use hashbrown::{HashMap, HashSet};
 use petgraph::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::better_bp::EntityId;
 use crate::bp_model::{BpModel, WorldEntity};
-use crate::pole_windows::{PoleCoverageWindows, WireReachWindows};
 use crate::position::{
-    ContractMax, IterTiles, MapPosition, TileBoundingBox,
+    BoundingBoxExt, ContractMax, IterTiles, MapPosition, TileBoundingBox, TilePosition,
     TileSpaceExt,
 };
 use crate::prototype_data::EntityPrototypeRef;
+use crate::radius_query::{PoleCoverageWindows, WireReachWindows};
 
 pub type PoleGraph = UnGraph<WorldEntity, f64>;
 
@@ -20,11 +21,7 @@ impl BpModel {
     pub fn get_disconnected_pole_graph(&self) -> (PoleGraph, HashMap<EntityId, NodeIndex>) {
         let mut graph = PoleGraph::new_undirected();
         let mut id_map = HashMap::new();
-        for entity in self.all_entities() {
-            let pole_data = entity.pole_data();
-            if pole_data.is_none() {
-                continue;
-            }
+        for entity in self.poles() {
             let idx = graph.add_node(entity.entity.clone());
             id_map.insert(entity.id(), idx);
         }
@@ -34,12 +31,8 @@ impl BpModel {
     /// Graph of existing poles and connections.
     pub fn get_current_pole_graph(&self) -> (PoleGraph, HashMap<EntityId, NodeIndex>) {
         let (mut graph, id_map) = self.get_disconnected_pole_graph();
-        for entity in self.all_entities() {
-            let pole_data = entity.pole_data();
-            if pole_data.is_none() {
-                continue;
-            }
-            let (_, connections) = pole_data.unwrap();
+        for entity in self.poles() {
+            let (_, connections) = entity.pole_data().unwrap();
             let id = &entity.id();
             let idx = id_map[id];
             for other_id in &connections.connections {
@@ -54,6 +47,12 @@ impl BpModel {
         (graph, id_map)
     }
 
+    /// Number of separate electric networks formed by the existing poles and connections.
+    pub fn count_pole_networks(&self) -> usize {
+        petgraph::algo::connected_components(&self.get_current_pole_graph().0)
+    }
+
+    #[tracing::instrument(name = "graph", skip_all)]
     pub fn get_maximally_connected_pole_graph(&self) -> (PoleGraph, HashMap<EntityId, NodeIndex>) {
         let (mut graph, id_map) = self.get_disconnected_pole_graph();
         self.maximally_connect_poles(&mut graph, &id_map);
@@ -91,7 +90,7 @@ impl BpModel {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CandPoleNode {
     pub entity: WorldEntity,
     pub powered_entities: HashSet<EntityId>,
@@ -129,10 +128,8 @@ impl BpModel {
             |_, node| CandPoleNode {
                 entity: node.clone(),
                 powered_entities: windows
-                    .get_window_for(node)
-                    .cur_items()
-                    .filter(|id| self.get(**id).is_some_and(|e| e.uses_power()))
-                    .copied()
+                    .query(node)
+                    .filter(|id| self.get(*id).is_some_and(|e| e.uses_power()))
                     .collect(),
             },
             |_, &w| w,
@@ -170,6 +167,78 @@ impl BpModel {
         }
         pole_model
     }
+
+    /// Like [Self::with_all_candidate_poles], but only places poles on every `step`th tile in each direction,
+    /// for about `1/step^2` as many candidates. Entities that none of those poles would power
+    /// get candidates on every tile around them, so nothing becomes impossible to power.
+    pub fn with_coarse_candidate_poles(
+        &self,
+        area: TileBoundingBox,
+        pole_prototypes: &[impl Borrow<EntityPrototypeRef>],
+        step: i32,
+    ) -> BpModel {
+        if step <= 1 {
+            return self.with_all_candidate_poles(area, pole_prototypes);
+        }
+        let candidate_at = |top_left: TilePosition, pole_prototype: &EntityPrototypeRef| {
+            let width = pole_prototype.tile_width;
+            WorldEntity {
+                position: top_left.corner_map_pos() + vec2(width as f64 / 2.0, width as f64 / 2.0),
+                direction: 0,
+                prototype: pole_prototype.clone(),
+            }
+        };
+        let mut pole_model = self.clone();
+        let mut covered = HashSet::new();
+        for pole_ref in pole_prototypes {
+            let pole_prototype = pole_ref.borrow();
+            let pole_data = pole_prototype.pole_data.unwrap();
+            let possible_area = area.contract_max((pole_prototype.tile_width - 1) as i32);
+            for top_left in possible_area.iter_tiles() {
+                if top_left.x.rem_euclid(step) != 0 || top_left.y.rem_euclid(step) != 0 {
+                    continue;
+                }
+                let entity = candidate_at(top_left, pole_prototype);
+                if self.can_place(&entity) {
+                    covered.extend(
+                        self.powered_entities(entity.position, pole_data)
+                            .map(|entity| entity.id()),
+                    );
+                    pole_model.add_overlap(entity);
+                }
+            }
+        }
+        let uncovered = self
+            .all_entities()
+            .filter(|entity| entity.uses_power() && !covered.contains(&entity.id()))
+            .map(|entity| entity.world_bbox().round_out_to_tiles())
+            .collect::<Vec<_>>();
+        for pole_ref in pole_prototypes {
+            let pole_prototype = pole_ref.borrow();
+            let width = pole_prototype.tile_width;
+            let reach =
+                pole_prototype.pole_data.unwrap().supply_radius.ceil() as i32 + width as i32;
+            let possible_area = area.contract_max((width - 1) as i32);
+            let mut placed = HashSet::new();
+            for entity_tiles in &uncovered {
+                let Some(around) = entity_tiles
+                    .inflate(reach, reach)
+                    .intersection(&possible_area)
+                else {
+                    continue;
+                };
+                for top_left in around.iter_tiles() {
+                    let on_grid =
+                        top_left.x.rem_euclid(step) == 0 && top_left.y.rem_euclid(step) == 0;
+                    let entity = candidate_at(top_left, pole_prototype);
+                    if !on_grid && placed.insert(top_left) && self.can_place(&entity) {
+                        pole_model.add_overlap(entity);
+                    }
+                }
+            }
+        }
+        pole_model
+    }
 }
 
 impl BpModel {
@@ -192,7 +261,10 @@ impl BpModel {
     }
 
     pub fn remove_all_poles(&mut self) {
-        self.retain(|e| !e.prototype.is_pole());
+        let poles = self.poles().map(|e| e.id()).collect::<Vec<_>>();
+        for id in poles {
+            self.remove(&id);
+        }
     }
 }
 
@@ -243,6 +315,8 @@ mod tests {
         assert_eq!(graph.neighbors(i2).collect_vec(), [i1]);
         assert_eq!(graph.neighbors(i3).collect_vec(), []);
 
+        assert_eq!(model.count_pole_networks(), 2);
+
         let (graph, idx_map) = model.get_maximally_connected_pole_graph();
         let (i1, i2, i3) = test_nodes_correct(&graph, &idx_map);
         assert_eq!(graph.edge_count(), 3);
@@ -286,4 +360,35 @@ mod tests {
         assert_eq!(at2[0].prototype, pole_prototype);
         assert_eq!(at2[0].position, point2(0, 1).center_map_pos());
     }
+
+    #[test]
+    fn test_with_coarse_candidate_poles() {
+        let mut model = BpModel::new();
+        for x in 0..12 {
+            model.add_test_powerable(point2(x, 0));
+        }
+        let pole_prototype = small_pole_prototype();
+        let area = TileBoundingBox::new(point2(0, -2), point2(12, 3));
+        let num_poles = |model: &BpModel| {
+            model
+                .all_entities()
+                .filter(|e| e.prototype.is_pole())
+                .count()
+        };
+        let all = model.with_all_candidate_poles(area, &[&pole_prototype]);
+        let coarse = model.with_coarse_candidate_poles(area, &[&pole_prototype], 2);
+        assert!(num_poles(&coarse) * 3 < num_poles(&all));
+        assert_eq!(coarse.unpowered_entities().count(), 0);
+
+        // the only free row is off the grid
+        let mut model = BpModel::new();
+        for x in 0..12 {
+            model.add_test_powerable(point2(x, 0));
+            model.add_test_powerable(point2(x, 2));
+        }
+        let area = TileBoundingBox::new(point2(0, 0), point2(12, 3));
+        let coarse = model.with_coarse_candidate_poles(area, &[&pole_prototype], 3);
+        assert!(num_poles(&coarse) > 0);
+        assert_eq!(coarse.unpowered_entities().count(), 0);
+    }
 }
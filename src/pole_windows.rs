@@ -1,18 +1,16 @@
 #[cfg(test)]
 use std::collections::HashSet;
 use std::hash::Hash;
-use std::marker::PhantomData;
 
-use euclid::{point2, vec2, Vector2D};
+use euclid::{vec2, Vector2D};
 use hashbrown::HashMap;
 use num_traits::abs;
 
 use crate::better_bp::EntityId;
-use crate::bp_model::{BpModel, WorldEntity};
+use crate::bp_model::BpModel;
 #[cfg(test)]
 use crate::position::IterTiles;
-use crate::position::{MapPosition, MapPositionExt, TilePosition, TileSpace};
-use crate::prototype_data::{EntityPrototypeRef, PoleData};
+use crate::position::{TilePosition, TileSpace};
 
 pub trait GetAtPos {
     type Id: Eq + Hash;
@@ -195,77 +193,6 @@ impl<T: GetAtPos> Moving2DWindow<T> {
     }
 }
 
-pub trait PoleWindowParams {
-    fn get_radius(pole_data: PoleData) -> f64;
-}
-
-pub struct PoleWindows<'a, P: PoleWindowParams> {
-    model: &'a BpModel,
-    windows_by_proto: HashMap<EntityPrototypeRef, Moving2DWindow<&'a BpModel>>,
-    marker: PhantomData<P>,
-}
-
-impl<'a, P: PoleWindowParams> PoleWindows<'a, P> {
-    pub fn new(model: &'a BpModel) -> Self {
-        Self {
-            model,
-            windows_by_proto: HashMap::new(),
-            marker: PhantomData,
-        }
-    }
-
-    fn get_window_top_left(pole_data: PoleData, pos: MapPosition) -> TilePosition {
-        let radius = P::get_radius(pole_data);
-        (pos - vec2(radius, radius)).tile_pos()
-    }
-    fn get_window_size(prototype: &EntityPrototypeRef, pole_data: PoleData) -> i32 {
-        let tile_width = prototype.tile_width;
-        let tile_height = prototype.tile_height;
-        let rep_center = point2(
-            (tile_width % 2) as f64 / 2.0,
-            (tile_height % 2) as f64 / 2.0,
-        );
-        let radius = P::get_radius(pole_data);
-        let top_left = Self::get_window_top_left(pole_data, rep_center);
-        let bottom_right = (rep_center + vec2(radius, radius)).tile_pos();
-        let size = bottom_right - top_left;
-        size.x.max(size.y) + 1
-    }
-    pub fn get_window_for(&mut self, pole: &WorldEntity) -> &mut Moving2DWindow<&'a BpModel> {
-        let prototype = &pole.prototype;
-        let pole_data = prototype.pole_data.unwrap();
-        let top_left = Self::get_window_top_left(pole_data, pole.position);
-        let window = self
-            .windows_by_proto
-            .entry(prototype.clone())
-            .or_insert_with(|| {
-                let size = Self::get_window_size(prototype, pole_data);
-                Moving2DWindow::new(self.model, top_left, size)
-            });
-        window.move_to(top_left);
-        window
-    }
-}
-
-pub struct WireReach;
-
-impl PoleWindowParams for WireReach {
-    fn get_radius(pole_data: PoleData) -> f64 {
-        pole_data.wire_distance
-    }
-}
-
-pub struct PoleCoverage;
-
-impl PoleWindowParams for PoleCoverage {
-    fn get_radius(pole_data: PoleData) -> f64 {
-        pole_data.supply_radius
-    }
-}
-
-pub type WireReachWindows<'a> = PoleWindows<'a, WireReach>;
-pub type PoleCoverageWindows<'a> = PoleWindows<'a, PoleCoverage>;
-
 #[cfg(test)]
 mod tests {
     use std::collections::HashSet;
@@ -356,40 +283,4 @@ mod tests {
             test_window_correct(&window, new_pos);
         }
     }
-
-    #[test]
-    fn test_window_params() {
-        let pole_data = PoleData {
-            supply_radius: 2.0,
-            wire_distance: 3.0,
-        };
-        assert_eq!(WireReach::get_radius(pole_data), 3.0);
-        assert_eq!(PoleCoverage::get_radius(pole_data), 2.0);
-    }
-
-    #[test]
-    fn test_pole_windows() {
-        let prototype = small_pole_prototype();
-        let model = BpModel::new();
-        let mut wire_windows = WireReachWindows::new(&model);
-        let mut coverage_windows = PoleCoverageWindows::new(&model);
-        let entity = WorldEntity {
-            position: point2(1.5, 2.5),
-            direction: 0,
-            prototype,
-        };
-
-        let wire_window = wire_windows.get_window_for(&entity);
-        assert_eq!(wire_window.size(), 15);
-        assert_eq!(
-            wire_window.top_left(),
-            (entity.position - vec2(7.5, 7.5)).tile_pos()
-        );
-        let coverage_window = coverage_windows.get_window_for(&entity);
-        assert_eq!(coverage_window.size(), 5);
-        assert_eq!(
-            coverage_window.top_left(),
-            (entity.position - vec2(2.5, 2.5)).tile_pos()
-        );
-    }
 }
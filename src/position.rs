@@ -1,3 +1,4 @@
+use std::cell::Cell;
 use std::ops::{Neg, Sub};
 
 use euclid::*;
@@ -7,6 +8,42 @@ use serde_with::{DeserializeAs, SerializeAs};
 
 use factorio_blueprint::objects as fbp;
 
+thread_local! {
+    static EXACT_GAME_MATH: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Makes reach checks use the game's fixed point positions (`--exact-game-math`),
+/// instead of floats with a small tolerance.
+pub fn use_exact_game_math(exact: bool) {
+    EXACT_GAME_MATH.with(|cell| cell.set(exact));
+}
+
+/// If [use_exact_game_math] is on, in this thread.
+pub fn exact_game_math() -> bool {
+    EXACT_GAME_MATH.with(|cell| cell.get())
+}
+
+/// The game stores map positions in fixed point, in units of 1/256 tile.
+const FIXED_POINT_PER_TILE: i64 = 256;
+/// Tolerance of float reach checks.
+const EPS: f64 = 1e-6;
+
+fn to_fixed_point(value: f64) -> i64 {
+    (value * FIXED_POINT_PER_TILE as f64).round() as i64
+}
+
+/// If `a` and `b` are at most `distance` apart, as for wire reach.
+pub fn within_distance(a: MapPosition, b: MapPosition, distance: f64) -> bool {
+    if exact_game_math() {
+        let dx = to_fixed_point(a.x) - to_fixed_point(b.x);
+        let dy = to_fixed_point(a.y) - to_fixed_point(b.y);
+        let max = to_fixed_point(distance);
+        dx * dx + dy * dy <= max * max
+    } else {
+        (a - b).square_length() <= distance * distance + EPS
+    }
+}
+
 /// Marks coordinate system where +x is right and +y is down.
 trait PosRightDownCoords {}
 
@@ -96,9 +133,22 @@ impl BoundingBoxExt for BoundingBox {
     }
 
     fn round_to_tiles_covering_center(&self) -> TileBoundingBox {
-        let eps = 1e-6;
-        let min = self.min - vec2(eps, eps);
-        let max = self.max + vec2(eps, eps);
+        if exact_game_math() {
+            // tile t's center is at t * 256 + 128
+            let half = FIXED_POINT_PER_TILE / 2;
+            let start = |min: f64| {
+                (to_fixed_point(min) - half + FIXED_POINT_PER_TILE - 1)
+                    .div_euclid(FIXED_POINT_PER_TILE) as i32
+            };
+            let end =
+                |max: f64| (to_fixed_point(max) - half).div_euclid(FIXED_POINT_PER_TILE) as i32 + 1;
+            return Box2D::new(
+                point2(start(self.min.x), start(self.min.y)),
+                point2(end(self.max.x), end(self.max.y)),
+            );
+        }
+        let min = self.min - vec2(EPS, EPS);
+        let max = self.max + vec2(EPS, EPS);
         Box2D::new(min, max).round().to_i32().cast_unit()
     }
 
@@ -158,6 +208,22 @@ impl CardinalDirection {
     }
 }
 
+/// Vector for one of the 8 directions in blueprints: 0 is north, increasing clockwise.
+/// Diagonal vectors are (±1, ±1).
+pub fn direction8_vector(direction: u8) -> Vector2D<f64, MapSpace> {
+    const VECTORS: [(f64, f64); 8] = [
+        (0.0, -1.0),
+        (1.0, -1.0),
+        (1.0, 0.0),
+        (1.0, 1.0),
+        (0.0, 1.0),
+        (-1.0, 1.0),
+        (-1.0, 0.0),
+        (-1.0, -1.0),
+    ];
+    VECTORS[(direction % 8) as usize].into()
+}
+
 pub trait Rotate {
     #[must_use]
     fn rotate(&self, direction: CardinalDirection) -> Self;
@@ -236,6 +302,30 @@ mod tests {
     use CardinalDirection::*;
     use super::*;
     
+    #[test]
+    fn exact_game_math_matches_floats() {
+        let boxes = [
+            Box2D::new(point2(-7.5, -7.5), point2(7.5, 7.5)),
+            Box2D::new(point2(0.3, -2.6), point2(4.7, 1.2)),
+            Box2D::new(point2(-1.0, 2.0), point2(3.0, 5.0)),
+        ];
+        let a = point2(0.5, 0.5);
+        // exactly at reach, within, and just beyond
+        let points = [point2(8.0, 0.5), point2(6.0, 4.0), point2(7.5, 3.5)];
+        let floats = (
+            boxes.map(|b| b.round_to_tiles_covering_center()),
+            points.map(|b| within_distance(a, b, 7.5)),
+        );
+        use_exact_game_math(true);
+        let exact = (
+            boxes.map(|b| b.round_to_tiles_covering_center()),
+            points.map(|b| within_distance(a, b, 7.5)),
+        );
+        use_exact_game_math(false);
+        assert_eq!(floats, exact);
+        assert_eq!(floats.1, [true, true, false]);
+    }
+
     #[test]
     fn iter_tiles() {
         let box_ = Box2D::new(point2(1, 2), point2(3, 4));
@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use clap::parser::ValueSource;
+use clap::{ArgMatches, ValueEnum};
+use itertools::Itertools;
+use serde::Deserialize;
+
+use crate::algorithms::WireAxis;
+use crate::error::OptimizerError;
+use crate::OptimizePoles;
+
+/// A bundle of `optimize` options (`--preset`). Options given on the command line take precedence.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Preset {
+    pub poles: Option<Vec<String>>,
+    pub pole_costs: Option<String>,
+    pub distance_cost: Option<f64>,
+    /// Like `--distance-metric`, e.g. `"l1"`.
+    pub distance_metric: Option<String>,
+    /// Whether poles must be connected; `false` is like `--no-connectivity`.
+    pub connectivity: Option<bool>,
+    pub center_pos: Option<String>,
+    pub wire_axis: Option<String>,
+    pub polish: Option<f64>,
+    /// Like `--priority`, e.g. `"beacon=required,roboport=required,*=soft"`.
+    pub priority: Option<String>,
+}
+
+fn strings(values: &[&str]) -> Option<Vec<String>> {
+    Some(values.iter().map(|value| value.to_string()).collect())
+}
+
+/// Presets available without a `--preset-file`.
+pub fn builtin_presets() -> BTreeMap<String, Preset> {
+    BTreeMap::from([
+        (
+            // big, regular builds: substations where they fit, straight wire runs
+            "megabase".to_string(),
+            Preset {
+                poles: strings(&["m", "t"]),
+                pole_costs: Some("m=1,t=1.5".to_string()),
+                distance_cost: Some(0.0),
+                wire_axis: Some("auto".to_string()),
+                ..Default::default()
+            },
+        ),
+        (
+            // cheap and sparse: poles reaching far, connected to where power arrives
+            "outpost".to_string(),
+            Preset {
+                poles: strings(&["m", "b"]),
+                pole_costs: Some("m=1,b=1.5".to_string()),
+                distance_cost: Some(0.0),
+                connectivity: Some(true),
+                ..Default::default()
+            },
+        ),
+        (
+            // fewest tiles taken by poles
+            "compact".to_string(),
+            Preset {
+                poles: strings(&["s", "m"]),
+                pole_costs: Some("s=1,m=1".to_string()),
+                ..Default::default()
+            },
+        ),
+        (
+            // symmetric-looking, with short straight wires
+            "pretty".to_string(),
+            Preset {
+                poles: strings(&["m"]),
+                distance_cost: Some(10.0),
+                wire_axis: Some("auto".to_string()),
+                polish: Some(5.0),
+                ..Default::default()
+            },
+        ),
+    ])
+}
+
+/// Builtin presets, with those in `preset_file` added, or replacing builtins with the same name.
+/// The file is a JSON object from preset names to options, e.g. `{"mine": {"poles": ["m"], "polish": 10}}`.
+pub fn load_presets(
+    preset_file: Option<&Path>,
+) -> Result<BTreeMap<String, Preset>, OptimizerError> {
+    let mut presets = builtin_presets();
+    if let Some(path) = preset_file {
+        let from_file: BTreeMap<String, Preset> =
+            serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        presets.extend(from_file);
+    }
+    Ok(presets)
+}
+
+impl Preset {
+    /// Sets the options in this preset that weren't given on the command line.
+    /// `matches` are the matches of the `optimize` command.
+    pub fn apply(
+        &self,
+        args: &mut OptimizePoles,
+        matches: &ArgMatches,
+    ) -> Result<(), OptimizerError> {
+        let unset = |id: &str| matches.value_source(id) != Some(ValueSource::CommandLine);
+        if let Some(poles) = self.poles.as_ref().filter(|_| unset("POLES")) {
+            args.use_poles = poles.clone();
+        }
+        if let Some(pole_costs) = self.pole_costs.as_ref().filter(|_| unset("pole_costs")) {
+            args.pole_costs = Some(pole_costs.clone());
+        }
+        if let Some(distance_cost) = self.distance_cost.filter(|_| unset("distance_cost")) {
+            args.distance_cost = distance_cost;
+        }
+        if let Some(metric) = self
+            .distance_metric
+            .as_ref()
+            .filter(|_| unset("distance_metric"))
+        {
+            args.distance_metric = metric.clone();
+        }
+        // the flag is SetFalse, so it is true when poles must be connected
+        if let Some(connectivity) = self.connectivity.filter(|_| unset("no_connectivity")) {
+            args.no_connectivity = connectivity;
+        }
+        if let Some(center_pos) = self.center_pos.as_ref().filter(|_| unset("center_pos")) {
+            args.center_pos = center_pos.clone();
+        }
+        if let Some(wire_axis) = self.wire_axis.as_ref().filter(|_| unset("wire_axis")) {
+            args.wire_axis = WireAxis::from_str(wire_axis, true)
+                .map_err(|_| format!("Unknown wire axis in preset: {}", wire_axis))?;
+        }
+        if let Some(polish) = self.polish.filter(|_| unset("polish")) {
+            args.polish = Some(polish);
+        }
+        if let Some(priority) = self.priority.as_ref().filter(|_| unset("priority")) {
+            args.priority = Some(priority.clone());
+        }
+        Ok(())
+    }
+}
+
+/// Applies `--preset`, if given.
+pub fn apply_preset(args: &mut OptimizePoles, matches: &ArgMatches) -> Result<(), OptimizerError> {
+    let Some(name) = args.preset.clone() else {
+        return Ok(());
+    };
+    let presets = load_presets(args.preset_file.as_deref())?;
+    let preset = presets.get(&name).ok_or_else(|| {
+        format!(
+            "Unknown preset: {}; available presets: {}",
+            name,
+            presets.keys().join(", ")
+        )
+    })?;
+    println!("Using preset {}", name);
+    preset.apply(args, matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{CommandFactory, FromArgMatches};
+
+    use super::*;
+
+    fn parse(args: &[&str]) -> (OptimizePoles, ArgMatches) {
+        let matches = OptimizePoles::command()
+            .try_get_matches_from(std::iter::once("optimize").chain(args.iter().copied()))
+            .unwrap();
+        (OptimizePoles::from_arg_matches(&matches).unwrap(), matches)
+    }
+
+    #[test]
+    fn test_command_line_overrides_preset() {
+        let (mut args, matches) = parse(&["--preset", "pretty", "--distance-cost", "3"]);
+        apply_preset(&mut args, &matches).unwrap();
+        assert_eq!(args.use_poles, vec!["m".to_string()]);
+        assert_eq!(args.distance_cost, 3.0);
+        assert_eq!(args.polish, Some(5.0));
+
+        let (mut args, matches) = parse(&["s", "--preset", "outpost", "--no-connectivity"]);
+        apply_preset(&mut args, &matches).unwrap();
+        assert_eq!(args.use_poles, vec!["s".to_string()]);
+        assert!(!args.no_connectivity);
+
+        let (mut args, matches) = parse(&["--preset", "nope"]);
+        assert!(apply_preset(&mut args, &matches).is_err());
+    }
+}
@@ -1,13 +1,19 @@
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufReader, BufWriter};
 use std::ops::Index;
-use std::path::PathBuf;
-use std::rc::Rc;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
+use clap::ValueEnum;
+use euclid::point2;
+use once_cell::sync::Lazy;
 use serde::*;
 use serde_with::{serde_as, skip_serializing_none};
 
+use crate::error::OptimizerError;
+use crate::intern::Name;
 use crate::position::*;
 use crate::rcid::RcId;
 
@@ -35,6 +41,9 @@ pub enum CollisionMask {
 pub struct EnergySource {
     #[serde(rename = "type")]
     type_: String,
+    usage_priority: Option<String>,
+    drain: Option<String>,
+    buffer_capacity: Option<String>,
 }
 
 #[serde_as]
@@ -51,17 +60,236 @@ struct RawPrototypeData {
     collision_box: BoundingBox,
 
     energy_source: Option<EnergySource>,
+    energy_usage: Option<String>,
+    energy_usage_per_tick: Option<String>,
+    /// Solar panels
+    production: Option<String>,
+    /// Electric energy interfaces
+    energy_production: Option<String>,
+    /// Burner generators
+    max_power_output: Option<String>,
+    /// Steam engines and turbines
+    fluid_usage_per_tick: Option<f64>,
+    maximum_temperature: Option<f64>,
+    effectivity: Option<f64>,
+
+    /// Fluid boxes; `fluid_boxes` is a list, or a map with extra keys like `off_when_no_fluid_recipe`.
+    fluid_box: Option<serde_json::Value>,
+    fluid_boxes: Option<serde_json::Value>,
+    input_fluid_box: Option<serde_json::Value>,
+    output_fluid_box: Option<serde_json::Value>,
 
     supply_area_distance: Option<f64>,
+    /// Modded poles with a rectangular supply area, relative to the pole's position.
+    #[serde_as(as = "Option<FactorioPos>")]
+    #[serde(default)]
+    supply_area: Option<BoundingBox>,
     maximum_wire_distance: Option<f64>,
+    attack_parameters: Option<RawAttackParameters>,
+
+    #[serde_as(as = "Option<FactorioPos>")]
+    #[serde(default)]
+    pickup_position: Option<MapPosition>,
+    #[serde_as(as = "Option<FactorioPos>")]
+    #[serde(default)]
+    insert_position: Option<MapPosition>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RawAttackParameters {
+    range: f64,
 }
 
 #[derive(Serialize, Deserialize, Debug, Copy, Clone)]
 pub struct PoleData {
+    /// For a rectangular [Self::supply_area], the farthest it reaches from the pole,
+    /// so that code only using the radius over-approximates.
     pub supply_radius: f64,
+    /// Supply area relative to the pole's position, if it isn't a square of `supply_radius`.
+    /// None for vanilla poles, whose supply area is faster to query.
+    #[serde(default)]
+    pub supply_area: Option<BoundingBox>,
     pub wire_distance: f64,
 }
 
+impl PoleData {
+    /// Supply area relative to the pole's position.
+    pub fn supply_box(&self) -> BoundingBox {
+        self.supply_area
+            .unwrap_or_else(|| BoundingBox::around_point(MapPosition::origin(), self.supply_radius))
+    }
+}
+
+/// Pickup and drop positions, relative to the inserter when facing north.
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+pub struct InserterData {
+    #[serde_as(as = "FactorioPos")]
+    pub pickup_position: MapPosition,
+    #[serde_as(as = "FactorioPos")]
+    pub insert_position: MapPosition,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+pub struct TurretData {
+    pub range: f64,
+}
+
+/// Power use of an entity with an electric energy source, in watts.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Default, PartialEq)]
+pub struct EnergyData {
+    /// Max draw while working, including drain.
+    /// Entities that use energy per action, e.g. inserters, only count their drain.
+    #[serde(default)]
+    pub consumption: f64,
+    /// Max output of generators and solar panels.
+    #[serde(default)]
+    pub production: f64,
+    /// Energy stored by accumulators, in joules.
+    #[serde(default)]
+    pub buffer: f64,
+}
+
+impl EnergyData {
+    /// If this can supply power to a network: generators, solar panels, and accumulators.
+    pub fn is_source(&self) -> bool {
+        self.production > 0.0 || self.buffer > 0.0
+    }
+}
+
+/// A pipe connection of a fluid box.
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FluidConnection {
+    /// Position just outside the entity that the connection points to, relative to the entity's center.
+    /// Either one position for north, rotated with the entity, or one for each of north, east, south, west.
+    #[serde_as(as = "Vec<FactorioPos>")]
+    pub positions: Vec<MapPosition>,
+    /// For underground pipes, the max distance to the other end.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_underground_distance: Option<u32>,
+}
+
+impl FluidConnection {
+    pub fn position(&self, direction: CardinalDirection) -> MapPosition {
+        match self.positions.as_slice() {
+            [position] => position.rotate(direction),
+            positions => positions[direction as usize],
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FluidData {
+    pub connections: Vec<FluidConnection>,
+    /// If the fluid boxes are only used by some recipes or resources, e.g. assembling machines and mining drills.
+    #[serde(default)]
+    pub optional: bool,
+}
+
+fn fluid_data(raw: &RawPrototypeData) -> Option<FluidData> {
+    // mining drills only need fluid for some resources; pumpjacks do output fluid
+    let mut optional = raw.type_ == "mining-drill" && raw.output_fluid_box.is_none();
+    let mut boxes = vec![];
+    for fluid_box in [&raw.fluid_box, &raw.input_fluid_box, &raw.output_fluid_box]
+        .into_iter()
+        .flatten()
+    {
+        boxes.push(fluid_box);
+    }
+    match &raw.fluid_boxes {
+        Some(serde_json::Value::Array(list)) => boxes.extend(list),
+        Some(serde_json::Value::Object(map)) => {
+            optional |= map
+                .get("off_when_no_fluid_recipe")
+                .is_some_and(|value| value.as_bool() == Some(true));
+            boxes.extend(map.values().filter(|value| value.is_object()));
+        }
+        _ => {}
+    }
+    let parse_position = |value: &serde_json::Value| -> Option<MapPosition> {
+        Some(point2(value.get(0)?.as_f64()?, value.get(1)?.as_f64()?))
+    };
+    let connections = boxes
+        .into_iter()
+        .filter_map(|fluid_box| fluid_box.get("pipe_connections")?.as_array())
+        .flatten()
+        .filter_map(|connection| {
+            let positions = match connection.get("positions") {
+                Some(positions) => positions
+                    .as_array()?
+                    .iter()
+                    .map(parse_position)
+                    .collect::<Option<Vec<_>>>()?,
+                None => vec![parse_position(connection.get("position")?)?],
+            };
+            Some(FluidConnection {
+                positions,
+                max_underground_distance: connection
+                    .get("max_underground_distance")
+                    .and_then(|distance| distance.as_u64())
+                    .map(|distance| distance as u32),
+            })
+        })
+        .collect::<Vec<_>>();
+    (!connections.is_empty()).then_some(FluidData {
+        connections,
+        optional,
+    })
+}
+
+/// Parses an energy or power value, like "90kW" or "5MJ", into watts or joules.
+fn parse_energy(value: &str) -> Option<f64> {
+    let value = value.trim_end_matches(['W', 'J']);
+    let (number, multiplier) = match value.chars().last()? {
+        'k' | 'K' => (&value[..value.len() - 1], 1e3),
+        'M' => (&value[..value.len() - 1], 1e6),
+        'G' => (&value[..value.len() - 1], 1e9),
+        'T' => (&value[..value.len() - 1], 1e12),
+        _ => (value, 1.0),
+    };
+    number.parse::<f64>().ok().map(|number| number * multiplier)
+}
+
+/// Heat capacity of water/steam, in joules per unit per degree.
+const FLUID_HEAT_CAPACITY: f64 = 200.0;
+/// Temperature of water from offshore pumps.
+const WATER_TEMPERATURE: f64 = 15.0;
+
+fn energy_data(raw: &RawPrototypeData) -> Option<EnergyData> {
+    let source = raw
+        .energy_source
+        .as_ref()
+        .filter(|es| es.type_ == "electric")?;
+    let parse = |value: &Option<String>| value.as_deref().and_then(parse_energy).unwrap_or(0.0);
+    let fluid_power = raw
+        .fluid_usage_per_tick
+        .zip(raw.maximum_temperature)
+        .map(|(per_tick, temperature)| {
+            per_tick
+                * 60.0
+                * (temperature - WATER_TEMPERATURE)
+                * FLUID_HEAT_CAPACITY
+                * raw.effectivity.unwrap_or(1.0)
+        })
+        .unwrap_or(0.0);
+    let is_accumulator = source.usage_priority.as_deref() == Some("tertiary");
+    Some(EnergyData {
+        consumption: parse(&raw.energy_usage)
+            + parse(&raw.energy_usage_per_tick)
+            + parse(&source.drain),
+        production: parse(&raw.production)
+            + parse(&raw.energy_production)
+            + parse(&raw.max_power_output)
+            + fluid_power,
+        buffer: if is_accumulator {
+            parse(&source.buffer_capacity)
+        } else {
+            0.0
+        },
+    })
+}
+
 #[serde_as]
 #[derive(Serialize, Deserialize, Debug)]
 pub struct EntityPrototype {
@@ -75,17 +303,36 @@ pub struct EntityPrototype {
 
     pub uses_power: bool,
     pub pole_data: Option<PoleData>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inserter_data: Option<InserterData>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub turret_data: Option<TurretData>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub energy_data: Option<EnergyData>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fluid_data: Option<FluidData>,
+    /// Collision box for each blueprint direction 0 to 7, for entities whose footprint isn't a rotation
+    /// of `collision_box`. From [PLACEMENT_OVERRIDES_FILE].
+    #[serde_as(as = "Option<Vec<FactorioPos>>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub direction_boxes: Option<Vec<BoundingBox>>,
 }
 
 impl EntityPrototype {
     pub fn is_pole(&self) -> bool {
         self.pole_data.is_some()
     }
+
+    /// Size in tiles, from the collision box. `tile_width`/`tile_height` are often missing from the data.
+    pub fn footprint(&self) -> (u32, u32) {
+        let size = self.collision_box.size();
+        (size.width.ceil() as u32, size.height.ceil() as u32)
+    }
 }
 
 pub type EntityPrototypeRef = RcId<EntityPrototype>;
 #[derive(Debug, Clone)]
-pub struct EntityPrototypeDict(pub Rc<HashMap<String, EntityPrototypeRef>>);
+pub struct EntityPrototypeDict(pub Arc<HashMap<String, EntityPrototypeRef>>);
 impl Index<&str> for EntityPrototypeDict {
     type Output = EntityPrototypeRef;
 
@@ -162,7 +409,7 @@ static ENTITY_TYPES: &[&str] = &[
 #[allow(dead_code)]
 pub fn load_prototype_data_from_raw(
     data_raw_file: &PathBuf,
-) -> Result<EntityPrototypeDict, Box<dyn std::error::Error>> {
+) -> Result<EntityPrototypeDict, OptimizerError> {
     // load as json
     let data_raw: serde_json::Value = serde_json::from_reader(File::open(data_raw_file)?)?;
     let mut entity_data = HashMap::new();
@@ -179,6 +426,8 @@ pub fn load_prototype_data_from_raw(
         }
         let is_pole = entity_type == &"electric-pole";
         for (name, raw_data) in prototypes.unwrap() {
+            let energy_data = energy_data(&raw_data);
+            let fluid_data = fluid_data(&raw_data);
             let data = RcId::new(EntityPrototype {
                 type_: raw_data.type_,
                 name: raw_data.name,
@@ -192,22 +441,40 @@ pub fn load_prototype_data_from_raw(
 
                 pole_data: if is_pole {
                     Some(PoleData {
-                        supply_radius: raw_data.supply_area_distance.unwrap_or(0.0),
+                        supply_radius: match raw_data.supply_area {
+                            Some(area) => [area.min.x, area.min.y, area.max.x, area.max.y]
+                                .into_iter()
+                                .fold(0.0, |max, coord: f64| max.max(coord.abs())),
+                            None => raw_data.supply_area_distance.unwrap_or(0.0),
+                        },
+                        supply_area: raw_data.supply_area,
                         wire_distance: raw_data.maximum_wire_distance.unwrap_or(0.0),
                     })
                 } else {
                     None
                 },
+                inserter_data: raw_data.pickup_position.zip(raw_data.insert_position).map(
+                    |(pickup_position, insert_position)| InserterData {
+                        pickup_position,
+                        insert_position,
+                    },
+                ),
+                turret_data: raw_data.attack_parameters.map(|attack| TurretData {
+                    range: attack.range,
+                }),
+                energy_data,
+                fluid_data,
+                direction_boxes: None,
             });
             entity_data.insert(name, data);
         }
     }
-    Ok(EntityPrototypeDict(Rc::new(entity_data)))
+    Ok(EntityPrototypeDict(Arc::new(entity_data)))
 }
 
 static ENTITY_PROTOTYPE_FILE: &str = "data/entity-data.json";
 #[allow(dead_code)]
-pub fn save_prototype_data(prototype_data: &EntityPrototypeDict) -> Result<(), Box<dyn std::error::Error>> {
+pub fn save_prototype_data(prototype_data: &EntityPrototypeDict) -> Result<(), OptimizerError> {
     let file = File::create(ENTITY_PROTOTYPE_FILE)?;
     let writer = BufWriter::new(file);
     let copy = prototype_data
@@ -218,14 +485,176 @@ pub fn save_prototype_data(prototype_data: &EntityPrototypeDict) -> Result<(), B
     Ok(())
 }
 
-pub fn load_prototype_data() -> Result<EntityPrototypeDict, Box<dyn std::error::Error>> {
-    let file = File::open(ENTITY_PROTOTYPE_FILE)?;
-    let entity_data =
-        serde_json::from_reader::<_, HashMap<String, EntityPrototype>>(BufReader::new(file))?
-            .into_iter()
-            .map(|(k, v)| (k, RcId::new(v)))
-            .collect();
-    Ok(EntityPrototypeDict(Rc::new(entity_data)))
+/// Prototype data for overhaul mods, in the same format as [ENTITY_PROTOTYPE_FILE].
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ModPack {
+    /// Space Exploration
+    Se,
+    /// Krastorio 2
+    K2,
+    /// Pyanodons
+    Py,
+}
+
+impl ModPack {
+    pub fn file(self) -> PathBuf {
+        let name = match self {
+            ModPack::Se => "se",
+            ModPack::K2 => "k2",
+            ModPack::Py => "py",
+        };
+        PathBuf::from(format!("data/mod-packs/{}.json", name))
+    }
+}
+
+/// Prototype data loaded so far, by mod pack, shared by all threads,
+/// so batch and server modes load each only once, and prototypes compare equal across threads.
+static PROTOTYPE_CACHE: Lazy<Mutex<HashMap<Option<ModPack>, EntityPrototypeDict>>> =
+    Lazy::new(Default::default);
+
+thread_local! {
+    static PROTOTYPE_DATA: RefCell<Option<EntityPrototypeDict>> = const { RefCell::new(None) };
+    static SERDE_PROTOTYPES: RefCell<Option<EntityPrototypeDict>> = const { RefCell::new(None) };
+    static MOD_PACK: Cell<Option<ModPack>> = const { Cell::new(None) };
+}
+
+/// Also loads prototypes from `mod_pack`, overriding vanilla ones with the same name.
+/// Must be called before the first [load_prototype_data].
+pub fn use_mod_pack(mod_pack: ModPack) -> Result<(), OptimizerError> {
+    if !mod_pack.file().exists() {
+        return Err(format!(
+            "Prototype data for {:?} is not available; expected it at {:?}. See data/mod-packs/README.md",
+            mod_pack,
+            mod_pack.file()
+        )
+        .into());
+    }
+    assert!(
+        PROTOTYPE_DATA.with(|data| data.borrow().is_none()),
+        "prototype data already loaded"
+    );
+    MOD_PACK.with(|pack| pack.set(Some(mod_pack)));
+    Ok(())
+}
+
+/// The mod pack from [use_mod_pack] in this thread, to use in other threads.
+pub fn mod_pack() -> Option<ModPack> {
+    MOD_PACK.with(|pack| pack.get())
+}
+
+/// The files [load_prototype_data] reads in this thread, for caches of results that depend on them.
+pub fn prototype_data_files() -> Vec<PathBuf> {
+    let mut files = vec![
+        PathBuf::from(ENTITY_PROTOTYPE_FILE),
+        PathBuf::from(PLACEMENT_OVERRIDES_FILE),
+    ];
+    files.extend(mod_pack().map(ModPack::file));
+    files
+}
+
+/// Collision boxes by direction that the game data doesn't give directly, e.g. for curved rails,
+/// which extend past their 4x4 collision box. Applied to vanilla and mod pack prototypes alike.
+static PLACEMENT_OVERRIDES_FILE: &str = "data/placement-overrides.json";
+
+#[serde_as]
+#[derive(Deserialize)]
+struct PlacementOverrides(
+    #[serde_as(as = "HashMap<_, Vec<FactorioPos>>")] HashMap<String, Vec<BoundingBox>>,
+);
+
+fn read_placement_overrides() -> Result<HashMap<String, Vec<BoundingBox>>, OptimizerError> {
+    let file = File::open(PLACEMENT_OVERRIDES_FILE)?;
+    let PlacementOverrides(overrides) = serde_json::from_reader(BufReader::new(file))?;
+    if let Some((name, _)) = overrides.iter().find(|(_, boxes)| boxes.len() != 8) {
+        return Err(format!(
+            "{} in {} must have a collision box for each of the 8 directions",
+            name, PLACEMENT_OVERRIDES_FILE
+        )
+        .into());
+    }
+    Ok(overrides)
+}
+
+fn read_prototype_file(
+    path: impl AsRef<Path>,
+) -> Result<HashMap<String, EntityPrototype>, OptimizerError> {
+    let file = File::open(path)?;
+    Ok(serde_json::from_reader(BufReader::new(file))?)
+}
+
+/// Prototypes are compared by identity, so this is only loaded once per mod pack, and shared by all threads.
+pub fn load_prototype_data() -> Result<EntityPrototypeDict, OptimizerError> {
+    if let Some(dict) = PROTOTYPE_DATA.with(|data| data.borrow().clone()) {
+        return Ok(dict);
+    }
+    let mod_pack = MOD_PACK.with(|pack| pack.get());
+    // held while loading, so threads starting together don't each load it
+    let mut cache = PROTOTYPE_CACHE.lock().unwrap();
+    let dict = match cache.get(&mod_pack) {
+        Some(dict) => dict.clone(),
+        None => {
+            let dict = read_prototype_data(mod_pack)?;
+            cache.insert(mod_pack, dict.clone());
+            dict
+        }
+    };
+    PROTOTYPE_DATA.with(|data| *data.borrow_mut() = Some(dict.clone()));
+    Ok(dict)
+}
+
+fn read_prototype_data(mod_pack: Option<ModPack>) -> Result<EntityPrototypeDict, OptimizerError> {
+    let mut prototypes = read_prototype_file(ENTITY_PROTOTYPE_FILE)?;
+    if let Some(mod_pack) = mod_pack {
+        prototypes.extend(read_prototype_file(mod_pack.file())?);
+    }
+    for (name, boxes) in read_placement_overrides()? {
+        if let Some(prototype) = prototypes.get_mut(&name) {
+            prototype.direction_boxes = Some(boxes);
+        }
+    }
+    Name::intern(prototypes.keys().map(String::as_str));
+    let entity_data = prototypes
+        .into_iter()
+        .map(|(k, v)| (k, RcId::new(v)))
+        .collect();
+    Ok(EntityPrototypeDict(Arc::new(entity_data)))
+}
+
+/// Runs `f` using `dict` to look up prototypes when deserializing with [prototype_by_name].
+/// Otherwise, [load_prototype_data] is used.
+pub fn with_serde_prototypes<R>(dict: &EntityPrototypeDict, f: impl FnOnce() -> R) -> R {
+    let prev = SERDE_PROTOTYPES.with(|prototypes| prototypes.replace(Some(dict.clone())));
+    let result = f();
+    SERDE_PROTOTYPES.with(|prototypes| *prototypes.borrow_mut() = prev);
+    result
+}
+
+/// Serializes an [EntityPrototypeRef] as its name.
+pub mod prototype_by_name {
+    use serde::de::Error;
+
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        prototype: &EntityPrototypeRef,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&prototype.name)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<EntityPrototypeRef, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        let dict = match SERDE_PROTOTYPES.with(|prototypes| prototypes.borrow().clone()) {
+            Some(dict) => dict,
+            None => load_prototype_data().map_err(D::Error::custom)?,
+        };
+        dict.0
+            .get(&name)
+            .cloned()
+            .ok_or_else(|| D::Error::custom(format!("Unknown entity type: {}", name)))
+    }
 }
 
 #[cfg(test)]
@@ -248,6 +677,61 @@ mod tests {
         save_prototype_data(&entity_data).unwrap();
     }
 
+    #[test]
+    fn test_parse_energy() {
+        assert_eq!(parse_energy("90kW"), Some(90e3));
+        assert_eq!(parse_energy("5KW"), Some(5e3));
+        assert_eq!(parse_energy("5MJ"), Some(5e6));
+        assert_eq!(parse_energy("0.4kW"), Some(400.0));
+        assert_eq!(parse_energy("watts"), None);
+    }
+
+    #[test]
+    fn test_shared_across_threads() {
+        let pole = load_prototype_data().unwrap()["small-electric-pole"].clone();
+        let other_thread =
+            std::thread::spawn(|| load_prototype_data().unwrap()["small-electric-pole"].clone())
+                .join()
+                .unwrap();
+        assert!(pole == other_thread);
+    }
+
+    #[test]
+    fn test_energy_data() {
+        let entity_data = load_prototype_data().unwrap();
+        let energy = |name: &str| entity_data[name].energy_data.unwrap();
+        assert_eq!(energy("assembling-machine-1").consumption, 75e3);
+        assert_eq!(energy("steam-engine").production, 900e3);
+        assert_eq!(energy("solar-panel").production, 60e3);
+        assert!(energy("accumulator").is_source());
+        assert!(!energy("electric-furnace").is_source());
+        assert!(entity_data["stone-furnace"].energy_data.is_none());
+    }
+
+    #[test]
+    fn test_fluid_data() {
+        let entity_data = load_prototype_data().unwrap();
+        let pipe = entity_data["pipe"].fluid_data.as_ref().unwrap();
+        assert_eq!(pipe.connections.len(), 4);
+        let pipe_to_ground = entity_data["pipe-to-ground"].fluid_data.as_ref().unwrap();
+        assert_eq!(
+            pipe_to_ground.connections[1].position(CardinalDirection::East),
+            point2(-1.0, 0.0)
+        );
+        assert_eq!(
+            pipe_to_ground.connections[1].max_underground_distance,
+            Some(10)
+        );
+        assert!(
+            entity_data["assembling-machine-2"]
+                .fluid_data
+                .as_ref()
+                .unwrap()
+                .optional
+        );
+        assert!(entity_data["assembling-machine-1"].fluid_data.is_none());
+    }
+
     #[test]
     fn do_load_prototype_data() {
         let entity_data = load_prototype_data().unwrap();
@@ -0,0 +1,105 @@
+use clap::Parser;
+use factorio_blueprint::objects::Blueprint;
+use log::warn;
+
+use crate::better_bp::BlueprintEntities;
+use crate::bp_model::BpModel;
+use crate::error::OptimizerError;
+use crate::pipeline::{self, PipelineState};
+use crate::prototype_data;
+use crate::OptimizePoles;
+
+#[derive(Parser, Debug)]
+pub struct PruneArgs {
+    #[arg(
+        short = 't',
+        long,
+        default_value_t = 120.0,
+        help = "Time limit for ILP solver"
+    )]
+    time_limit: f64,
+
+    #[arg(short, long, help = "Don't output stuff from ILP solver")]
+    quiet: bool,
+}
+
+/// The `optimize` options for `prune`: only the input poles are candidates.
+fn optimize_args(allow_unpowered: bool, args: &PruneArgs) -> Result<OptimizePoles, OptimizerError> {
+    let mut command = vec![
+        "optimize".to_string(),
+        "--only-input-poles".to_string(),
+        "--time-limit".to_string(),
+        args.time_limit.to_string(),
+    ];
+    if allow_unpowered {
+        command.push("--allow-unpowered".to_string());
+    }
+    if args.quiet {
+        command.push("--quiet".to_string());
+    }
+    OptimizePoles::try_parse_from(command).map_err(|err| OptimizerError::from(err.to_string()))
+}
+
+/// Removes the poles of `bp` that aren't needed to power its entities and connect the rest.
+/// Returns None if there is nothing to prune.
+pub fn run_prune(bp: Blueprint, args: &PruneArgs) -> Result<Option<PipelineState>, OptimizerError> {
+    let dict = prototype_data::load_prototype_data()?;
+    let model = BpModel::from_bp_entities(&BlueprintEntities::from_blueprint(&bp), &dict);
+    if model.poles().next().is_none() {
+        println!("Nothing to prune: the blueprint has no poles");
+        return Ok(None);
+    }
+    let networks = model.count_pole_networks();
+    if networks > 1 {
+        return Err(format!(
+            "The poles form {} separate networks, so connectivity can't be kept; connect them first, e.g. with fix-power",
+            networks
+        )
+        .into());
+    }
+    let unpowered = model.unpowered_entities().count();
+    if unpowered > 0 {
+        warn!(
+            "{} entities are unpowered in the input, and will stay unpowered",
+            unpowered
+        );
+    }
+    let opt = optimize_args(unpowered > 0, args)?;
+    pipeline::run_optimize_pipeline(bp, &opt).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use itertools::Itertools;
+
+    use super::*;
+    use crate::output_book::{is_pole, pole_key};
+
+    #[test]
+    fn test_prune_only_removes() {
+        let bp = crate::read_blueprint(&PathBuf::from("test-data/bigtest_out.txt")).unwrap();
+        let dict = prototype_data::load_prototype_data().unwrap();
+        let input = BlueprintEntities::from_blueprint(&bp)
+            .entities
+            .values()
+            .filter(|entity| is_pole(&dict, entity))
+            .map(pole_key)
+            .collect_vec();
+
+        let args = PruneArgs::try_parse_from(["prune", "--quiet"]).unwrap();
+        let state = run_prune(bp, &args).unwrap().unwrap();
+        assert_eq!(state.report.unpowered_after, 0);
+        assert_eq!(state.model.count_pole_networks(), 1);
+        let after = state
+            .entities
+            .entities
+            .values()
+            .filter(|entity| is_pole(&dict, entity))
+            .map(pole_key)
+            .collect_vec();
+        assert!(after.len() <= input.len());
+        assert!(after.iter().all(|pole| input.contains(pole)));
+    }
+}
@@ -0,0 +1,232 @@
+use std::path::Path;
+
+use clap::Parser;
+use euclid::{vec2, Box2D};
+use factorio_blueprint::objects::Blueprint;
+use good_lp::highs;
+use hashbrown::{HashMap, HashSet};
+use itertools::Itertools;
+use petgraph::prelude::*;
+
+use crate::algorithms::{
+    ColumnGenerationSolver, LpRoundingSolver, PoleCoverSolver, SetCoverILPSolver,
+    SpatialSplitSolver,
+};
+use crate::better_bp::{BlueprintEntities, BlueprintEntityData, EntityId};
+use crate::bp_io::BlueprintFormat;
+use crate::bp_model::{BpModel, WorldEntity};
+use crate::error::OptimizerError;
+use crate::highs_solve::HighsOptions;
+use crate::pole_graph::{CandPoleGraph, CandPoleNode};
+use crate::prototype_data::{self, EntityPrototypeRef};
+use crate::{require_prototype, write_blueprint, SolverKind};
+
+#[derive(Parser, Debug)]
+pub struct RadarsArgs {
+    #[arg(long, default_value = "radar", help = "Radar entity to place")]
+    radar: String,
+
+    #[arg(
+        long,
+        default_value = "64",
+        help = "Half-width of the square around a radar that is always continuously revealed, wherever the blueprint is placed relative to chunks. Vanilla radars reveal the 2 chunks around their own chunk, so at least 64 tiles"
+    )]
+    reveal_radius: f64,
+
+    #[arg(long, value_enum, default_value = "ilp", help = "Solver to use")]
+    solver: SolverKind,
+
+    #[arg(
+        long,
+        default_value = "60",
+        help = "Time limit for the solver, in seconds"
+    )]
+    time_limit: f64,
+}
+
+/// Entities whose collision box is entirely within the continuously revealed area of `radar`.
+fn revealed_entities(model: &BpModel, radar: &WorldEntity, reveal_radius: f64) -> Vec<EntityId> {
+    let reveal_area = Box2D::new(
+        radar.position - vec2(reveal_radius, reveal_radius),
+        radar.position + vec2(reveal_radius, reveal_radius),
+    );
+    model
+        .all_entities()
+        .filter(|entity| entity.prototype != radar.prototype)
+        .filter(|entity| reveal_area.contains_box(&entity.world_bbox()))
+        .map(|entity| entity.id())
+        .sorted()
+        .collect()
+}
+
+/// A graph of all positions a radar could be placed in the blueprint, with the entities each would reveal.
+/// Positions revealing the same entities as an earlier position, or none, are skipped.
+/// Existing radars are always included, and are returned as fixed.
+fn candidate_radars(
+    model: &BpModel,
+    radar: &EntityPrototypeRef,
+    reveal_radius: f64,
+) -> (CandPoleGraph, HashSet<NodeIndex>) {
+    let mut graph = CandPoleGraph::new_undirected();
+    let mut fixed = HashSet::new();
+    let mut seen = HashSet::new();
+    let candidates = model.with_all_candidate_poles(model.get_bounding_box(), &[radar]);
+    for candidate in candidates.all_entities_grid_order() {
+        if candidate.prototype != *radar {
+            continue;
+        }
+        let existing = model.get(candidate.id()).is_some();
+        let revealed = revealed_entities(model, candidate, reveal_radius);
+        if !existing && (revealed.is_empty() || seen.contains(&revealed)) {
+            continue;
+        }
+        let idx = graph.add_node(CandPoleNode {
+            entity: candidate.entity.clone(),
+            powered_entities: revealed.iter().copied().collect(),
+        });
+        seen.insert(revealed);
+        if existing {
+            fixed.insert(idx);
+        }
+    }
+    (graph, fixed)
+}
+
+/// Chooses radar positions so every entity in `model` is continuously revealed, using as few radars as possible.
+/// Returns the radars to add; existing radars are kept.
+pub fn place_radars(
+    model: &BpModel,
+    radar: &EntityPrototypeRef,
+    reveal_radius: f64,
+    solver: SolverKind,
+    time_limit: f64,
+) -> Result<Vec<WorldEntity>, OptimizerError> {
+    let (graph, fixed_poles) = candidate_radars(model, radar, reveal_radius);
+    let reachable = graph
+        .node_weights()
+        .flat_map(|node| node.powered_entities.iter().copied())
+        .collect::<HashSet<_>>();
+    let unreachable = model
+        .all_entities()
+        .filter(|entity| entity.prototype != *radar && !reachable.contains(&entity.id()))
+        .count();
+    if unreachable > 0 {
+        return Err(format!(
+            "{} entities are not within {} tiles of any free radar position",
+            unreachable, reveal_radius
+        )
+        .into());
+    }
+    println!(
+        "Choosing from {} candidate radar positions",
+        graph.node_count()
+    );
+
+    let config = |options: HighsOptions| -> Result<HighsOptions, OptimizerError> {
+        Ok(options.set_time_limit(time_limit))
+    };
+    let ilp = SetCoverILPSolver {
+        solver: &highs,
+        config: &config,
+        cost: &|_, _| 1.0,
+        connectivity: None,
+        fixed_poles,
+        max_count: HashMap::new(),
+        lazy: false,
+        max_pole_types: None,
+        max_load: None,
+        initial_solution: Default::default(),
+        soft_entities: Default::default(),
+    };
+    let solution = match solver {
+        SolverKind::Ilp => ilp.solve(&graph)?,
+        SolverKind::LpRound => LpRoundingSolver {
+            lp: ilp,
+            rounds: None,
+            seed: 0,
+        }
+        .solve(&graph)?,
+        SolverKind::ColumnGen => ColumnGenerationSolver {
+            ilp,
+            columns_per_round: 200,
+            max_rounds: 1000,
+        }
+        .solve(&graph)?,
+        SolverKind::Split => SpatialSplitSolver {
+            ilp,
+            region_size: 256.0,
+            overlap: 32.0,
+            threads: std::thread::available_parallelism().map_or(1, usize::from),
+        }
+        .solve(&graph)?,
+    };
+    Ok(solution
+        .node_weights()
+        .map(|node| node.entity.clone())
+        .filter(|entity| model.can_place(entity))
+        .collect())
+}
+
+/// Adds radars to a blueprint so that all of it is continuously revealed. Writes nothing if `out_file` is None.
+pub fn run_radars(
+    mut bp: Blueprint,
+    args: &RadarsArgs,
+    out_file: Option<&Path>,
+    format: BlueprintFormat,
+) -> Result<(), OptimizerError> {
+    let dict = prototype_data::load_prototype_data()?;
+    let radar = require_prototype(&args.radar, &dict)?;
+    let mut entities = BlueprintEntities::from_blueprint(&bp);
+    let model = BpModel::from_bp_entities(&entities, &dict);
+    let radars = place_radars(
+        &model,
+        &radar,
+        args.reveal_radius,
+        args.solver,
+        args.time_limit,
+    )?;
+    println!("Adding {} radars", radars.len());
+    for entity in radars {
+        entities.add_entity(BlueprintEntityData::new(
+            entity.prototype.name.clone(),
+            entity.position,
+            None,
+        ));
+    }
+    entities.write_to_blueprint(&mut bp);
+    match out_file {
+        Some(out_file) => write_blueprint(bp, &out_file.to_path_buf(), format, None).map(|_| ()),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::point2;
+
+    use super::*;
+
+    #[test]
+    fn test_place_radars() {
+        let dict = prototype_data::load_prototype_data().unwrap();
+        let mut entities = BlueprintEntities::new();
+        for i in 0..30 {
+            entities.add_entity(BlueprintEntityData::new(
+                "wooden-chest".to_string(),
+                point2(i as f64 * 5.0 + 0.5, 0.5),
+                None,
+            ));
+        }
+        let model = BpModel::from_bp_entities(&entities, &dict);
+        let radar = dict["radar"].clone();
+        let radars = place_radars(&model, &radar, 20.0, SolverKind::Ilp, 10.0).unwrap();
+
+        // 150 tiles long, and each radar reveals 40
+        assert_eq!(radars.len(), 4);
+        for entity in model.all_entities() {
+            assert!(radars
+                .iter()
+                .any(|radar| revealed_entities(&model, radar, 20.0).contains(&entity.id())));
+        }
+    }
+}
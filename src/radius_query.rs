@@ -0,0 +1,314 @@
+use std::marker::PhantomData;
+
+use euclid::point2;
+use hashbrown::HashMap;
+
+use crate::better_bp::EntityId;
+use crate::bp_model::{BpModel, WorldEntity};
+use crate::pole_windows::Moving2DWindow;
+use crate::position::{
+    BoundingBox, BoundingBoxExt, MapPosition, MapPositionExt, TileBoundingBox, TilePosition,
+};
+use crate::prototype_data::{EntityPrototype, EntityPrototypeRef};
+
+/// A kind of range of an entity, e.g. wire reach or supply area.
+pub trait RadiusParams {
+    fn get_radius(prototype: &EntityPrototype) -> f64;
+
+    /// The range relative to the entity's position; a square of [Self::get_radius] unless overridden.
+    fn get_area(prototype: &EntityPrototype) -> BoundingBox {
+        BoundingBox::around_point(MapPosition::origin(), Self::get_radius(prototype))
+    }
+
+    /// If [Self::get_area] is a square, so the window contains exactly the entities in range.
+    fn is_square(_prototype: &EntityPrototype) -> bool {
+        true
+    }
+}
+
+/// Finds entities within a square radius (given by `P`) around other entities.
+/// Rectangular areas use a square window covering them, and filter its entities.
+///
+/// Keeps one [Moving2DWindow] per prototype, which is moved to each queried entity.
+/// Querying entities in grid order (see [BpModel::all_entities_grid_order]) only touches the tiles
+/// at the edges of the window, which is much faster than looking up every tile in range.
+pub struct RadiusQueryCache<'a, P: RadiusParams> {
+    model: &'a BpModel,
+    windows_by_proto: HashMap<EntityPrototypeRef, Moving2DWindow<&'a BpModel>>,
+    marker: PhantomData<P>,
+}
+
+impl<'a, P: RadiusParams> RadiusQueryCache<'a, P> {
+    pub fn new(model: &'a BpModel) -> Self {
+        Self {
+            model,
+            windows_by_proto: HashMap::new(),
+            marker: PhantomData,
+        }
+    }
+
+    fn get_window_top_left(prototype: &EntityPrototype, pos: MapPosition) -> TilePosition {
+        (pos + P::get_area(prototype).min.to_vector()).tile_pos()
+    }
+    /// Tiles in range of an entity at `pos`; a range ending on a tile edge doesn't reach the next tile.
+    fn get_area_tiles(prototype: &EntityPrototype, pos: MapPosition) -> TileBoundingBox {
+        P::get_area(prototype)
+            .translate(pos.to_vector())
+            .round_out_to_tiles()
+    }
+    fn get_window_size(prototype: &EntityPrototype) -> i32 {
+        let tile_width = prototype.tile_width;
+        let tile_height = prototype.tile_height;
+        let rep_center = point2(
+            (tile_width % 2) as f64 / 2.0,
+            (tile_height % 2) as f64 / 2.0,
+        );
+        let size = Self::get_area_tiles(prototype, rep_center).size();
+        size.width.max(size.height)
+    }
+    pub fn get_window_for(&mut self, entity: &WorldEntity) -> &mut Moving2DWindow<&'a BpModel> {
+        let prototype = &entity.prototype;
+        let top_left = Self::get_window_top_left(prototype, entity.position);
+        let window = self
+            .windows_by_proto
+            .entry(prototype.clone())
+            .or_insert_with(|| {
+                let size = Self::get_window_size(prototype);
+                Moving2DWindow::new(self.model, top_left, size)
+            });
+        window.move_to(top_left);
+        window
+    }
+
+    /// Entities on any tile within the range of `entity`.
+    pub fn query(
+        &mut self,
+        entity: &WorldEntity,
+    ) -> impl Iterator<Item = EntityId> + use<'_, 'a, P> {
+        let model = self.model;
+        let area = (!P::is_square(&entity.prototype))
+            .then(|| Self::get_area_tiles(&entity.prototype, entity.position));
+        self.get_window_for(entity)
+            .cur_items()
+            .copied()
+            .filter(move |id| match area {
+                None => true,
+                Some(area) => model
+                    .get(*id)
+                    .is_some_and(|other| other.world_bbox().round_out_to_tiles().intersects(&area)),
+            })
+    }
+}
+
+pub struct WireReach;
+
+impl RadiusParams for WireReach {
+    fn get_radius(prototype: &EntityPrototype) -> f64 {
+        prototype.pole_data.unwrap().wire_distance
+    }
+}
+
+pub struct PoleCoverage;
+
+impl RadiusParams for PoleCoverage {
+    fn get_radius(prototype: &EntityPrototype) -> f64 {
+        prototype.pole_data.unwrap().supply_radius
+    }
+    fn get_area(prototype: &EntityPrototype) -> BoundingBox {
+        prototype.pole_data.unwrap().supply_box()
+    }
+    fn is_square(prototype: &EntityPrototype) -> bool {
+        prototype.pole_data.unwrap().supply_area.is_none()
+    }
+}
+
+/// Range of a turret; not limited to poles.
+pub struct TurretRange;
+
+impl RadiusParams for TurretRange {
+    fn get_radius(prototype: &EntityPrototype) -> f64 {
+        prototype.turret_data.unwrap().range
+    }
+}
+
+pub type WireReachWindows<'a> = RadiusQueryCache<'a, WireReach>;
+pub type PoleCoverageWindows<'a> = RadiusQueryCache<'a, PoleCoverage>;
+pub type TurretRangeWindows<'a> = RadiusQueryCache<'a, TurretRange>;
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use euclid::vec2;
+    use hashbrown::HashSet;
+    use itertools::Itertools;
+    use rand::prelude::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::bp_model::test_util::{
+        powerable_prototype, small_pole_prototype, test_pole_prototype,
+    };
+    use crate::position::{IterTiles, TileSpaceExt};
+    use crate::prototype_data::PoleData;
+
+    use super::*;
+
+    #[test]
+    fn test_window_params() {
+        let prototype = small_pole_prototype();
+        let pole_data = prototype.pole_data.unwrap();
+        assert_eq!(WireReach::get_radius(&prototype), pole_data.wire_distance);
+        assert_eq!(
+            PoleCoverage::get_radius(&prototype),
+            pole_data.supply_radius
+        );
+    }
+
+    #[test]
+    fn test_pole_windows() {
+        let prototype = small_pole_prototype();
+        let model = BpModel::new();
+        let mut wire_windows = WireReachWindows::new(&model);
+        let mut coverage_windows = PoleCoverageWindows::new(&model);
+        let entity = WorldEntity {
+            position: point2(1.5, 2.5),
+            direction: 0,
+            prototype,
+        };
+
+        let wire_window = wire_windows.get_window_for(&entity);
+        assert_eq!(wire_window.size(), 15);
+        assert_eq!(
+            wire_window.top_left(),
+            (entity.position - vec2(7.5, 7.5)).tile_pos()
+        );
+        let coverage_window = coverage_windows.get_window_for(&entity);
+        assert_eq!(coverage_window.size(), 5);
+        assert_eq!(
+            coverage_window.top_left(),
+            (entity.position - vec2(2.5, 2.5)).tile_pos()
+        );
+    }
+
+    fn random_model(size: i32, seed: u64) -> BpModel {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut model = BpModel::new();
+        for tile in TileBoundingBox::new(point2(0, 0), point2(size, size)).iter_tiles() {
+            if rng.gen_bool(0.3) {
+                model.add_test_powerable(tile);
+            } else if rng.gen_bool(0.1) {
+                model.add_test_pole(tile);
+            }
+        }
+        model
+    }
+
+    /// Entities on any tile in the same square as [RadiusQueryCache], looking up every tile.
+    fn direct_query<P: RadiusParams>(model: &BpModel, entity: &WorldEntity) -> HashSet<EntityId> {
+        let radius = P::get_radius(&entity.prototype);
+        BoundingBox::around_point(entity.position, radius)
+            .round_out_to_tiles()
+            .iter_tiles()
+            .flat_map(|tile| model.get_at_tile(tile))
+            .map(|entity| entity.id())
+            .collect()
+    }
+
+    fn poles(model: &BpModel) -> Vec<WorldEntity> {
+        model
+            .all_entities_grid_order()
+            .filter(|entity| entity.prototype.is_pole())
+            .map(|entity| entity.entity.clone())
+            .collect_vec()
+    }
+
+    #[test]
+    fn test_matches_direct_query() {
+        let model = random_model(30, 1);
+        let mut cache = PoleCoverageWindows::new(&model);
+        for pole in poles(&model) {
+            assert_eq!(
+                cache.query(&pole).collect::<HashSet<_>>(),
+                direct_query::<PoleCoverage>(&model, &pole)
+            );
+        }
+        // also a prototype that isn't in the model
+        let pole = WorldEntity {
+            position: point2(3, 4).center_map_pos(),
+            direction: 0,
+            prototype: powerable_prototype(),
+        };
+        let mut cache = RadiusQueryCache::<TestRadius>::new(&model);
+        assert_eq!(
+            cache.query(&pole).collect::<HashSet<_>>(),
+            direct_query::<TestRadius>(&model, &pole)
+        );
+        // radius 5 around a 1x1 entity
+        assert_eq!(cache.get_window_for(&pole).size(), 11);
+    }
+
+    #[test]
+    fn test_rectangular_supply_area() {
+        let model = random_model(30, 3);
+        let pole = WorldEntity {
+            position: point2(10, 12).center_map_pos(),
+            direction: 0,
+            prototype: test_pole_prototype(PoleData {
+                wire_distance: 7.5,
+                supply_radius: 6.5,
+                supply_area: Some(BoundingBox::new(point2(-1.5, -4.5), point2(6.5, 0.5))),
+            }),
+        };
+        let expected = model
+            .powered_entities(pole.position, pole.prototype.pole_data.unwrap())
+            .map(|entity| entity.id())
+            .collect::<HashSet<_>>();
+        let mut cache = PoleCoverageWindows::new(&model);
+        let in_window = cache.get_window_for(&pole).cur_items().count();
+        let found = cache.query(&pole).collect::<HashSet<_>>();
+        assert!(found.is_superset(&expected));
+        // the window is square, so has entities outside the area
+        assert!(found.len() < in_window);
+        let area = TileBoundingBox::new(point2(9, 8), point2(18, 14));
+        for id in found {
+            let tiles = model.get(id).unwrap().world_bbox().round_out_to_tiles();
+            assert!(tiles.intersects(&area));
+        }
+    }
+
+    struct TestRadius;
+    impl RadiusParams for TestRadius {
+        fn get_radius(_: &EntityPrototype) -> f64 {
+            5.0
+        }
+    }
+
+    /// Benchmark against looking up every tile in range:
+    /// `cargo test --release bench_radius_query -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_radius_query() {
+        let model = random_model(300, 2);
+        let poles = poles(&model);
+
+        let start = Instant::now();
+        let mut cache = WireReachWindows::new(&model);
+        let cached_total: usize = poles.iter().map(|pole| cache.query(pole).count()).sum();
+        let cached_time = start.elapsed();
+
+        let start = Instant::now();
+        let direct_total: usize = poles
+            .iter()
+            .map(|pole| direct_query::<WireReach>(&model, pole).len())
+            .sum();
+        let direct_time = start.elapsed();
+
+        println!(
+            "{} queries: cached {:?}, direct {:?}",
+            poles.len(),
+            cached_time,
+            direct_time
+        );
+        assert_eq!(cached_total, direct_total);
+    }
+}
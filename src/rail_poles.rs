@@ -0,0 +1,133 @@
+use clap::ValueEnum;
+use hashbrown::HashSet;
+
+use crate::bp_model::{BpModel, WorldEntity};
+
+/// How strongly to place big poles between parallel rails, for `--rail-poles`.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RailPoles {
+    /// Big poles elsewhere cost a little more; see [OFF_RAIL_GRID_COST].
+    Prefer,
+    /// Only big poles between rails are placed. Existing poles are still candidates.
+    Require,
+}
+
+/// Added to the cost of a big pole not between rails; less than a pole, so it never costs an extra pole.
+pub const OFF_RAIL_GRID_COST: f64 = 0.05;
+
+/// Distance between the centers of parallel tracks with a 2 tile gap between them, in half tiles.
+const TRACK_SPACING: i32 = 8;
+/// Poles are every 2 rail segments along the track, in half tiles.
+const POLE_PERIOD: i32 = 8;
+
+/// The conventional big pole positions: centered in the gap between parallel straight rails 4 tiles apart,
+/// at every other joint between rail segments, with rails on both sides.
+#[derive(Debug, Clone, Default)]
+pub struct RailPoleGrid {
+    /// Pole centers, in half tiles.
+    positions: HashSet<(i32, i32)>,
+}
+
+impl RailPoleGrid {
+    pub fn from_model(model: &BpModel) -> Self {
+        // ends of straight rails running along each axis, with the axis swapped to x for horizontal ones
+        let mut vertical = HashSet::new();
+        let mut horizontal = HashSet::new();
+        for entity in model.all_entities() {
+            if entity.prototype.type_ != "straight-rail" {
+                continue;
+            }
+            let Some(ends) = entity.rail_ends() else {
+                continue;
+            };
+            for end in ends {
+                let (x, y) = end.half_tile_position;
+                match end.direction {
+                    0 | 4 => vertical.insert((x, y)),
+                    2 | 6 => horizontal.insert((y, x)),
+                    _ => false,
+                };
+            }
+        }
+        let between = |ends: &HashSet<(i32, i32)>| {
+            ends.iter()
+                .filter(|(across, along)| {
+                    along.rem_euclid(POLE_PERIOD) == 0
+                        && ends.contains(&(across + TRACK_SPACING, *along))
+                })
+                .map(|(across, along)| (across + TRACK_SPACING / 2, *along))
+                .collect::<Vec<_>>()
+        };
+        let mut positions = HashSet::new();
+        positions.extend(between(&vertical));
+        positions.extend(between(&horizontal).into_iter().map(|(y, x)| (x, y)));
+        RailPoleGrid { positions }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// Only poles 2 tiles wide fit between the tracks: big poles, and substations.
+    pub fn applies_to(&self, entity: &WorldEntity) -> bool {
+        entity.prototype.is_pole() && entity.prototype.footprint().0 == 2
+    }
+
+    /// Positions on the grid, for candidates of a pole 2 tiles wide.
+    pub fn positions(&self) -> impl Iterator<Item = (f64, f64)> + '_ {
+        self.positions
+            .iter()
+            .map(|(x, y)| (*x as f64 / 2.0, *y as f64 / 2.0))
+    }
+
+    /// If the entity is a pole this applies to, and is not on the grid.
+    pub fn is_off_grid(&self, entity: &WorldEntity) -> bool {
+        let position = (
+            (entity.position.x * 2.0).round() as i32,
+            (entity.position.y * 2.0).round() as i32,
+        );
+        self.applies_to(entity) && !self.positions.contains(&position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::point2;
+
+    use crate::better_bp::{BlueprintEntities, BlueprintEntityData};
+    use crate::prototype_data;
+
+    use super::*;
+
+    #[test]
+    fn test_rail_pole_grid() {
+        let dict = prototype_data::load_prototype_data().unwrap();
+        let mut entities = BlueprintEntities::new();
+        // two vertical tracks from y = 0 to 10, with a 2 tile gap at x = 2..4
+        for x in [1.0, 5.0] {
+            for y in [1.0, 3.0, 5.0, 7.0, 9.0] {
+                entities.add_entity(BlueprintEntityData::new(
+                    "straight-rail".to_string(),
+                    point2(x, y),
+                    Some(0),
+                ));
+            }
+        }
+        let model = BpModel::from_bp_entities(&entities, &dict);
+        let grid = RailPoleGrid::from_model(&model);
+        let mut positions = grid.positions().collect::<Vec<_>>();
+        positions.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(positions, vec![(3.0, 0.0), (3.0, 4.0), (3.0, 8.0)]);
+
+        let pole = |name: &str, x: f64, y: f64| WorldEntity {
+            prototype: dict[name].clone(),
+            position: point2(x, y),
+            direction: 0,
+        };
+        assert!(!grid.is_off_grid(&pole("big-electric-pole", 3.0, 4.0)));
+        assert!(grid.is_off_grid(&pole("big-electric-pole", 3.0, 2.0)));
+        assert!(!grid.is_off_grid(&pole("small-electric-pole", 3.5, 2.5)));
+
+        assert!(RailPoleGrid::from_model(&BpModel::new()).is_empty());
+    }
+}
@@ -1,14 +1,14 @@
 use std::cmp::Ordering;
 use std::hash::{Hash, Hasher};
 use std::ops::Deref;
-use std::rc::Rc;
+use std::sync::Arc;
 
 #[derive(Debug)]
 #[repr(transparent)]
-pub struct RcId<T>(Rc<T>);
+pub struct RcId<T>(Arc<T>);
 impl<T> RcId<T> {
     pub fn new(value: T) -> Self {
-        RcId(Rc::new(value))
+        RcId(Arc::new(value))
     }
 }
 
@@ -20,12 +20,12 @@ impl<T> Clone for RcId<T> {
 
 impl<T> Hash for RcId<T> {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        Rc::as_ptr(&self.0).hash(state)
+        Arc::as_ptr(&self.0).hash(state)
     }
 }
 impl<T> PartialEq for RcId<T> {
     fn eq(&self, other: &Self) -> bool {
-        Rc::ptr_eq(&self.0, &other.0)
+        Arc::ptr_eq(&self.0, &other.0)
     }
 }
 impl<T> Eq for RcId<T> {}
@@ -36,7 +36,7 @@ impl<T> PartialOrd for RcId<T> {
 }
 impl<T> Ord for RcId<T> {
     fn cmp(&self, other: &Self) -> Ordering {
-        Rc::as_ptr(&self.0).cmp(&Rc::as_ptr(&other.0))
+        Arc::as_ptr(&self.0).cmp(&Arc::as_ptr(&other.0))
     }
 }
 
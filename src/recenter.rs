@@ -0,0 +1,112 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+use clap::Parser;
+use euclid::{vec2, Vector2D};
+use factorio_blueprint::objects::Blueprint;
+
+use crate::better_bp::{BlueprintEntities, BlueprintTiles};
+use crate::bp_io::{self, BlueprintFormat};
+use crate::bp_model::BpModel;
+use crate::error::OptimizerError;
+use crate::parse_tuple;
+use crate::position::MapSpace;
+use crate::prototype_data::{self, EntityPrototypeDict};
+
+#[derive(Parser, Debug)]
+pub struct RecenterArgs {
+    #[arg(
+        long,
+        default_value = "0,0",
+        help = "Position to move the center of the blueprint to. Format: 'x,y'"
+    )]
+    anchor: String,
+}
+
+/// Rails must stay on the 2x2 rail grid.
+fn needs_even_offset(model: &BpModel) -> bool {
+    model.all_entities().any(|entity| {
+        let type_ = entity.prototype.type_.as_str();
+        type_.contains("rail") || type_ == "train-stop"
+    })
+}
+
+/// Offset that moves the center of the blueprint's bounding box to `anchor`, rounded to whole tiles
+/// (or to even tiles, if there are rails).
+pub fn recenter_offset(model: &BpModel, anchor: (f64, f64)) -> Vector2D<f64, MapSpace> {
+    let center = model.get_bounding_box().to_f64().center();
+    let grid = if needs_even_offset(model) { 2.0 } else { 1.0 };
+    // round half up, so recentering again does nothing
+    let round = |x: f64| (x / grid + 0.5).floor() * grid;
+    vec2(round(anchor.0 - center.x), round(anchor.1 - center.y))
+}
+
+/// Moves all entities and tiles so the blueprint is centered at `anchor`. Returns the offset.
+pub fn recenter(
+    bp: &mut Blueprint,
+    anchor: (f64, f64),
+    dict: &EntityPrototypeDict,
+) -> Vector2D<f64, MapSpace> {
+    let mut entities = BlueprintEntities::from_blueprint(bp);
+    let model = BpModel::from_bp_entities(&entities, dict);
+    let offset = recenter_offset(&model, anchor);
+    entities.translate(offset);
+    entities.write_to_blueprint(bp);
+    let mut tiles = BlueprintTiles::from_blueprint(bp);
+    tiles.translate(offset.round().to_i32().cast_unit());
+    bp.tiles = tiles.to_blueprint_tiles();
+    offset
+}
+
+/// Recenters a blueprint, or every blueprint in a book. Writes nothing if `out_file` is None.
+/// Snap-to-grid settings are not read from or written to blueprints, so are not adjusted.
+pub fn run_recenter(
+    args: &RecenterArgs,
+    in_file: &Path,
+    out_file: Option<&Path>,
+    format: BlueprintFormat,
+) -> Result<(), OptimizerError> {
+    let anchor = parse_tuple(&args.anchor)?;
+    let dict = prototype_data::load_prototype_data()?;
+    let mut container = bp_io::decode(BufReader::new(File::open(in_file)?))?;
+    for bp in bp_io::blueprints_mut(&mut container) {
+        let offset = recenter(bp, anchor, &dict);
+        println!("Moved blueprint by ({}, {})", offset.x, offset.y);
+    }
+    match out_file {
+        Some(out_file) => {
+            bp_io::encode(BufWriter::new(File::create(out_file)?), &container, format)
+        }
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::point2;
+
+    use crate::better_bp::BlueprintEntityData;
+
+    use super::*;
+
+    #[test]
+    fn test_recenter_offset() {
+        let dict = prototype_data::load_prototype_data().unwrap();
+        let mut entities = BlueprintEntities::new();
+        for x in [1000.5, 1003.5] {
+            entities.add_entity(BlueprintEntityData::new(
+                "wooden-chest".to_string(),
+                point2(x, -500.5),
+                None,
+            ));
+        }
+        let model = BpModel::from_bp_entities(&entities, &dict);
+        assert_eq!(recenter_offset(&model, (0.0, 0.0)), vec2(-1002.0, 501.0));
+        assert_eq!(recenter_offset(&model, (10.0, 0.0)), vec2(-992.0, 501.0));
+
+        entities.translate(recenter_offset(&model, (0.0, 0.0)));
+        let model = BpModel::from_bp_entities(&entities, &dict);
+        assert_eq!(recenter_offset(&model, (0.0, 0.0)), vec2(0.0, 0.0));
+    }
+}
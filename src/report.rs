@@ -0,0 +1,276 @@
+use std::collections::BTreeMap;
+
+use serde_json::{json, Value};
+
+use crate::bp_model::BpModel;
+use crate::mip_stats::MipStats;
+
+/// Counts of entities, by prototype name.
+pub type EntityCounts = BTreeMap<String, usize>;
+
+pub fn count_by_name<'a>(names: impl IntoIterator<Item = &'a str>) -> EntityCounts {
+    let mut counts = EntityCounts::new();
+    for name in names {
+        *counts.entry(name.to_string()).or_default() += 1;
+    }
+    counts
+}
+
+pub fn count_poles(model: &BpModel) -> EntityCounts {
+    count_by_name(model.poles().map(|entity| entity.prototype.name.as_str()))
+}
+
+/// Total length of copper wire between poles, in tiles.
+pub fn wire_length(model: &BpModel) -> f64 {
+    model
+        .poles()
+        .flat_map(|pole| {
+            let (_, connections) = pole.pole_data().unwrap();
+            connections
+                .connections
+                .iter()
+                .filter(move |other| **other > pole.id())
+                .filter_map(move |other| model.get(*other))
+                .map(move |other| pole.position.distance_to(other.position))
+        })
+        .sum()
+}
+
+/// Raw materials to craft one of each vanilla pole, from its recipe, down to plates, wood, and plastic.
+const POLE_RAW_MATERIALS: &[(&str, &[(&str, f64)])] = &[
+    (
+        "small-electric-pole",
+        &[("copper-plate", 0.5), ("wood", 0.5)],
+    ),
+    (
+        "medium-electric-pole",
+        &[("copper-plate", 2.0), ("iron-plate", 12.0)],
+    ),
+    (
+        "big-electric-pole",
+        &[("copper-plate", 4.0), ("iron-plate", 29.0)],
+    ),
+    (
+        "substation",
+        &[
+            ("copper-plate", 35.0),
+            ("iron-plate", 62.0),
+            ("plastic-bar", 12.0),
+        ],
+    ),
+];
+
+/// Raw materials to craft the poles in `counts`, and names of poles with unknown recipes, e.g. modded ones.
+pub fn raw_materials(counts: &EntityCounts) -> (BTreeMap<&'static str, f64>, Vec<String>) {
+    let mut materials = BTreeMap::new();
+    let mut unknown = vec![];
+    for (name, &count) in counts {
+        match POLE_RAW_MATERIALS
+            .iter()
+            .find(|(pole, _)| *pole == name.as_str())
+        {
+            Some((_, recipe)) => {
+                for (material, amount) in *recipe {
+                    *materials.entry(*material).or_default() += amount * count as f64;
+                }
+            }
+            None => unknown.push(name.clone()),
+        }
+    }
+    (materials, unknown)
+}
+
+/// Summary of what an optimization run changed.
+#[derive(Debug, Default, Clone)]
+pub struct OptimizationReport {
+    /// Entities removed from the input before optimizing, e.g. with `--remove-entities`.
+    pub removed_entities: EntityCounts,
+    pub poles_before: EntityCounts,
+    pub poles_after: EntityCounts,
+    /// Total length of copper wire between poles, in tiles; see [wire_length].
+    pub wire_length_before: f64,
+    pub wire_length_after: f64,
+    /// Entities that need power but are not powered in the result.
+    pub unpowered_after: usize,
+    /// From the last MIP solve, if the solver was HiGHS's MIP.
+    pub mip_stats: Option<MipStats>,
+    /// If the input poles were kept, as the solution was no better, or not better by `--only-if-better`.
+    pub kept_input: bool,
+}
+
+impl OptimizationReport {
+    /// Net change in poles, e.g. "removed 10 small-electric-pole".
+    pub fn pole_changes(&self) -> Vec<String> {
+        let names = self
+            .poles_before
+            .keys()
+            .chain(self.poles_after.keys())
+            .collect::<std::collections::BTreeSet<_>>();
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let before = self.poles_before.get(name).copied().unwrap_or(0);
+                let after = self.poles_after.get(name).copied().unwrap_or(0);
+                match after.cmp(&before) {
+                    std::cmp::Ordering::Less => {
+                        Some(format!("removed {} {}", before - after, name))
+                    }
+                    std::cmp::Ordering::Greater => {
+                        Some(format!("added {} {}", after - before, name))
+                    }
+                    std::cmp::Ordering::Equal => None,
+                }
+            })
+            .collect()
+    }
+
+    /// The report as JSON, for `serve-http` and `--notify-url`.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "removed_entities": self.removed_entities,
+            "poles_before": self.poles_before,
+            "poles_after": self.poles_after,
+            "changes": self.pole_changes(),
+            "wire_length_before": self.wire_length_before,
+            "wire_length_after": self.wire_length_after,
+            "materials_before": raw_materials(&self.poles_before).0,
+            "materials_after": raw_materials(&self.poles_after).0,
+            "unpowered_after": self.unpowered_after,
+            "kept_input": self.kept_input,
+        })
+    }
+
+    pub fn print(&self) {
+        if !self.removed_entities.is_empty() {
+            println!("Removed entities:");
+            for (name, count) in &self.removed_entities {
+                println!("  {:>6} {}", count, name);
+            }
+        }
+        println!("Poles:");
+        let names = self
+            .poles_before
+            .keys()
+            .chain(self.poles_after.keys())
+            .collect::<std::collections::BTreeSet<_>>();
+        for name in names {
+            let before = self.poles_before.get(name).copied().unwrap_or(0);
+            let after = self.poles_after.get(name).copied().unwrap_or(0);
+            println!("  {:>6} -> {:<6} {}", before, after, name);
+        }
+        println!("Pole types used: {}", self.poles_after.len());
+        let changes = self.pole_changes();
+        if !changes.is_empty() {
+            println!("Net change: {}", changes.join(", "));
+        }
+        println!(
+            "Copper wire: {:.1} -> {:.1} tiles (saved {:.1})",
+            self.wire_length_before,
+            self.wire_length_after,
+            self.wire_length_before - self.wire_length_after
+        );
+        self.print_materials();
+        if self.unpowered_after > 0 {
+            println!("{} entities are unpowered", self.unpowered_after);
+        }
+        if let Some(mip_stats) = &self.mip_stats {
+            mip_stats.print();
+        }
+    }
+}
+
+impl OptimizationReport {
+    /// Raw materials of the poles before and after, and what was saved.
+    fn print_materials(&self) {
+        let (before, unknown_before) = raw_materials(&self.poles_before);
+        let (after, unknown_after) = raw_materials(&self.poles_after);
+        let names = before
+            .keys()
+            .chain(after.keys())
+            .collect::<std::collections::BTreeSet<_>>();
+        if names.is_empty() {
+            return;
+        }
+        println!("Pole materials:");
+        for name in names {
+            let before = before.get(name).copied().unwrap_or(0.0);
+            let after = after.get(name).copied().unwrap_or(0.0);
+            println!(
+                "  {:>8.1} -> {:<8.1} {} (saved {:.1})",
+                before,
+                after,
+                name,
+                before - after
+            );
+        }
+        let unknown = unknown_before
+            .into_iter()
+            .chain(unknown_after)
+            .collect::<std::collections::BTreeSet<_>>();
+        if !unknown.is_empty() {
+            println!(
+                "  not counting poles with unknown recipes: {}",
+                unknown.into_iter().collect::<Vec<_>>().join(", ")
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::point2;
+
+    use super::*;
+
+    #[test]
+    fn test_count_poles() {
+        let mut model = BpModel::new();
+        model.add_test_poles(&[point2(0, 0), point2(3, 0)]);
+        model.add_test_powerable(point2(1, 0));
+        let counts = count_poles(&model);
+        assert_eq!(counts, EntityCounts::from([("test".to_string(), 2)]));
+    }
+
+    #[test]
+    fn test_wire_length() {
+        let mut model = BpModel::new();
+        let poles = model.add_test_poles(&[point2(0, 0), point2(3, 4), point2(6, 0)]);
+        assert_eq!(wire_length(&model), 0.0);
+        model.add_cable_connection(poles[0], poles[1]).unwrap();
+        model.add_cable_connection(poles[1], poles[2]).unwrap();
+        assert!((wire_length(&model) - 10.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_raw_materials() {
+        let (materials, unknown) = raw_materials(&EntityCounts::from([
+            ("small-electric-pole".to_string(), 4),
+            ("medium-electric-pole".to_string(), 1),
+            ("modded-pole".to_string(), 2),
+        ]));
+        assert_eq!(
+            materials,
+            BTreeMap::from([("copper-plate", 4.0), ("iron-plate", 12.0), ("wood", 2.0)])
+        );
+        assert_eq!(unknown, ["modded-pole"]);
+    }
+
+    #[test]
+    fn test_pole_changes() {
+        let report = OptimizationReport {
+            poles_before: EntityCounts::from([
+                ("small".to_string(), 10),
+                ("medium".to_string(), 2),
+            ]),
+            poles_after: EntityCounts::from([
+                ("small".to_string(), 4),
+                ("substation".to_string(), 3),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(
+            report.pole_changes(),
+            ["removed 2 medium", "removed 6 small", "added 3 substation"]
+        );
+    }
+}
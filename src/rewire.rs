@@ -0,0 +1,119 @@
+use clap::Parser;
+use factorio_blueprint::objects::Blueprint;
+use log::warn;
+
+use crate::algorithms::WireAxis;
+use crate::better_bp::BlueprintEntities;
+use crate::bp_model::BpModel;
+use crate::circuit::CarryCircuit;
+use crate::error::OptimizerError;
+use crate::pipeline::{self, PipelineState};
+use crate::prototype_data;
+use crate::OptimizePoles;
+
+#[derive(Parser, Debug)]
+pub struct RewireArgs {
+    #[arg(
+        long,
+        value_enum,
+        default_value = "auto",
+        help = "Prefer wires between poles to run along this axis. By default, the longer side of the blueprint, if it is at least twice as long as the other"
+    )]
+    wire_axis: WireAxis,
+
+    #[arg(
+        long,
+        help = "Keep the style of the current wires: as many wires per pole, and only horizontal and vertical wires if it has almost only those"
+    )]
+    match_wire_style: bool,
+
+    #[arg(
+        long,
+        help = "Make the longest wire as short as it can be while still connecting the poles, then the total length as usual"
+    )]
+    minimize_longest_wire: bool,
+}
+
+/// The `optimize` options for `rewire`: the input poles are the only candidates, all kept,
+/// with their circuit wires carried over.
+fn optimize_args(
+    allow_unpowered: bool,
+    args: &RewireArgs,
+) -> Result<OptimizePoles, OptimizerError> {
+    let mut opt =
+        OptimizePoles::try_parse_from(["optimize", "--only-input-poles", "--no-connectivity"])
+            .map_err(|err| OptimizerError::from(err.to_string()))?;
+    opt.allow_unpowered = allow_unpowered;
+    opt.carry_circuit = Some(CarryCircuit::Both);
+    opt.wire_axis = args.wire_axis;
+    opt.match_wire_style = args.match_wire_style;
+    opt.minimize_longest_wire = args.minimize_longest_wire;
+    Ok(opt)
+}
+
+/// Replaces the copper wires between the poles of `bp` with new ones from the connector,
+/// without adding, moving, or removing any pole. Returns None if there are no poles.
+pub fn run_rewire(
+    bp: Blueprint,
+    args: &RewireArgs,
+) -> Result<Option<PipelineState>, OptimizerError> {
+    let dict = prototype_data::load_prototype_data()?;
+    let model = BpModel::from_bp_entities(&BlueprintEntities::from_blueprint(&bp), &dict);
+    if model.poles().next().is_none() {
+        println!("Nothing to rewire: the blueprint has no poles");
+        return Ok(None);
+    }
+    let networks = model.count_pole_networks();
+    if networks > 1 {
+        println!(
+            "Note: the poles form {} separate networks; any in wire reach of each other will be joined",
+            networks
+        );
+    }
+    let unpowered = model.unpowered_entities().count();
+    if unpowered > 0 {
+        warn!(
+            "{} entities are unpowered in the input, and will stay unpowered",
+            unpowered
+        );
+    }
+    let opt = optimize_args(unpowered > 0, args)?;
+    pipeline::run_rewire_pipeline(bp, &opt).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use itertools::Itertools;
+
+    use super::*;
+    use crate::output_book::{is_pole, pole_key};
+
+    #[test]
+    fn test_rewire_keeps_poles() {
+        let bp = crate::read_blueprint(&PathBuf::from("test-data/bigtest.txt")).unwrap();
+        let dict = prototype_data::load_prototype_data().unwrap();
+        let poles = |entities: &BlueprintEntities| {
+            entities
+                .entities
+                .values()
+                .filter(|entity| is_pole(&dict, entity))
+                .map(pole_key)
+                .sorted()
+                .collect_vec()
+        };
+        let input = poles(&BlueprintEntities::from_blueprint(&bp));
+        let input_model = BpModel::from_bp_entities(&BlueprintEntities::from_blueprint(&bp), &dict);
+
+        let args = RewireArgs::try_parse_from(["rewire"]).unwrap();
+        let state = run_rewire(bp, &args).unwrap().unwrap();
+        assert_eq!(poles(&state.entities), input);
+        // the input leaves some entities unpowered, and poles don't move
+        assert_eq!(
+            state.report.unpowered_after,
+            input_model.unpowered_entities().count()
+        );
+        assert!(state.model.count_pole_networks() <= input_model.count_pole_networks());
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use factorio_blueprint::objects::Blueprint;
+use itertools::Itertools;
+use serde_json::Value;
+
+use crate::error::OptimizerError;
+use crate::prototype_data::EntityPrototypeDict;
+use crate::{bp_io, read_container};
+
+/// Most differences listed in the error.
+const MAX_LISTED: usize = 5;
+
+/// An entity's name and position, which identify it across renumbering.
+fn entity_key(entity: &Value) -> String {
+    format!(
+        "{} at ({}, {})",
+        entity["name"].as_str().unwrap_or_default(),
+        entity["position"]["x"],
+        entity["position"]["y"]
+    )
+}
+
+fn is_empty(value: &Value) -> bool {
+    match value {
+        Value::Array(items) => items.is_empty(),
+        Value::Object(map) => map.is_empty(),
+        _ => false,
+    }
+}
+
+/// Replaces entity numbers in wire connections with entity keys, drops connections to `skipped`
+/// and the default `circuit_id` of 1, and sorts lists, so connections compare equal regardless of
+/// numbering and order.
+fn canonical_connections(value: &mut Value, keys: &HashMap<u64, String>, skipped: &HashSet<u64>) {
+    match value {
+        Value::Object(map) => {
+            if let Some(key) = map
+                .get("entity_id")
+                .and_then(Value::as_u64)
+                .and_then(|id| keys.get(&id))
+            {
+                map.insert("entity_id".to_string(), Value::String(key.clone()));
+            }
+            if map.get("circuit_id").and_then(Value::as_u64) == Some(1) {
+                map.remove("circuit_id");
+            }
+            for value in map.values_mut() {
+                canonical_connections(value, keys, skipped);
+            }
+            map.retain(|_, value| !is_empty(value));
+        }
+        Value::Array(items) => {
+            items.retain(|item| {
+                !item
+                    .get("entity_id")
+                    .and_then(Value::as_u64)
+                    .is_some_and(|id| skipped.contains(&id))
+            });
+            for item in items.iter_mut() {
+                canonical_connections(item, keys, skipped);
+            }
+            items.sort_by_cached_key(Value::to_string);
+        }
+        _ => {}
+    }
+}
+
+/// If `entity` JSON is a pole.
+fn is_pole(entity: &Value, dict: &EntityPrototypeDict) -> bool {
+    entity["name"]
+        .as_str()
+        .and_then(|name| dict.0.get(name))
+        .is_some_and(|prototype| prototype.is_pole())
+}
+
+/// The entities not matching `skip` as sorted JSON strings, without entity numbers, cable connections,
+/// or wire connections to skipped entities, and the rest of the blueprint, with schedule locomotives as entity keys.
+/// Two blueprints with the same entities in a different order or numbering give the same result.
+pub fn canonical(
+    bp: &Blueprint,
+    skip: impl Fn(&Value) -> bool,
+) -> Result<(Vec<String>, Value), OptimizerError> {
+    let mut value = serde_json::to_value(bp)?;
+    let Some(Value::Array(entities)) = value.as_object_mut().and_then(|bp| bp.remove("entities"))
+    else {
+        return Ok((vec![], value));
+    };
+    let number = |entity: &Value| entity["entity_number"].as_u64().unwrap_or_default();
+    let keys = entities
+        .iter()
+        .map(|entity| (number(entity), entity_key(entity)))
+        .collect::<HashMap<_, _>>();
+    let skipped = entities
+        .iter()
+        .filter(|entity| skip(entity))
+        .map(number)
+        .collect::<HashSet<_>>();
+
+    let canonical_entities = entities
+        .into_iter()
+        .filter(|entity| !skip(entity))
+        .map(|mut entity| {
+            if let Some(entity) = entity.as_object_mut() {
+                entity.remove("entity_number");
+                entity.remove("neighbours");
+                if let Some(connections) = entity.get_mut("connections") {
+                    canonical_connections(connections, &keys, &skipped);
+                    if is_empty(connections) {
+                        entity.remove("connections");
+                    }
+                }
+            }
+            entity.to_string()
+        })
+        .sorted()
+        .collect();
+
+    if let Some(Value::Array(schedules)) = value.get_mut("schedules") {
+        for schedule in schedules {
+            if let Some(Value::Array(locomotives)) = schedule.get_mut("locomotives") {
+                for locomotive in locomotives {
+                    if let Some(key) = locomotive.as_u64().and_then(|id| keys.get(&id)) {
+                        *locomotive = Value::String(key.clone());
+                    }
+                }
+            }
+        }
+    }
+    Ok((canonical_entities, value))
+}
+
+/// Describes how `output` differs from `input` other than in poles, or None if it doesn't.
+pub fn differences(
+    input: &Blueprint,
+    output: &Blueprint,
+    dict: &EntityPrototypeDict,
+) -> Result<Option<String>, OptimizerError> {
+    let (input_entities, input_rest) = canonical(input, |entity| is_pole(entity, dict))?;
+    let (output_entities, output_rest) = canonical(output, |entity| is_pole(entity, dict))?;
+    let mut problems = vec![];
+    if input_entities != output_entities {
+        let output_set = output_entities.iter().collect::<HashSet<_>>();
+        let input_set = input_entities.iter().collect::<HashSet<_>>();
+        let describe = |entity: &String| {
+            serde_json::from_str::<Value>(entity)
+                .map_or_else(|_| entity.clone(), |v| entity_key(&v))
+        };
+        let changed = input_entities
+            .iter()
+            .filter(|entity| !output_set.contains(entity))
+            .map(|entity| format!("{} changed or removed", describe(entity)))
+            .chain(
+                output_entities
+                    .iter()
+                    .filter(|entity| !input_set.contains(entity))
+                    .map(|entity| format!("{} changed or added", describe(entity))),
+            )
+            .collect_vec();
+        problems.extend(changed.iter().take(MAX_LISTED).cloned());
+        if changed.len() > MAX_LISTED {
+            problems.push(format!("and {} more", changed.len() - MAX_LISTED));
+        }
+    }
+    if input_rest != output_rest {
+        let (Value::Object(input_rest), Value::Object(output_rest)) = (&input_rest, &output_rest)
+        else {
+            unreachable!("blueprints serialize as objects");
+        };
+        let fields = input_rest
+            .keys()
+            .chain(output_rest.keys())
+            .unique()
+            .filter(|key| input_rest.get(*key) != output_rest.get(*key))
+            .join(", ");
+        problems.push(format!("blueprint fields changed: {}", fields));
+    }
+    Ok((!problems.is_empty()).then(|| problems.join("; ")))
+}
+
+/// For `--verify-roundtrip`: decodes the written `out_file` again, and fails if its blueprints differ from
+/// `inputs` in anything other than poles. `skip` is the number of blueprints in the output before the ones to compare.
+pub fn verify_roundtrip(
+    inputs: &[Blueprint],
+    out_file: &Path,
+    skip: usize,
+    dict: &EntityPrototypeDict,
+) -> Result<(), OptimizerError> {
+    let mut output = read_container(&out_file.to_path_buf())?;
+    let outputs = bp_io::blueprints_mut(&mut output);
+    if outputs.len() < skip + inputs.len() {
+        return Err(OptimizerError::RoundtripMismatch(format!(
+            "expected {} blueprints in the output, found {}",
+            skip + inputs.len(),
+            outputs.len()
+        )));
+    }
+    for (i, (input, output)) in inputs.iter().zip(&outputs[skip..]).enumerate() {
+        if let Some(problems) = differences(input, output, dict)? {
+            let which = if inputs.len() > 1 {
+                format!("blueprint {}: ", i + 1)
+            } else {
+                String::new()
+            };
+            return Err(OptimizerError::RoundtripMismatch(which + &problems));
+        }
+    }
+    println!("Verified that only poles changed");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::better_bp::{BlueprintEntities, BlueprintEntityData};
+    use crate::position::MapPosition;
+    use crate::{prototype_data, read_blueprint};
+
+    use super::*;
+
+    #[test]
+    fn test_differences() {
+        let dict = prototype_data::load_prototype_data().unwrap();
+        let input = read_blueprint(&PathBuf::from("test-data/bigtest.txt")).unwrap();
+        let is_pole = |entity: &BlueprintEntityData| dict[entity.name.as_str()].is_pole();
+
+        // replacing poles, and renumbering, is fine
+        let mut entities = BlueprintEntities::from_blueprint(&input);
+        entities.retain(|entity| !is_pole(entity));
+        entities.add_entity(BlueprintEntityData::new(
+            "small-electric-pole".to_string(),
+            MapPosition::new(1000.5, 1000.5),
+            None,
+        ));
+        let mut output = input.clone();
+        entities.write_to_blueprint(&mut output);
+        assert_eq!(differences(&input, &output, &dict).unwrap(), None);
+
+        // removing anything else isn't
+        let mut removed_one = false;
+        entities.retain(|entity| is_pole(entity) || std::mem::replace(&mut removed_one, true));
+        entities.write_to_blueprint(&mut output);
+        let problems = differences(&input, &output, &dict).unwrap().unwrap();
+        assert!(problems.contains("changed or removed"), "{}", problems);
+    }
+}
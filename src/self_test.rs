@@ -0,0 +1,185 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::PathBuf;
+
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+
+use crate::bp_model::BpModel;
+use crate::error::OptimizerError;
+use crate::report::{count_poles, EntityCounts};
+use crate::{optimize_poles, read_blueprint, OptimizePoles};
+
+static GOLDEN_DIR: &str = "test-data/golden";
+
+struct SelfTestCase {
+    name: &'static str,
+    input: &'static str,
+    /// Arguments to the optimize command.
+    args: &'static [&'static str],
+}
+
+static SELF_TEST_CASES: &[SelfTestCase] = &[
+    SelfTestCase {
+        name: "bigtest",
+        input: "test-data/bigtest.txt",
+        args: &["s", "-q", "-t", "60"],
+    },
+    SelfTestCase {
+        name: "bigtest_no_connectivity",
+        input: "test-data/bigtest.txt",
+        args: &["s", "-q", "-t", "60", "--no-connectivity"],
+    },
+];
+
+#[derive(Parser, Debug)]
+pub struct SelfTestArgs {
+    #[arg(
+        long,
+        help = "Overwrite the golden files with the current results, instead of comparing against them",
+        action = clap::ArgAction::SetTrue
+    )]
+    bless: bool,
+
+    #[arg(help = "Only run test cases with these names")]
+    cases: Vec<String>,
+}
+
+/// Structural invariants of an optimization result, compared against golden files.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Snapshot {
+    poles: EntityCounts,
+    unpowered_entities: usize,
+    pole_networks: usize,
+}
+
+impl Snapshot {
+    fn of_model(model: &BpModel) -> Self {
+        Snapshot {
+            poles: count_poles(model),
+            unpowered_entities: model.unpowered_entities().count(),
+            pole_networks: model.count_pole_networks(),
+        }
+    }
+
+    fn total_poles(&self) -> usize {
+        self.poles.values().sum()
+    }
+
+    /// Returns a list of regressions compared to the golden snapshot.
+    /// Using fewer poles than the golden snapshot is not a regression.
+    fn regressions(&self, golden: &Snapshot) -> Vec<String> {
+        let mut result = vec![];
+        if self.unpowered_entities > golden.unpowered_entities {
+            result.push(format!(
+                "{} unpowered entities, expected {}",
+                self.unpowered_entities, golden.unpowered_entities
+            ));
+        }
+        if self.pole_networks > golden.pole_networks {
+            result.push(format!(
+                "{} separate pole networks, expected {}",
+                self.pole_networks, golden.pole_networks
+            ));
+        }
+        if self.total_poles() > golden.total_poles() {
+            result.push(format!(
+                "{} poles ({:?}), expected at most {} ({:?})",
+                self.total_poles(),
+                self.poles,
+                golden.total_poles(),
+                golden.poles
+            ));
+        }
+        result
+    }
+}
+
+fn golden_path(case: &SelfTestCase) -> PathBuf {
+    PathBuf::from(GOLDEN_DIR).join(format!("{}.json", case.name))
+}
+
+fn run_case(case: &SelfTestCase) -> Result<Snapshot, OptimizerError> {
+    let bp = read_blueprint(&PathBuf::from(case.input))?;
+    let args =
+        OptimizePoles::try_parse_from(std::iter::once("optimize").chain(case.args.iter().copied()))
+            .map_err(|err| err.to_string())?;
+    let result = optimize_poles(bp, &args)?;
+    Ok(Snapshot::of_model(&result.model))
+}
+
+/// Runs the full pipeline on the bundled blueprints, and compares the results to golden files.
+pub fn run_self_test(args: &SelfTestArgs) -> Result<(), OptimizerError> {
+    let mut failed = vec![];
+    for case in SELF_TEST_CASES {
+        if !args.cases.is_empty() && !args.cases.iter().any(|name| name == case.name) {
+            continue;
+        }
+        println!("Running self test: {}", case.name);
+        let snapshot = run_case(case)?;
+        let path = golden_path(case);
+        if args.bless {
+            std::fs::create_dir_all(GOLDEN_DIR)?;
+            serde_json::to_writer_pretty(BufWriter::new(File::create(&path)?), &snapshot)?;
+            println!("  wrote {:?}", path);
+            continue;
+        }
+        let Ok(file) = File::open(&path) else {
+            println!(
+                "  missing golden file {:?}; run with --bless to create it",
+                path
+            );
+            failed.push(case.name);
+            continue;
+        };
+        let golden: Snapshot = serde_json::from_reader(BufReader::new(file))?;
+        let regressions = snapshot.regressions(&golden);
+        if regressions.is_empty() {
+            if snapshot.total_poles() < golden.total_poles() {
+                println!(
+                    "  ok; improved from {} to {} poles, consider running with --bless",
+                    golden.total_poles(),
+                    snapshot.total_poles()
+                );
+            } else {
+                println!("  ok");
+            }
+        } else {
+            for regression in regressions {
+                println!("  FAILED: {}", regression);
+            }
+            failed.push(case.name);
+        }
+    }
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("Self test failed: {}", failed.join(", ")).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_regressions() {
+        let golden = Snapshot {
+            poles: EntityCounts::from([("small-electric-pole".to_string(), 10)]),
+            unpowered_entities: 0,
+            pole_networks: 1,
+        };
+        let better = Snapshot {
+            poles: EntityCounts::from([("small-electric-pole".to_string(), 9)]),
+            ..golden
+        };
+        assert!(better.regressions(&golden).is_empty());
+
+        let worse = Snapshot {
+            poles: EntityCounts::from([("small-electric-pole".to_string(), 11)]),
+            unpowered_entities: 1,
+            pole_networks: 2,
+        };
+        assert_eq!(worse.regressions(&golden).len(), 3);
+    }
+}
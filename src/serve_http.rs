@@ -0,0 +1,628 @@
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser};
+use factorio_blueprint::Container;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::bp_io::{self, BlueprintFormat};
+use crate::cancel::CancellationToken;
+use crate::error::OptimizerError;
+use crate::{better_bp, pipeline, position, presets, prototype_data, OptimizePoles};
+
+#[derive(Parser, Debug)]
+pub struct ServeHttpArgs {
+    #[arg(long, default_value_t = 8080, help = "Port to listen on")]
+    port: u16,
+
+    #[arg(
+        long,
+        default_value = "127.0.0.1",
+        help = "Address to listen on; use 0.0.0.0 to accept connections from other machines"
+    )]
+    host: String,
+
+    #[arg(
+        long,
+        default_value_t = 2,
+        help = "Most requests to optimize at once; others get 503 Service Unavailable"
+    )]
+    max_concurrent: usize,
+
+    #[arg(
+        long,
+        default_value_t = 64,
+        help = "Most connections open at once, including ones still sending their request; others are closed with 503 Service Unavailable"
+    )]
+    max_connections: usize,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 60.0,
+        help = "Time budget of each request, like --budget; a request's own --budget or --time-limit may be lower"
+    )]
+    max_time: f64,
+}
+
+/// Largest request body accepted, in bytes.
+const MAX_BODY_BYTES: usize = 16 << 20;
+/// Largest request line and headers accepted, in bytes, all together.
+const MAX_HEADER_BYTES: usize = 16 << 10;
+/// How long a client has to send its whole request.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often to check if the client is still connected while optimizing.
+const DISCONNECT_POLL: Duration = Duration::from_millis(200);
+
+/// Options of `optimize` that can be given over HTTP, by their long name.
+/// Any others may read or write files on the server, or need more than one blueprint in and out.
+const SUPPORTED_OPTIONS: &[&str] = &[
+    "preset",
+    "auto-poles",
+    "only-input-poles",
+    "remove-entities",
+    "replace-in-place",
+    "treat-as-obstacle",
+    "treat-as-free",
+    "only-if-better",
+    "certificate",
+    "keep-input-poles",
+    "keep-poles-in",
+    "connect-kept-poles",
+    "allow-landfill",
+    "landfill-cost",
+    "avoid-tiles",
+    "assume-powered-border",
+    "pole-costs",
+    "carry-circuit",
+    "max-count",
+    "max-pole-types",
+    "max-load",
+    "max-load-mw",
+    "remove-empty-poles",
+    "expand",
+    "allow-unpowered",
+    "priority",
+    "unpowered-cost",
+    "no-connectivity",
+    "no-verify",
+    "max-hops",
+    "anchor",
+    "center-pos",
+    "center",
+    "roots",
+    "root-spacing",
+    "distance-cost",
+    "distance-metric",
+    "chunk-align",
+    "chunk-period",
+    "chunk-offset",
+    "rail-poles",
+    "time-limit",
+    "mip-rel-gap",
+    "mip-abs-gap",
+    "lazy-constraints",
+    "quiet",
+    "solver",
+    "rounding-rounds",
+    "columns-per-round",
+    "split-size",
+    "split-overlap",
+    "seed",
+    "polish",
+    "budget",
+    "trunk",
+    "wire-axis",
+    "match-wire-style",
+    "minimize-longest-wire",
+];
+
+/// Body of `POST /optimize`, e.g. `{"blueprint": "0eNq...", "options": {"poles": ["m"], "max-count": "m=10"}}`.
+/// Options are named like the command line options of `optimize`, without dashes in front;
+/// `poles` are the pole types, and `true` turns on a flag.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct OptimizeRequest {
+    blueprint: String,
+    #[serde(default)]
+    options: Map<String, Value>,
+}
+
+/// An HTTP error response.
+#[derive(Debug)]
+struct HttpError {
+    status: u16,
+    message: String,
+}
+
+impl HttpError {
+    fn new(status: u16, message: impl ToString) -> Self {
+        HttpError {
+            status,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<OptimizerError> for HttpError {
+    fn from(err: OptimizerError) -> Self {
+        let status = match err {
+            OptimizerError::Decode(_)
+            | OptimizerError::Json(_)
+            | OptimizerError::UnknownPrototype(_) => 400,
+            OptimizerError::Infeasible(_) | OptimizerError::SolverTimeout { .. } => 422,
+            _ => 500,
+        };
+        HttpError::new(status, err)
+    }
+}
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+}
+
+/// Reads from a stream until a deadline, however slowly the other end sends.
+struct DeadlineReader {
+    stream: TcpStream,
+    deadline: Instant,
+}
+
+impl Read for DeadlineReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(ErrorKind::TimedOut.into());
+        }
+        self.stream.set_read_timeout(Some(remaining))?;
+        self.stream.read(buf)
+    }
+}
+
+fn read_error(err: std::io::Error) -> HttpError {
+    match err.kind() {
+        ErrorKind::TimedOut | ErrorKind::WouldBlock => HttpError::new(
+            408,
+            format!("Request not received within {:?}", READ_TIMEOUT),
+        ),
+        _ => HttpError::new(400, err),
+    }
+}
+
+/// Reads a line into `line`, counting it against the `remaining` bytes allowed for the request line and headers.
+fn read_header_line(
+    reader: &mut impl BufRead,
+    line: &mut String,
+    remaining: &mut usize,
+) -> Result<(), HttpError> {
+    line.clear();
+    let read = reader
+        .by_ref()
+        .take(*remaining as u64)
+        .read_line(line)
+        .map_err(read_error)?;
+    *remaining -= read;
+    if !line.ends_with('\n') && *remaining == 0 {
+        return Err(HttpError::new(
+            431,
+            format!(
+                "Request line and headers are larger than {} bytes",
+                MAX_HEADER_BYTES
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Reads a request line, headers, and a body of `Content-Length` bytes.
+fn read_request(reader: &mut impl BufRead) -> Result<HttpRequest, HttpError> {
+    let mut line = String::new();
+    let mut remaining = MAX_HEADER_BYTES;
+    read_header_line(reader, &mut line, &mut remaining)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(HttpError::new(400, "Malformed request line"));
+    };
+    let (method, path) = (method.to_string(), path.to_string());
+
+    let mut content_length = 0;
+    loop {
+        read_header_line(reader, &mut line, &mut remaining)?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| HttpError::new(400, "Invalid Content-Length"))?;
+            }
+        }
+    }
+    if content_length > MAX_BODY_BYTES {
+        return Err(HttpError::new(
+            413,
+            format!("Body is larger than {} bytes", MAX_BODY_BYTES),
+        ));
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).map_err(read_error)?;
+    Ok(HttpRequest { method, path, body })
+}
+
+fn write_response(writer: &mut impl Write, status: u16, body: &Value) -> std::io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        413 => "Payload Too Large",
+        422 => "Unprocessable Entity",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    };
+    let body = body.to_string();
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        body.len(),
+        body
+    )?;
+    writer.flush()
+}
+
+/// Answers a connection over `--max-connections` with 503, without waiting for its request.
+fn reject_connection(mut stream: TcpStream) {
+    println!("Warning: too many connections; rejecting one");
+    // the response fits in the send buffer, so this shouldn't block the accepting thread
+    let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+    let body = json!({ "error": "Too many connections; try again later" });
+    let _ = write_response(&mut stream, 503, &body);
+}
+
+/// Command line arguments for `optimize` equivalent to `options`.
+fn option_args(options: &Map<String, Value>) -> Result<Vec<String>, HttpError> {
+    let value_str = |key: &str, value: &Value| match value {
+        Value::String(value) => Ok(value.clone()),
+        Value::Number(value) => Ok(value.to_string()),
+        _ => Err(HttpError::new(
+            400,
+            format!("Option '{}' must be a string or number", key),
+        )),
+    };
+    let mut poles = vec![];
+    let mut args = vec![];
+    for (key, value) in options {
+        // a key with '=' could smuggle in another option's value, e.g. 'cache-dir=/x'
+        if key != "poles" && (key.contains('=') || !SUPPORTED_OPTIONS.contains(&key.as_str())) {
+            return Err(HttpError::new(
+                400,
+                format!("Option '{}' is not supported over HTTP", key),
+            ));
+        }
+        let values: Vec<&Value> = match value {
+            Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            match (key.as_str(), value) {
+                ("poles", value) => {
+                    let pole = value_str(key, value)?;
+                    // would be parsed as an option instead
+                    if pole.starts_with('-') {
+                        return Err(HttpError::new(400, format!("Invalid pole type '{}'", pole)));
+                    }
+                    poles.push(pole);
+                }
+                (_, Value::Bool(true)) => args.push(format!("--{}", key)),
+                (_, Value::Bool(false)) => {}
+                (_, value) => args.push(format!("--{}={}", key, value_str(key, value)?)),
+            }
+        }
+    }
+    poles.extend(args);
+    Ok(poles)
+}
+
+/// Checks that only [SUPPORTED_OPTIONS] were given, in case [option_args] let another one through.
+fn check_parsed_options(matches: &ArgMatches) -> Result<(), HttpError> {
+    let command = OptimizePoles::command();
+    let unsupported = command.get_arguments().find(|arg| {
+        arg.get_long()
+            .is_some_and(|long| !SUPPORTED_OPTIONS.contains(&long))
+            && matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+    });
+    match unsupported {
+        Some(arg) => Err(HttpError::new(
+            400,
+            format!(
+                "Option '{}' is not supported over HTTP",
+                arg.get_long().unwrap()
+            ),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Parses `options` like the `optimize` command line, applying `preset` if given,
+/// and limits the time to `max_time` seconds.
+fn parse_options(options: &Map<String, Value>, max_time: f64) -> Result<OptimizePoles, HttpError> {
+    let matches = OptimizePoles::command()
+        .try_get_matches_from(std::iter::once("optimize".to_string()).chain(option_args(options)?))
+        .map_err(|err| HttpError::new(400, err.render()))?;
+    check_parsed_options(&matches)?;
+    let mut opt =
+        OptimizePoles::from_arg_matches(&matches).map_err(|err| HttpError::new(400, err))?;
+    presets::apply_preset(&mut opt, &matches)?;
+    // progress bars would only go to the server's console
+    opt.quiet = true;
+    opt.budget = Some(opt.budget.map_or(max_time, |budget| budget.min(max_time)));
+    opt.time_limit = opt.time_limit.min(max_time);
+    Ok(opt)
+}
+
+/// Cancels `cancel` if the client closes the connection before `done` is set,
+/// as no one would get the result.
+fn cancel_on_disconnect(stream: &TcpStream, cancel: &CancellationToken, done: &AtomicBool) {
+    if stream.set_read_timeout(Some(DISCONNECT_POLL)).is_err() {
+        return;
+    }
+    while !done.load(Ordering::SeqCst) {
+        match stream.peek(&mut [0]) {
+            Ok(0) => break,
+            // sent more than the request; it may still be waiting for the response
+            Ok(_) => std::thread::sleep(DISCONNECT_POLL),
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(_) => break,
+        }
+    }
+    if !done.load(Ordering::SeqCst) {
+        println!("Client disconnected; stopping");
+        cancel.cancel();
+    }
+}
+
+/// Handles `POST /optimize`: returns the optimized blueprint string and the report.
+/// Stops early if the client disconnects from `stream`.
+fn optimize(body: &[u8], args: &ServeHttpArgs, stream: &TcpStream) -> Result<Value, HttpError> {
+    let request: OptimizeRequest =
+        serde_json::from_slice(body).map_err(|err| HttpError::new(400, err))?;
+    let opt = parse_options(&request.options, args.max_time)?;
+    let Container::Blueprint(bp) = bp_io::decode(request.blueprint.as_bytes())? else {
+        return Err(HttpError::new(
+            400,
+            "Only single blueprints are supported, not books",
+        ));
+    };
+    let cancel = CancellationToken::new();
+    let done = AtomicBool::new(false);
+    let state = std::thread::scope(|scope| {
+        scope.spawn(|| cancel_on_disconnect(stream, &cancel, &done));
+        let result = pipeline::run_cancellable_optimize_pipeline(bp, &opt, cancel.clone());
+        done.store(true, Ordering::SeqCst);
+        result
+    })?;
+    let mut encoded = vec![];
+    bp_io::encode(
+        &mut encoded,
+        &Container::Blueprint(state.blueprint),
+        BlueprintFormat::String,
+    )?;
+    Ok(json!({
+        "blueprint": String::from_utf8_lossy(&encoded),
+        "report": state.report.to_json(),
+    }))
+}
+
+/// Decrements the number of active requests or connections when dropped, even if handling them panics.
+struct Active<'a>(&'a AtomicUsize);
+
+impl Drop for Active<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn handle_request(
+    request: &HttpRequest,
+    args: &ServeHttpArgs,
+    active: &AtomicUsize,
+    stream: &TcpStream,
+) -> Result<Value, HttpError> {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("POST", "/optimize") => {}
+        (_, "/optimize") => return Err(HttpError::new(405, "Use POST /optimize")),
+        _ => return Err(HttpError::new(404, "Not found; use POST /optimize")),
+    }
+    active
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+            (count < args.max_concurrent).then_some(count + 1)
+        })
+        .map_err(|_| HttpError::new(503, "Too many requests being optimized; try again later"))?;
+    let _active = Active(active);
+    optimize(&request.body, args, stream)
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    args: &ServeHttpArgs,
+    active: &AtomicUsize,
+) -> std::io::Result<()> {
+    let mut reader = BufReader::new(DeadlineReader {
+        stream: stream.try_clone()?,
+        deadline: Instant::now() + READ_TIMEOUT,
+    });
+    let mut writer = stream;
+    let result = read_request(&mut reader).and_then(|request| {
+        println!("{} {}", request.method, request.path);
+        handle_request(&request, args, active, &writer)
+    });
+    match result {
+        Ok(body) => write_response(&mut writer, 200, &body),
+        Err(err) => {
+            println!("Request failed ({}): {}", err.status, err.message);
+            write_response(&mut writer, err.status, &json!({ "error": err.message }))
+        }
+    }
+}
+
+/// Serves `POST /optimize` over HTTP until the process is stopped.
+/// Each connection gets its own thread, up to `--max-connections`.
+pub fn run_serve_http(args: &ServeHttpArgs) -> Result<(), OptimizerError> {
+    if args.max_concurrent == 0 || args.max_connections == 0 || args.max_time <= 0.0 {
+        return Err("--max-concurrent, --max-connections and --max-time must be positive".into());
+    }
+    let listener = TcpListener::bind((args.host.as_str(), args.port))?;
+    println!("Listening on http://{}:{}", args.host, args.port);
+    let active = AtomicUsize::new(0);
+    let connections = AtomicUsize::new(0);
+    // settings are per thread
+    let mod_pack = prototype_data::mod_pack();
+    let stable_numbering = better_bp::stable_numbering();
+    let exact_game_math = position::exact_game_math();
+    std::thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    println!("Warning: failed to accept connection: {}", err);
+                    continue;
+                }
+            };
+            if connections.fetch_add(1, Ordering::SeqCst) >= args.max_connections {
+                connections.fetch_sub(1, Ordering::SeqCst);
+                reject_connection(stream);
+                continue;
+            }
+            let connection = Active(&connections);
+            let active = &active;
+            scope.spawn(move || {
+                let _connection = connection;
+                better_bp::use_stable_numbering(stable_numbering);
+                position::use_exact_game_math(exact_game_math);
+                if let Some(mod_pack) = mod_pack {
+                    // already checked to exist on startup
+                    prototype_data::use_mod_pack(mod_pack).unwrap();
+                }
+                if let Err(err) = handle_connection(stream, args, active) {
+                    println!("Warning: connection failed: {}", err);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_read_request() {
+        let raw = "POST /optimize HTTP/1.1\r\nHost: x\r\ncontent-length: 4\r\n\r\nbodyextra";
+        let request = read_request(&mut Cursor::new(raw)).unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/optimize");
+        assert_eq!(request.body, b"body");
+
+        let too_big = format!(
+            "POST /optimize HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY_BYTES + 1
+        );
+        assert_eq!(
+            read_request(&mut Cursor::new(too_big))
+                .err()
+                .unwrap()
+                .status,
+            413
+        );
+
+        let long_header = format!(
+            "POST /optimize HTTP/1.1\r\nX-Padding: {}\r\n\r\n",
+            "a".repeat(MAX_HEADER_BYTES)
+        );
+        assert_eq!(
+            read_request(&mut Cursor::new(long_header))
+                .err()
+                .unwrap()
+                .status,
+            431
+        );
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let options = json!({
+            "poles": ["m", "s"],
+            "max-count": "m=10",
+            "distance-cost": 2,
+            "allow-unpowered": true,
+            "quiet": false,
+            "budget": 500,
+        });
+        let opt = parse_options(options.as_object().unwrap(), 60.0).unwrap();
+        assert_eq!(opt.use_poles, vec!["m".to_string(), "s".to_string()]);
+        assert_eq!(opt.max_count.as_deref(), Some("m=10"));
+        assert_eq!(opt.distance_cost, 2.0);
+        assert!(opt.allow_unpowered);
+        assert_eq!(opt.budget, Some(60.0));
+
+        let options = json!({ "context": "/etc/passwd" });
+        let err = parse_options(options.as_object().unwrap(), 60.0).unwrap_err();
+        assert_eq!(err.status, 400);
+        let options = json!({ "no-such-option": 1 });
+        assert!(parse_options(options.as_object().unwrap(), 60.0).is_err());
+    }
+
+    #[test]
+    fn test_options_cant_smuggle_files() {
+        let options = json!({ "poles": ["--dump-stage=emit=/x"] });
+        let err = parse_options(options.as_object().unwrap(), 60.0).unwrap_err();
+        assert_eq!(err.status, 400);
+        let options = json!({ "poles": ["m", "--cache-dir", "/x"] });
+        let err = parse_options(options.as_object().unwrap(), 60.0).unwrap_err();
+        assert_eq!(err.status, 400);
+        let options = json!({ "cache-dir=/x": true });
+        let err = parse_options(options.as_object().unwrap(), 60.0).unwrap_err();
+        assert_eq!(err.status, 400);
+        let options = json!({ "max-count=m": "10" });
+        let err = parse_options(options.as_object().unwrap(), 60.0).unwrap_err();
+        assert_eq!(err.status, 400);
+    }
+
+    #[test]
+    fn test_check_parsed_options() {
+        for option in ["--dump-stage=emit=/x", "--cache-dir=/x", "--lua-script=/x"] {
+            let matches = OptimizePoles::command()
+                .try_get_matches_from(["optimize", "m", option])
+                .unwrap();
+            assert_eq!(check_parsed_options(&matches).unwrap_err().status, 400);
+        }
+        let matches = OptimizePoles::command()
+            .try_get_matches_from(["optimize", "m", "--max-count=m=10"])
+            .unwrap();
+        assert!(check_parsed_options(&matches).is_ok());
+    }
+
+    #[test]
+    fn test_write_response() {
+        let mut out = vec![];
+        write_response(&mut out, 503, &json!({ "error": "busy" })).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(out.ends_with("\r\n\r\n{\"error\":\"busy\"}"));
+    }
+}
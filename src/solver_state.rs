@@ -0,0 +1,128 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use hashbrown::HashSet;
+use petgraph::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::error::OptimizerError;
+use crate::pole_graph::CandPoleGraph;
+
+/// A pole of a saved solution, by name and position, so it can be found again among
+/// the candidates of a slightly modified blueprint.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct SavedPole {
+    name: String,
+    x: f64,
+    y: f64,
+}
+
+impl SavedPole {
+    /// In half tiles, so that it is exact.
+    fn key(&self) -> (String, i64, i64) {
+        (
+            self.name.clone(),
+            (self.x * 2.0).round() as i64,
+            (self.y * 2.0).round() as i64,
+        )
+    }
+}
+
+/// Solution poles from a previous run, for `--save-solver-state` and `--load-solver-state`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SolverState {
+    poles: Vec<SavedPole>,
+}
+
+fn state_file(dir: &Path) -> PathBuf {
+    dir.join("solution.json")
+}
+
+impl SolverState {
+    pub fn from_solution(solution: &CandPoleGraph) -> Self {
+        SolverState {
+            poles: solution
+                .node_weights()
+                .map(|node| SavedPole {
+                    name: node.entity.prototype.name.clone(),
+                    x: node.entity.position.x,
+                    y: node.entity.position.y,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<(), OptimizerError> {
+        std::fs::create_dir_all(dir)?;
+        serde_json::to_writer(BufWriter::new(File::create(state_file(dir))?), self)?;
+        Ok(())
+    }
+
+    /// The saved state in `dir`, or None if nothing was saved there yet.
+    pub fn load(dir: &Path) -> Result<Option<Self>, OptimizerError> {
+        let path = state_file(dir);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_reader(BufReader::new(File::open(
+            path,
+        )?))?))
+    }
+
+    /// Candidates that are poles of the saved solution. Returns them, and how many saved poles weren't found.
+    pub fn match_candidates(&self, candidates: &CandPoleGraph) -> (HashSet<NodeIndex>, usize) {
+        let saved = self
+            .poles
+            .iter()
+            .map(SavedPole::key)
+            .collect::<HashSet<_>>();
+        let matched = candidates
+            .node_indices()
+            .filter(|&idx| {
+                let entity = &candidates[idx].entity;
+                saved.contains(&(
+                    entity.prototype.name.clone(),
+                    (entity.position.x * 2.0).round() as i64,
+                    (entity.position.y * 2.0).round() as i64,
+                ))
+            })
+            .collect::<HashSet<_>>();
+        let missing = self.poles.len() - matched.len().min(self.poles.len());
+        (matched, missing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::point2;
+
+    use crate::bp_model::BpModel;
+    use crate::pole_graph::ToCandidatePoleGraph;
+
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let mut model = BpModel::new();
+        model.add_test_poles(&[point2(0, 0), point2(5, 0), point2(10, 0)]);
+        let graph = model
+            .get_maximally_connected_pole_graph()
+            .0
+            .to_cand_pole_graph(&model);
+        let solution = graph.filter_map(
+            |_, node| (node.entity.position.x < 8.0).then(|| node.clone()),
+            |_, w| Some(*w),
+        );
+
+        let dir = std::env::temp_dir().join(format!("solver-state-test-{}", std::process::id()));
+        SolverState::from_solution(&solution).save(&dir).unwrap();
+        let loaded = SolverState::load(&dir).unwrap().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let (matched, missing) = loaded.match_candidates(&graph);
+        assert_eq!(matched.len(), 2);
+        assert_eq!(missing, 0);
+        assert!(SolverState::load(&dir).unwrap().is_none());
+    }
+}
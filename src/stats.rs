@@ -0,0 +1,150 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use factorio_blueprint::objects::Blueprint;
+
+use crate::better_bp::BlueprintEntities;
+use crate::bp_io;
+use crate::bp_model::BpModel;
+use crate::error::OptimizerError;
+use crate::prototype_data::{self, EntityPrototypeDict};
+use crate::report::{count_by_name, EntityCounts};
+
+/// Items to place entities whose item has a different name or count, in vanilla.
+const PLACEMENT_ITEMS: [(&str, &str, usize); 2] =
+    [("straight-rail", "rail", 1), ("curved-rail", "rail", 4)];
+
+#[derive(Debug, Clone, Default)]
+pub struct BlueprintStats {
+    pub entity_counts: EntityCounts,
+    /// Total tiles covered by entities, not counting those with unknown prototypes.
+    pub footprint_area: u64,
+    /// Items needed to build the entities; item requests, e.g. modules, are not included.
+    pub item_cost: EntityCounts,
+    /// Width and height of the tile bounding box of entities with known prototypes.
+    pub size: (i32, i32),
+    pub num_tiles: usize,
+    /// Entities not in the prototype data.
+    pub num_unknown: usize,
+}
+
+pub fn blueprint_stats(bp: &Blueprint, dict: &EntityPrototypeDict) -> BlueprintStats {
+    BlueprintStats {
+        num_tiles: bp.tiles.len(),
+        ..entity_stats(&BlueprintEntities::from_blueprint(bp), dict)
+    }
+}
+
+fn entity_stats(entities: &BlueprintEntities, dict: &EntityPrototypeDict) -> BlueprintStats {
+    let mut known = entities.clone();
+    known.retain(|entity| dict.0.contains_key(entity.data.name.as_str()));
+    let model = BpModel::from_bp_entities(&known, dict);
+    let names = entities
+        .entities
+        .values()
+        .map(|entity| entity.data.name.as_str());
+    let mut stats = BlueprintStats {
+        entity_counts: count_by_name(names),
+        ..Default::default()
+    };
+    if model.all_entities().next().is_some() {
+        let size = model.get_bounding_box().size();
+        stats.size = (size.width, size.height);
+    }
+    for (name, &count) in &stats.entity_counts {
+        let Some(prototype) = dict.0.get(name) else {
+            stats.num_unknown += count;
+            continue;
+        };
+        let (width, height) = prototype.footprint();
+        stats.footprint_area += (width * height) as u64 * count as u64;
+        let (item, per_entity) = PLACEMENT_ITEMS
+            .iter()
+            .find(|(entity, _, _)| entity == name)
+            .map_or((name.as_str(), 1), |(_, item, count)| (*item, *count));
+        *stats.item_cost.entry(item.to_string()).or_default() += count * per_entity;
+    }
+    stats
+}
+
+fn print_stats(stats: &BlueprintStats) {
+    let num_entities = stats.entity_counts.values().sum::<usize>();
+    let (width, height) = stats.size;
+    println!("  Size: {}x{} tiles", width, height);
+    println!(
+        "  Entities: {}, covering {} tiles ({:.1}% of the bounding box)",
+        num_entities,
+        stats.footprint_area,
+        stats.footprint_area as f64 * 100.0 / (width * height).max(1) as f64
+    );
+    if stats.num_tiles > 0 {
+        println!("  Tiles: {}", stats.num_tiles);
+    }
+    println!("  Entity counts:");
+    for (name, count) in &stats.entity_counts {
+        println!("  {:>6} {}", count, name);
+    }
+    println!("  Item cost:");
+    for (name, count) in &stats.item_cost {
+        println!("  {:>6} {}", count, name);
+    }
+    if stats.num_unknown > 0 {
+        println!(
+            "Warning: {} entities are not in the entity data, and are not in the area or item cost",
+            stats.num_unknown
+        );
+    }
+}
+
+/// Prints entity counts, footprint, item cost, and size of a blueprint, or each blueprint in a book.
+pub fn run_stats(in_file: &Path) -> Result<(), OptimizerError> {
+    let dict = prototype_data::load_prototype_data()?;
+    let mut container = bp_io::decode(BufReader::new(File::open(in_file)?))?;
+    let blueprints = bp_io::blueprints_mut(&mut container);
+    let is_book = blueprints.len() > 1;
+    for (i, bp) in blueprints.into_iter().enumerate() {
+        if is_book {
+            println!("Blueprint {}:", i + 1);
+        } else {
+            println!("Blueprint:");
+        }
+        print_stats(&blueprint_stats(bp, &dict));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::point2;
+
+    use crate::better_bp::BlueprintEntityData;
+
+    use super::*;
+
+    #[test]
+    fn test_blueprint_stats() {
+        let dict = prototype_data::load_prototype_data().unwrap();
+        let mut entities = BlueprintEntities::new();
+        let mut add = |name: &str, x: f64, y: f64| {
+            entities.add_entity(BlueprintEntityData::new(
+                name.to_string(),
+                point2(x, y),
+                Some(0),
+            ));
+        };
+        add("assembling-machine-2", 1.5, 1.5);
+        add("small-electric-pole", 3.5, 0.5);
+        add("small-electric-pole", 3.5, 2.5);
+        add("curved-rail", 10.0, 10.0);
+        add("not-an-entity", 20.5, 20.5);
+
+        let stats = entity_stats(&entities, &dict);
+        assert_eq!(stats.entity_counts["small-electric-pole"], 2);
+        assert_eq!(stats.item_cost["rail"], 4);
+        assert!(!stats.item_cost.contains_key("curved-rail"));
+        assert_eq!(stats.num_unknown, 1);
+        let (width, height) = dict.0["curved-rail"].footprint();
+        assert_eq!(stats.footprint_area, 9 + 2 + (width * height) as u64);
+    }
+}
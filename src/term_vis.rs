@@ -0,0 +1,107 @@
+use std::collections::HashSet;
+
+use euclid::point2;
+
+use crate::bp_model::{BpModel, ModelEntity};
+
+/// What a character of the map shows; later ones take precedence when a character covers several tiles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Cell {
+    Empty,
+    Blocker,
+    Powered,
+    Pole,
+    Unpowered,
+}
+
+impl Cell {
+    fn char(self) -> char {
+        match self {
+            Cell::Empty => ' ',
+            Cell::Blocker => '░',
+            Cell::Powered => '▓',
+            Cell::Pole => '●',
+            Cell::Unpowered => '!',
+        }
+    }
+}
+
+const LEGEND: &str = "● pole  ▓ powered  ! unpowered  ░ other";
+
+/// Default width when `COLUMNS` isn't set.
+const DEFAULT_WIDTH: usize = 120;
+
+fn terminal_width() -> usize {
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .unwrap_or(DEFAULT_WIDTH)
+}
+
+/// Draws the model as text, one character per `scale`x`scale` tiles,
+/// with `scale` the smallest that fits in `max_width` characters.
+pub fn render(model: &BpModel, max_width: usize) -> String {
+    let bbox = model.get_bounding_box();
+    let size = bbox.size();
+    let scale = (size.width.max(1) as usize)
+        .div_ceil(max_width.max(1))
+        .max(1) as i32;
+    let unpowered = model
+        .unpowered_entities()
+        .map(ModelEntity::id)
+        .collect::<HashSet<_>>();
+    let tile_cell = |entity: &ModelEntity| {
+        if entity.prototype.is_pole() {
+            Cell::Pole
+        } else if unpowered.contains(&entity.id()) {
+            Cell::Unpowered
+        } else if entity.uses_power() {
+            Cell::Powered
+        } else {
+            Cell::Blocker
+        }
+    };
+
+    let mut out = String::new();
+    for y in (bbox.min.y..bbox.max.y).step_by(scale as usize) {
+        let mut line = String::new();
+        for x in (bbox.min.x..bbox.max.x).step_by(scale as usize) {
+            let cell = (y..(y + scale).min(bbox.max.y))
+                .flat_map(|ty| (x..(x + scale).min(bbox.max.x)).map(move |tx| point2(tx, ty)))
+                .flat_map(|tile| model.get_at_tile(tile).map(tile_cell))
+                .max()
+                .unwrap_or(Cell::Empty);
+            line.push(cell.char());
+        }
+        out += line.trim_end();
+        out.push('\n');
+    }
+    out
+}
+
+/// Prints the model to the terminal (`--vis-term`), scaled to fit its width.
+pub fn print_model(model: &BpModel) {
+    let width = terminal_width();
+    let scale = (model.get_bounding_box().size().width.max(1) as usize).div_ceil(width);
+    if scale > 1 {
+        println!("Each character is {}x{} tiles", scale, scale);
+    }
+    print!("{}", render(model, width));
+    println!("{}", LEGEND);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut model = BpModel::new();
+        model.add_test_pole(point2(0, 0));
+        model.add_test_powerable(point2(1, 0));
+        model.add_test_powerable(point2(9, 1));
+        assert_eq!(render(&model, 80), "●▓\n         !\n");
+        // 2x2 tiles per character; the unpowered entity takes precedence
+        assert_eq!(render(&model, 5), "●   !\n");
+    }
+}
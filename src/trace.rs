@@ -0,0 +1,39 @@
+use std::path::Path;
+
+use tracing::{info_span, Span};
+use tracing_chrome::{ChromeLayerBuilder, FlushGuard};
+use tracing_subscriber::prelude::*;
+
+/// Records spans to `path` as a Chrome trace (`--trace`), to open in Perfetto or chrome://tracing.
+/// The trace is written when the returned guard is dropped.
+pub fn start(path: &Path) -> FlushGuard {
+    let (layer, guard) = ChromeLayerBuilder::new()
+        .file(path)
+        .include_args(true)
+        .build();
+    // not `init`, which would also replace the `log` logger
+    tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))
+        .expect("tracing subscriber already set");
+    guard
+}
+
+/// Span for a pipeline stage, named after the stage so each shows separately in the trace.
+pub fn stage_span(name: &'static str) -> Span {
+    // span names must be known at compile time
+    match name {
+        "decode" => info_span!("decode"),
+        "model" => info_span!("model"),
+        "candidates" => info_span!("candidates"),
+        "solve" => info_span!("solve"),
+        "keep-all" => info_span!("keep-all"),
+        "trunk" => info_span!("trunk"),
+        "polish" => info_span!("polish"),
+        "compare" => info_span!("compare"),
+        "estimate" => info_span!("estimate"),
+        "export-model" => info_span!("export-model"),
+        "connect" => info_span!("connect"),
+        "emit" => info_span!("emit"),
+        "verify" => info_span!("verify"),
+        _ => info_span!("stage", name),
+    }
+}
@@ -0,0 +1,276 @@
+use std::path::Path;
+
+use clap::{ArgAction, Parser};
+use euclid::vec2;
+use factorio_blueprint::objects::Blueprint;
+use good_lp::*;
+use hashbrown::HashMap;
+use itertools::Itertools;
+
+use crate::better_bp::{BlueprintEntities, BlueprintEntityData, EntityId};
+use crate::bp_io::BlueprintFormat;
+use crate::bp_model::{BpModel, WorldEntity};
+use crate::error::OptimizerError;
+use crate::position::{IterTiles, TileSpaceExt};
+use crate::prototype_data::{self, EntityPrototypeRef};
+use crate::radius_query::TurretRangeWindows;
+use crate::{require_prototype, write_blueprint};
+
+#[derive(Parser, Debug)]
+pub struct TurretsArgs {
+    #[arg(long, default_value = "gun-turret", help = "Turret entity to place")]
+    turret: String,
+
+    #[arg(
+        short = 'n',
+        long,
+        default_value = "1",
+        help = "Number of turrets every wall must be in range of"
+    )]
+    min_coverage: usize,
+
+    #[arg(
+        long,
+        help = "Only check that existing turrets cover every wall; don't add turrets",
+        action = ArgAction::SetTrue
+    )]
+    check: bool,
+
+    #[arg(
+        long,
+        default_value = "60",
+        help = "Time limit for the solver, in seconds"
+    )]
+    time_limit: f64,
+}
+
+fn is_wall(entity: &WorldEntity) -> bool {
+    matches!(entity.prototype.type_.as_str(), "wall" | "gate")
+}
+
+/// If any part of `target` is within range of `turret`.
+fn in_range(turret: &WorldEntity, target: &WorldEntity) -> bool {
+    let range = turret.prototype.turret_data.unwrap().range;
+    let bbox = target.world_bbox();
+    let closest = turret.position.clamp(bbox.min, bbox.max);
+    (closest - turret.position).length() <= range
+}
+
+/// Walls within range of `turret`.
+fn covered_walls(
+    model: &BpModel,
+    windows: &mut TurretRangeWindows,
+    turret: &WorldEntity,
+) -> Vec<EntityId> {
+    windows
+        .get_window_for(turret)
+        .cur_items()
+        .filter_map(|id| model.get(*id))
+        .filter(|entity| is_wall(entity) && in_range(turret, entity))
+        .map(|entity| entity.id())
+        .sorted()
+        .collect()
+}
+
+/// Number of existing turrets in range of each wall.
+pub fn wall_coverage(model: &BpModel) -> HashMap<EntityId, usize> {
+    let mut windows = TurretRangeWindows::new(model);
+    let mut coverage = model
+        .all_entities()
+        .filter(|entity| is_wall(entity))
+        .map(|entity| (entity.id(), 0))
+        .collect::<HashMap<_, _>>();
+    for turret in model.all_entities_grid_order() {
+        if turret.prototype.turret_data.is_none() {
+            continue;
+        }
+        for wall in covered_walls(model, &mut windows, turret) {
+            *coverage.get_mut(&wall).unwrap() += 1;
+        }
+    }
+    coverage
+}
+
+/// All positions `turret` can be placed in the blueprint's bounding box, with the walls each would cover.
+/// Keeps at most `max_copies` positions covering the same walls, and none covering no walls.
+fn candidate_turrets(
+    model: &BpModel,
+    turret: &EntityPrototypeRef,
+    max_copies: usize,
+) -> Vec<(WorldEntity, Vec<EntityId>)> {
+    let (width, height) = turret.footprint();
+    let mut windows = TurretRangeWindows::new(model);
+    let mut copies = HashMap::<Vec<EntityId>, usize>::new();
+    let mut candidates = vec![];
+    for top_left in model.get_bounding_box().iter_tiles() {
+        let entity = WorldEntity {
+            position: top_left.corner_map_pos() + vec2(width as f64 / 2.0, height as f64 / 2.0),
+            direction: 0,
+            prototype: turret.clone(),
+        };
+        if !model.can_place(&entity) {
+            continue;
+        }
+        let walls = covered_walls(model, &mut windows, &entity);
+        let num_copies = copies.entry(walls.clone()).or_default();
+        if walls.is_empty() || *num_copies >= max_copies {
+            continue;
+        }
+        *num_copies += 1;
+        candidates.push((entity, walls));
+    }
+    candidates
+}
+
+/// Chooses the fewest turrets to add so that every wall is in range of at least `min_coverage` turrets,
+/// counting existing turrets. Like the pole set cover, but each wall must be covered `min_coverage` times.
+pub fn place_turrets(
+    model: &BpModel,
+    turret: &EntityPrototypeRef,
+    min_coverage: usize,
+    time_limit: f64,
+) -> Result<Vec<WorldEntity>, OptimizerError> {
+    if turret.turret_data.is_none() {
+        return Err(format!("{} is not a turret", turret.name).into());
+    }
+    let needed = wall_coverage(model)
+        .into_iter()
+        .filter(|(_, count)| *count < min_coverage)
+        .map(|(wall, count)| (wall, min_coverage - count))
+        .sorted()
+        .collect_vec();
+    if needed.is_empty() {
+        return Ok(vec![]);
+    }
+    let candidates = candidate_turrets(model, turret, min_coverage);
+    let mut coverage = HashMap::<EntityId, Vec<usize>>::new();
+    for (i, (_, walls)) in candidates.iter().enumerate() {
+        for wall in walls {
+            coverage.entry(*wall).or_default().push(i);
+        }
+    }
+    let impossible = needed
+        .iter()
+        .filter(|(wall, count)| coverage.get(wall).map_or(0, Vec::len) < *count)
+        .count();
+    if impossible > 0 {
+        return Err(format!(
+            "{} walls cannot be covered by {} turrets; not enough free space in range",
+            impossible, min_coverage
+        )
+        .into());
+    }
+    println!(
+        "Choosing from {} candidate turret positions for {} walls",
+        candidates.len(),
+        needed.len()
+    );
+
+    let mut vars = ProblemVariables::new();
+    let turret_vars = candidates
+        .iter()
+        .map(|_| vars.add(variable().binary()))
+        .collect_vec();
+    let objective: Expression = turret_vars.iter().copied().sum();
+    let mut problem = highs(vars.minimise(objective));
+    problem.set_verbose(false);
+    let mut problem = problem.set_time_limit(time_limit);
+    for (wall, count) in &needed {
+        let var_sum: Expression = coverage[wall].iter().map(|&i| turret_vars[i]).sum();
+        problem.add_constraint(constraint!(var_sum >= *count as f64));
+    }
+    let solution = problem.solve()?;
+
+    Ok(candidates
+        .into_iter()
+        .zip(turret_vars)
+        .filter(|(_, var)| solution.value(*var) > 0.5)
+        .map(|((entity, _), _)| entity)
+        .collect())
+}
+
+/// Checks or adds turrets so that every wall in a blueprint is covered. Writes nothing if `out_file` is None.
+pub fn run_turrets(
+    mut bp: Blueprint,
+    args: &TurretsArgs,
+    out_file: Option<&Path>,
+    format: BlueprintFormat,
+) -> Result<(), OptimizerError> {
+    let dict = prototype_data::load_prototype_data()?;
+    let mut entities = BlueprintEntities::from_blueprint(&bp);
+    let model = BpModel::from_bp_entities(&entities, &dict);
+    let coverage = wall_coverage(&model);
+    let uncovered = coverage
+        .values()
+        .filter(|count| **count < args.min_coverage)
+        .count();
+    println!(
+        "{} of {} walls are in range of fewer than {} turrets",
+        uncovered,
+        coverage.len(),
+        args.min_coverage
+    );
+    if args.check {
+        return if uncovered == 0 {
+            Ok(())
+        } else {
+            Err(format!("{} walls not covered", uncovered).into())
+        };
+    }
+
+    let turret = require_prototype(&args.turret, &dict)?;
+    let turrets = place_turrets(&model, &turret, args.min_coverage, args.time_limit)?;
+    println!("Adding {} {}", turrets.len(), turret.name);
+    for entity in turrets {
+        entities.add_entity(BlueprintEntityData::new(
+            entity.prototype.name.clone(),
+            entity.position,
+            None,
+        ));
+    }
+    entities.write_to_blueprint(&mut bp);
+    match out_file {
+        Some(out_file) => write_blueprint(bp, &out_file.to_path_buf(), format, None).map(|_| ()),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::point2;
+
+    use super::*;
+
+    #[test]
+    fn test_place_turrets() {
+        let dict = prototype_data::load_prototype_data().unwrap();
+        let mut entities = BlueprintEntities::new();
+        // a 30x30 ring of walls
+        for x in 0..30 {
+            for y in 0..30 {
+                if x == 0 || x == 29 || y == 0 || y == 29 {
+                    entities.add_entity(BlueprintEntityData::new(
+                        "stone-wall".to_string(),
+                        point2(x as f64 + 0.5, y as f64 + 0.5),
+                        None,
+                    ));
+                }
+            }
+        }
+        let model = BpModel::from_bp_entities(&entities, &dict);
+        assert!(wall_coverage(&model).values().all(|count| *count == 0));
+
+        let turret = dict["gun-turret"].clone();
+        let turrets = place_turrets(&model, &turret, 2, 10.0).unwrap();
+        assert!(!turrets.is_empty());
+        for wall in model.all_entities() {
+            let count = turrets.iter().filter(|t| in_range(t, wall)).count();
+            assert!(
+                count >= 2,
+                "wall at {:?} covered {} times",
+                wall.position,
+                count
+            );
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+use clap::Parser;
+use hashbrown::HashMap;
+
+use crate::better_bp::BlueprintEntities;
+use crate::bp_io::{self, BlueprintFormat};
+use crate::error::OptimizerError;
+use crate::intern::Name;
+use crate::prototype_data::{self, EntityPrototypeDict, EntityPrototypeRef};
+use crate::report::{count_by_name, EntityCounts};
+use crate::{require_prototype, sep_commas};
+
+#[derive(Parser, Debug)]
+pub struct UpgradeArgs {
+    #[arg(
+        long,
+        required = true,
+        help = "Entities to replace; format: 'old=new' separated by commas. Can be given multiple times"
+    )]
+    map: Vec<String>,
+}
+
+/// Replacement for an entity prototype.
+#[derive(Debug, Clone)]
+pub struct EntityUpgrade {
+    pub to: EntityPrototypeRef,
+    /// If the new footprint is the old one rotated by 90 degrees; entities are rotated to match.
+    pub rotate: bool,
+}
+
+/// Parses `old=new` pairs, checking that the new entity fits in the old one's footprint.
+pub fn parse_upgrade_map(
+    map: &[String],
+    dict: &EntityPrototypeDict,
+) -> Result<HashMap<String, EntityUpgrade>, OptimizerError> {
+    sep_commas(map)
+        .map(|part| {
+            let (from, to) = part
+                .split_once('=')
+                .ok_or_else(|| format!("Expected 'old=new', got '{}'", part))?;
+            let from = require_prototype(from, dict)?;
+            let to = require_prototype(to, dict)?;
+            let (from_size, to_size) = (from.footprint(), to.footprint());
+            let rotate = if to_size == from_size {
+                false
+            } else if (to_size.1, to_size.0) == from_size {
+                true
+            } else {
+                return Err(format!(
+                    "Cannot replace {} ({}x{}) with {} ({}x{}): sizes are different",
+                    from.name, from_size.0, from_size.1, to.name, to_size.0, to_size.1
+                )
+                .into());
+            };
+            Ok((from.name.clone(), EntityUpgrade { to, rotate }))
+        })
+        .collect()
+}
+
+impl BlueprintEntities {
+    /// Replaces entity prototypes according to `map`. Returns counts of replaced entities, by old name.
+    pub fn upgrade(&mut self, map: &HashMap<String, EntityUpgrade>) -> EntityCounts {
+        let mut replaced = vec![];
+        for entity in self.entities.values_mut() {
+            let Some(upgrade) = map.get(entity.data.name.as_str()) else {
+                continue;
+            };
+            replaced.push(std::mem::replace(
+                &mut entity.data.name,
+                Name::new(&upgrade.to.name),
+            ));
+            if upgrade.rotate {
+                let direction = (entity.data.direction.unwrap_or(0) + 2) % 8;
+                entity.data.direction = Some(direction).filter(|&d| d != 0);
+            }
+        }
+        count_by_name(replaced.iter().map(|name| name.as_str()))
+    }
+}
+
+/// Upgrades entities in a blueprint, or every blueprint in a book. Writes nothing if `out_file` is None.
+pub fn run_upgrade(
+    args: &UpgradeArgs,
+    in_file: &Path,
+    out_file: Option<&Path>,
+    format: BlueprintFormat,
+) -> Result<(), OptimizerError> {
+    let map = parse_upgrade_map(&args.map, &prototype_data::load_prototype_data()?)?;
+    let mut container = bp_io::decode(BufReader::new(File::open(in_file)?))?;
+    let mut replaced = EntityCounts::new();
+    for bp in bp_io::blueprints_mut(&mut container) {
+        let mut entities = BlueprintEntities::from_blueprint(bp);
+        for (name, count) in entities.upgrade(&map) {
+            *replaced.entry(name).or_default() += count;
+        }
+        entities.write_to_blueprint(bp);
+    }
+    println!("Replaced entities:");
+    for (name, count) in &replaced {
+        println!("  {:>6} {} -> {}", count, name, map[name].to.name);
+    }
+    match out_file {
+        Some(out_file) => {
+            bp_io::encode(BufWriter::new(File::create(out_file)?), &container, format)
+        }
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::point2;
+
+    use crate::better_bp::BlueprintEntityData;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_upgrade_map() {
+        let dict = prototype_data::load_prototype_data().unwrap();
+        let map =
+            parse_upgrade_map(&["transport-belt=fast-transport-belt".to_string()], &dict).unwrap();
+        assert_eq!(map["transport-belt"].to.name, "fast-transport-belt");
+        assert!(!map["transport-belt"].rotate);
+
+        assert!(
+            parse_upgrade_map(&["wooden-chest=assembling-machine-1".to_string()], &dict).is_err()
+        );
+        assert!(parse_upgrade_map(&["wooden-chest".to_string()], &dict).is_err());
+    }
+
+    #[test]
+    fn test_upgrade_rotates() {
+        let dict = prototype_data::load_prototype_data().unwrap();
+        let mut entities = BlueprintEntities::new();
+        let id = entities.add_entity(BlueprintEntityData::new(
+            "wooden-chest".to_string(),
+            point2(0.5, 0.5),
+            None,
+        ));
+        let map = HashMap::from([(
+            "wooden-chest".to_string(),
+            EntityUpgrade {
+                to: dict["iron-chest"].clone(),
+                rotate: true,
+            },
+        )]);
+        let replaced = entities.upgrade(&map);
+        assert_eq!(
+            replaced,
+            EntityCounts::from([("wooden-chest".to_string(), 1)])
+        );
+        let entity = entities.get_mut(id).unwrap();
+        assert_eq!(entity.data.name, "iron-chest");
+        assert_eq!(entity.data.direction, Some(2));
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use itertools::Itertools;
+
+use crate::bp_model::{BpModel, ModelEntity};
+use crate::position::{BoundingBoxExt, MapPosition, TileBoundingBox};
+
+/// Size of the squares pole supply areas are grouped by, in tiles.
+const CELL_SIZE: i32 = 16;
+
+/// Something wrong with a solution, found by [verify_solution].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    /// An entity that uses power is not in the supply area of any pole.
+    Unpowered { name: String, position: MapPosition },
+    /// The poles form more than one network.
+    Disconnected { networks: usize },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Violation::Unpowered { name, position } => {
+                write!(
+                    f,
+                    "{} at ({}, {}) is unpowered",
+                    name, position.x, position.y
+                )
+            }
+            Violation::Disconnected { networks } => {
+                write!(f, "poles form {} separate networks", networks)
+            }
+        }
+    }
+}
+
+fn cells(area: TileBoundingBox) -> impl Iterator<Item = (i32, i32)> {
+    let cell = |min: i32, max: i32| min.div_euclid(CELL_SIZE)..=(max - 1).div_euclid(CELL_SIZE);
+    itertools::iproduct!(cell(area.min.x, area.max.x), cell(area.min.y, area.max.y))
+}
+
+/// Checks a finished model from scratch, without the tile index the optimizer uses:
+/// every entity that uses power must overlap the supply area of a pole, and all poles must be connected.
+/// Unpowered entities come first, in grid order.
+pub fn verify_solution(model: &BpModel) -> Vec<Violation> {
+    let mut supply_areas = HashMap::<(i32, i32), Vec<TileBoundingBox>>::new();
+    for pole in model.poles() {
+        let Some((pole_data, _)) = pole.pole_data() else {
+            continue;
+        };
+        let area = pole_data
+            .supply_box()
+            .translate(pole.position.to_vector())
+            .round_out_to_tiles();
+        for cell in cells(area) {
+            supply_areas.entry(cell).or_default().push(area);
+        }
+    }
+    let is_powered = |entity: &ModelEntity| {
+        let tiles = entity.world_bbox().round_out_to_tiles();
+        cells(tiles).any(|cell| {
+            supply_areas
+                .get(&cell)
+                .is_some_and(|areas| areas.iter().any(|area| area.intersects(&tiles)))
+        })
+    };
+    let mut violations = model
+        .all_entities_grid_order()
+        .filter(|entity| entity.uses_power() && !is_powered(entity))
+        .map(|entity| Violation::Unpowered {
+            name: entity.prototype.name.clone(),
+            position: entity.position,
+        })
+        .collect_vec();
+    let networks = model.pole_networks().len();
+    if networks > 1 {
+        violations.push(Violation::Disconnected { networks });
+    }
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::point2;
+
+    use super::*;
+    use crate::bp_model::test_util::powerable_prototype;
+    use crate::bp_model::WorldEntity;
+    use crate::position::{TilePosition, TileSpaceExt};
+
+    fn add_powerable(model: &mut BpModel, x: i32, y: i32) {
+        model.add_overlap(WorldEntity {
+            position: TilePosition::new(x, y).center_map_pos(),
+            direction: 0,
+            prototype: powerable_prototype(),
+        });
+    }
+
+    #[test]
+    fn test_verify_solution() {
+        let mut model = BpModel::new();
+        let poles = model.add_test_poles(&[point2(0, 0), point2(5, 0)]);
+        add_powerable(&mut model, 2, 2);
+        add_powerable(&mut model, 7, -2);
+        assert_eq!(
+            verify_solution(&model),
+            vec![Violation::Disconnected { networks: 2 }]
+        );
+
+        model.add_cable_connection(poles[0], poles[1]).unwrap();
+        assert_eq!(verify_solution(&model), vec![]);
+
+        add_powerable(&mut model, 20, 0);
+        assert_eq!(
+            verify_solution(&model),
+            vec![Violation::Unpowered {
+                name: "solar-panel".to_string(),
+                position: point2(20.5, 0.5),
+            }]
+        );
+        assert_eq!(
+            model.unpowered_entities().count(),
+            verify_solution(&model).len()
+        );
+    }
+}
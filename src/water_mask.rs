@@ -0,0 +1,61 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use euclid::point2;
+use image::RgbaImage;
+
+use crate::error::OptimizerError;
+use crate::position::TilePosition;
+
+/// Reads the water tiles for `--water-mask`, in blueprint tile coordinates.
+///
+/// A `.json` file is an array of tile positions: `[[x, y], ...]`.
+/// In an image, each pixel is a tile, with the top-left pixel at `origin`; see [water_in_image].
+pub fn load(path: &Path, origin: (i32, i32)) -> Result<Vec<TilePosition>, OptimizerError> {
+    let is_json = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+    if is_json {
+        let tiles: Vec<(i32, i32)> = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        return Ok(tiles.into_iter().map(|(x, y)| point2(x, y)).collect());
+    }
+    let image = image::open(path)
+        .map_err(|err| format!("Can't read --water-mask image {:?}: {}", path, err))?;
+    Ok(water_in_image(&image.to_rgba8(), origin))
+}
+
+/// Pixels that are mostly blue, as water is on the map; transparent pixels are land.
+pub fn water_in_image(image: &RgbaImage, origin: (i32, i32)) -> Vec<TilePosition> {
+    image
+        .enumerate_pixels()
+        .filter(|(_, _, pixel)| {
+            let [r, g, b, a] = pixel.0;
+            a >= 128 && b > r && b > g
+        })
+        .map(|(x, y, _)| point2(origin.0 + x as i32, origin.1 + y as i32))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+
+    #[test]
+    fn test_water_in_image() {
+        let mut image = RgbaImage::from_pixel(3, 2, Rgba([40, 80, 30, 255]));
+        image.put_pixel(1, 0, Rgba([20, 60, 200, 255]));
+        image.put_pixel(2, 1, Rgba([20, 60, 200, 0]));
+        assert_eq!(water_in_image(&image, (-1, 5)), vec![point2(0, 5)]);
+
+        let path = std::env::temp_dir().join("water_mask_test.json");
+        std::fs::write(&path, "[[1, 2], [-3, 4]]").unwrap();
+        assert_eq!(
+            load(&path, (0, 0)).unwrap(),
+            vec![point2(1, 2), point2(-3, 4)]
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,13 @@
+use std::process::Command;
+
+/// Runs the full optimization pipeline on the bundled blueprints; slow.
+/// Golden files are (re)generated with `cargo run --release -- self-test --bless`.
+#[ignore]
+#[test]
+fn self_test_matches_golden_files() {
+    let status = Command::new(env!("CARGO_BIN_EXE_factorio-opti-poles"))
+        .arg("self-test")
+        .status()
+        .expect("failed to run self test");
+    assert!(status.success());
+}